@@ -12,38 +12,632 @@
 //! Derive procedural macros for the [`mem_dbg`](https://crates.io/crates/mem_dbg) crate.
 
 use proc_macro::TokenStream;
-use quote::{quote, ToTokens};
+use quote::{quote, quote_spanned, ToTokens};
 use syn::{
     parse_macro_input, parse_quote, parse_quote_spanned, spanned::Spanned, Data, DeriveInput,
 };
 
+/// Attributes `#[mem_dbg(...)]` of a type.
+#[derive(Default)]
+struct ContainerAttrs {
+    /// `bound = "..."`: the predicates replacing the automatically generated
+    /// bounds on the types of the fields.
+    bound: Option<Vec<syn::WherePredicate>>,
+    /// Whether the type is `#[repr(packed)]`, in which case its fields cannot
+    /// be borrowed, as they might be unaligned.
+    packed: bool,
+    /// `capacity`: compute sizes as if `CAPACITY` were always set.
+    capacity: bool,
+    /// Whether the type has no generic parameters, in which case the element
+    /// types of its fields are checked by [`copy_type_assertions`].
+    concrete: bool,
+}
+
+impl ContainerAttrs {
+    /// Parses the attributes of a type, checking also the attributes of all
+    /// its fields, so that [`FieldAttrs::of`] cannot fail afterwards.
+    fn parse(input: &DeriveInput) -> syn::Result<Self> {
+        let mut container_attrs = Self {
+            concrete: input.generics.params.is_empty(),
+            ..Self::default()
+        };
+        for attr in input.attrs.iter().filter(|x| x.path().is_ident("mem_dbg")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("bound") {
+                    let value: syn::LitStr = meta.value()?.parse()?;
+                    let predicates = value.parse_with(
+                        syn::punctuated::Punctuated::<syn::WherePredicate, syn::Token![,]>::parse_terminated,
+                    )?;
+                    container_attrs.bound = Some(predicates.into_iter().collect());
+                    Ok(())
+                } else if meta.path.is_ident("capacity") {
+                    container_attrs.capacity = true;
+                    Ok(())
+                } else {
+                    Err(meta.error("unsupported mem_dbg attribute"))
+                }
+            })?;
+        }
+        for attr in input.attrs.iter().filter(|x| x.path().is_ident("repr")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("packed") {
+                    container_attrs.packed = true;
+                }
+                // Skip the arguments of packed(N), align(N), etc.
+                if meta.input.peek(syn::token::Paren) {
+                    let content;
+                    syn::parenthesized!(content in meta.input);
+                    content.parse::<proc_macro2::TokenStream>()?;
+                }
+                Ok(())
+            })?;
+        }
+
+        let fields: Vec<&syn::Field> = match &input.data {
+            Data::Struct(s) => s.fields.iter().collect(),
+            Data::Enum(e) => e.variants.iter().flat_map(|v| v.fields.iter()).collect(),
+            Data::Union(u) => u.fields.named.iter().collect(),
+        };
+        for field in fields {
+            FieldAttrs::parse(field)?;
+        }
+
+        Ok(container_attrs)
+    }
+
+    /// Adds to `where_clause` the bound `predicate` on the type of `field`,
+    /// unless the bounds have been replaced by `#[mem_dbg(bound = "...")]` or
+    /// the field is annotated with `#[mem_dbg(no_bound)]`.
+    fn push_field_bound(
+        &self,
+        where_clause: &mut syn::WhereClause,
+        field: &syn::Field,
+        predicate: syn::WherePredicate,
+    ) {
+        if self.bound.is_none() && !FieldAttrs::of(field).no_bound {
+            where_clause.predicates.push(predicate);
+        }
+    }
+
+    /// Returns the bound `MemSize` on the type of `field`.
+    ///
+    /// If the element type of the field is checked by
+    /// [`copy_type_assertions`], the bound is higher-ranked, so that it is not
+    /// checked on the definition of the type: otherwise, a missing
+    /// implementation of `CopyType` would be reported also as an obscure
+    /// missing implementation of `MemSize` for the field.
+    fn mem_size_bound(&self, field: &syn::Field) -> syn::WherePredicate {
+        let field_ty = &field.ty;
+        if self.concrete && checked_element_type(field).is_some() {
+            parse_quote_spanned!(field.span()=> for<'memsize> #field_ty: mem_dbg::MemSize)
+        } else {
+            parse_quote_spanned!(field.span()=> #field_ty: mem_dbg::MemSize)
+        }
+    }
+
+    /// Returns a reference to the field `field_ident` of `self`.
+    ///
+    /// For packed types, the reference points to a bitwise copy of the field,
+    /// which is never dropped (see `mem_dbg::_read_packed`).
+    fn field_ref(&self, field_ident: &proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+        if self.packed {
+            quote!(&*unsafe { mem_dbg::_read_packed(core::ptr::addr_of!(self.#field_ident)) })
+        } else {
+            quote!(&self.#field_ident)
+        }
+    }
+
+    /// Returns the code setting `CAPACITY` in the flags `flags` of type
+    /// `flags_ty` (`SizeFlags` or `DbgFlags`) if the type is annotated with
+    /// `#[mem_dbg(capacity)]`, to be placed at the start of the generated
+    /// methods.
+    fn capacity_code(
+        &self,
+        flags: proc_macro2::TokenStream,
+        flags_ty: proc_macro2::TokenStream,
+    ) -> proc_macro2::TokenStream {
+        if self.capacity {
+            quote!(let #flags = #flags | mem_dbg::#flags_ty::CAPACITY;)
+        } else {
+            quote!()
+        }
+    }
+
+    /// Returns the code of `MemSize::_mem_size_rec` given the code
+    /// `visit_code` visiting the fields with the flags `_memsize_flags`: if
+    /// the type is annotated with `#[mem_dbg(capacity)]`, `CAPACITY` is set
+    /// in the flags and the heap capacity of the fields is counted as used.
+    fn mem_size_rec_code(&self, visit_code: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+        if self.capacity {
+            quote! {
+                let _memsize_flags = _memsize_ctx.flags() | mem_dbg::SizeFlags::CAPACITY;
+                _memsize_ctx._capacity_as_used(|_memsize_ctx| { #visit_code })
+            }
+        } else {
+            quote! {
+                let _memsize_flags = _memsize_ctx.flags();
+                #visit_code
+            }
+        }
+    }
+
+    /// Adds to `where_clause` the predicates specified by
+    /// `#[mem_dbg(bound = "...")]`, if any.
+    fn push_bound(&self, where_clause: &mut syn::WhereClause) {
+        if let Some(bound) = &self.bound {
+            where_clause.predicates.extend(bound.iter().cloned());
+        }
+    }
+}
+
+/// Attributes `#[mem_dbg(...)]` of a field.
+#[derive(Default)]
+struct FieldAttrs {
+    /// `no_bound`: do not generate a bound on the type of the field.
+    no_bound: bool,
+    /// `opaque`: display the field as a single line, without recursing into
+    /// it; the type of the field needs only implement `MemSize`.
+    opaque: bool,
+    /// `follow`: follow references and reference-counted pointers in the
+    /// field, regardless of the flags.
+    follow: bool,
+    /// `no_follow`: never follow references and reference-counted pointers
+    /// in the field, regardless of the flags.
+    no_follow: bool,
+}
+
+impl FieldAttrs {
+    fn parse(field: &syn::Field) -> syn::Result<Self> {
+        let mut field_attrs = Self::default();
+        for attr in field.attrs.iter().filter(|x| x.path().is_ident("mem_dbg")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("no_bound") {
+                    field_attrs.no_bound = true;
+                    Ok(())
+                } else if meta.path.is_ident("opaque") {
+                    field_attrs.opaque = true;
+                    Ok(())
+                } else if meta.path.is_ident("follow") {
+                    field_attrs.follow = true;
+                    Ok(())
+                } else if meta.path.is_ident("no_follow") {
+                    field_attrs.no_follow = true;
+                    Ok(())
+                } else {
+                    Err(meta.error("unsupported mem_dbg attribute"))
+                }
+            })?;
+        }
+        if field_attrs.follow && field_attrs.no_follow {
+            return Err(syn::Error::new(
+                field.span(),
+                "the mem_dbg attributes follow and no_follow are mutually exclusive",
+            ));
+        }
+        Ok(field_attrs)
+    }
+
+    /// Returns the attributes of a field that has already been checked by
+    /// [`ContainerAttrs::parse`].
+    fn of(field: &syn::Field) -> Self {
+        Self::parse(field).unwrap_or_default()
+    }
+
+    /// Returns the flags of type `flags_ty` (`SizeFlags` or `DbgFlags`) to be
+    /// passed to the field, given the flags `flags` of the containing value:
+    /// `FOLLOW_REFS` and `FOLLOW_RCS` are set by `#[mem_dbg(follow)]` and
+    /// cleared by `#[mem_dbg(no_follow)]`.
+    fn field_flags(
+        field: &syn::Field,
+        flags: proc_macro2::TokenStream,
+        flags_ty: proc_macro2::TokenStream,
+    ) -> proc_macro2::TokenStream {
+        let field_attrs = Self::of(field);
+        if field_attrs.follow {
+            quote!((#flags | mem_dbg::#flags_ty::FOLLOW_REFS | mem_dbg::#flags_ty::FOLLOW_RCS))
+        } else if field_attrs.no_follow {
+            quote!(#flags.difference(mem_dbg::#flags_ty::FOLLOW_REFS | mem_dbg::#flags_ty::FOLLOW_RCS))
+        } else {
+            flags
+        }
+    }
+
+    /// Returns the size flags to be passed to the field by the `MemSize`
+    /// derive; see [`FieldAttrs::field_flags`].
+    fn size_flags(field: &syn::Field) -> proc_macro2::TokenStream {
+        Self::field_flags(field, quote!(_memsize_flags), quote!(SizeFlags))
+    }
+}
+
+/// Returns the bound needed by the `MemDbg` derive on the type of a field.
+fn mem_dbg_field_bound(field: &syn::Field) -> syn::WherePredicate {
+    let field_ty = &field.ty;
+    if FieldAttrs::of(field).opaque {
+        parse_quote_spanned!(field.span() => #field_ty: mem_dbg::MemSize)
+    } else {
+        parse_quote_spanned!(field.span() => #field_ty: mem_dbg::MemDbgImpl)
+    }
+}
+
+/// Returns the code adding the node of a field given a reference to it and
+/// the code computing its offset, recursively or, if the field is annotated
+/// with `#[mem_dbg(opaque)]`, as a single node.
+fn mem_dbg_field_code(
+    field: &syn::Field,
+    field_ref: proc_macro2::TokenStream,
+    field_ident_str: proc_macro2::TokenStream,
+    offset: proc_macro2::TokenStream,
+) -> proc_macro2::TokenStream {
+    let field_ty = &field.ty;
+    let flags = FieldAttrs::field_flags(field, quote!(_memdbg_flags), quote!(DbgFlags));
+    let node = if FieldAttrs::of(field).opaque {
+        quote! {
+            mem_dbg::MemNode::new::<#field_ty>(#field_ref, #field_ident_str, _memdbg_padded_size, #flags)
+        }
+    } else {
+        quote! {
+            <#field_ty as mem_dbg::MemDbgImpl>::_mem_dbg_node(#field_ref, #field_ident_str, _memdbg_padded_size, _memdbg_max_depth - 1, #flags)
+        }
+    };
+    quote! {
+        _memdbg_node.children.push(mem_dbg::MemNode { offset: Some(#offset), ..#node })
+    }
+}
+
+/// Returns the code adding the nodes of the fields of a structure or of an enum
+/// variant given the code computing the type names and sizes of the fields,
+/// and the arms of the match statement adding the node of each field.
+///
+/// The code expects `_memdbg_id_sizes` to contain the result of
+/// `mem_dbg::_padded_sizes`.
+fn mem_dbg_fields_code(
+    type_names: &[proc_macro2::TokenStream],
+    sizes: &[proc_macro2::TokenStream],
+    match_code: &[proc_macro2::TokenStream],
+) -> proc_macro2::TokenStream {
+    let n = type_names.len();
+    quote! {
+        let _memdbg_lines: [mem_dbg::_FieldLine; #n] = if _memdbg_flags.contains(mem_dbg::DbgFlags::DEDUP_TYPES) {
+            let _memdbg_size_flags = _memdbg_flags.to_size_flags();
+            mem_dbg::_FieldLine::dedup(&_memdbg_id_sizes, [#(#type_names),*], [#(#sizes),*])
+        } else {
+            mem_dbg::_FieldLine::fields()
+        };
+
+        for (_memdbg_i, &(_memdbg_field_idx, _memdbg_padded_size)) in _memdbg_id_sizes.iter().enumerate() {
+            if !_memdbg_lines[_memdbg_i].push_on(_memdbg_node, _memdbg_flags) {
+                continue;
+            }
+            match _memdbg_field_idx {
+                #(#match_code)*
+                _ => unreachable!(),
+            }
+        }
+    }
+}
+
+/// Returns the code computing the type name and the size of a field given a
+/// reference to it, as needed by [`mem_dbg_fields_code`].
+fn mem_dbg_field_type_name_and_size(
+    field: &syn::Field,
+    field_ref: &proc_macro2::TokenStream,
+) -> (proc_macro2::TokenStream, proc_macro2::TokenStream) {
+    let field_ty = &field.ty;
+    let flags = FieldAttrs::field_flags(field, quote!(_memdbg_size_flags), quote!(SizeFlags));
+    (
+        quote! { core::any::type_name::<#field_ty>() },
+        quote! { <#field_ty as mem_dbg::MemSize>::mem_size(#field_ref, #flags) },
+    )
+}
+
+/// Returns the identifier to which the field of index `field_idx` of an enum
+/// variant is bound, so that it cannot clash with the names used by the
+/// generated code.
+fn field_binding(field_idx: usize) -> syn::Ident {
+    syn::Ident::new(
+        &format!("_memdbg_field_{}", field_idx),
+        proc_macro2::Span::call_site(),
+    )
+}
+
+/// Returns the identifier of the variable storing the offset of the field of
+/// index `field_idx` of an enum variant.
+fn offset_ident(field_idx: usize) -> syn::Ident {
+    syn::Ident::new(
+        &format!("_memdbg_offset_{}", field_idx),
+        proc_macro2::Span::call_site(),
+    )
+}
+
+/// Returns the code computing the offset of the field of index `field_idx` of
+/// an enum variant bound to `binding`; `offset_of_args` are the arguments of
+/// `offset_of!` for the field.
+///
+/// On stable Rust, `offset_of!` does not support enums, so the offset is
+/// computed by subtracting the address of the enum from the address of the
+/// field, which lies within the enum.
+fn offset_code(
+    field_idx: usize,
+    binding: &syn::Ident,
+    offset_of_args: proc_macro2::TokenStream,
+) -> proc_macro2::TokenStream {
+    let offset_ident = offset_ident(field_idx);
+    quote! {
+        #[cfg(feature = "offset_of_enum")]
+        let #offset_ident = core::mem::offset_of!(#offset_of_args);
+        #[cfg(not(feature = "offset_of_enum"))]
+        let #offset_ident = #binding as *const _ as usize - self as *const Self as usize;
+    }
+}
+
+/// How to compute the size of a union, as specified by the
+/// `#[mem_size(union = "...")]` attribute.
+enum UnionMode {
+    /// `union = "shallow"`: the size is the stack size of the union.
+    Shallow,
+    /// `union = "with = path"`: the size is computed by the function
+    /// `path`, which must have signature `fn(&Self, SizeFlags) -> usize`.
+    With(syn::Path),
+}
+
+/// Parses the `#[mem_size(union = "...")]` attribute, if present.
+fn parse_union_mode(attrs: &[syn::Attribute]) -> syn::Result<Option<UnionMode>> {
+    let mut union_mode = None;
+    for attr in attrs.iter().filter(|x| x.path().is_ident("mem_size")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("union") {
+                let value: syn::LitStr = meta.value()?.parse()?;
+                if value.value() == "shallow" {
+                    union_mode = Some(UnionMode::Shallow);
+                } else {
+                    union_mode = Some(UnionMode::With(value.parse_with(
+                        |input: syn::parse::ParseStream| {
+                            let with: syn::Ident = input.parse()?;
+                            if with != "with" {
+                                return Err(syn::Error::new(
+                                    with.span(),
+                                    "expected \"shallow\" or \"with = path::to::function\"",
+                                ));
+                            }
+                            input.parse::<syn::Token![=]>()?;
+                            input.parse::<syn::Path>()
+                        },
+                    )?));
+                }
+                Ok(())
+            } else {
+                Err(meta.error("unsupported mem_size attribute"))
+            }
+        })?;
+    }
+    Ok(union_mode)
+}
+
+/// Returns the type of the elements of `ty`, if `ty` is a vector, an array,
+/// or a slice, possibly behind a reference or a [`Box`].
+fn element_type(ty: &syn::Type) -> Option<&syn::Type> {
+    match ty {
+        syn::Type::Array(array) => Some(&array.elem),
+        syn::Type::Slice(slice) => Some(&slice.elem),
+        syn::Type::Reference(reference) => element_type(&reference.elem),
+        syn::Type::Paren(paren) => element_type(&paren.elem),
+        syn::Type::Group(group) => element_type(&group.elem),
+        syn::Type::Path(path) => {
+            let segment = path.path.segments.last()?;
+            let syn::PathArguments::AngleBracketed(args) = &segment.arguments else {
+                return None;
+            };
+            let Some(syn::GenericArgument::Type(arg)) = args.args.first() else {
+                return None;
+            };
+            match segment.ident.to_string().as_str() {
+                "Vec" | "VecDeque" => Some(arg),
+                "Box" => match arg {
+                    syn::Type::Slice(slice) => Some(&slice.elem),
+                    _ => None,
+                },
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Returns the element type of `field` checked by [`copy_type_assertions`],
+/// that is, the element type of a vector, an array, or a slice not mentioning
+/// `Self`.
+fn checked_element_type(field: &syn::Field) -> Option<&syn::Type> {
+    element_type(&field.ty).filter(|elem| !mentions_self(elem.to_token_stream()))
+}
+
+/// Returns whether `tokens` contain `Self`, which cannot be used outside of
+/// the implementations.
+fn mentions_self(tokens: proc_macro2::TokenStream) -> bool {
+    tokens.into_iter().any(|token| match token {
+        proc_macro2::TokenTree::Ident(ident) => ident == "Self",
+        proc_macro2::TokenTree::Group(group) => mentions_self(group.stream()),
+        _ => false,
+    })
+}
+
+/// Returns the code checking that the element types of the fields that are
+/// vectors, arrays, or slices implement `CopyType`, so that a missing
+/// implementation is reported with a clear message, rather than as a missing
+/// implementation of `MemSize` for the field.
+///
+/// Generic types are not checked, as element types might depend on type
+/// parameters, which are not bounded by `CopyType`, and neither are element
+/// types mentioning `Self`.
+fn copy_type_assertions(input: &DeriveInput) -> proc_macro2::TokenStream {
+    if !input.generics.params.is_empty() {
+        return quote! {};
+    }
+    let fields: Vec<&syn::Field> = match &input.data {
+        Data::Struct(s) => s.fields.iter().collect(),
+        Data::Enum(e) => e.variants.iter().flat_map(|v| v.fields.iter()).collect(),
+        Data::Union(u) => u.fields.named.iter().collect(),
+    };
+    let assertions = fields
+        .into_iter()
+        .filter_map(checked_element_type)
+        .map(|elem| {
+            quote_spanned! {elem.span()=>
+                const _: () = mem_dbg::_assert_copy_type::<#elem>();
+            }
+        });
+    quote! { #(#assertions)* }
+}
+
+/// Returns the part of `ty` that is a well-known type of the standard library
+/// that is not `Copy`, or a mutable reference, possibly within a tuple, an
+/// array, an `Option`, or a `Result`.
+fn non_copy_type(ty: &syn::Type) -> Option<&syn::Type> {
+    match ty {
+        syn::Type::Reference(reference) => reference.mutability.map(|_| ty),
+        syn::Type::Array(array) => non_copy_type(&array.elem),
+        syn::Type::Tuple(tuple) => tuple.elems.iter().find_map(non_copy_type),
+        syn::Type::Paren(paren) => non_copy_type(&paren.elem),
+        syn::Type::Group(group) => non_copy_type(&group.elem),
+        syn::Type::Path(path) if path.qself.is_none() => {
+            let segment = path.path.segments.last()?;
+            match segment.ident.to_string().as_str() {
+                "String" | "Vec" | "VecDeque" | "LinkedList" | "BinaryHeap" | "HashMap"
+                | "HashSet" | "BTreeMap" | "BTreeSet" | "Box" | "Rc" | "Arc" | "Cow"
+                | "PathBuf" | "OsString" | "CString" | "Cell" | "RefCell" | "Mutex" | "RwLock" => {
+                    Some(ty)
+                }
+                "Option" | "Result" => {
+                    let syn::PathArguments::AngleBracketed(args) = &segment.arguments else {
+                        return None;
+                    };
+                    args.args.iter().find_map(|arg| match arg {
+                        syn::GenericArgument::Type(arg) => non_copy_type(arg),
+                        _ => None,
+                    })
+                }
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Checks the fields of a type annotated with `#[copy_type]`, which must be
+/// `Copy`, returning an error pointing at the first field whose type is
+/// recognized as not `Copy` (see [`non_copy_type`]), and otherwise the code
+/// checking that all fields are `Copy`.
+///
+/// As in the case of [`copy_type_assertions`], the code is generated only
+/// for non-generic types, and it does not check fields mentioning `Self`.
+fn copy_type_field_assertions(input: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let fields: Vec<&syn::Field> = match &input.data {
+        Data::Struct(s) => s.fields.iter().collect(),
+        Data::Enum(e) => e.variants.iter().flat_map(|v| v.fields.iter()).collect(),
+        Data::Union(u) => u.fields.named.iter().collect(),
+    };
+    if let Some(ty) = fields.iter().find_map(|field| non_copy_type(&field.ty)) {
+        return Err(syn::Error::new_spanned(
+            ty,
+            "this type is not `Copy`, but `#[copy_type]` requires all fields to be `Copy`",
+        ));
+    }
+    if !input.generics.params.is_empty() {
+        return Ok(quote! {});
+    }
+    let assertions = fields
+        .into_iter()
+        .map(|field| &field.ty)
+        .filter(|ty| !mentions_self(ty.to_token_stream()))
+        .map(|ty| {
+            quote_spanned! {ty.span()=>
+                const _: () = mem_dbg::_assert_copy::<#ty>();
+            }
+        });
+    Ok(quote! { #(#assertions)* })
+}
+
 /**
 
 Generate a `mem_dbg::MemSize` implementation for custom types.
 
-Presently we do not support unions.
-
 The attribute `copy_type` can be used on [`Copy`] types that do not contain non-`'static` references
 to make `MemSize::mem_size` faster on arrays, vectors and slices. Note that specifying
-`copy_type` will add the bound that the type is `Copy + 'static`.
+`copy_type` will add the bound that the type is `Copy + 'static`. Fields whose
+type is recognized as not `Copy` (e.g., `String`, `Vec`, or `Cow`) are reported
+as errors, and for non-generic types an error is reported for every field that
+is not `Copy`.
 
 See `mem_dbg::CopyType` for more details.
 
+Single-field unions are supported out of the box. Since it is not possible to know
+which field of a union is initialized, unions with more than one field must be
+annotated with one of the following attributes:
+
+- `#[mem_size(union = "shallow")]`: the size is the stack size of the union;
+  this option requires all fields to be [`Copy`];
+- `#[mem_size(union = "with = path::to::function")]`: the size is computed by
+  the given function, which must have signature `fn(&Self, mem_dbg::SizeFlags) -> usize`
+  and return the whole size of the union; it is called both with and without
+  `SizeFlags::CAPACITY` to compute the used and the allocated heap memory.
+
+By default, a bound `FieldType: mem_dbg::MemSize` is generated for the type of
+each field. The attribute `#[mem_dbg(bound = "T: mem_dbg::MemSize, ...")]` on
+the type replaces all such bounds with the given predicates (as in the case of
+recursive types, for which the generated bounds cannot be satisfied), whereas
+the attribute `#[mem_dbg(no_bound)]` on a field omits only the bound for that
+field. Both attributes are shared with the `MemDbg` derive.
+
+The attribute `#[mem_dbg(follow)]` on a field makes the size of the field
+computed as if `SizeFlags::FOLLOW_REFS` and `SizeFlags::FOLLOW_RCS` were
+set, whereas `#[mem_dbg(no_follow)]` makes it computed as if they were not,
+whatever the flags passed to `mem_size`; this is useful, for example, for a
+`&'static` lookup table owned by the type. Both attributes are shared with the
+`MemDbg` derive, where they affect the corresponding `DbgFlags`.
+
+The attribute `#[mem_dbg(capacity)]` on a type makes its size computed as if
+`SizeFlags::CAPACITY` were set, which is useful for types, such as arenas, for
+which the meaningful size is the allocated memory: passing
+`SizeFlags::default()` or `SizeFlags::CAPACITY` to `mem_size` gives the same
+result, which includes the capacity of the fields, as the flag is passed on to
+them. For the same reason, `mem_stats` reports the heap capacity as used heap,
+so the slack of such a type is zero. In the `MemDbg` derive, the attribute
+sets `DbgFlags::CAPACITY` when displaying the fields.
+
+Since the fields of `#[repr(packed)]` structures might be unaligned, they
+cannot be borrowed: the size of each field is thus computed on a bitwise copy
+of the field, read with `core::ptr::read_unaligned` and never dropped, so
+fields need not be [`Copy`]. Fields whose validity depends on their address,
+such as self-referential types, are not supported. The same holds for the
+`MemDbg` derive.
+
 */
-#[proc_macro_derive(MemSize, attributes(copy_type))]
+#[proc_macro_derive(MemSize, attributes(copy_type, mem_size, mem_dbg))]
 pub fn mem_dbg_mem_size(input: TokenStream) -> TokenStream {
     let mut input = parse_macro_input!(input as DeriveInput);
 
-    let input_ident = input.ident;
-    input.generics.make_where_clause();
-    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
-    let mut where_clause = where_clause.unwrap().clone(); // We just created it
+    let container_attrs = match ContainerAttrs::parse(&input) {
+        Ok(container_attrs) => container_attrs,
+        Err(err) => return err.to_compile_error().into(),
+    };
 
     let is_copy_type = input
         .attrs
         .iter()
         .any(|x| x.meta.path().is_ident("copy_type"));
 
+    let mut copy_type_assertions = copy_type_assertions(&input);
+    if is_copy_type {
+        match copy_type_field_assertions(&input) {
+            Ok(assertions) => copy_type_assertions.extend(assertions),
+            Err(err) => return err.to_compile_error().into(),
+        }
+    }
+    let input_ident = input.ident;
+    input.generics.make_where_clause();
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let mut where_clause = where_clause.unwrap().clone(); // We just created it
+    container_attrs.push_bound(&mut where_clause);
+
     // If copy_type, add the Copy + 'static bound
     let copy_type: syn::Expr = if is_copy_type {
         where_clause
@@ -54,26 +648,32 @@ pub fn mem_dbg_mem_size(input: TokenStream) -> TokenStream {
         parse_quote!(mem_dbg::False)
     };
 
-    match input.data {
+    let code = match input.data {
         Data::Struct(s) => {
-            let mut fields_ident = vec![];
+            let mut fields_ref = vec![];
             let mut fields_ty = vec![];
+            let mut fields_flags = vec![];
 
             for (field_idx, field) in s.fields.iter().enumerate() {
-                fields_ident.push(
-                    field
-                        .ident
-                        .to_owned()
-                        .map(|t| t.to_token_stream())
-                        .unwrap_or(syn::Index::from(field_idx).to_token_stream()),
-                );
+                let field_ident = field
+                    .ident
+                    .to_owned()
+                    .map(|t| t.to_token_stream())
+                    .unwrap_or(syn::Index::from(field_idx).to_token_stream());
+                fields_ref.push(container_attrs.field_ref(&field_ident));
                 fields_ty.push(field.ty.to_token_stream());
-                let field_ty = &field.ty;
+                fields_flags.push(FieldAttrs::size_flags(field));
                 // Add MemSize bound to all fields
-                where_clause
-                    .predicates
-                    .push(parse_quote_spanned!(field.span()=> #field_ty: mem_dbg::MemSize));
+                container_attrs.push_field_bound(
+                    &mut where_clause,
+                    field,
+                    container_attrs.mem_size_bound(field),
+                );
             }
+            let mem_size_code = container_attrs.mem_size_rec_code(quote! {
+                #(mem_dbg::_field_rec::<#fields_ty>(#fields_ref, #fields_flags, _memsize_ctx)?;)*
+                core::ops::ControlFlow::Continue(())
+            });
             quote! {
                 #[automatically_derived]
                 impl #impl_generics mem_dbg::CopyType for #input_ident #ty_generics #where_clause
@@ -83,10 +683,8 @@ pub fn mem_dbg_mem_size(input: TokenStream) -> TokenStream {
 
                 #[automatically_derived]
                 impl #impl_generics mem_dbg::MemSize for #input_ident #ty_generics #where_clause {
-                    fn mem_size(&self, _memsize_flags: mem_dbg::SizeFlags) -> usize {
-                        let mut bytes = core::mem::size_of::<Self>();
-                        #(bytes += <#fields_ty as mem_dbg::MemSize>::mem_size(&self.#fields_ident, _memsize_flags) - core::mem::size_of::<#fields_ty>();)*
-                        bytes
+                    fn _mem_size_rec(&self, _memsize_ctx: &mut mem_dbg::SizeContext) -> core::ops::ControlFlow<()> {
+                        #mem_size_code
                     }
                 }
             }
@@ -94,28 +692,30 @@ pub fn mem_dbg_mem_size(input: TokenStream) -> TokenStream {
 
         Data::Enum(e) => {
             let mut variants = Vec::new();
-            let mut variants_size = Vec::new();
+            let mut variants_code = Vec::new();
 
             for variant in e.variants {
                 let mut res = variant.ident.to_owned().to_token_stream();
-                let mut var_args_size = quote! {core::mem::size_of::<Self>()};
+                let mut var_args_code = quote! {};
                 match &variant.fields {
                     syn::Fields::Unit => {}
                     syn::Fields::Named(fields) => {
                         let mut args = proc_macro2::TokenStream::new();
-                        for field in &fields.named {
+                        for (field_idx, field) in fields.named.iter().enumerate() {
                             let field_ty = &field.ty;
-                            where_clause
-                                .predicates
-                                .push(parse_quote_spanned!(field.span() => #field_ty: mem_dbg::MemSize));
-                                let field_ident = &field.ident;
-                                let field_ty = field.ty.to_token_stream();
-                                var_args_size.extend([quote! {
-                                    + <#field_ty as mem_dbg::MemSize>::mem_size(#field_ident, _memsize_flags) - core::mem::size_of::<#field_ty>()
-                                }]);
-                                args.extend([field_ident.to_token_stream()]);
-                                args.extend([quote! {,}]);
-                            }
+                            container_attrs.push_field_bound(
+                                &mut where_clause,
+                                field,
+                                container_attrs.mem_size_bound(field),
+                            );
+                            let field_ident = &field.ident;
+                            let binding = field_binding(field_idx);
+                            let flags = FieldAttrs::size_flags(field);
+                            var_args_code.extend([quote! {
+                                mem_dbg::_field_rec::<#field_ty>(#binding, #flags, _memsize_ctx)?;
+                            }]);
+                            args.extend([quote! { #field_ident: #binding, }]);
+                        }
                         // extend res with the args sourrounded by curly braces
                         res.extend(quote! {
                             { #args }
@@ -125,21 +725,20 @@ pub fn mem_dbg_mem_size(input: TokenStream) -> TokenStream {
                         let mut args = proc_macro2::TokenStream::new();
 
                         for (field_idx, field) in fields.unnamed.iter().enumerate() {
-                            let ident = syn::Ident::new(
-                                &format!("v{}", field_idx),
-                                proc_macro2::Span::call_site(),
-                            )
-                            .to_token_stream();
+                            let ident = field_binding(field_idx).to_token_stream();
                             let field_ty = field.ty.to_token_stream();
-                            var_args_size.extend([quote! {
-                                + <#field_ty as mem_dbg::MemSize>::mem_size(#ident, _memsize_flags) - core::mem::size_of::<#field_ty>()
+                            let flags = FieldAttrs::size_flags(field);
+                            var_args_code.extend([quote! {
+                                mem_dbg::_field_rec::<#field_ty>(#ident, #flags, _memsize_ctx)?;
                             }]);
                             args.extend([ident]);
                             args.extend([quote! {,}]);
 
-                            where_clause
-                                .predicates
-                                .push(parse_quote_spanned!(field.span()=> #field_ty: mem_dbg::MemSize));
+                            container_attrs.push_field_bound(
+                                &mut where_clause,
+                                field,
+                                container_attrs.mem_size_bound(field),
+                            );
                         }
                         // extend res with the args sourrounded by curly braces
                         res.extend(quote! {
@@ -148,9 +747,24 @@ pub fn mem_dbg_mem_size(input: TokenStream) -> TokenStream {
                     }
                 }
                 variants.push(res);
-                variants_size.push(var_args_size);
+                variants_code.push(var_args_code);
             }
 
+            // Empty enums cannot be instantiated, but matching on a
+            // reference to them requires dereferencing it
+            let mem_size_code = if variants.is_empty() {
+                quote! { match *self {} }
+            } else {
+                container_attrs.mem_size_rec_code(quote! {
+                    match self {
+                        #(
+                           #input_ident::#variants => { #variants_code }
+                        )*
+                    }
+                    core::ops::ControlFlow::Continue(())
+                })
+            };
+
             quote! {
                 #[automatically_derived]
                 impl #impl_generics mem_dbg::CopyType for #input_ident #ty_generics #where_clause
@@ -160,75 +774,137 @@ pub fn mem_dbg_mem_size(input: TokenStream) -> TokenStream {
 
                 #[automatically_derived]
                 impl #impl_generics mem_dbg::MemSize for #input_ident #ty_generics #where_clause {
-                    fn mem_size(&self, _memsize_flags: mem_dbg::SizeFlags) -> usize {
-                        match self {
-                            #(
-                               #input_ident::#variants => #variants_size,
-                            )*
-                        }
+                    fn _mem_size_rec(&self, _memsize_ctx: &mut mem_dbg::SizeContext) -> core::ops::ControlFlow<()> {
+                        #mem_size_code
                     }
                 }
             }
         }
 
         Data::Union(u) => {
-            // We only support single-field unions completely or call to mem_size on unions
-            // without the FOLLOW_REFS flag, as we cannot know programmatically
-            // which field is initialized.
+            // We cannot know programmatically which field is initialized, so
+            // unions with more than one field need the user to tell us how to
+            // compute their size.
+            let union_mode = match parse_union_mode(&input.attrs) {
+                Ok(union_mode) => union_mode,
+                Err(err) => return err.to_compile_error().into(),
+            };
 
             let fields = u.fields.named.iter().collect::<Vec<_>>();
 
-            match fields.len() {
-                0 => unreachable!("Empty unions are not supported by the Rust programming language."),
-                1 => {
+            let mem_size_code = match (union_mode, fields.len()) {
+                (Some(UnionMode::Shallow), _) => {
+                    // The size is exact only if no field owns other memory
+                    for field in &fields {
+                        let field_ty = &field.ty;
+                        where_clause
+                            .predicates
+                            .push(parse_quote_spanned!(field.span() => #field_ty: Copy));
+                    }
+                    quote! {
+                        core::ops::ControlFlow::Continue(())
+                    }
+                }
+                // The function returns the whole size, so we call it without
+                // and with CAPACITY to report the used and allocated heap
+                (Some(UnionMode::With(path)), _) => quote! {
+                    let _memsize_stack = core::mem::size_of::<Self>();
+                    _memsize_ctx.add_heap(
+                        #path(self, _memsize_flags.difference(mem_dbg::SizeFlags::CAPACITY)).saturating_sub(_memsize_stack),
+                        #path(self, _memsize_flags | mem_dbg::SizeFlags::CAPACITY).saturating_sub(_memsize_stack),
+                    )
+                },
+                (None, 0) => unreachable!("Empty unions are not supported by the Rust programming language."),
+                (None, 1) => {
                     let field = fields[0];
                     let field_ty = &field.ty;
                     let ident = field.ident.as_ref().unwrap();
-                    where_clause
-                        .predicates
-                        .push(parse_quote_spanned!(field.span() => #field_ty: mem_dbg::MemSize));
+                    container_attrs.push_field_bound(&mut where_clause, field, container_attrs.mem_size_bound(field));
+                    let flags = FieldAttrs::size_flags(field);
                     quote! {
-                        #[automatically_derived]
-                        impl #impl_generics mem_dbg::CopyType for #input_ident #ty_generics #where_clause
-                        {
-                            type Copy = #copy_type;
-                        }
-
-                        #[automatically_derived]
-                        impl #impl_generics mem_dbg::MemSize for #input_ident #ty_generics #where_clause {
-                            fn mem_size(&self, _memsize_flags: mem_dbg::SizeFlags) -> usize {
-                                unsafe{<#field_ty as mem_dbg::MemSize>::mem_size(&self.#ident, _memsize_flags)}
-                            }
-                        }
+                        mem_dbg::_field_rec::<#field_ty>(unsafe { &self.#ident }, #flags, _memsize_ctx)
                     }
                 }
-                number_of_fields => unimplemented!(
-                    "mem_dbg::MemSize for unions with more than one field ({}) is not supported.", number_of_fields
+                (None, number_of_fields) => return syn::Error::new(
+                    input_ident.span(),
+                    format!(
+                        "mem_dbg::MemSize cannot be derived for unions with more than one field ({}) without specifying how to compute their size: use #[mem_size(union = \"shallow\")] or #[mem_size(union = \"with = path::to::function\")]",
+                        number_of_fields
+                    ),
                 )
+                .to_compile_error()
+                .into(),
+            };
+            let mem_size_code = container_attrs.mem_size_rec_code(mem_size_code);
+
+            quote! {
+                #[automatically_derived]
+                impl #impl_generics mem_dbg::CopyType for #input_ident #ty_generics #where_clause
+                {
+                    type Copy = #copy_type;
+                }
+
+                #[automatically_derived]
+                impl #impl_generics mem_dbg::MemSize for #input_ident #ty_generics #where_clause {
+                    fn _mem_size_rec(&self, _memsize_ctx: &mut mem_dbg::SizeContext) -> core::ops::ControlFlow<()> {
+                        #mem_size_code
+                    }
+                }
             }
         }
-    }.into()
+    };
+    quote! {
+        #code
+        #copy_type_assertions
+    }
+    .into()
 }
 
 /**
 
 Generate a `mem_dbg::MemDbg` implementation for custom types.
 
-Presently we do not support unions.
+Unions are displayed with a label `union`. Unions annotated with
+`#[mem_size(union = "...")]` (see the `MemSize` derive) are displayed without
+their fields, as it is not possible to know which field is initialized.
+
+The generated bounds `FieldType: mem_dbg::MemDbgImpl` can be replaced or
+omitted using the attributes `#[mem_dbg(bound = "...")]` and
+`#[mem_dbg(no_bound)]` (see the `MemSize` derive).
+
+The attribute `#[mem_dbg(opaque)]` on a field displays the field as a single
+line, including its whole size, without recursing into it. In this case, the
+type of the field needs only implement `mem_dbg::MemSize`.
+
+The attributes `#[mem_dbg(follow)]` and `#[mem_dbg(no_follow)]` on a field
+(see the `MemSize` derive) set or clear `DbgFlags::FOLLOW_REFS` and
+`DbgFlags::FOLLOW_RCS` when displaying the field.
+
+The attribute `#[mem_dbg(capacity)]` on a type (see the `MemSize` derive) sets
+`DbgFlags::CAPACITY` when displaying its fields.
 
 */
-#[proc_macro_derive(MemDbg)]
+#[proc_macro_derive(MemDbg, attributes(mem_size, mem_dbg))]
 pub fn mem_dbg_mem_dbg(input: TokenStream) -> TokenStream {
     let mut input = parse_macro_input!(input as DeriveInput);
 
+    let container_attrs = match ContainerAttrs::parse(&input) {
+        Ok(container_attrs) => container_attrs,
+        Err(err) => return err.to_compile_error().into(),
+    };
+
     let input_ident = input.ident;
     input.generics.make_where_clause();
     let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
     let mut where_clause = where_clause.unwrap().clone(); // We just created it
+    container_attrs.push_bound(&mut where_clause);
+    let capacity_code = container_attrs.capacity_code(quote!(_memdbg_flags), quote!(DbgFlags));
 
     match input.data {
         Data::Struct(s) => {
-            let mut id_offset_pushes = vec![];
+            let mut offsets = vec![];
+            let mut type_names = vec![];
+            let mut sizes = vec![];
             let mut match_code = vec![];
 
             for (field_idx, field) in s.fields.iter().enumerate() {
@@ -246,57 +922,46 @@ pub fn mem_dbg_mem_dbg(input: TokenStream) -> TokenStream {
                     .map(|t| t.to_string().to_token_stream())
                     .unwrap_or_else(|| field_idx.to_string().to_token_stream());
 
-                let field_ty = &field.ty;
-                where_clause
-                    .predicates
-                    .push(parse_quote_spanned!(field.span() => #field_ty: mem_dbg::MemDbgImpl));
+                container_attrs.push_field_bound(&mut where_clause, field, mem_dbg_field_bound(field));
 
-                // We push the field index and its offset
-                id_offset_pushes.push(quote!{
-                    id_sizes.push((#field_idx, core::mem::offset_of!(#input_ident #ty_generics, #field_ident)));
-                });
+                // We collect the offset of the field
+                let offset = quote!{
+                    core::mem::offset_of!(#input_ident #ty_generics, #field_ident)
+                };
+                offsets.push(offset.clone());
                 // This is the arm of the match statement that invokes
-                // _mem_dbg_depth_on on the field.
+                // _mem_dbg_node on the field.
+                let field_ref = container_attrs.field_ref(&field_ident);
+                let (type_name, size) = mem_dbg_field_type_name_and_size(field, &field_ref);
+                type_names.push(type_name);
+                sizes.push(size);
+                let field_code = mem_dbg_field_code(field, field_ref, field_ident_str, offset);
                 match_code.push(quote!{
-                    #field_idx => <#field_ty as mem_dbg::MemDbgImpl>::_mem_dbg_depth_on(&self.#field_ident, _memdbg_writer, _memdbg_total_size, _memdbg_max_depth, _memdbg_prefix, Some(#field_ident_str), i == n - 1, padded_size, _memdbg_flags)?,
+                    #field_idx => #field_code,
                 });
             }
 
+            let fields_code = mem_dbg_fields_code(&type_names, &sizes, &match_code);
+
             quote! {
                 #[automatically_derived]
                 impl #impl_generics mem_dbg::MemDbgImpl for #input_ident #ty_generics #where_clause {
                     #[inline(always)]
-                    fn _mem_dbg_rec_on(
+                    fn _mem_dbg_rec(
                         &self,
-                        _memdbg_writer: &mut impl core::fmt::Write,
-                        _memdbg_total_size: usize,
+                        _memdbg_node: &mut mem_dbg::MemNode,
                         _memdbg_max_depth: usize,
-                        _memdbg_prefix: &mut String,
-                        _memdbg_is_last: bool,
                         _memdbg_flags: mem_dbg::DbgFlags,
-                    ) -> core::fmt::Result {
-                        let mut id_sizes: Vec<(usize, usize)> = vec![];
-                        #(#id_offset_pushes)*
-                        let n = id_sizes.len();
-                        id_sizes.push((n, core::mem::size_of::<Self>()));
-                        // Sort by offset
-                        id_sizes.sort_by_key(|x| x.1);
-                        // Compute padded sizes
-                        for i in 0..n {
-                            id_sizes[i].1 = id_sizes[i + 1].1 - id_sizes[i].1;
+                    ) {
+                        #capacity_code
+                        // Padded sizes are computed at compile time, in
+                        // declaration order unless the user requested otherwise
+                        let _memdbg_id_sizes: &[(usize, usize)] = if _memdbg_flags.contains(mem_dbg::DbgFlags::RUST_LAYOUT) {
+                            const { &mem_dbg::_padded_sizes([#(#offsets),*], core::mem::size_of::<Self>(), true) }
+                        } else {
+                            const { &mem_dbg::_padded_sizes([#(#offsets),*], core::mem::size_of::<Self>(), false) }
                         };
-                        // Put the candle back unless the user requested otherwise
-                        if ! _memdbg_flags.contains(mem_dbg::DbgFlags::RUST_LAYOUT) {
-                            id_sizes.sort_by_key(|x| x.0);
-                        }
-
-                        for (i, (field_idx, padded_size)) in id_sizes.into_iter().enumerate().take(n) {
-                            match field_idx {
-                                #(#match_code)*
-                                _ => unreachable!(),
-                            }
-                        }
-                        Ok(())
+                        #fields_code
                     }
                 }
             }
@@ -309,94 +974,60 @@ pub fn mem_dbg_mem_dbg(input: TokenStream) -> TokenStream {
             for variant in &e.variants {
                 let variant_ident = &variant.ident;
                 let mut res = variant.ident.to_owned().to_token_stream();
-                // Depending on the presence of the feature offset_of_enum, this
-                // will contains field indices and offset_of or field indices
-                // and size_of; in the latter case, we will assume size_of to be
-                // the padded size, resulting in no padding.
-                let mut id_offset_pushes = vec![];
+                // The code computing the offsets of the fields
+                let mut offsets = vec![];
+                let mut type_names = vec![];
+                let mut sizes = vec![];
                 let mut match_code = vec![];
-                let mut arrow = '╰';
                 match &variant.fields {
                     syn::Fields::Unit => {},
                     syn::Fields::Named(fields) => {
                         let mut args = proc_macro2::TokenStream::new();
-                        if !fields.named.is_empty() {
-                            arrow = '├';
-                        }
                         for (field_idx, field) in fields.named.iter().enumerate() {
-                            let field_ty = &field.ty;
                             let field_ident = field.ident.as_ref().unwrap();
                             let field_ident_str = format!("{}", field_ident);
-                            id_offset_pushes.push(quote!{
-                                // We push the offset of the field, which will
-                                // be used to compute the padded size.
-                                #[cfg(feature = "offset_of_enum")]
-                                id_sizes.push((#field_idx, core::mem::offset_of!(#input_ident #ty_generics, #variant_ident . #field_ident)));
-                                // We push the size of the field, which will be
-                                // used as a surrogate of the padded size.
-                                #[cfg(not(feature = "offset_of_enum"))]
-                                id_sizes.push((#field_idx, std::mem::size_of_val(#field_ident)));
-                            });
+                            let binding = field_binding(field_idx);
+                            offsets.push(offset_code(field_idx, &binding, quote!(#input_ident #ty_generics, #variant_ident . #field_ident)));
 
                             // This is the arm of the match statement that
-                            // invokes _mem_dbg_depth_on on the field.
+                            // invokes _mem_dbg_node on the field.
+                            let (type_name, size) = mem_dbg_field_type_name_and_size(field, &binding.to_token_stream());
+                            type_names.push(type_name);
+                            sizes.push(size);
+                            let field_code = mem_dbg_field_code(field, binding.to_token_stream(), field_ident_str.to_token_stream(), offset_ident(field_idx).to_token_stream());
                             match_code.push(quote! {
-                                #field_idx => <#field_ty as mem_dbg::MemDbgImpl>::_mem_dbg_depth_on(#field_ident, _memdbg_writer, _memdbg_total_size, _memdbg_max_depth, _memdbg_prefix, Some(#field_ident_str), i == n - 1, padded_size, _memdbg_flags)?,
+                                #field_idx => #field_code,
                             });
-                            args.extend([field_ident.to_token_stream()]);
-                            args.extend([quote! {,}]);
-
-                            let field_ty = &field.ty;
-                            where_clause
-                                .predicates
-                                .push(parse_quote_spanned!(field.span()=> #field_ty: mem_dbg::MemDbgImpl));
+                            args.extend([quote! { #field_ident: #binding, }]);
+                            container_attrs.push_field_bound(&mut where_clause, field, mem_dbg_field_bound(field));
                         }
                         // extend res with the args sourrounded by curly braces
                         res.extend(quote! {
-                            // TODO: sanitize somehow the names or it'll be
-                            // havoc.
                             { #args }
                         });
                     }
                     syn::Fields::Unnamed(fields) => {
                         let mut args = proc_macro2::TokenStream::new();
-                        if !fields.unnamed.is_empty() {
-                            arrow = '├';
-                        }
                         for (field_idx, field) in fields.unnamed.iter().enumerate() {
-                            let field_ident = syn::Ident::new(
-                                &format!("v{}", field_idx),
-                                proc_macro2::Span::call_site(),
-                            )
-                            .to_token_stream();
-                            let field_ty = &field.ty;
+                            let field_ident = field_binding(field_idx).to_token_stream();
                             let field_ident_str = format!("{}", field_idx);
                             let field_tuple_idx = syn::Index::from(field_idx);
 
-                            id_offset_pushes.push(quote!{
-                                // We push the offset of the field, which will
-                                // be used to compute the padded size.
-                                #[cfg(feature = "offset_of_enum")]
-                                id_sizes.push((#field_idx, core::mem::offset_of!(#input_ident #ty_generics, #variant_ident . #field_tuple_idx)));
-                                // We push the size of the field, which will be
-                                // used as a surrogate of the padded size.
-                                #[cfg(not(feature = "offset_of_enum"))]
-                                id_sizes.push((#field_idx, std::mem::size_of_val(#field_ident)));
-                            });
+                            offsets.push(offset_code(field_idx, &field_binding(field_idx), quote!(#input_ident #ty_generics, #variant_ident . #field_tuple_idx)));
 
                             // This is the arm of the match statement that
-                            // invokes _mem_dbg_depth_on on the field.
+                            // invokes _mem_dbg_node on the field.
+                            let (type_name, size) = mem_dbg_field_type_name_and_size(field, &field_ident);
+                            type_names.push(type_name);
+                            sizes.push(size);
+                            let field_code = mem_dbg_field_code(field, field_ident.to_token_stream(), field_ident_str.to_token_stream(), offset_ident(field_idx).to_token_stream());
                             match_code.push(quote! {
-                                #field_idx => <#field_ty as mem_dbg::MemDbgImpl>::_mem_dbg_depth_on(#field_ident, _memdbg_writer, _memdbg_total_size, _memdbg_max_depth, _memdbg_prefix, Some(#field_ident_str), i == n - 1, padded_size, _memdbg_flags)?,
+                                #field_idx => #field_code,
                             });
 
                             args.extend([field_ident]);
                             args.extend([quote! {,}]);
-
-                            let field_ty = &field.ty;
-                            where_clause
-                                .predicates
-                                .push(parse_quote_spanned!(field.span()=> #field_ty: mem_dbg::MemDbgImpl));
+                            container_attrs.push_field_bound(&mut where_clause, field, mem_dbg_field_bound(field));
                         }
                         // extend res with the args sourrounded by curly braces
                         res.extend(quote! {
@@ -405,125 +1036,112 @@ pub fn mem_dbg_mem_dbg(input: TokenStream) -> TokenStream {
                     }
                 }
                 variants.push(res);
-                let variant_name = format!("Variant: {}\n", variant.ident);
+                let n = offsets.len();
+                let offset_idents = (0..n).map(offset_ident);
+                let variant_name = format!("Variant: {}", variant.ident);
+                let fields_code = mem_dbg_fields_code(&type_names, &sizes, &match_code);
                 variants_code.push(quote!{{
-                    _memdbg_writer.write_char(#arrow)?;
-                    _memdbg_writer.write_char('╴')?;
-                    _memdbg_writer.write_str(#variant_name)?;
-
-                    let mut id_sizes: Vec<(usize, usize)> = vec![];
-                    #(#id_offset_pushes)*
-                    let n = id_sizes.len();
-                    #[cfg(feature = "offset_of_enum")]
-                    {
-                        // We use the offset_of information to build the real
-                        // space occupied by a field.
-                        id_sizes.push((n, core::mem::size_of::<Self>()));
-                        // Sort by offset
-                        id_sizes.sort_by_key(|x| x.1);
-                        // Compute padded sizes
-                        for i in 0..n {
-                            id_sizes[i].1 = id_sizes[i + 1].1 - id_sizes[i].1;
-                        };
-                        // Put the candle back unless the user requested otherwise
-                        if ! _memdbg_flags.contains(mem_dbg::DbgFlags::RUST_LAYOUT) {
-                            id_sizes.sort_by_key(|x| x.0);
-                        }
-                    }
-                    #[cfg(not(feature = "offset_of_enum"))]
-                    {
-                        // Lacking offset_of for enums, id_sizes contains the
-                        // size_of of each field which we use as a surrogate of
-                        // the padded size.
-                        assert!(!_memdbg_flags.contains(mem_dbg::DbgFlags::RUST_LAYOUT), "DbgFlags::RUST_LAYOUT for enums requires the offset_of_enum feature");
-                    }
-                    for (i, (field_idx, padded_size)) in id_sizes.into_iter().enumerate().take(n) {
-                        match field_idx {
-                            #(#match_code)*
-                            _ => unreachable!(),
-                        }
-                    }
+                    _memdbg_node.children.push(mem_dbg::MemNode::label(#variant_name));
+
+                    #(#offsets)*
+                    let _memdbg_id_sizes = mem_dbg::_padded_sizes(
+                        [#(#offset_idents),*],
+                        core::mem::size_of::<Self>(),
+                        _memdbg_flags.contains(mem_dbg::DbgFlags::RUST_LAYOUT),
+                    );
+                    #fields_code
 
                 }});
             }
 
+            // See the MemSize derive
+            let mem_dbg_code = if variants.is_empty() {
+                quote! { match *self {} }
+            } else {
+                quote! {
+                    match self {
+                        #(
+                           #input_ident::#variants => #variants_code,
+                        )*
+                    }
+                }
+            };
+
             quote! {
                 #[automatically_derived]
                 impl #impl_generics mem_dbg::MemDbgImpl  for #input_ident #ty_generics #where_clause {
                     #[inline(always)]
-                    fn _mem_dbg_rec_on(
+                    fn _mem_dbg_rec(
                         &self,
-                        _memdbg_writer: &mut impl core::fmt::Write,
-                        _memdbg_total_size: usize,
+                        _memdbg_node: &mut mem_dbg::MemNode,
                         _memdbg_max_depth: usize,
-                        _memdbg_prefix: &mut String,
-                        _memdbg_is_last: bool,
                         _memdbg_flags: mem_dbg::DbgFlags,
-                    ) -> core::fmt::Result {
-                        let mut _memdbg_digits_number = mem_dbg::n_of_digits(_memdbg_total_size);
-                        if _memdbg_flags.contains(mem_dbg::DbgFlags::SEPARATOR) {
-                            _memdbg_digits_number += _memdbg_digits_number / 3;
-                        }
-                        if _memdbg_flags.contains(mem_dbg::DbgFlags::HUMANIZE) {
-                            _memdbg_digits_number = 6;
-                        }
-
-                        if _memdbg_flags.contains(mem_dbg::DbgFlags::PERCENTAGE) {
-                            _memdbg_digits_number += 8;
-                        }
-
-                        for _ in 0.._memdbg_digits_number + 3 {
-                            _memdbg_writer.write_char(' ')?;
-                        }
-                        if !_memdbg_prefix.is_empty() {
-                            _memdbg_writer.write_str(&_memdbg_prefix[2..])?;
-                        }
-                        match self {
-                            #(
-                               #input_ident::#variants => #variants_code,
-                            )*
-                        }
-                        Ok(())
+                    ) {
+                        #capacity_code
+                        #mem_dbg_code
                    }
                 }
             }
         }
 
         Data::Union(u) => {
-            // We only support single-field unions for the MemDbg.
+            // Unions are labeled as such, like enum variants. Unions whose
+            // size is computed as specified by the user are displayed
+            // without children, as we cannot know which field is
+            // initialized; single-field unions are fully supported.
+            let union_mode = match parse_union_mode(&input.attrs) {
+                Ok(union_mode) => union_mode,
+                Err(err) => return err.to_compile_error().into(),
+            };
 
             let fields = u.fields.named.iter().collect::<Vec<_>>();
 
-            match fields.len() {
-                0 => unreachable!("Empty unions are not supported by the Rust programming language."),
-                1 => {
+            let rec_code = match (union_mode, fields.len()) {
+                (Some(_), _) => {
+                    where_clause
+                        .predicates
+                        .push(parse_quote_spanned!(input_ident.span() => Self: mem_dbg::MemSize));
+                    quote! {}
+                }
+                (None, 0) => unreachable!("Empty unions are not supported by the Rust programming language."),
+                (None, 1) => {
                     let field = fields[0];
                     let field_ty = &field.ty;
                     let ident = field.ident.as_ref().unwrap();
-                    where_clause
-                        .predicates
-                        .push(parse_quote_spanned!(field.span() => #field_ty: mem_dbg::MemDbgImpl));
+                    container_attrs.push_field_bound(&mut where_clause, field, mem_dbg_field_bound(field));
+                    let flags = FieldAttrs::field_flags(field, quote!(_memdbg_flags), quote!(DbgFlags));
+                    // The padded size of the field includes the tail padding
+                    // of the union
                     quote! {
-                        #[automatically_derived]
-                        impl #impl_generics mem_dbg::MemDbgImpl for #input_ident #ty_generics #where_clause {
-                            #[inline(always)]
-                            fn _mem_dbg_rec_on(
-                                &self,
-                                _memdbg_writer: &mut impl core::fmt::Write,
-                                _memdbg_total_size: usize,
-                                _memdbg_max_depth: usize,
-                                _memdbg_prefix: &mut String,
-                                _memdbg_is_last: bool,
-                                _memdbg_flags: mem_dbg::DbgFlags,
-                            ) -> core::fmt::Result {
-                                unsafe{<#field_ty as mem_dbg::MemDbgImpl>::_mem_dbg_depth_on(&self.#ident, _memdbg_writer, _memdbg_total_size, _memdbg_max_depth, _memdbg_prefix, None, _memdbg_is_last, core::mem::size_of::<#field_ty>(), _memdbg_flags)}
-                            }
-                        }
+                        _memdbg_node.children.push(unsafe{<#field_ty as mem_dbg::MemDbgImpl>::_mem_dbg_node(&self.#ident, "", core::mem::size_of::<Self>(), _memdbg_max_depth - 1, #flags)})
                     }
                 }
-                _ => unimplemented!(
-                    "mem_dbg::MemDbg for unions with more than one field is not supported."
+                (None, number_of_fields) => return syn::Error::new(
+                    input_ident.span(),
+                    format!(
+                        "mem_dbg::MemDbg cannot be derived for unions with more than one field ({}) without specifying how to compute their size: use #[mem_size(union = \"shallow\")] or #[mem_size(union = \"with = path::to::function\")]",
+                        number_of_fields
+                    ),
                 )
+                .to_compile_error()
+                .into(),
+            };
+
+            quote! {
+                #[automatically_derived]
+                impl #impl_generics mem_dbg::MemDbgImpl for #input_ident #ty_generics #where_clause {
+                    #[inline(always)]
+                    fn _mem_dbg_rec(
+                        &self,
+                        _memdbg_node: &mut mem_dbg::MemNode,
+                        _memdbg_max_depth: usize,
+                        _memdbg_flags: mem_dbg::DbgFlags,
+                    ) {
+                        #capacity_code
+                        _memdbg_node.children.push(mem_dbg::MemNode::label("union"));
+                        #rec_code
+                    }
+                }
             }
         }
     }.into()
@@ -0,0 +1,45 @@
+/*
+ * SPDX-FileCopyrightText: 2023 Tommaso Fontana
+ * SPDX-FileCopyrightText: 2023 Inria
+ * SPDX-FileCopyrightText: 2023 Sebastiano Vigna
+ *
+ * SPDX-License-Identifier: Apache-2.0 OR LGPL-2.1-or-later
+ */
+
+//! Benchmark comparing [`MemSize::mem_size`] and [`ParMemSize::par_mem_size`]
+//! on large collections of non-[`Copy`] elements.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use mem_dbg::*;
+use std::collections::HashMap;
+
+const N: usize = 1_000_000;
+
+fn bench_vec(c: &mut Criterion) {
+    let v = (0..N).map(|i| vec![0_u8; i % 16]).collect::<Vec<_>>();
+    let mut group = c.benchmark_group("Vec<Vec<u8>>");
+    group.bench_function("mem_size", |b| {
+        b.iter(|| black_box(&v).mem_size(SizeFlags::default()))
+    });
+    group.bench_function("par_mem_size", |b| {
+        b.iter(|| black_box(&v).par_mem_size(SizeFlags::default()))
+    });
+    group.finish();
+}
+
+fn bench_hash_map(c: &mut Criterion) {
+    let m = (0..N)
+        .map(|i| (i.to_string(), vec![0_u64; i % 16]))
+        .collect::<HashMap<_, _>>();
+    let mut group = c.benchmark_group("HashMap<String, Vec<u64>>");
+    group.bench_function("mem_size", |b| {
+        b.iter(|| black_box(&m).mem_size(SizeFlags::default()))
+    });
+    group.bench_function("par_mem_size", |b| {
+        b.iter(|| black_box(&m).par_mem_size(SizeFlags::default()))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_vec, bench_hash_map);
+criterion_main!(benches);
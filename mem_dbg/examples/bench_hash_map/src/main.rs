@@ -34,4 +34,13 @@ fn main() {
     let start = Instant::now();
     let size = m.mem_size(SizeFlags::default());
     println!("mem_size:     {} {:?} ns", size, start.elapsed().as_nanos());
+
+    let start = Instant::now();
+    let stats = m.mem_stats(SizeFlags::default());
+    println!(
+        "mem_stats:    {} {:?} ns (slack: {:.2}%)",
+        stats.stack + stats.heap_capacity,
+        start.elapsed().as_nanos(),
+        100.0 * stats.slack() as f64 / stats.heap_capacity as f64
+    );
 }
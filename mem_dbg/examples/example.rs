@@ -108,13 +108,11 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!();
     s.mem_dbg(DbgFlags::empty())?;
 
-    #[cfg(feature = "offset_of_enum")]
-    {
-        println!();
-        println!("DbgFlags::HUMANIZE | DbgFlags::RUST_LAYOUT:");
-        println!();
-        s.mem_dbg(DbgFlags::HUMANIZE | DbgFlags::RUST_LAYOUT)?;
-    }
+    println!();
+    println!("DbgFlags::HUMANIZE | DbgFlags::RUST_LAYOUT:");
+    println!();
+    s.mem_dbg(DbgFlags::HUMANIZE | DbgFlags::RUST_LAYOUT)?;
+
     let s = Struct {
         a: 0_u8,
         b: 0_u8,
@@ -8,8 +8,6 @@
 #![cfg_attr(feature = "offset_of_enum", feature(offset_of_enum, offset_of_nested))]
 #![allow(dead_code)]
 
-use mem_dbg::*;
-
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     use mem_dbg::*;
 
@@ -62,15 +60,13 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     s.mem_dbg(DbgFlags::default() | DbgFlags::CAPACITY | DbgFlags::HUMANIZE)?;
 
-    #[cfg(feature = "offset_of_enum")]
-    {
-        println!();
+    println!();
 
-        println!("size:     {}", s.mem_size(SizeFlags::default()));
-        println!("capacity: {}", s.mem_size(SizeFlags::CAPACITY));
-        println!();
+    println!("size:     {}", s.mem_size(SizeFlags::default()));
+    println!("capacity: {}", s.mem_size(SizeFlags::CAPACITY));
+    println!();
+
+    s.mem_dbg(DbgFlags::empty() | DbgFlags::RUST_LAYOUT)?;
 
-        s.mem_dbg(DbgFlags::empty() | DbgFlags::RUST_LAYOUT)?;
-    }
     Ok(())
 }
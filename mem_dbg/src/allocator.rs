@@ -0,0 +1,133 @@
+/*
+ * SPDX-FileCopyrightText: 2023 Tommaso Fontana
+ * SPDX-FileCopyrightText: 2023 Inria
+ * SPDX-FileCopyrightText: 2023 Sebastiano Vigna
+ *
+ * SPDX-License-Identifier: Apache-2.0 OR LGPL-2.1-or-later
+ */
+
+//! Support for [`SizeFlags::ALLOCATOR`](crate::SizeFlags), which queries the
+//! system allocator for the size of the blocks it handed out.
+//!
+//! Querying the system allocator about a block it did not allocate is
+//! undefined behavior, so queries are performed only after the caller has
+//! guaranteed, by calling the unsafe function [`enable_allocator_queries`],
+//! that the global allocator is the system allocator.
+
+use crate::SizeFlags;
+
+#[cfg(feature = "allocator")]
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Whether [`enable_allocator_queries`] has been called.
+#[cfg(feature = "allocator")]
+static QUERIES_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Enables the queries to the system allocator requested by
+/// [`SizeFlags::ALLOCATOR`] and [`DbgFlags::ALLOCATOR`](crate::DbgFlags).
+///
+/// Until this function is called, these flags are equivalent to
+/// [`SizeFlags::CAPACITY`] and [`DbgFlags::CAPACITY`](crate::DbgFlags).
+///
+/// ```
+/// use mem_dbg::*;
+///
+/// let v = Vec::<u8>::with_capacity(10);
+/// assert_eq!(
+///     v.mem_size(SizeFlags::ALLOCATOR),
+///     v.mem_size(SizeFlags::CAPACITY)
+/// );
+/// // SAFETY: this program uses the system allocator
+/// unsafe { enable_allocator_queries() };
+/// assert!(v.mem_size(SizeFlags::ALLOCATOR) >= v.mem_size(SizeFlags::CAPACITY));
+/// ```
+///
+/// # Safety
+///
+/// The global allocator must be the system allocator, or an allocator
+/// replacing the system `malloc` (e.g., an allocator linked with
+/// `LD_PRELOAD`), as the system allocator is otherwise queried about blocks
+/// it did not allocate. In particular, this function must not be called if
+/// the program sets a `#[global_allocator]` other than
+/// [`System`](std::alloc::System), such as jemalloc or mimalloc.
+#[cfg(feature = "allocator")]
+pub unsafe fn enable_allocator_queries() {
+    QUERIES_ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Returns the size of the heap block of `size` bytes with alignment `align`
+/// starting at `ptr`.
+///
+/// If [`SizeFlags::ALLOCATOR`] is set and [`enable_allocator_queries`] has
+/// been called, the size of the block is queried from the system allocator;
+/// if this is not possible on the current platform, or if no block has been
+/// allocated (`size` is zero), `size` is returned.
+#[inline(always)]
+#[allow(unused_variables)]
+pub(crate) fn heap_size(ptr: *const u8, size: usize, align: usize, flags: SizeFlags) -> usize {
+    #[cfg(feature = "allocator")]
+    if size != 0 && flags.contains(SizeFlags::ALLOCATOR) && QUERIES_ENABLED.load(Ordering::Relaxed)
+    {
+        // SAFETY: the pointer points to a nonempty block allocated by the
+        // global allocator, which the caller of enable_allocator_queries
+        // guaranteed to be the system allocator
+        return unsafe { usable_size(ptr, align) }.unwrap_or(size);
+    }
+    size
+}
+
+#[cfg(all(
+    feature = "allocator",
+    any(target_os = "linux", target_os = "android", target_os = "freebsd")
+))]
+unsafe fn usable_size(ptr: *const u8, _align: usize) -> Option<usize> {
+    extern "C" {
+        fn malloc_usable_size(ptr: *mut core::ffi::c_void) -> usize;
+    }
+    Some(malloc_usable_size(ptr as *mut core::ffi::c_void))
+}
+
+#[cfg(all(feature = "allocator", any(target_os = "macos", target_os = "ios")))]
+unsafe fn usable_size(ptr: *const u8, _align: usize) -> Option<usize> {
+    extern "C" {
+        fn malloc_size(ptr: *const core::ffi::c_void) -> usize;
+    }
+    Some(malloc_size(ptr as *const core::ffi::c_void))
+}
+
+#[cfg(all(feature = "allocator", target_os = "windows"))]
+unsafe fn usable_size(ptr: *const u8, align: usize) -> Option<usize> {
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GetProcessHeap() -> *mut core::ffi::c_void;
+        fn HeapSize(
+            heap: *mut core::ffi::c_void,
+            flags: u32,
+            ptr: *const core::ffi::c_void,
+        ) -> usize;
+    }
+    // The system allocator uses HeapAlloc, but blocks with a large alignment
+    // start before the pointer it returns
+    if align > 2 * core::mem::size_of::<usize>() {
+        return None;
+    }
+    match HeapSize(GetProcessHeap(), 0, ptr as *const core::ffi::c_void) {
+        usize::MAX => None,
+        size => Some(size),
+    }
+}
+
+#[cfg(all(
+    feature = "allocator",
+    not(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "freebsd",
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "windows"
+    ))
+))]
+unsafe fn usable_size(_ptr: *const u8, _align: usize) -> Option<usize> {
+    None
+}
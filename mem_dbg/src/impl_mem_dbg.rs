@@ -10,13 +10,16 @@ use core::marker::PhantomPinned;
 use core::num::*;
 use core::ops::Deref;
 use core::{marker::PhantomData, sync::atomic::*};
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 
-use crate::impl_mem_size::MemSizeHelper2;
-use crate::{impl_mem_size::MemSizeHelper, CopyType, DbgFlags, MemDbgImpl};
+use crate::impl_mem_size::{btree_node_count, hash_buckets, MemSizeHelper2, GROUP_WIDTH};
+use crate::utils::format_number;
+use crate::{
+    impl_mem_size::MemSizeHelper, Boolean, CopyType, DbgFlags, MemDbgImpl, MemNode, MemSize,
+    SizeContext,
+};
 
 /// Implements [`MemDbg`] using the default implementation of [`MemDbgImpl`].
-
 macro_rules! impl_mem_dbg {
      ($($ty:ty),*) => {$(
  impl MemDbgImpl for $ty {}
@@ -32,68 +35,164 @@ impl_mem_dbg! {
     AtomicU8, AtomicU16, AtomicU32, AtomicU64, AtomicUsize,
     NonZeroI8, NonZeroI16, NonZeroI32, NonZeroI64, NonZeroI128, NonZeroIsize,
     NonZeroU8, NonZeroU16, NonZeroU32, NonZeroU64, NonZeroU128, NonZeroUsize,
-    PhantomPinned, str, String
+    PhantomPinned, core::convert::Infallible, str
+}
+
+/// Returns the information displayed by collections with [`DbgFlags::LEN`],
+/// that is, their length and, with [`DbgFlags::CAPACITY`], their capacity, if
+/// they have one.
+fn len_info(len: usize, capacity: Option<usize>, flags: DbgFlags) -> Option<String> {
+    if !flags.contains(DbgFlags::LEN) {
+        return None;
+    }
+    Some(match capacity {
+        Some(capacity) if flags.contains(DbgFlags::CAPACITY) => format!(
+            "len={}, capacity={}",
+            format_number(len, flags),
+            format_number(capacity, flags)
+        ),
+        _ => format!("len={}", format_number(len, flags)),
+    })
+}
+
+impl MemDbgImpl for String {
+    fn _mem_dbg_info(&self, flags: DbgFlags) -> Option<String> {
+        len_info(self.len(), Some(self.capacity()), flags)
+    }
 }
 
 impl<T: ?Sized> MemDbgImpl for PhantomData<T> {}
 
+impl<T> MemDbgImpl for core::mem::MaybeUninit<T> {}
+
 // References: we recurse only if FOLLOW_REFS is set
 
 impl<T: ?Sized + MemDbgImpl> MemDbgImpl for &'_ T {
-    fn _mem_dbg_rec_on(
-        &self,
-        writer: &mut impl core::fmt::Write,
-        total_size: usize,
-        max_depth: usize,
-        prefix: &mut String,
-        is_last: bool,
-        flags: DbgFlags,
-    ) -> core::fmt::Result {
+    fn _mem_dbg_rec(&self, node: &mut MemNode, max_depth: usize, flags: DbgFlags) {
         if flags.contains(DbgFlags::FOLLOW_REFS) {
-            (**self)._mem_dbg_rec_on(writer, total_size, max_depth, prefix, is_last, flags)
-        } else {
-            Ok(())
+            (**self)._mem_dbg_rec(node, max_depth, flags)
         }
     }
 }
 
 impl<T: ?Sized + MemDbgImpl> MemDbgImpl for &'_ mut T {
-    fn _mem_dbg_rec_on(
-        &self,
-        writer: &mut impl core::fmt::Write,
-        total_size: usize,
-        max_depth: usize,
-        prefix: &mut String,
-        is_last: bool,
-        flags: DbgFlags,
-    ) -> core::fmt::Result {
+    fn _mem_dbg_rec(&self, node: &mut MemNode, max_depth: usize, flags: DbgFlags) {
         if flags.contains(DbgFlags::FOLLOW_REFS) {
-            (**self)._mem_dbg_rec_on(writer, total_size, max_depth, prefix, is_last, flags)
-        } else {
-            Ok(())
+            (**self)._mem_dbg_rec(node, max_depth, flags)
+        }
+    }
+}
+
+// Option, Result, and ControlFlow: we recurse into the payload, whose padded
+// size is the size of the whole value, so that the discriminant, if any, is
+// displayed as padding
+
+impl<T: MemDbgImpl> MemDbgImpl for Option<T> {
+    fn _mem_dbg_rec(&self, node: &mut MemNode, max_depth: usize, flags: DbgFlags) {
+        if let Some(x) = self {
+            node.children.push(x._mem_dbg_node(
+                "Some",
+                core::mem::size_of::<Self>(),
+                max_depth - 1,
+                flags,
+            ));
         }
     }
 }
 
-// Option
+impl<T: MemDbgImpl, E: MemDbgImpl> MemDbgImpl for Result<T, E> {
+    fn _mem_dbg_rec(&self, node: &mut MemNode, max_depth: usize, flags: DbgFlags) {
+        let padded_size = core::mem::size_of::<Self>();
+        node.children.push(match self {
+            Ok(x) => x._mem_dbg_node("Ok", padded_size, max_depth - 1, flags),
+            Err(e) => e._mem_dbg_node("Err", padded_size, max_depth - 1, flags),
+        });
+    }
+}
 
-impl<T: MemDbgImpl> MemDbgImpl for Option<T> {}
+impl<B: MemDbgImpl, C: MemDbgImpl> MemDbgImpl for core::ops::ControlFlow<B, C> {
+    fn _mem_dbg_rec(&self, node: &mut MemNode, max_depth: usize, flags: DbgFlags) {
+        let padded_size = core::mem::size_of::<Self>();
+        node.children.push(match self {
+            core::ops::ControlFlow::Continue(c) => {
+                c._mem_dbg_node("Continue", padded_size, max_depth - 1, flags)
+            }
+            core::ops::ControlFlow::Break(b) => {
+                b._mem_dbg_node("Break", padded_size, max_depth - 1, flags)
+            }
+        });
+    }
+}
 
 // Box
 
 #[cfg(feature = "alloc")]
 impl<T: ?Sized + MemDbgImpl> MemDbgImpl for Box<T> {
-    fn _mem_dbg_rec_on(
-        &self,
-        writer: &mut impl core::fmt::Write,
-        total_size: usize,
-        max_depth: usize,
-        prefix: &mut String,
-        is_last: bool,
-        flags: DbgFlags,
-    ) -> core::fmt::Result {
-        self.as_ref()
-            ._mem_dbg_rec_on(writer, total_size, max_depth, prefix, is_last, flags)
+    fn _mem_dbg_info(&self, flags: DbgFlags) -> Option<String> {
+        self.as_ref()._mem_dbg_info(flags)
+    }
+
+    fn _mem_dbg_rec(&self, node: &mut MemNode, max_depth: usize, flags: DbgFlags) {
+        self.as_ref()._mem_dbg_rec(node, max_depth, flags)
+    }
+}
+
+// Reference-counted pointers: we recurse into the content unless the
+// allocation has already been displayed (with FOLLOW_RCS) or is being
+// displayed (that is, we are following a cycle)
+
+#[cfg(feature = "std")]
+std::thread_local! {
+    /// The addresses of the reference-counted allocations already displayed.
+    static RC_VISITED: core::cell::RefCell<HashSet<usize>> = core::cell::RefCell::new(HashSet::new());
+}
+
+/// Runs `f` with an empty set of displayed reference-counted allocations,
+/// restoring the previous set afterwards, so that nested invocations of
+/// [`MemDbg`](crate::MemDbg) methods do not interfere.
+pub(crate) fn with_new_rc_visited<R>(f: impl FnOnce() -> R) -> R {
+    #[cfg(feature = "std")]
+    let visited = RC_VISITED.with(|visited| core::mem::take(&mut *visited.borrow_mut()));
+    let result = f();
+    #[cfg(feature = "std")]
+    RC_VISITED.with(|v| *v.borrow_mut() = visited);
+    result
+}
+
+/// Recurses into the content of a reference-counted pointer to `value`, which
+/// is stored at address `ptr`, or adds a label referring to the address if
+/// the allocation has already been displayed.
+///
+/// Without the `std` feature allocations are not tracked, so cycles are not
+/// detected.
+fn rc_mem_dbg<T: ?Sized + MemDbgImpl>(
+    ptr: *const T,
+    value: &T,
+    node: &mut MemNode,
+    max_depth: usize,
+    flags: DbgFlags,
+) {
+    let addr = ptr as *const u8 as usize;
+    node.address = Some(addr);
+    #[cfg(feature = "std")]
+    if !RC_VISITED.with(|visited| visited.borrow_mut().insert(addr)) {
+        let arrow = if flags.contains(DbgFlags::ASCII) {
+            "->"
+        } else {
+            "→"
+        };
+        // The address of the data, without the metadata of trait objects
+        node.children.push(MemNode::label(&format!(
+            "{} @{:p} (already counted)",
+            arrow, ptr as *const u8
+        )));
+        return;
+    }
+    value._mem_dbg_rec(node, max_depth, flags);
+    // Without FOLLOW_RCS, shared allocations are displayed at each pointer
+    #[cfg(feature = "std")]
+    if !flags.contains(DbgFlags::FOLLOW_RCS) {
+        RC_VISITED.with(|visited| visited.borrow_mut().remove(&addr));
     }
 }
 
@@ -102,38 +201,193 @@ use alloc::sync::Arc;
 #[cfg(feature = "std")]
 use std::sync::Arc;
 #[cfg(feature = "alloc")]
-impl<T: MemDbgImpl> MemDbgImpl for Arc<T> {
-    fn _mem_dbg_rec_on(
-        &self,
-        writer: &mut impl core::fmt::Write,
-        total_size: usize,
-        max_depth: usize,
-        prefix: &mut String,
-        is_last: bool,
-        flags: DbgFlags,
-    ) -> core::fmt::Result {
-        self.as_ref()
-            ._mem_dbg_rec_on(writer, total_size, max_depth, prefix, is_last, flags)
+impl<T: ?Sized + MemDbgImpl> MemDbgImpl for Arc<T> {
+    fn _mem_dbg_rec(&self, node: &mut MemNode, max_depth: usize, flags: DbgFlags) {
+        rc_mem_dbg(Arc::as_ptr(self), self.as_ref(), node, max_depth, flags)
+    }
+}
+
+#[cfg(all(feature = "alloc", not(feature = "std")))]
+use alloc::rc::Rc;
+#[cfg(feature = "std")]
+use std::rc::Rc;
+#[cfg(feature = "alloc")]
+impl<T: ?Sized + MemDbgImpl> MemDbgImpl for Rc<T> {
+    fn _mem_dbg_rec(&self, node: &mut MemNode, max_depth: usize, flags: DbgFlags) {
+        rc_mem_dbg(Rc::as_ptr(self), self.as_ref(), node, max_depth, flags)
+    }
+}
+
+// Slices, arrays, vectors, and double-ended queues
+
+/// The default maximum number of elements displayed by
+/// [`DbgFlags::EXPAND_ELEMENTS`] (see [`DbgConfig::max_elements`]).
+///
+/// [`DbgConfig::max_elements`]: crate::DbgConfig::max_elements
+pub(crate) const MAX_EXPANDED_ELEMENTS: usize = 8;
+
+#[cfg(feature = "std")]
+std::thread_local! {
+    /// The maximum number of elements displayed by
+    /// [`DbgFlags::EXPAND_ELEMENTS`] in the tree being built.
+    static MAX_ELEMENTS: core::cell::Cell<usize> = const { core::cell::Cell::new(MAX_EXPANDED_ELEMENTS) };
+}
+
+/// Runs `f` displaying at most `max_elements` elements of each sequence,
+/// restoring the previous maximum afterwards.
+///
+/// Without the `std` feature, the maximum is always
+/// [`MAX_EXPANDED_ELEMENTS`].
+#[allow(unused_variables)]
+pub(crate) fn with_max_elements<R>(max_elements: usize, f: impl FnOnce() -> R) -> R {
+    #[cfg(feature = "std")]
+    let previous = MAX_ELEMENTS.with(|max| max.replace(max_elements));
+    let result = f();
+    #[cfg(feature = "std")]
+    MAX_ELEMENTS.with(|max| max.set(previous));
+    result
+}
+
+/// Returns the maximum number of elements displayed by
+/// [`DbgFlags::EXPAND_ELEMENTS`] in the tree being built.
+fn max_elements() -> usize {
+    #[cfg(feature = "std")]
+    return MAX_ELEMENTS.with(|max| max.get());
+    #[cfg(not(feature = "std"))]
+    MAX_EXPANDED_ELEMENTS
+}
+
+/// Adds to `node` the elements of a sequence if [`DbgFlags::EXPAND_ELEMENTS`]
+/// is set and the elements are not [`Copy`]; the elements after the first
+/// [`DbgConfig::max_elements`] (by default, [`MAX_EXPANDED_ELEMENTS`]) are
+/// summarized in a single line, and if [`DbgFlags::DEDUP_TYPES`] is set all
+/// elements are.
+///
+/// [`DbgConfig::max_elements`]: crate::DbgConfig::max_elements
+fn mem_dbg_elements<'a, T: CopyType + MemDbgImpl + 'a>(
+    mut elements: impl ExactSizeIterator<Item = &'a T>,
+    node: &mut MemNode,
+    max_depth: usize,
+    flags: DbgFlags,
+) {
+    if !flags.contains(DbgFlags::EXPAND_ELEMENTS) || <T::Copy as Boolean>::VALUE {
+        return;
+    }
+
+    let len = elements.len();
+    let size_flags = flags.to_size_flags();
+    if flags.contains(DbgFlags::DEDUP_TYPES) && len > 1 {
+        let size = elements
+            .map(|element| <T as crate::MemSize>::mem_size(element, size_flags))
+            .sum::<usize>();
+        node.children.push(crate::dedup_node(
+            core::any::type_name::<T>(),
+            len,
+            size,
+            flags,
+        ));
+        return;
+    }
+
+    let expanded = len.min(max_elements());
+    for (i, element) in elements.by_ref().take(expanded).enumerate() {
+        node.children.push(element._mem_dbg_node(
+            &format!("[{}]", i),
+            core::mem::size_of::<T>(),
+            max_depth - 1,
+            flags,
+        ));
+    }
+
+    if len > expanded {
+        let size = elements
+            .map(|element| <T as crate::MemSize>::mem_size(element, size_flags))
+            .sum::<usize>();
+        let ellipsis = if flags.contains(DbgFlags::ASCII) {
+            "..."
+        } else {
+            "…"
+        };
+        node.children.push(MemNode::label(&format!(
+            "{} and {} more ({} bytes)",
+            ellipsis,
+            len - expanded,
+            size
+        )));
     }
 }
 
-// Slices
+impl<T: CopyType + MemDbgImpl> MemDbgImpl for [T]
+where
+    [T]: MemSizeHelper<<T as CopyType>::Copy>,
+{
+    fn _mem_dbg_info(&self, flags: DbgFlags) -> Option<String> {
+        len_info(self.len(), None, flags)
+    }
 
-impl<T: CopyType + MemDbgImpl> MemDbgImpl for [T] where [T]: MemSizeHelper<<T as CopyType>::Copy> {}
+    fn _mem_dbg_rec(&self, node: &mut MemNode, max_depth: usize, flags: DbgFlags) {
+        mem_dbg_elements(self.iter(), node, max_depth, flags)
+    }
+}
 
-// Arrays
+impl<T: CopyType + MemDbgImpl, const N: usize> MemDbgImpl for [T; N]
+where
+    [T; N]: MemSizeHelper<<T as CopyType>::Copy>,
+{
+    fn _mem_dbg_rec(&self, node: &mut MemNode, max_depth: usize, flags: DbgFlags) {
+        mem_dbg_elements(self.iter(), node, max_depth, flags)
+    }
+}
 
-impl<T: CopyType + MemDbgImpl, const N: usize> MemDbgImpl for [T; N] where
-    [T; N]: MemSizeHelper<<T as CopyType>::Copy>
+#[cfg(feature = "alloc")]
+impl<T: CopyType + MemDbgImpl> MemDbgImpl for Vec<T>
+where
+    Vec<T>: MemSizeHelper<<T as CopyType>::Copy>,
 {
+    fn _mem_dbg_info(&self, flags: DbgFlags) -> Option<String> {
+        len_info(self.len(), Some(self.capacity()), flags)
+    }
+
+    fn _mem_dbg_rec(&self, node: &mut MemNode, max_depth: usize, flags: DbgFlags) {
+        mem_dbg_elements(self.iter(), node, max_depth, flags)
+    }
 }
 
-// Vectors
+#[cfg(all(feature = "alloc", not(feature = "std")))]
+use alloc::collections::VecDeque;
+#[cfg(feature = "std")]
+use std::collections::VecDeque;
+#[cfg(feature = "alloc")]
+impl<T: CopyType + MemDbgImpl> MemDbgImpl for VecDeque<T>
+where
+    VecDeque<T>: MemSizeHelper<<T as CopyType>::Copy>,
+{
+    fn _mem_dbg_info(&self, flags: DbgFlags) -> Option<String> {
+        len_info(self.len(), Some(self.capacity()), flags)
+    }
+
+    fn _mem_dbg_rec(&self, node: &mut MemNode, max_depth: usize, flags: DbgFlags) {
+        mem_dbg_elements(self.iter(), node, max_depth, flags)
+    }
+}
 
+#[cfg(all(feature = "alloc", not(feature = "std")))]
+use alloc::collections::BinaryHeap;
+#[cfg(feature = "std")]
+use std::collections::BinaryHeap;
 #[cfg(feature = "alloc")]
-impl<T: CopyType + MemDbgImpl> MemDbgImpl for Vec<T> where
-    Vec<T>: MemSizeHelper<<T as CopyType>::Copy>
+impl<T: CopyType + MemDbgImpl> MemDbgImpl for BinaryHeap<T>
+where
+    [T]: MemSize,
 {
+    fn _mem_dbg_info(&self, flags: DbgFlags) -> Option<String> {
+        len_info(self.len(), Some(self.capacity()), flags)
+    }
+
+    fn _mem_dbg_rec(&self, node: &mut MemNode, max_depth: usize, flags: DbgFlags) {
+        // Elements are displayed in the order of the underlying vector
+        mem_dbg_elements(self.iter(), node, max_depth, flags)
+    }
 }
 
 // Tuples
@@ -162,19 +416,7 @@ macro_rules! impl_tuples_muncher {
     // Implement on reversed list and tuple type
     ([($idx:tt => $ty:ident); $( ($nidx:tt => $nty:ident); )*], $tty:ty) => {
         impl<$ty: crate::MemSize + MemDbgImpl, $($nty: crate::MemSize + MemDbgImpl,)*> MemDbgImpl for ($ty, $($nty,)*)  {
-            fn _mem_dbg_rec_on(
-                &self,
-                writer: &mut impl core::fmt::Write,
-                total_size: usize,
-                max_depth: usize,
-                prefix: &mut String,
-                _is_last: bool,
-                flags: DbgFlags,
-            ) -> core::fmt::Result {
-                // Compute size of tuple minus one for last-field check.
-                let mut _max_idx = $idx;
-                $(_max_idx = _max_idx.max($nidx);)*
-
+            fn _mem_dbg_rec(&self, node: &mut MemNode, max_depth: usize, flags: DbgFlags) {
                 let mut id_sizes: Vec<(usize, usize)> = vec![];
                 let n;
 
@@ -195,11 +437,10 @@ macro_rules! impl_tuples_muncher {
                     id_sizes.sort_by_key(|x| x.0);
                 }
 
-                self.$idx._mem_dbg_depth_on(writer, total_size, max_depth, prefix, Some(stringify!($idx)), $idx == _max_idx, id_sizes[$idx].1, flags)?;
+                node.children.push(self.$idx._mem_dbg_node(stringify!($idx), id_sizes[$idx].1, max_depth - 1, flags));
                 $(
-                    self.$nidx._mem_dbg_depth_on(writer, total_size, max_depth, prefix, Some(stringify!($nidx)), $nidx == _max_idx, id_sizes[$nidx].1, flags)?;
+                    node.children.push(self.$nidx._mem_dbg_node(stringify!($nidx), id_sizes[$nidx].1, max_depth - 1, flags));
                 )*
-                Ok(())
             }
         }
     };
@@ -226,12 +467,157 @@ impl<A, B, R> MemDbgImpl for fn(A, B) -> R {}
 impl<A, B, C, R> MemDbgImpl for fn(A, B, C) -> R {}
 impl<A, B, C, D, R> MemDbgImpl for fn(A, B, C, D) -> R {}
 
-// Hash-based containers from the standard library
+// Maps and sets
+//
+// Unless all keys and values are Copy, we display synthetic children: the
+// overall size of the keys, the overall size of the values, and the overhead
+// of the container, that is, the rest of its heap memory. Keys and values are
+// sized in a single context, so that, as in the overall size of the container,
+// reference-counted allocations they share are counted once.
+
+/// Returns the overall size of `items`, computed in `ctx`.
+fn shared_size<'a, T: MemSize + 'a>(
+    items: impl Iterator<Item = &'a T>,
+    ctx: &mut SizeContext,
+) -> usize {
+    let heap_size = ctx.heap_size();
+    let stack_size = items
+        .map(|item| {
+            let _ = item._mem_size_rec(ctx);
+            core::mem::size_of_val(item)
+        })
+        .sum::<usize>();
+    stack_size + (ctx.heap_size() - heap_size)
+}
+
+fn mem_dbg_breakdown(node: &mut MemNode, heap_size: usize, keys: usize, values: Option<usize>) {
+    node.children.push(MemNode::group("keys", keys));
+    if let Some(values) = values {
+        node.children.push(MemNode::group("values", values));
+    }
+    node.children.push(MemNode::group(
+        "overhead",
+        heap_size
+            .saturating_sub(keys)
+            .saturating_sub(values.unwrap_or(0)),
+    ));
+}
+
+// Hash-based containers from the standard library: the overhead is made of
+// empty buckets and control bytes
+
+/// Returns the information displayed by hash-based containers, that is,
+/// [`len_info`] followed, if [`DbgFlags::GROUP_WIDTH_NOTE`] is set, by the
+/// estimated number of buckets and of control bytes, and by the group width.
+fn hash_info(len: usize, capacity: usize, flags: DbgFlags) -> Option<String> {
+    let info = len_info(len, Some(capacity), flags);
+    if !flags.contains(DbgFlags::GROUP_WIDTH_NOTE) {
+        return info;
+    }
+    let buckets = hash_buckets(len, capacity, flags.to_size_flags());
+    let note = format!(
+        "buckets={}, ctrl_bytes={}, group_width={}",
+        format_number(buckets, flags),
+        format_number(buckets * core::mem::size_of::<u8>(), flags),
+        GROUP_WIDTH
+    );
+    Some(match info {
+        Some(info) => format!("{}, {}", info, note),
+        None => note,
+    })
+}
+
+impl<K: CopyType + MemSize> MemDbgImpl for HashSet<K>
+where
+    HashSet<K>: MemSizeHelper<<K as CopyType>::Copy>,
+{
+    fn _mem_dbg_info(&self, flags: DbgFlags) -> Option<String> {
+        hash_info(self.len(), self.capacity(), flags)
+    }
+
+    fn _mem_dbg_rec(&self, node: &mut MemNode, _max_depth: usize, flags: DbgFlags) {
+        if <K::Copy as Boolean>::VALUE {
+            return;
+        }
+        let size_flags = flags.to_size_flags();
+        let mut ctx = SizeContext::new(size_flags);
+        let keys = shared_size(self.iter(), &mut ctx);
+        mem_dbg_breakdown(node, self.heap_size(size_flags), keys, None)
+    }
+}
 
-impl<K: CopyType> MemDbgImpl for HashSet<K> where HashSet<K>: MemSizeHelper<<K as CopyType>::Copy> {}
-impl<K: CopyType, V: CopyType> MemDbgImpl for HashMap<K, V> where
-    HashMap<K, V>: MemSizeHelper2<<K as CopyType>::Copy, <V as CopyType>::Copy>
+impl<K: CopyType + MemSize, V: CopyType + MemSize> MemDbgImpl for HashMap<K, V>
+where
+    HashMap<K, V>: MemSizeHelper2<<K as CopyType>::Copy, <V as CopyType>::Copy>,
 {
+    fn _mem_dbg_info(&self, flags: DbgFlags) -> Option<String> {
+        hash_info(self.len(), self.capacity(), flags)
+    }
+
+    fn _mem_dbg_rec(&self, node: &mut MemNode, _max_depth: usize, flags: DbgFlags) {
+        if <K::Copy as Boolean>::VALUE && <V::Copy as Boolean>::VALUE {
+            return;
+        }
+        let size_flags = flags.to_size_flags();
+        let mut ctx = SizeContext::new(size_flags);
+        let keys = shared_size(self.keys(), &mut ctx);
+        let values = shared_size(self.values(), &mut ctx);
+        mem_dbg_breakdown(node, self.heap_size(size_flags), keys, Some(values))
+    }
+}
+
+// B-tree-based containers from the standard library: the overhead is made of
+// the estimated unused slots and pointers of the nodes, whose number we
+// display as the node sizes are not apparent from the overall size
+
+/// Adds to `node` a label with the number of entries of a B-tree with `len`
+/// entries and the estimated number of its nodes.
+fn mem_dbg_btree_label(node: &mut MemNode, len: usize) {
+    let nodes = btree_node_count(len);
+    node.children.push(MemNode::label(&format!(
+        "{} {}, ~{} {}",
+        len,
+        if len == 1 { "entry" } else { "entries" },
+        nodes,
+        if nodes == 1 { "node" } else { "nodes" }
+    )));
+}
+
+#[cfg(feature = "alloc")]
+impl<K: CopyType + MemSize> MemDbgImpl for BTreeSet<K> {
+    fn _mem_dbg_info(&self, flags: DbgFlags) -> Option<String> {
+        len_info(self.len(), None, flags)
+    }
+
+    fn _mem_dbg_rec(&self, node: &mut MemNode, _max_depth: usize, flags: DbgFlags) {
+        mem_dbg_btree_label(node, self.len());
+        if <K::Copy as Boolean>::VALUE {
+            return;
+        }
+        let size_flags = flags.to_size_flags();
+        let mut ctx = SizeContext::new(size_flags);
+        let keys = shared_size(self.iter(), &mut ctx);
+        mem_dbg_breakdown(node, self.heap_size(size_flags), keys, None)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<K: CopyType + MemSize, V: CopyType + MemSize> MemDbgImpl for BTreeMap<K, V> {
+    fn _mem_dbg_info(&self, flags: DbgFlags) -> Option<String> {
+        len_info(self.len(), None, flags)
+    }
+
+    fn _mem_dbg_rec(&self, node: &mut MemNode, _max_depth: usize, flags: DbgFlags) {
+        mem_dbg_btree_label(node, self.len());
+        if <K::Copy as Boolean>::VALUE && <V::Copy as Boolean>::VALUE {
+            return;
+        }
+        let size_flags = flags.to_size_flags();
+        let mut ctx = SizeContext::new(size_flags);
+        let keys = shared_size(self.keys(), &mut ctx);
+        let values = shared_size(self.values(), &mut ctx);
+        mem_dbg_breakdown(node, self.heap_size(size_flags), keys, Some(values))
+    }
 }
 
 // Hash stuff
@@ -259,81 +645,34 @@ impl MemDbgImpl for core::alloc::Layout {
 // Ranges
 
 impl<Idx: MemDbgImpl> MemDbgImpl for core::ops::Range<Idx> {
-    fn _mem_dbg_rec_on(
-        &self,
-        writer: &mut impl core::fmt::Write,
-        total_size: usize,
-        max_depth: usize,
-        prefix: &mut String,
-        is_last: bool,
-        flags: DbgFlags,
-    ) -> core::fmt::Result {
-        self.start
-            ._mem_dbg_rec_on(writer, total_size, max_depth, prefix, is_last, flags)?;
-        self.end
-            ._mem_dbg_rec_on(writer, total_size, max_depth, prefix, is_last, flags)
+    fn _mem_dbg_rec(&self, node: &mut MemNode, max_depth: usize, flags: DbgFlags) {
+        self.start._mem_dbg_rec(node, max_depth, flags);
+        self.end._mem_dbg_rec(node, max_depth, flags)
     }
 }
 
 impl<Idx: MemDbgImpl> MemDbgImpl for core::ops::RangeFrom<Idx> {
-    fn _mem_dbg_rec_on(
-        &self,
-        writer: &mut impl core::fmt::Write,
-        total_size: usize,
-        max_depth: usize,
-        prefix: &mut String,
-        is_last: bool,
-        flags: DbgFlags,
-    ) -> core::fmt::Result {
-        self.start
-            ._mem_dbg_rec_on(writer, total_size, max_depth, prefix, is_last, flags)
+    fn _mem_dbg_rec(&self, node: &mut MemNode, max_depth: usize, flags: DbgFlags) {
+        self.start._mem_dbg_rec(node, max_depth, flags)
     }
 }
 
 impl<Idx: MemDbgImpl> MemDbgImpl for core::ops::RangeInclusive<Idx> {
-    fn _mem_dbg_rec_on(
-        &self,
-        writer: &mut impl core::fmt::Write,
-        total_size: usize,
-        max_depth: usize,
-        prefix: &mut String,
-        is_last: bool,
-        flags: DbgFlags,
-    ) -> core::fmt::Result {
-        self.start()
-            ._mem_dbg_rec_on(writer, total_size, max_depth, prefix, is_last, flags)?;
-        self.end()
-            ._mem_dbg_rec_on(writer, total_size, max_depth, prefix, is_last, flags)
+    fn _mem_dbg_rec(&self, node: &mut MemNode, max_depth: usize, flags: DbgFlags) {
+        self.start()._mem_dbg_rec(node, max_depth, flags);
+        self.end()._mem_dbg_rec(node, max_depth, flags)
     }
 }
 
 impl<Idx: MemDbgImpl> MemDbgImpl for core::ops::RangeTo<Idx> {
-    fn _mem_dbg_rec_on(
-        &self,
-        writer: &mut impl core::fmt::Write,
-        total_size: usize,
-        max_depth: usize,
-        prefix: &mut String,
-        is_last: bool,
-        flags: DbgFlags,
-    ) -> core::fmt::Result {
-        self.end
-            ._mem_dbg_rec_on(writer, total_size, max_depth, prefix, is_last, flags)
+    fn _mem_dbg_rec(&self, node: &mut MemNode, max_depth: usize, flags: DbgFlags) {
+        self.end._mem_dbg_rec(node, max_depth, flags)
     }
 }
 
 impl<Idx: MemDbgImpl> MemDbgImpl for core::ops::RangeToInclusive<Idx> {
-    fn _mem_dbg_rec_on(
-        &self,
-        writer: &mut impl core::fmt::Write,
-        total_size: usize,
-        max_depth: usize,
-        prefix: &mut String,
-        is_last: bool,
-        flags: DbgFlags,
-    ) -> core::fmt::Result {
-        self.end
-            ._mem_dbg_rec_on(writer, total_size, max_depth, prefix, is_last, flags)
+    fn _mem_dbg_rec(&self, node: &mut MemNode, max_depth: usize, flags: DbgFlags) {
+        self.end._mem_dbg_rec(node, max_depth, flags)
     }
 }
 
@@ -353,160 +692,83 @@ impl_mem_dbg!(
 // Cells
 
 impl<T: MemDbgImpl> MemDbgImpl for core::cell::RefCell<T> {
-    fn _mem_dbg_rec_on(
-        &self,
-        writer: &mut impl core::fmt::Write,
-        total_size: usize,
-        max_depth: usize,
-        prefix: &mut String,
-        is_last: bool,
-        flags: DbgFlags,
-    ) -> core::fmt::Result {
-        self.borrow()
-            ._mem_dbg_rec_on(writer, total_size, max_depth, prefix, is_last, flags)
+    fn _mem_dbg_rec(&self, node: &mut MemNode, max_depth: usize, flags: DbgFlags) {
+        self.borrow()._mem_dbg_rec(node, max_depth, flags)
     }
 }
 
 impl<T: MemDbgImpl> MemDbgImpl for core::cell::Cell<T> {
-    fn _mem_dbg_rec_on(
-        &self,
-        writer: &mut impl core::fmt::Write,
-        total_size: usize,
-        max_depth: usize,
-        prefix: &mut String,
-        is_last: bool,
-        flags: DbgFlags,
-    ) -> core::fmt::Result {
-        unsafe {
-            (*self.as_ptr())._mem_dbg_rec_on(writer, total_size, max_depth, prefix, is_last, flags)
-        }
+    fn _mem_dbg_rec(&self, node: &mut MemNode, max_depth: usize, flags: DbgFlags) {
+        unsafe { (*self.as_ptr())._mem_dbg_rec(node, max_depth, flags) }
     }
 }
 
 impl<T: MemDbgImpl> MemDbgImpl for core::cell::UnsafeCell<T> {
-    fn _mem_dbg_rec_on(
-        &self,
-        writer: &mut impl core::fmt::Write,
-        total_size: usize,
-        max_depth: usize,
-        prefix: &mut String,
-        is_last: bool,
-        flags: DbgFlags,
-    ) -> core::fmt::Result {
-        unsafe {
-            (*self.get())._mem_dbg_rec_on(writer, total_size, max_depth, prefix, is_last, flags)
-        }
+    fn _mem_dbg_rec(&self, node: &mut MemNode, max_depth: usize, flags: DbgFlags) {
+        unsafe { (*self.get())._mem_dbg_rec(node, max_depth, flags) }
     }
 }
 
-// Mutexes
+// Mutexes: as in the case of MemSize, locks that are held or poisoned are
+// not followed
 
 #[cfg(feature = "std")]
 impl<T: MemDbgImpl> MemDbgImpl for std::sync::Mutex<T> {
-    fn _mem_dbg_rec_on(
-        &self,
-        writer: &mut impl core::fmt::Write,
-        total_size: usize,
-        max_depth: usize,
-        prefix: &mut String,
-        is_last: bool,
-        flags: DbgFlags,
-    ) -> core::fmt::Result {
-        self.lock()
-            .unwrap()
-            ._mem_dbg_rec_on(writer, total_size, max_depth, prefix, is_last, flags)
+    fn _mem_dbg_rec(&self, node: &mut MemNode, max_depth: usize, flags: DbgFlags) {
+        if let Ok(x) = self.try_lock() {
+            x._mem_dbg_rec(node, max_depth, flags)
+        }
     }
 }
 
 #[cfg(feature = "std")]
 impl<T: MemDbgImpl> MemDbgImpl for std::sync::RwLock<T> {
-    fn _mem_dbg_rec_on(
-        &self,
-        writer: &mut impl core::fmt::Write,
-        total_size: usize,
-        max_depth: usize,
-        prefix: &mut String,
-        is_last: bool,
-        flags: DbgFlags,
-    ) -> core::fmt::Result {
-        self.read()
-            .unwrap()
-            ._mem_dbg_rec_on(writer, total_size, max_depth, prefix, is_last, flags)
+    fn _mem_dbg_rec(&self, node: &mut MemNode, max_depth: usize, flags: DbgFlags) {
+        if let Ok(x) = self.try_read() {
+            x._mem_dbg_rec(node, max_depth, flags)
+        }
     }
 }
 
 #[cfg(feature = "std")]
 impl<T: MemDbgImpl> MemDbgImpl for std::cell::OnceCell<T> {
-    fn _mem_dbg_rec_on(
-        &self,
-        writer: &mut impl core::fmt::Write,
-        total_size: usize,
-        max_depth: usize,
-        prefix: &mut String,
-        is_last: bool,
-        flags: DbgFlags,
-    ) -> core::fmt::Result {
-        self.get()
-            ._mem_dbg_rec_on(writer, total_size, max_depth, prefix, is_last, flags)
+    fn _mem_dbg_rec(&self, node: &mut MemNode, max_depth: usize, flags: DbgFlags) {
+        self.get()._mem_dbg_rec(node, max_depth, flags)
+    }
+}
+
+/// The value is not forced, as forcing might have side effects.
+impl<T: MemDbgImpl, F> MemDbgImpl for core::cell::LazyCell<T, F> {
+    fn _mem_dbg_rec(&self, node: &mut MemNode, max_depth: usize, flags: DbgFlags) {
+        if let Some(x) = core::cell::LazyCell::get(self) {
+            x._mem_dbg_rec(node, max_depth, flags)
+        }
     }
 }
 
 #[cfg(feature = "std")]
 impl<T: MemDbgImpl> MemDbgImpl for std::sync::MutexGuard<'_, T> {
-    fn _mem_dbg_rec_on(
-        &self,
-        writer: &mut impl core::fmt::Write,
-        total_size: usize,
-        max_depth: usize,
-        prefix: &mut String,
-        is_last: bool,
-        flags: DbgFlags,
-    ) -> core::fmt::Result {
+    fn _mem_dbg_rec(&self, node: &mut MemNode, max_depth: usize, flags: DbgFlags) {
         if flags.contains(DbgFlags::FOLLOW_REFS) {
-            self.deref()
-                ._mem_dbg_rec_on(writer, total_size, max_depth, prefix, is_last, flags)
-        } else {
-            Ok(())
+            self.deref()._mem_dbg_rec(node, max_depth, flags)
         }
     }
 }
 
 #[cfg(feature = "std")]
 impl<T: MemDbgImpl> MemDbgImpl for std::sync::RwLockReadGuard<'_, T> {
-    fn _mem_dbg_rec_on(
-        &self,
-        writer: &mut impl core::fmt::Write,
-        total_size: usize,
-        max_depth: usize,
-        prefix: &mut String,
-        is_last: bool,
-        flags: DbgFlags,
-    ) -> core::fmt::Result {
+    fn _mem_dbg_rec(&self, node: &mut MemNode, max_depth: usize, flags: DbgFlags) {
         if flags.contains(DbgFlags::FOLLOW_REFS) {
-            self.deref()
-                ._mem_dbg_rec_on(writer, total_size, max_depth, prefix, is_last, flags)
-        } else {
-            Ok(())
+            self.deref()._mem_dbg_rec(node, max_depth, flags)
         }
     }
 }
 
 #[cfg(feature = "std")]
 impl<T: MemDbgImpl> MemDbgImpl for std::sync::RwLockWriteGuard<'_, T> {
-    fn _mem_dbg_rec_on(
-        &self,
-        writer: &mut impl core::fmt::Write,
-        total_size: usize,
-        max_depth: usize,
-        prefix: &mut String,
-        is_last: bool,
-        flags: DbgFlags,
-    ) -> core::fmt::Result {
+    fn _mem_dbg_rec(&self, node: &mut MemNode, max_depth: usize, flags: DbgFlags) {
         if flags.contains(DbgFlags::FOLLOW_REFS) {
-            self.deref()
-                ._mem_dbg_rec_on(writer, total_size, max_depth, prefix, is_last, flags)
-        } else {
-            Ok(())
+            self.deref()._mem_dbg_rec(node, max_depth, flags)
         }
     }
 }
@@ -524,59 +786,125 @@ impl_mem_dbg!(
     std::fs::Metadata,
     std::fs::FileTimes,
     std::fs::FileType,
-    std::fs::Permissions
+    std::fs::Permissions,
+    std::net::TcpStream,
+    std::net::TcpListener,
+    std::net::UdpSocket
+);
+
+// Time
+
+#[cfg(feature = "std")]
+impl_mem_dbg!(
+    std::time::Duration,
+    std::time::Instant,
+    std::time::SystemTime,
+    std::time::SystemTimeError
 );
 
 // I/O
 
 #[cfg(feature = "std")]
 impl<T: MemDbgImpl + std::io::Read> MemDbgImpl for std::io::BufReader<T> {
-    fn _mem_dbg_rec_on(
-        &self,
-        writer: &mut impl core::fmt::Write,
-        total_size: usize,
-        max_depth: usize,
-        prefix: &mut String,
-        is_last: bool,
-        flags: DbgFlags,
-    ) -> core::fmt::Result {
-        self.get_ref()
-            ._mem_dbg_rec_on(writer, total_size, max_depth, prefix, is_last, flags)
+    fn _mem_dbg_rec(&self, node: &mut MemNode, max_depth: usize, flags: DbgFlags) {
+        self.get_ref()._mem_dbg_rec(node, max_depth, flags)
     }
 }
 
 #[cfg(feature = "std")]
 impl<T: MemDbgImpl + std::io::Write> MemDbgImpl for std::io::BufWriter<T> {
-    fn _mem_dbg_rec_on(
-        &self,
-        writer: &mut impl core::fmt::Write,
-        total_size: usize,
-        max_depth: usize,
-        prefix: &mut String,
-        is_last: bool,
-        flags: DbgFlags,
-    ) -> core::fmt::Result {
-        self.get_ref()
-            ._mem_dbg_rec_on(writer, total_size, max_depth, prefix, is_last, flags)
+    fn _mem_dbg_rec(&self, node: &mut MemNode, max_depth: usize, flags: DbgFlags) {
+        self.get_ref()._mem_dbg_rec(node, max_depth, flags)
     }
 }
 
 #[cfg(feature = "std")]
 impl<T: MemDbgImpl> MemDbgImpl for std::io::Cursor<T> {
-    fn _mem_dbg_rec_on(
-        &self,
-        writer: &mut impl core::fmt::Write,
-        total_size: usize,
-        max_depth: usize,
-        prefix: &mut String,
-        is_last: bool,
-        flags: DbgFlags,
-    ) -> core::fmt::Result {
-        self.get_ref()
-            ._mem_dbg_rec_on(writer, total_size, max_depth, prefix, is_last, flags)
+    fn _mem_dbg_rec(&self, node: &mut MemNode, max_depth: usize, flags: DbgFlags) {
+        self.get_ref()._mem_dbg_rec(node, max_depth, flags)
+    }
+}
+
+#[cfg(feature = "std")]
+impl MemDbgImpl for std::io::IoSlice<'_> {}
+
+#[cfg(feature = "std")]
+impl MemDbgImpl for std::io::IoSliceMut<'_> {}
+
+// Processes
+
+#[cfg(feature = "std")]
+impl_mem_dbg!(std::process::ExitStatus, std::process::ExitCode);
+
+#[cfg(feature = "std")]
+impl MemDbgImpl for std::process::Output {
+    fn _mem_dbg_rec(&self, node: &mut MemNode, max_depth: usize, flags: DbgFlags) {
+        let id_sizes = crate::_padded_sizes(
+            [
+                core::mem::offset_of!(std::process::Output, status),
+                core::mem::offset_of!(std::process::Output, stdout),
+                core::mem::offset_of!(std::process::Output, stderr),
+            ],
+            core::mem::size_of::<Self>(),
+            false,
+        );
+        node.children.push(self.status._mem_dbg_node(
+            "status",
+            id_sizes[0].1,
+            max_depth - 1,
+            flags,
+        ));
+        node.children.push(self.stdout._mem_dbg_node(
+            "stdout",
+            id_sizes[1].1,
+            max_depth - 1,
+            flags,
+        ));
+        node.children.push(self.stderr._mem_dbg_node(
+            "stderr",
+            id_sizes[2].1,
+            max_depth - 1,
+            flags,
+        ));
     }
 }
 
+// Errors
+
+#[cfg(feature = "std")]
+impl MemDbgImpl for dyn std::error::Error {}
+#[cfg(feature = "std")]
+impl MemDbgImpl for dyn std::error::Error + Send {}
+#[cfg(feature = "std")]
+impl MemDbgImpl for dyn std::error::Error + Send + Sync {}
+
+// Closures
+
+macro_rules! impl_closure {
+    ($(($($arg:ident),*)),*) => {$(
+        impl_closure!(@impl [$($arg),*] dyn Fn($($arg),*) -> R + 'a);
+        impl_closure!(@impl [$($arg),*] dyn Fn($($arg),*) -> R + Send + 'a);
+        impl_closure!(@impl [$($arg),*] dyn Fn($($arg),*) -> R + Send + Sync + 'a);
+        impl_closure!(@impl [$($arg),*] dyn FnMut($($arg),*) -> R + 'a);
+        impl_closure!(@impl [$($arg),*] dyn FnMut($($arg),*) -> R + Send + 'a);
+        impl_closure!(@impl [$($arg),*] dyn FnMut($($arg),*) -> R + Send + Sync + 'a);
+        impl_closure!(@impl [$($arg),*] dyn FnOnce($($arg),*) -> R + 'a);
+        impl_closure!(@impl [$($arg),*] dyn FnOnce($($arg),*) -> R + Send + 'a);
+        impl_closure!(@impl [$($arg),*] dyn FnOnce($($arg),*) -> R + Send + Sync + 'a);
+    )*};
+
+    (@impl [$($arg:ident),*] $ty:ty) => {
+        impl<'a, $($arg,)* R> MemDbgImpl for $ty {}
+    };
+}
+
+impl_closure!((), (A), (A, B), (A, B, C), (A, B, C, D));
+
+// anyhow crate
+
+#[cfg(feature = "anyhow")]
+impl_mem_dbg!(anyhow::Error);
+
 // maligned crate
 
 #[cfg(feature = "maligned")]
@@ -594,17 +922,8 @@ impl_mem_dbg!(
 
 #[cfg(feature = "maligned")]
 impl<A: maligned::Alignment, T: MemDbgImpl> MemDbgImpl for maligned::Aligned<A, T> {
-    fn _mem_dbg_rec_on(
-        &self,
-        writer: &mut impl core::fmt::Write,
-        total_size: usize,
-        max_depth: usize,
-        prefix: &mut String,
-        is_last: bool,
-        flags: DbgFlags,
-    ) -> core::fmt::Result {
-        self.deref()
-            ._mem_dbg_rec_on(writer, total_size, max_depth, prefix, is_last, flags)
+    fn _mem_dbg_rec(&self, node: &mut MemNode, max_depth: usize, flags: DbgFlags) {
+        self.deref()._mem_dbg_rec(node, max_depth, flags)
     }
 }
 
@@ -612,3 +931,95 @@ impl<A: maligned::Alignment, T: MemDbgImpl> MemDbgImpl for maligned::Aligned<A,
 
 #[cfg(feature = "half")]
 impl_mem_dbg!(half::f16, half::bf16);
+
+// time crate
+
+#[cfg(feature = "time")]
+impl_mem_dbg!(
+    time::Date,
+    time::Duration,
+    time::OffsetDateTime,
+    time::PrimitiveDateTime,
+    time::Time,
+    time::UtcOffset
+);
+
+// uuid crate
+
+#[cfg(feature = "uuid")]
+impl_mem_dbg!(uuid::Uuid);
+
+// priority-queue crate
+
+#[cfg(feature = "priority-queue")]
+impl<I, P, H> MemDbgImpl for priority_queue::PriorityQueue<I, P, H>
+where
+    I: crate::MemSize + core::hash::Hash + Eq,
+    P: crate::MemSize + Ord,
+    H: crate::MemSize + core::hash::BuildHasher,
+{
+    // the internal structure is private so we cannot recurse
+}
+
+// im crate
+
+#[cfg(feature = "im")]
+impl<A: Clone + crate::MemSize> MemDbgImpl for im::Vector<A> {}
+
+#[cfg(feature = "im")]
+impl<K: crate::MemSize, V: crate::MemSize, S> MemDbgImpl for im::HashMap<K, V, S> where
+    im::HashMap<K, V, S>: crate::MemSize
+{
+}
+
+#[cfg(feature = "im")]
+impl<A: crate::MemSize, S> MemDbgImpl for im::HashSet<A, S> where im::HashSet<A, S>: crate::MemSize {}
+
+#[cfg(feature = "im")]
+impl<K: crate::MemSize, V: crate::MemSize> MemDbgImpl for im::OrdMap<K, V> where
+    im::OrdMap<K, V>: crate::MemSize
+{
+}
+
+#[cfg(feature = "im")]
+impl<A: crate::MemSize> MemDbgImpl for im::OrdSet<A> where im::OrdSet<A>: crate::MemSize {}
+
+// dashmap crate
+
+#[cfg(feature = "dashmap")]
+impl<K, V, S> MemDbgImpl for dashmap::DashMap<K, V, S>
+where
+    K: CopyType + crate::MemSize + Eq + core::hash::Hash,
+    V: CopyType + crate::MemSize,
+    S: core::hash::BuildHasher + Clone,
+{
+    // the shards are locked, so we cannot recurse; moreover, the caller
+    // might be holding a guard on a shard, so we display the length and the
+    // capacity only if no shard is locked
+    fn _mem_dbg_info(&self, flags: DbgFlags) -> Option<String> {
+        let mut len = 0;
+        let mut capacity = 0;
+        for shard in self.shards() {
+            let map = shard.try_read()?;
+            len += map.len();
+            capacity += map.capacity();
+        }
+        len_info(len, Some(capacity), flags)
+    }
+}
+
+// bytes crate
+
+#[cfg(feature = "bytes")]
+impl MemDbgImpl for bytes::Bytes {
+    fn _mem_dbg_info(&self, flags: DbgFlags) -> Option<String> {
+        len_info(self.len(), None, flags)
+    }
+}
+
+#[cfg(feature = "bytes")]
+impl MemDbgImpl for bytes::BytesMut {
+    fn _mem_dbg_info(&self, flags: DbgFlags) -> Option<String> {
+        len_info(self.len(), Some(self.capacity()), flags)
+    }
+}
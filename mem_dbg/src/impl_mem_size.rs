@@ -8,15 +8,164 @@
 
 use core::marker::{PhantomData, PhantomPinned};
 use core::num::*;
-use core::ops::Deref;
+use core::ops::{ControlFlow, Deref};
 use core::sync::atomic::*;
 use std::collections::{HashMap, HashSet};
 
-use crate::{Boolean, CopyType, False, MemSize, SizeFlags, True};
+use crate::allocator::heap_size;
+use crate::{And, Boolean, CopyType, False, MemSize, SizeContext, SizeFlags, True};
+
+/// The types of the standard library implementing [`MemSize`], as they would
+/// be written in code (with the `std` feature enabled).
+///
+/// Single-letter generic parameters stand for types implementing [`MemSize`],
+/// tuples have up to ten elements, and closures take up to four arguments and
+/// might be also [`Send`] or [`Send`] and [`Sync`]. The types supported when
+/// enabling the features for optional crates are listed in the
+/// documentation of the features.
+///
+/// The list is maintained alongside the implementations, and a test checks
+/// that every listed type implements [`MemSize`].
+pub const SUPPORTED_TYPES: &[&str] = &[
+    // Primitive types and strings
+    "()",
+    "bool",
+    "char",
+    "f32",
+    "f64",
+    "u8",
+    "u16",
+    "u32",
+    "u64",
+    "u128",
+    "usize",
+    "i8",
+    "i16",
+    "i32",
+    "i64",
+    "i128",
+    "isize",
+    "str",
+    "String",
+    "core::sync::atomic::AtomicBool",
+    "core::sync::atomic::AtomicI8",
+    "core::sync::atomic::AtomicI16",
+    "core::sync::atomic::AtomicI32",
+    "core::sync::atomic::AtomicI64",
+    "core::sync::atomic::AtomicIsize",
+    "core::sync::atomic::AtomicU8",
+    "core::sync::atomic::AtomicU16",
+    "core::sync::atomic::AtomicU32",
+    "core::sync::atomic::AtomicU64",
+    "core::sync::atomic::AtomicUsize",
+    "core::num::NonZeroI8",
+    "core::num::NonZeroI16",
+    "core::num::NonZeroI32",
+    "core::num::NonZeroI64",
+    "core::num::NonZeroI128",
+    "core::num::NonZeroIsize",
+    "core::num::NonZeroU8",
+    "core::num::NonZeroU16",
+    "core::num::NonZeroU32",
+    "core::num::NonZeroU64",
+    "core::num::NonZeroU128",
+    "core::num::NonZeroUsize",
+    "core::marker::PhantomPinned",
+    "core::convert::Infallible",
+    "core::marker::PhantomData<T>",
+    "core::mem::MaybeUninit<T>",
+    // References and pointers
+    "&T",
+    "&mut T",
+    "Box<T>",
+    "std::rc::Rc<T>",
+    "std::sync::Arc<T>",
+    "core::ptr::NonNull<T>",
+    // Sum types
+    "Option<T>",
+    "Result<T, E>",
+    "core::ops::ControlFlow<B, C>",
+    // Sequences
+    "[T]",
+    "[T; N]",
+    "(T0, ..., T9)",
+    "Vec<T>",
+    "std::collections::VecDeque<T>",
+    "std::collections::BinaryHeap<T>",
+    // Maps and sets
+    "std::collections::HashSet<T>",
+    "std::collections::HashMap<K, V>",
+    "std::collections::BTreeSet<T>",
+    "std::collections::BTreeMap<K, V>",
+    "core::hash::BuildHasherDefault<H>",
+    "std::collections::hash_map::RandomState",
+    // Functions, closures, and errors
+    "fn() -> R",
+    "fn(A) -> R",
+    "fn(A, B) -> R",
+    "fn(A, B, C) -> R",
+    "fn(A, B, C, D) -> R",
+    "dyn Fn(A, ...) -> R",
+    "dyn FnMut(A, ...) -> R",
+    "dyn FnOnce(A, ...) -> R",
+    "dyn std::error::Error",
+    "dyn std::error::Error + Send",
+    "dyn std::error::Error + Send + Sync",
+    // Ranges
+    "core::ops::Range<Idx>",
+    "core::ops::RangeFrom<Idx>",
+    "core::ops::RangeInclusive<Idx>",
+    "core::ops::RangeTo<Idx>",
+    "core::ops::RangeToInclusive<Idx>",
+    // Cells and locks
+    "core::cell::Cell<T>",
+    "core::cell::RefCell<T>",
+    "core::cell::UnsafeCell<T>",
+    "core::cell::OnceCell<T>",
+    "core::cell::LazyCell<T, F>",
+    "std::sync::Mutex<T>",
+    "std::sync::MutexGuard<'_, T>",
+    "std::sync::RwLock<T>",
+    "std::sync::RwLockReadGuard<'_, T>",
+    "std::sync::RwLockWriteGuard<'_, T>",
+    // Memory
+    "core::alloc::Layout",
+    // Operating system
+    "std::path::Path",
+    "std::path::PathBuf",
+    "std::ffi::OsStr",
+    "std::ffi::OsString",
+    "std::fs::File",
+    "std::fs::OpenOptions",
+    "std::fs::Metadata",
+    "std::fs::FileType",
+    "std::fs::FileTimes",
+    "std::fs::Permissions",
+    "std::io::BufReader<T>",
+    "std::io::BufWriter<T>",
+    "std::io::Cursor<T>",
+    "std::io::IoSlice<'_>",
+    "std::io::IoSliceMut<'_>",
+    "std::net::TcpStream",
+    "std::net::TcpListener",
+    "std::net::UdpSocket",
+    "std::net::Ipv4Addr",
+    "std::net::Ipv6Addr",
+    "std::net::IpAddr",
+    "std::net::SocketAddrV4",
+    "std::net::SocketAddrV6",
+    "std::net::SocketAddr",
+    "std::time::Duration",
+    "std::time::Instant",
+    "std::time::SystemTime",
+    "std::time::SystemTimeError",
+    "std::process::ExitStatus",
+    "std::process::ExitCode",
+    "std::process::Output",
+];
 
 /// A basic implementation using [`core::mem::size_of`] for non-[`Copy`] types,
 /// setting [`CopyType::Copy`] to [`False`].
-
 macro_rules! impl_size_of {
     ($($ty:ty),*) => {$(
         impl CopyType for $ty {
@@ -25,8 +174,8 @@ macro_rules! impl_size_of {
 
         impl MemSize for $ty {
             #[inline(always)]
-            fn mem_size(&self, _flags: SizeFlags) -> usize {
-                core::mem::size_of::<Self>()
+            fn _mem_size_rec(&self, _ctx: &mut SizeContext) -> ControlFlow<()> {
+                ControlFlow::Continue(())
             }
         }
     )*};
@@ -34,7 +183,6 @@ macro_rules! impl_size_of {
 
 /// A basic implementation using [`core::mem::size_of`] for [`Copy`] types,
 /// setting [`CopyType::Copy`] to [`True`].
-
 macro_rules! impl_copy_size_of {
     ($($ty:ty),*) => {$(
         impl CopyType for $ty {
@@ -43,8 +191,8 @@ macro_rules! impl_copy_size_of {
 
         impl MemSize for $ty {
             #[inline(always)]
-            fn mem_size(&self, _flags: SizeFlags) -> usize {
-                core::mem::size_of::<Self>()
+            fn _mem_size_rec(&self, _ctx: &mut SizeContext) -> ControlFlow<()> {
+                ControlFlow::Continue(())
             }
         }
     )*};
@@ -59,10 +207,10 @@ impl_copy_size_of! {
    AtomicU8, AtomicU16, AtomicU32, AtomicU64, AtomicUsize,
    NonZeroI8, NonZeroI16, NonZeroI32, NonZeroI64, NonZeroI128, NonZeroIsize,
    NonZeroU8, NonZeroU16, NonZeroU32, NonZeroU64, NonZeroU128, NonZeroUsize,
-   PhantomPinned
+   PhantomPinned, core::convert::Infallible
 }
 
-// Strings
+// Strings: a str is a slice of bytes, so its size is its length
 
 impl CopyType for str {
     type Copy = False;
@@ -70,8 +218,8 @@ impl CopyType for str {
 
 impl MemSize for str {
     #[inline(always)]
-    fn mem_size(&self, _flags: SizeFlags) -> usize {
-        core::mem::size_of::<usize>() + self.len()
+    fn _mem_size_rec(&self, _ctx: &mut SizeContext) -> ControlFlow<()> {
+        ControlFlow::Continue(())
     }
 }
 
@@ -81,12 +229,12 @@ impl CopyType for String {
 
 impl MemSize for String {
     #[inline(always)]
-    fn mem_size(&self, flags: SizeFlags) -> usize {
-        if flags.contains(SizeFlags::CAPACITY) {
-            core::mem::size_of::<Self>() + self.capacity()
-        } else {
-            core::mem::size_of::<Self>() + self.len()
-        }
+    fn _mem_size_rec(&self, ctx: &mut SizeContext) -> ControlFlow<()> {
+        ctx.add_allocations((self.capacity() != 0) as usize);
+        ctx.add_heap(
+            self.len(),
+            heap_size(self.as_ptr(), self.capacity(), 1, ctx.flags()),
+        )
     }
 }
 
@@ -98,12 +246,26 @@ impl<T> CopyType for PhantomData<T> {
 
 impl<T: ?Sized> MemSize for PhantomData<T> {
     #[inline(always)]
-    fn mem_size(&self, _flags: SizeFlags) -> usize {
-        0
+    fn _mem_size_rec(&self, _ctx: &mut SizeContext) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+}
+
+// MaybeUninit: the content might be uninitialized, so we never look at it
+
+impl<T> CopyType for core::mem::MaybeUninit<T> {
+    type Copy = True;
+}
+
+impl<T> MemSize for core::mem::MaybeUninit<T> {
+    #[inline(always)]
+    fn _mem_size_rec(&self, _ctx: &mut SizeContext) -> ControlFlow<()> {
+        ControlFlow::Continue(())
     }
 }
 
-// References: we recurse only if FOLLOW_REFS is set
+// References: we recurse only if FOLLOW_REFS is set, in which case the
+// referenced value is owned memory
 
 impl<T: ?Sized + MemSize> CopyType for &'_ T {
     type Copy = False;
@@ -111,11 +273,13 @@ impl<T: ?Sized + MemSize> CopyType for &'_ T {
 
 impl<T: ?Sized + MemSize> MemSize for &'_ T {
     #[inline(always)]
-    fn mem_size(&self, flags: SizeFlags) -> usize {
-        if flags.contains(SizeFlags::FOLLOW_REFS) {
-            core::mem::size_of::<Self>() + <T as MemSize>::mem_size(*self, flags)
+    fn _mem_size_rec(&self, ctx: &mut SizeContext) -> ControlFlow<()> {
+        if ctx.flags().contains(SizeFlags::FOLLOW_REFS) {
+            let size = core::mem::size_of_val::<T>(*self);
+            ctx.add_heap(size, size)?;
+            <T as MemSize>::_mem_size_rec(*self, ctx)
         } else {
-            core::mem::size_of::<Self>()
+            ControlFlow::Continue(())
         }
     }
 }
@@ -126,8 +290,8 @@ impl<T: ?Sized + MemSize> CopyType for &'_ mut T {
 
 impl<T: ?Sized + MemSize> MemSize for &'_ mut T {
     #[inline(always)]
-    fn mem_size(&self, flags: SizeFlags) -> usize {
-        <&'_ T as MemSize>::mem_size(&&**self, flags)
+    fn _mem_size_rec(&self, ctx: &mut SizeContext) -> ControlFlow<()> {
+        <&'_ T as MemSize>::_mem_size_rec(&&**self, ctx)
     }
 }
 
@@ -139,11 +303,49 @@ impl<T: CopyType + MemSize> CopyType for Option<T> {
 
 impl<T: MemSize> MemSize for Option<T> {
     #[inline(always)]
-    fn mem_size(&self, flags: SizeFlags) -> usize {
-        core::mem::size_of::<Self>()
-            + self.as_ref().map_or(0, |x| {
-                <T as MemSize>::mem_size(x, flags) - core::mem::size_of::<T>()
-            })
+    fn _mem_size_rec(&self, ctx: &mut SizeContext) -> ControlFlow<()> {
+        match self {
+            Some(x) => <T as MemSize>::_mem_size_rec(x, ctx),
+            None => ControlFlow::Continue(()),
+        }
+    }
+}
+
+// Result
+
+impl<T: CopyType, E: CopyType> CopyType for Result<T, E>
+where
+    T::Copy: And<E::Copy>,
+{
+    type Copy = <T::Copy as And<E::Copy>>::Output;
+}
+
+impl<T: MemSize, E: MemSize> MemSize for Result<T, E> {
+    #[inline(always)]
+    fn _mem_size_rec(&self, ctx: &mut SizeContext) -> ControlFlow<()> {
+        match self {
+            Ok(x) => <T as MemSize>::_mem_size_rec(x, ctx),
+            Err(e) => <E as MemSize>::_mem_size_rec(e, ctx),
+        }
+    }
+}
+
+// ControlFlow
+
+impl<B: CopyType, C: CopyType> CopyType for ControlFlow<B, C>
+where
+    B::Copy: And<C::Copy>,
+{
+    type Copy = <B::Copy as And<C::Copy>>::Output;
+}
+
+impl<B: MemSize, C: MemSize> MemSize for ControlFlow<B, C> {
+    #[inline(always)]
+    fn _mem_size_rec(&self, ctx: &mut SizeContext) -> ControlFlow<()> {
+        match self {
+            ControlFlow::Continue(c) => <C as MemSize>::_mem_size_rec(c, ctx),
+            ControlFlow::Break(b) => <B as MemSize>::_mem_size_rec(b, ctx),
+        }
     }
 }
 
@@ -151,11 +353,73 @@ impl<T: MemSize> MemSize for Option<T> {
 
 #[cfg(all(feature = "alloc", not(feature = "std")))]
 use alloc::boxed::Box;
+#[cfg(feature = "alloc")]
+impl<T: ?Sized> CopyType for Box<T> {
+    type Copy = False;
+}
+
 #[cfg(feature = "alloc")]
 impl<T: ?Sized + MemSize> MemSize for Box<T> {
     #[inline(always)]
-    fn mem_size(&self, flags: SizeFlags) -> usize {
-        core::mem::size_of::<Self>() + <T as MemSize>::mem_size(self.as_ref(), flags)
+    fn _mem_size_rec(&self, ctx: &mut SizeContext) -> ControlFlow<()> {
+        // The value is on the heap, so its stack size is the size of the
+        // block; boxes of zero-sized values do not allocate
+        let value = self.as_ref();
+        let value_size = core::mem::size_of_val::<T>(value);
+        ctx.add_allocations((value_size != 0) as usize);
+        ctx.add_heap(
+            value_size,
+            heap_size(
+                value as *const T as *const u8,
+                value_size,
+                core::mem::align_of_val::<T>(value),
+                ctx.flags(),
+            ),
+        )?;
+        <T as MemSize>::_mem_size_rec(value, ctx)
+    }
+}
+
+// Reference-counted pointers: unless FOLLOW_RCS is set, we count the memory
+// owned by the content, but not the allocation containing it. In both cases,
+// we record the addresses of the allocations we visit, so that cycles are
+// followed just once.
+
+/// Returns the size of the allocation of an [`Rc`] or an [`Arc`] containing
+/// `value`, which starts with the strong and weak counters.
+fn rc_inner_size<T: ?Sized>(value: &T) -> usize {
+    core::alloc::Layout::new::<[usize; 2]>()
+        .extend(core::alloc::Layout::for_value(value))
+        .unwrap()
+        .0
+        .pad_to_align()
+        .size()
+}
+
+/// Visits a reference-counted pointer to `value`, which is stored at address
+/// `ptr`.
+///
+/// If the allocation has already been visited (with
+/// [`FOLLOW_RCS`](SizeFlags::FOLLOW_RCS)) or it is being visited (that is, we
+/// are following a cycle), nothing is reported.
+fn rc_mem_size_rec<T: ?Sized + MemSize>(
+    ptr: *const T,
+    value: &T,
+    ctx: &mut SizeContext,
+) -> ControlFlow<()> {
+    let addr = ptr as *const u8 as usize;
+    if !ctx.visit(addr) {
+        return ControlFlow::Continue(());
+    }
+    if ctx.flags().contains(SizeFlags::FOLLOW_RCS) {
+        let size = rc_inner_size(value);
+        ctx.add_allocations(1);
+        ctx.add_heap(size, size)?;
+        <T as MemSize>::_mem_size_rec(value, ctx)
+    } else {
+        <T as MemSize>::_mem_size_rec(value, ctx)?;
+        ctx.leave(addr);
+        ControlFlow::Continue(())
     }
 }
 
@@ -164,11 +428,32 @@ use alloc::sync::Arc;
 #[cfg(feature = "std")]
 use std::sync::Arc;
 #[cfg(feature = "alloc")]
-impl<T: MemSize> MemSize for Arc<T> {
+impl<T: ?Sized> CopyType for Arc<T> {
+    type Copy = False;
+}
+
+#[cfg(feature = "alloc")]
+impl<T: ?Sized + MemSize> MemSize for Arc<T> {
+    #[inline(always)]
+    fn _mem_size_rec(&self, ctx: &mut SizeContext) -> ControlFlow<()> {
+        rc_mem_size_rec(Arc::as_ptr(self), self.as_ref(), ctx)
+    }
+}
+
+#[cfg(all(feature = "alloc", not(feature = "std")))]
+use alloc::rc::Rc;
+#[cfg(feature = "std")]
+use std::rc::Rc;
+#[cfg(feature = "alloc")]
+impl<T: ?Sized> CopyType for Rc<T> {
+    type Copy = False;
+}
+
+#[cfg(feature = "alloc")]
+impl<T: ?Sized + MemSize> MemSize for Rc<T> {
     #[inline(always)]
-    fn mem_size(&self, flags: SizeFlags) -> usize {
-        core::mem::size_of::<Self>() - core::mem::size_of::<T>()
-            + <T as MemSize>::mem_size(self.as_ref(), flags)
+    fn _mem_size_rec(&self, ctx: &mut SizeContext) -> ControlFlow<()> {
+        rc_mem_size_rec(Rc::as_ptr(self), self.as_ref(), ctx)
     }
 }
 
@@ -178,7 +463,7 @@ impl<T: MemSize> MemSize for Arc<T> {
 ///
 /// See [`crate::CopyType`] for more information.
 pub trait MemSizeHelper<T: Boolean> {
-    fn mem_size_impl(&self, flags: SizeFlags) -> usize;
+    fn mem_size_impl(&self, ctx: &mut SizeContext) -> ControlFlow<()>;
 }
 
 // Slices
@@ -188,30 +473,23 @@ where
     [T]: MemSizeHelper<<T as CopyType>::Copy>,
 {
     #[inline(always)]
-    fn mem_size(&self, flags: SizeFlags) -> usize {
-        <[T] as MemSizeHelper<<T as CopyType>::Copy>>::mem_size_impl(self, flags)
+    fn _mem_size_rec(&self, ctx: &mut SizeContext) -> ControlFlow<()> {
+        <[T] as MemSizeHelper<<T as CopyType>::Copy>>::mem_size_impl(self, ctx)
     }
 }
 
-#[cfg(all(feature = "alloc", not(feature = "std")))]
-use alloc::vec::Vec;
-#[cfg(feature = "alloc")]
 impl<T: CopyType + MemSize> MemSizeHelper<True> for [T] {
     #[inline(always)]
-    fn mem_size_impl(&self, _flags: SizeFlags) -> usize {
-        std::mem::size_of_val(self)
+    fn mem_size_impl(&self, _ctx: &mut SizeContext) -> ControlFlow<()> {
+        ControlFlow::Continue(())
     }
 }
 
-#[cfg(all(feature = "alloc", not(feature = "std")))]
-use alloc::vec::Vec;
-#[cfg(feature = "alloc")]
 impl<T: CopyType + MemSize> MemSizeHelper<False> for [T] {
     #[inline(always)]
-    fn mem_size_impl(&self, flags: SizeFlags) -> usize {
+    fn mem_size_impl(&self, ctx: &mut SizeContext) -> ControlFlow<()> {
         self.iter()
-            .map(|x| <T as MemSize>::mem_size(x, flags))
-            .sum::<usize>()
+            .try_for_each(|x| <T as MemSize>::_mem_size_rec(x, ctx))
     }
 }
 
@@ -226,30 +504,70 @@ where
     [T; N]: MemSizeHelper<<T as CopyType>::Copy>,
 {
     #[inline(always)]
-    fn mem_size(&self, flags: SizeFlags) -> usize {
-        <[T; N] as MemSizeHelper<<T as CopyType>::Copy>>::mem_size_impl(self, flags)
+    fn _mem_size_rec(&self, ctx: &mut SizeContext) -> ControlFlow<()> {
+        <[T; N] as MemSizeHelper<<T as CopyType>::Copy>>::mem_size_impl(self, ctx)
     }
 }
 
 impl<T: MemSize, const N: usize> MemSizeHelper<True> for [T; N] {
     #[inline(always)]
-    fn mem_size_impl(&self, _flags: SizeFlags) -> usize {
-        core::mem::size_of::<Self>()
+    fn mem_size_impl(&self, _ctx: &mut SizeContext) -> ControlFlow<()> {
+        ControlFlow::Continue(())
     }
 }
 
 impl<T: MemSize, const N: usize> MemSizeHelper<False> for [T; N] {
     #[inline(always)]
-    fn mem_size_impl(&self, flags: SizeFlags) -> usize {
-        core::mem::size_of::<Self>()
-            + self
-                .iter()
-                .map(|x| <T as MemSize>::mem_size(x, flags) - core::mem::size_of::<T>())
-                .sum::<usize>()
+    fn mem_size_impl(&self, ctx: &mut SizeContext) -> ControlFlow<()> {
+        self.iter()
+            .try_for_each(|x| <T as MemSize>::_mem_size_rec(x, ctx))
+    }
+}
+
+// Vectors: vectors of zero-sized elements never allocate, and their
+// capacity is usize::MAX, so we compute the size of their buffer explicitly
+// rather than relying on the product of the capacity by the size of the
+// elements being zero.
+
+/// Returns the size in bytes of the buffer of a vector or double-ended queue
+/// of `T` containing `capacity` elements.
+#[inline(always)]
+fn vec_buffer_size<T>(capacity: usize) -> usize {
+    if core::mem::size_of::<T>() == 0 {
+        0
+    } else {
+        capacity * core::mem::size_of::<T>()
     }
 }
 
-// Vectors
+/// Returns the used and allocated size in bytes of the buffer of a vector.
+pub(crate) fn vec_heap<T>(vec: &Vec<T>, flags: SizeFlags) -> (usize, usize) {
+    (
+        vec.len() * core::mem::size_of::<T>(),
+        heap_size(
+            vec.as_ptr() as *const u8,
+            vec_buffer_size::<T>(vec.capacity()),
+            core::mem::align_of::<T>(),
+            flags,
+        ),
+    )
+}
+
+/// Returns the number of allocations of the buffer of a vector of `T` with
+/// capacity `capacity`: vectors do not allocate if they are empty or if `T`
+/// is zero-sized.
+pub(crate) fn vec_allocations<T>(capacity: usize) -> usize {
+    (capacity != 0 && core::mem::size_of::<T>() != 0) as usize
+}
+
+/// Reports the buffer of a vector, whose elements are then visited by the
+/// caller, if needed.
+#[inline(always)]
+fn vec_buffer_rec<T>(vec: &Vec<T>, ctx: &mut SizeContext) -> ControlFlow<()> {
+    let (used, capacity) = vec_heap(vec, ctx.flags());
+    ctx.add_allocations(vec_allocations::<T>(vec.capacity()));
+    ctx.add_heap(used, capacity)
+}
 
 impl<T> CopyType for Vec<T> {
     type Copy = False;
@@ -260,8 +578,8 @@ where
     Vec<T>: MemSizeHelper<<T as CopyType>::Copy>,
 {
     #[inline(always)]
-    fn mem_size(&self, flags: SizeFlags) -> usize {
-        <Vec<T> as MemSizeHelper<<T as CopyType>::Copy>>::mem_size_impl(self, flags)
+    fn _mem_size_rec(&self, ctx: &mut SizeContext) -> ControlFlow<()> {
+        <Vec<T> as MemSizeHelper<<T as CopyType>::Copy>>::mem_size_impl(self, ctx)
     }
 }
 
@@ -270,35 +588,109 @@ use alloc::vec::Vec;
 #[cfg(feature = "alloc")]
 impl<T: CopyType + MemSize> MemSizeHelper<True> for Vec<T> {
     #[inline(always)]
-    fn mem_size_impl(&self, flags: SizeFlags) -> usize {
-        if flags.contains(SizeFlags::CAPACITY) {
-            core::mem::size_of::<Self>() + self.capacity() * core::mem::size_of::<T>()
-        } else {
-            core::mem::size_of::<Self>() + self.len() * core::mem::size_of::<T>()
-        }
+    fn mem_size_impl(&self, ctx: &mut SizeContext) -> ControlFlow<()> {
+        vec_buffer_rec(self, ctx)
     }
 }
 
-#[cfg(all(feature = "alloc", not(feature = "std")))]
-use alloc::vec::Vec;
 #[cfg(feature = "alloc")]
 impl<T: CopyType + MemSize> MemSizeHelper<False> for Vec<T> {
     #[inline(always)]
-    fn mem_size_impl(&self, flags: SizeFlags) -> usize {
-        if flags.contains(SizeFlags::CAPACITY) {
-            core::mem::size_of::<Self>()
-                + self
-                    .iter()
-                    .map(|x| <T as MemSize>::mem_size(x, flags))
-                    .sum::<usize>()
-                + (self.capacity() - self.len()) * core::mem::size_of::<T>()
-        } else {
-            core::mem::size_of::<Self>()
-                + self
-                    .iter()
-                    .map(|x| <T as MemSize>::mem_size(x, flags))
-                    .sum::<usize>()
-        }
+    fn mem_size_impl(&self, ctx: &mut SizeContext) -> ControlFlow<()> {
+        vec_buffer_rec(self, ctx)?;
+        self.iter()
+            .try_for_each(|x| <T as MemSize>::_mem_size_rec(x, ctx))
+    }
+}
+
+// Double-ended queues: the ring buffer might wrap around, but its capacity
+// is the capacity of the backing buffer, so the slack is the same as for
+// vectors.
+
+#[cfg(all(feature = "alloc", not(feature = "std")))]
+use alloc::collections::VecDeque;
+#[cfg(feature = "std")]
+use std::collections::VecDeque;
+
+/// Reports the ring buffer of a double-ended queue, whose elements are then
+/// visited by the caller, if needed.
+#[cfg(feature = "alloc")]
+#[inline(always)]
+fn vec_deque_buffer_rec<T>(deque: &VecDeque<T>, ctx: &mut SizeContext) -> ControlFlow<()> {
+    // The elements might wrap around the end of the ring buffer
+    let (front, back) = deque.as_slices();
+    ctx.add_allocations(vec_allocations::<T>(deque.capacity()));
+    ctx.add_heap(
+        core::mem::size_of_val(front) + core::mem::size_of_val(back),
+        vec_buffer_size::<T>(deque.capacity()),
+    )
+}
+
+#[cfg(feature = "alloc")]
+impl<T> CopyType for VecDeque<T> {
+    type Copy = False;
+}
+
+#[cfg(feature = "alloc")]
+impl<T: CopyType> MemSize for VecDeque<T>
+where
+    VecDeque<T>: MemSizeHelper<<T as CopyType>::Copy>,
+{
+    #[inline(always)]
+    fn _mem_size_rec(&self, ctx: &mut SizeContext) -> ControlFlow<()> {
+        <VecDeque<T> as MemSizeHelper<<T as CopyType>::Copy>>::mem_size_impl(self, ctx)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<T: CopyType + MemSize> MemSizeHelper<True> for VecDeque<T> {
+    #[inline(always)]
+    fn mem_size_impl(&self, ctx: &mut SizeContext) -> ControlFlow<()> {
+        vec_deque_buffer_rec(self, ctx)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<T: CopyType + MemSize> MemSizeHelper<False> for VecDeque<T> {
+    #[inline(always)]
+    fn mem_size_impl(&self, ctx: &mut SizeContext) -> ControlFlow<()> {
+        vec_deque_buffer_rec(self, ctx)?;
+        self.iter()
+            .try_for_each(|x| <T as MemSize>::_mem_size_rec(x, ctx))
+    }
+}
+
+// Binary heaps: the heap is stored in a vector, whose elements we visit in
+// arbitrary order without rearranging them
+
+#[cfg(all(feature = "alloc", not(feature = "std")))]
+use alloc::collections::BinaryHeap;
+#[cfg(feature = "std")]
+use std::collections::BinaryHeap;
+
+#[cfg(feature = "alloc")]
+impl<T> CopyType for BinaryHeap<T> {
+    type Copy = False;
+}
+
+#[cfg(feature = "alloc")]
+impl<T> MemSize for BinaryHeap<T>
+where
+    [T]: MemSize,
+{
+    fn _mem_size_rec(&self, ctx: &mut SizeContext) -> ControlFlow<()> {
+        let slice = self.as_slice();
+        ctx.add_allocations(vec_allocations::<T>(self.capacity()));
+        ctx.add_heap(
+            core::mem::size_of_val(slice),
+            heap_size(
+                slice.as_ptr() as *const u8,
+                vec_buffer_size::<T>(self.capacity()),
+                core::mem::align_of::<T>(),
+                ctx.flags(),
+            ),
+        )?;
+        <[T] as MemSize>::_mem_size_rec(slice, ctx)
     }
 }
 
@@ -328,11 +720,10 @@ macro_rules! impl_tuples_muncher {
 		impl<$ty: MemSize, $($nty: MemSize,)*> MemSize for ($ty, $($nty,)*)
         {
             #[inline(always)]
-            fn mem_size(&self, flags: SizeFlags) -> usize {
-                let mut bytes = core::mem::size_of::<Self>();
-                bytes += <$ty as MemSize>::mem_size(&self.$idx, flags) - core::mem::size_of::<$ty>();
-                $( bytes += <$nty as MemSize>::mem_size(&self.$nidx, flags) - core::mem::size_of::<$nty>(); )*
-                bytes
+            fn _mem_size_rec(&self, ctx: &mut SizeContext) -> ControlFlow<()> {
+                <$ty as MemSize>::_mem_size_rec(&self.$idx, ctx)?;
+                $( <$nty as MemSize>::_mem_size_rec(&self.$nidx, ctx)?; )*
+                ControlFlow::Continue(())
             }
         }
     }
@@ -360,8 +751,8 @@ impl<R> CopyType for fn() -> R {
 
 impl<R> MemSize for fn() -> R {
     #[inline(always)]
-    fn mem_size(&self, _flags: SizeFlags) -> usize {
-        core::mem::size_of::<Self>()
+    fn _mem_size_rec(&self, _ctx: &mut SizeContext) -> ControlFlow<()> {
+        ControlFlow::Continue(())
     }
 }
 
@@ -371,8 +762,8 @@ impl<A, R> CopyType for fn(A) -> R {
 
 impl<A, R> MemSize for fn(A) -> R {
     #[inline(always)]
-    fn mem_size(&self, _flags: SizeFlags) -> usize {
-        core::mem::size_of::<Self>()
+    fn _mem_size_rec(&self, _ctx: &mut SizeContext) -> ControlFlow<()> {
+        ControlFlow::Continue(())
     }
 }
 
@@ -382,8 +773,8 @@ impl<A, B, R> CopyType for fn(A, B) -> R {
 
 impl<A, B, R> MemSize for fn(A, B) -> R {
     #[inline(always)]
-    fn mem_size(&self, _flags: SizeFlags) -> usize {
-        core::mem::size_of::<Self>()
+    fn _mem_size_rec(&self, _ctx: &mut SizeContext) -> ControlFlow<()> {
+        ControlFlow::Continue(())
     }
 }
 
@@ -393,8 +784,8 @@ impl<A, B, C, R> CopyType for fn(A, B, C) -> R {
 
 impl<A, B, C, R> MemSize for fn(A, B, C) -> R {
     #[inline(always)]
-    fn mem_size(&self, _flags: SizeFlags) -> usize {
-        core::mem::size_of::<Self>()
+    fn _mem_size_rec(&self, _ctx: &mut SizeContext) -> ControlFlow<()> {
+        ControlFlow::Continue(())
     }
 }
 
@@ -404,8 +795,8 @@ impl<A, B, C, D, R> CopyType for fn(A, B, C, D) -> R {
 
 impl<A, B, C, D, R> MemSize for fn(A, B, C, D) -> R {
     #[inline(always)]
-    fn mem_size(&self, _flags: SizeFlags) -> usize {
-        core::mem::size_of::<Self>()
+    fn _mem_size_rec(&self, _ctx: &mut SizeContext) -> ControlFlow<()> {
+        ControlFlow::Continue(())
     }
 }
 
@@ -417,11 +808,9 @@ impl<Idx: CopyType> CopyType for core::ops::Range<Idx> {
 
 impl<Idx: MemSize> MemSize for core::ops::Range<Idx> {
     #[inline(always)]
-    fn mem_size(&self, flags: SizeFlags) -> usize {
-        core::mem::size_of::<Self>()
-            + <Idx as MemSize>::mem_size(&self.start, flags)
-            + <Idx as MemSize>::mem_size(&self.end, flags)
-            - 2 * core::mem::size_of::<Idx>()
+    fn _mem_size_rec(&self, ctx: &mut SizeContext) -> ControlFlow<()> {
+        <Idx as MemSize>::_mem_size_rec(&self.start, ctx)?;
+        <Idx as MemSize>::_mem_size_rec(&self.end, ctx)
     }
 }
 
@@ -431,9 +820,8 @@ impl<Idx: CopyType> CopyType for core::ops::RangeFrom<Idx> {
 
 impl<Idx: MemSize> MemSize for core::ops::RangeFrom<Idx> {
     #[inline(always)]
-    fn mem_size(&self, flags: SizeFlags) -> usize {
-        core::mem::size_of::<Self>() + <Idx as MemSize>::mem_size(&self.start, flags)
-            - core::mem::size_of::<Idx>()
+    fn _mem_size_rec(&self, ctx: &mut SizeContext) -> ControlFlow<()> {
+        <Idx as MemSize>::_mem_size_rec(&self.start, ctx)
     }
 }
 
@@ -443,11 +831,9 @@ impl<Idx: CopyType> CopyType for core::ops::RangeInclusive<Idx> {
 
 impl<Idx: MemSize> MemSize for core::ops::RangeInclusive<Idx> {
     #[inline(always)]
-    fn mem_size(&self, flags: SizeFlags) -> usize {
-        core::mem::size_of::<Self>()
-            + <Idx as MemSize>::mem_size(self.start(), flags)
-            + <Idx as MemSize>::mem_size(self.end(), flags)
-            - 2 * core::mem::size_of::<Idx>()
+    fn _mem_size_rec(&self, ctx: &mut SizeContext) -> ControlFlow<()> {
+        <Idx as MemSize>::_mem_size_rec(self.start(), ctx)?;
+        <Idx as MemSize>::_mem_size_rec(self.end(), ctx)
     }
 }
 
@@ -457,9 +843,8 @@ impl<Idx: CopyType> CopyType for core::ops::RangeTo<Idx> {
 
 impl<Idx: MemSize> MemSize for core::ops::RangeTo<Idx> {
     #[inline(always)]
-    fn mem_size(&self, flags: SizeFlags) -> usize {
-        core::mem::size_of::<Self>() + <Idx as MemSize>::mem_size(&self.end, flags)
-            - core::mem::size_of::<Idx>()
+    fn _mem_size_rec(&self, ctx: &mut SizeContext) -> ControlFlow<()> {
+        <Idx as MemSize>::_mem_size_rec(&self.end, ctx)
     }
 }
 
@@ -469,9 +854,8 @@ impl<Idx: CopyType> CopyType for core::ops::RangeToInclusive<Idx> {
 
 impl<Idx: MemSize> MemSize for core::ops::RangeToInclusive<Idx> {
     #[inline(always)]
-    fn mem_size(&self, flags: SizeFlags) -> usize {
-        core::mem::size_of::<Self>() + <Idx as MemSize>::mem_size(&self.end, flags)
-            - core::mem::size_of::<Idx>()
+    fn _mem_size_rec(&self, ctx: &mut SizeContext) -> ControlFlow<()> {
+        <Idx as MemSize>::_mem_size_rec(&self.end, ctx)
     }
 }
 
@@ -491,9 +875,8 @@ impl<T: CopyType> CopyType for core::cell::RefCell<T> {
 }
 
 impl<T: MemSize> MemSize for core::cell::RefCell<T> {
-    fn mem_size(&self, flags: SizeFlags) -> usize {
-        core::mem::size_of::<Self>() - core::mem::size_of::<T>()
-            + <T as MemSize>::mem_size(&self.borrow(), flags)
+    fn _mem_size_rec(&self, ctx: &mut SizeContext) -> ControlFlow<()> {
+        <T as MemSize>::_mem_size_rec(&self.borrow(), ctx)
     }
 }
 
@@ -502,9 +885,8 @@ impl<T: CopyType> CopyType for core::cell::Cell<T> {
 }
 
 impl<T: MemSize> MemSize for core::cell::Cell<T> {
-    fn mem_size(&self, flags: SizeFlags) -> usize {
-        core::mem::size_of::<Self>() - core::mem::size_of::<T>()
-            + unsafe { <T as MemSize>::mem_size(&*self.as_ptr(), flags) }
+    fn _mem_size_rec(&self, ctx: &mut SizeContext) -> ControlFlow<()> {
+        unsafe { <T as MemSize>::_mem_size_rec(&*self.as_ptr(), ctx) }
     }
 }
 
@@ -513,9 +895,27 @@ impl<T: CopyType> CopyType for core::cell::OnceCell<T> {
 }
 
 impl<T: MemSize> MemSize for core::cell::OnceCell<T> {
-    fn mem_size(&self, flags: SizeFlags) -> usize {
-        core::mem::size_of::<Self>() - core::mem::size_of::<T>()
-            + <Option<&T> as MemSize>::mem_size(&self.get(), flags)
+    fn _mem_size_rec(&self, ctx: &mut SizeContext) -> ControlFlow<()> {
+        match self.get() {
+            Some(x) => <T as MemSize>::_mem_size_rec(x, ctx),
+            None => ControlFlow::Continue(()),
+        }
+    }
+}
+
+impl<T: CopyType, F> CopyType for core::cell::LazyCell<T, F> {
+    type Copy = T::Copy;
+}
+
+/// The value is not forced, as forcing might have side effects: if the value
+/// has not been initialized, only the stack size is returned. The state
+/// captured by the initialization function is not sized.
+impl<T: MemSize, F> MemSize for core::cell::LazyCell<T, F> {
+    fn _mem_size_rec(&self, ctx: &mut SizeContext) -> ControlFlow<()> {
+        match core::cell::LazyCell::get(self) {
+            Some(x) => <T as MemSize>::_mem_size_rec(x, ctx),
+            None => ControlFlow::Continue(()),
+        }
     }
 }
 
@@ -524,13 +924,13 @@ impl<T: CopyType> CopyType for core::cell::UnsafeCell<T> {
 }
 
 impl<T: MemSize> MemSize for core::cell::UnsafeCell<T> {
-    fn mem_size(&self, flags: SizeFlags) -> usize {
-        core::mem::size_of::<Self>() - core::mem::size_of::<T>()
-            + unsafe { <T as MemSize>::mem_size(&*self.get(), flags) }
+    fn _mem_size_rec(&self, ctx: &mut SizeContext) -> ControlFlow<()> {
+        unsafe { <T as MemSize>::_mem_size_rec(&*self.get(), ctx) }
     }
 }
 
-// Mutexes
+// Mutexes: we use try_lock and try_read, as the caller might be holding the
+// lock, and if the lock is held or poisoned we count just the stack size
 
 #[cfg(feature = "std")]
 impl<T: CopyType> CopyType for std::sync::Mutex<T> {
@@ -539,9 +939,11 @@ impl<T: CopyType> CopyType for std::sync::Mutex<T> {
 
 #[cfg(feature = "std")]
 impl<T: MemSize> MemSize for std::sync::Mutex<T> {
-    fn mem_size(&self, flags: SizeFlags) -> usize {
-        core::mem::size_of::<Self>() - core::mem::size_of::<T>()
-            + <T as MemSize>::mem_size(&self.lock().unwrap(), flags)
+    fn _mem_size_rec(&self, ctx: &mut SizeContext) -> ControlFlow<()> {
+        match self.try_lock() {
+            Ok(x) => <T as MemSize>::_mem_size_rec(&x, ctx),
+            Err(_) => ControlFlow::Continue(()),
+        }
     }
 }
 
@@ -552,9 +954,11 @@ impl<T: CopyType> CopyType for std::sync::RwLock<T> {
 
 #[cfg(feature = "std")]
 impl<T: MemSize> MemSize for std::sync::RwLock<T> {
-    fn mem_size(&self, flags: SizeFlags) -> usize {
-        core::mem::size_of::<Self>() - core::mem::size_of::<T>()
-            + <T as MemSize>::mem_size(&self.read().unwrap(), flags)
+    fn _mem_size_rec(&self, ctx: &mut SizeContext) -> ControlFlow<()> {
+        match self.try_read() {
+            Ok(x) => <T as MemSize>::_mem_size_rec(&x, ctx),
+            Err(_) => ControlFlow::Continue(()),
+        }
     }
 }
 
@@ -565,12 +969,11 @@ impl<T: CopyType> CopyType for std::sync::MutexGuard<'_, T> {
 
 #[cfg(feature = "std")]
 impl<T: MemSize> MemSize for std::sync::MutexGuard<'_, T> {
-    fn mem_size(&self, flags: SizeFlags) -> usize {
-        if flags.contains(SizeFlags::FOLLOW_REFS) {
-            core::mem::size_of::<Self>() - core::mem::size_of::<T>()
-                + <T as MemSize>::mem_size(self.deref(), flags)
+    fn _mem_size_rec(&self, ctx: &mut SizeContext) -> ControlFlow<()> {
+        if ctx.flags().contains(SizeFlags::FOLLOW_REFS) {
+            <T as MemSize>::_mem_size_rec(self.deref(), ctx)
         } else {
-            0
+            ControlFlow::Continue(())
         }
     }
 }
@@ -582,12 +985,11 @@ impl<T: CopyType> CopyType for std::sync::RwLockReadGuard<'_, T> {
 
 #[cfg(feature = "std")]
 impl<T: MemSize> MemSize for std::sync::RwLockReadGuard<'_, T> {
-    fn mem_size(&self, flags: SizeFlags) -> usize {
-        if flags.contains(SizeFlags::FOLLOW_REFS) {
-            core::mem::size_of::<Self>() - core::mem::size_of::<T>()
-                + <T as MemSize>::mem_size(self.deref(), flags)
+    fn _mem_size_rec(&self, ctx: &mut SizeContext) -> ControlFlow<()> {
+        if ctx.flags().contains(SizeFlags::FOLLOW_REFS) {
+            <T as MemSize>::_mem_size_rec(self.deref(), ctx)
         } else {
-            0
+            ControlFlow::Continue(())
         }
     }
 }
@@ -599,12 +1001,11 @@ impl<T: CopyType> CopyType for std::sync::RwLockWriteGuard<'_, T> {
 
 #[cfg(feature = "std")]
 impl<T: MemSize> MemSize for std::sync::RwLockWriteGuard<'_, T> {
-    fn mem_size(&self, flags: SizeFlags) -> usize {
-        if flags.contains(SizeFlags::FOLLOW_REFS) {
-            core::mem::size_of::<Self>() - core::mem::size_of::<T>()
-                + <T as MemSize>::mem_size(self.deref(), flags)
+    fn _mem_size_rec(&self, ctx: &mut SizeContext) -> ControlFlow<()> {
+        if ctx.flags().contains(SizeFlags::FOLLOW_REFS) {
+            <T as MemSize>::_mem_size_rec(self.deref(), ctx)
         } else {
-            0
+            ControlFlow::Continue(())
         }
     }
 }
@@ -618,8 +1019,8 @@ impl CopyType for std::path::Path {
 
 #[cfg(feature = "std")]
 impl MemSize for std::path::Path {
-    fn mem_size(&self, flags: SizeFlags) -> usize {
-        <std::ffi::OsStr as MemSize>::mem_size(self.as_os_str(), flags)
+    fn _mem_size_rec(&self, ctx: &mut SizeContext) -> ControlFlow<()> {
+        <std::ffi::OsStr as MemSize>::_mem_size_rec(self.as_os_str(), ctx)
     }
 }
 
@@ -630,12 +1031,17 @@ impl CopyType for std::path::PathBuf {
 
 #[cfg(feature = "std")]
 impl MemSize for std::path::PathBuf {
-    fn mem_size(&self, flags: SizeFlags) -> usize {
-        if flags.contains(SizeFlags::CAPACITY) {
-            core::mem::size_of::<Self>() + core::mem::size_of::<usize>()
-        } else {
-            <std::ffi::OsStr as MemSize>::mem_size(self.as_os_str(), flags)
-        }
+    fn _mem_size_rec(&self, ctx: &mut SizeContext) -> ControlFlow<()> {
+        ctx.add_allocations((self.capacity() != 0) as usize);
+        ctx.add_heap(
+            self.as_os_str().len(),
+            heap_size(
+                self.as_os_str().as_encoded_bytes().as_ptr(),
+                self.capacity(),
+                1,
+                ctx.flags(),
+            ),
+        )
     }
 }
 
@@ -646,12 +1052,9 @@ impl CopyType for std::ffi::OsStr {
 
 #[cfg(feature = "std")]
 impl MemSize for std::ffi::OsStr {
-    fn mem_size(&self, flags: SizeFlags) -> usize {
-        if flags.contains(SizeFlags::FOLLOW_REFS) {
-            self.as_encoded_bytes().len()
-        } else {
-            0
-        }
+    fn _mem_size_rec(&self, _ctx: &mut SizeContext) -> ControlFlow<()> {
+        // As for str
+        ControlFlow::Continue(())
     }
 }
 
@@ -662,15 +1065,17 @@ impl CopyType for std::ffi::OsString {
 
 #[cfg(feature = "std")]
 impl MemSize for std::ffi::OsString {
-    fn mem_size(&self, flags: SizeFlags) -> usize {
-        core::mem::size_of::<Self>()
-            + if flags.contains(SizeFlags::CAPACITY) {
-                // Capacity is an usize
-                core::mem::size_of::<usize>()
-            } else {
-                // Len is an usize
-                core::mem::size_of::<usize>()
-            }
+    fn _mem_size_rec(&self, ctx: &mut SizeContext) -> ControlFlow<()> {
+        ctx.add_allocations((self.capacity() != 0) as usize);
+        ctx.add_heap(
+            self.len(),
+            heap_size(
+                self.as_encoded_bytes().as_ptr(),
+                self.capacity(),
+                1,
+                ctx.flags(),
+            ),
+        )
     }
 }
 
@@ -681,7 +1086,10 @@ impl_size_of!(
     std::fs::Metadata,
     std::fs::FileType,
     std::fs::FileTimes,
-    std::fs::Permissions
+    std::fs::Permissions,
+    std::net::TcpStream,
+    std::net::TcpListener,
+    std::net::UdpSocket
 );
 
 // I/O
@@ -693,9 +1101,9 @@ impl<T: MemSize + std::io::Read> CopyType for std::io::BufReader<T> {
 
 #[cfg(feature = "std")]
 impl<T: MemSize + std::io::Read> MemSize for std::io::BufReader<T> {
-    fn mem_size(&self, flags: SizeFlags) -> usize {
-        core::mem::size_of::<Self>() - core::mem::size_of::<T>()
-            + <T as MemSize>::mem_size(self.get_ref(), flags)
+    fn _mem_size_rec(&self, ctx: &mut SizeContext) -> ControlFlow<()> {
+        ctx.add_allocations((self.capacity() != 0) as usize);
+        <T as MemSize>::_mem_size_rec(self.get_ref(), ctx)
     }
 }
 
@@ -706,9 +1114,9 @@ impl<T: MemSize + std::io::Write> CopyType for std::io::BufWriter<T> {
 
 #[cfg(feature = "std")]
 impl<T: MemSize + std::io::Write> MemSize for std::io::BufWriter<T> {
-    fn mem_size(&self, flags: SizeFlags) -> usize {
-        core::mem::size_of::<Self>() - core::mem::size_of::<T>()
-            + <T as MemSize>::mem_size(self.get_ref(), flags)
+    fn _mem_size_rec(&self, ctx: &mut SizeContext) -> ControlFlow<()> {
+        ctx.add_allocations((self.capacity() != 0) as usize);
+        <T as MemSize>::_mem_size_rec(self.get_ref(), ctx)
     }
 }
 
@@ -719,9 +1127,35 @@ impl<T> CopyType for std::io::Cursor<T> {
 
 #[cfg(feature = "std")]
 impl<T: MemSize> MemSize for std::io::Cursor<T> {
-    fn mem_size(&self, flags: SizeFlags) -> usize {
-        core::mem::size_of::<Self>() - core::mem::size_of::<T>()
-            + <T as MemSize>::mem_size(self.get_ref(), flags)
+    fn _mem_size_rec(&self, ctx: &mut SizeContext) -> ControlFlow<()> {
+        <T as MemSize>::_mem_size_rec(self.get_ref(), ctx)
+    }
+}
+
+// I/O slices do not own the bytes they point to, so, as for references, we
+// recurse only if FOLLOW_REFS is set
+
+#[cfg(feature = "std")]
+impl CopyType for std::io::IoSlice<'_> {
+    type Copy = False;
+}
+
+#[cfg(feature = "std")]
+impl MemSize for std::io::IoSlice<'_> {
+    fn _mem_size_rec(&self, ctx: &mut SizeContext) -> ControlFlow<()> {
+        <&[u8] as MemSize>::_mem_size_rec(&self.deref(), ctx)
+    }
+}
+
+#[cfg(feature = "std")]
+impl CopyType for std::io::IoSliceMut<'_> {
+    type Copy = False;
+}
+
+#[cfg(feature = "std")]
+impl MemSize for std::io::IoSliceMut<'_> {
+    fn _mem_size_rec(&self, ctx: &mut SizeContext) -> ControlFlow<()> {
+        <&[u8] as MemSize>::_mem_size_rec(&self.deref(), ctx)
     }
 }
 
@@ -745,7 +1179,101 @@ impl_copy_size_of!(
     std::time::SystemTimeError
 );
 
+// Processes
+
+#[cfg(feature = "std")]
+impl_copy_size_of!(std::process::ExitStatus, std::process::ExitCode);
+
+#[cfg(feature = "std")]
+impl CopyType for std::process::Output {
+    type Copy = False;
+}
+
+#[cfg(feature = "std")]
+impl MemSize for std::process::Output {
+    fn _mem_size_rec(&self, ctx: &mut SizeContext) -> ControlFlow<()> {
+        <Vec<u8> as MemSize>::_mem_size_rec(&self.stdout, ctx)?;
+        <Vec<u8> as MemSize>::_mem_size_rec(&self.stderr, ctx)
+    }
+}
+
+// Errors
+//
+// Errors are usually type-erased, so we can only count the stack size of the
+// concrete value: the memory owned by its fields cannot be followed.
+
+macro_rules! impl_error {
+    ($($ty:ty),*) => {$(
+#[cfg(feature = "std")]
+impl MemSize for $ty {
+    #[inline(always)]
+    fn _mem_size_rec(&self, _ctx: &mut SizeContext) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+}
+    )*};
+}
+
+impl_error!(
+    dyn std::error::Error,
+    dyn std::error::Error + Send,
+    dyn std::error::Error + Send + Sync
+);
+
+// Closures
+//
+// Closures are type-erased, too, so we can only count the size of their
+// captured environment, but not the memory owned by the captured values.
+
+macro_rules! impl_closure {
+    ($(($($arg:ident),*)),*) => {$(
+        impl_closure!(@impl [$($arg),*] dyn Fn($($arg),*) -> R + 'a);
+        impl_closure!(@impl [$($arg),*] dyn Fn($($arg),*) -> R + Send + 'a);
+        impl_closure!(@impl [$($arg),*] dyn Fn($($arg),*) -> R + Send + Sync + 'a);
+        impl_closure!(@impl [$($arg),*] dyn FnMut($($arg),*) -> R + 'a);
+        impl_closure!(@impl [$($arg),*] dyn FnMut($($arg),*) -> R + Send + 'a);
+        impl_closure!(@impl [$($arg),*] dyn FnMut($($arg),*) -> R + Send + Sync + 'a);
+        impl_closure!(@impl [$($arg),*] dyn FnOnce($($arg),*) -> R + 'a);
+        impl_closure!(@impl [$($arg),*] dyn FnOnce($($arg),*) -> R + Send + 'a);
+        impl_closure!(@impl [$($arg),*] dyn FnOnce($($arg),*) -> R + Send + Sync + 'a);
+    )*};
+
+    (@impl [$($arg:ident),*] $ty:ty) => {
+        impl<'a, $($arg,)* R> MemSize for $ty {
+            #[inline(always)]
+            fn _mem_size_rec(&self, _ctx: &mut SizeContext) -> ControlFlow<()> {
+                ControlFlow::Continue(())
+            }
+        }
+    };
+}
+
+impl_closure!((), (A), (A, B), (A, B, C), (A, B, C, D));
+
+// anyhow crate
+
+#[cfg(feature = "anyhow")]
+impl CopyType for anyhow::Error {
+    type Copy = False;
+}
+
+#[cfg(feature = "anyhow")]
+impl MemSize for anyhow::Error {
+    fn _mem_size_rec(&self, ctx: &mut SizeContext) -> ControlFlow<()> {
+        // The error is always boxed
+        let error: &(dyn std::error::Error + Send + Sync) = &**self;
+        let size = core::mem::size_of_val(error);
+        ctx.add_allocations(1);
+        ctx.add_heap(size, size)?;
+        <dyn std::error::Error + Send + Sync as MemSize>::_mem_size_rec(error, ctx)
+    }
+}
+
 // mmap-rs crate
+//
+// The memory of a map is owned only if SizeFlags::FOLLOW_REFS is set, and it
+// then counts as one allocation, even if it is not obtained from the global
+// allocator.
 
 #[cfg(feature = "mmap-rs")]
 impl CopyType for mmap_rs::Mmap {
@@ -755,13 +1283,13 @@ impl CopyType for mmap_rs::Mmap {
 #[cfg(feature = "mmap-rs")]
 impl MemSize for mmap_rs::Mmap {
     #[inline(always)]
-    fn mem_size(&self, flags: SizeFlags) -> usize {
-        core::mem::size_of::<Self>()
-            + if flags.contains(SizeFlags::FOLLOW_REFS) {
-                self.len()
-            } else {
-                0
-            }
+    fn _mem_size_rec(&self, ctx: &mut SizeContext) -> ControlFlow<()> {
+        if ctx.flags().contains(SizeFlags::FOLLOW_REFS) {
+            ctx.add_allocations(1);
+            ctx.add_heap(self.len(), self.len())
+        } else {
+            ControlFlow::Continue(())
+        }
     }
 }
 
@@ -773,13 +1301,13 @@ impl CopyType for mmap_rs::MmapMut {
 #[cfg(feature = "mmap-rs")]
 impl MemSize for mmap_rs::MmapMut {
     #[inline(always)]
-    fn mem_size(&self, flags: SizeFlags) -> usize {
-        core::mem::size_of::<Self>()
-            + if flags.contains(SizeFlags::FOLLOW_REFS) {
-                self.len()
-            } else {
-                0
-            }
+    fn _mem_size_rec(&self, ctx: &mut SizeContext) -> ControlFlow<()> {
+        if ctx.flags().contains(SizeFlags::FOLLOW_REFS) {
+            ctx.add_allocations(1);
+            ctx.add_heap(self.len(), self.len())
+        } else {
+            ControlFlow::Continue(())
+        }
     }
 }
 
@@ -788,10 +1316,9 @@ impl MemSize for mmap_rs::MmapMut {
 // If the standard library changes load factor, this code will have to change
 // accordingly.
 
-// Straight from hashbrown
+// Straight from hashbrown (which does not allocate if cap == 0, a case handled
+// by hash_buckets)
 fn capacity_to_buckets(cap: usize) -> Option<usize> {
-    // TODO: check that cap == 0 is handled correctly (we presently return 4)
-
     // For small tables we require at least 1 empty bucket so that lookups are
     // guaranteed to terminate if an element doesn't exist in the table.
     if cap < 8 {
@@ -813,54 +1340,123 @@ fn capacity_to_buckets(cap: usize) -> Option<usize> {
     Some(adjusted_cap.next_power_of_two())
 }
 
+/// Returns the number of buckets of the hash tables of the standard library
+/// ([`HashMap`] and [`HashSet`]), which are based on `hashbrown`, that are
+/// allocated to contain `len_or_cap` elements, or `usize::MAX` if the
+/// computation overflows.
+///
+/// This is the estimate used by [`MemSize`] to size hash tables, given their
+/// length or, with [`SizeFlags::CAPACITY`], their capacity. It mirrors the
+/// growth policy of `hashbrown` (at least one empty bucket in tables of fewer
+/// than 8 buckets, a maximum load factor of 87.5% otherwise, and a power of
+/// two of buckets), so it may need updating if `hashbrown` changes it. Note
+/// that a table with capacity zero has no buckets, as it does not allocate.
+///
+/// ```
+/// use mem_dbg::*;
+///
+/// assert_eq!(estimated_buckets(1), 4);
+/// assert_eq!(estimated_buckets(3), 4);
+/// assert_eq!(estimated_buckets(4), 8);
+/// assert_eq!(estimated_buckets(7), 8);
+/// assert_eq!(estimated_buckets(8), 16);
+/// assert_eq!(estimated_buckets(14), 16);
+/// assert_eq!(estimated_buckets(15), 32);
+/// assert_eq!(estimated_buckets(usize::MAX), usize::MAX);
+/// ```
+pub fn estimated_buckets(len_or_cap: usize) -> usize {
+    capacity_to_buckets(len_or_cap).unwrap_or(usize::MAX)
+}
+
+/// The number of control bytes probed at once by the hash tables of the
+/// standard library, which depends on the SIMD instructions available on the
+/// target. The control bytes of a table are as many as its buckets plus a
+/// group width, but only the former are counted.
+pub(crate) const GROUP_WIDTH: usize = if cfg!(all(
+    any(target_arch = "x86", target_arch = "x86_64"),
+    target_feature = "sse2"
+)) {
+    16
+} else {
+    // NEON groups on aarch64 and generic groups are made of eight bytes on
+    // 64-bit targets
+    core::mem::size_of::<usize>()
+};
+
+/// Returns the number of buckets of a hash table containing `len` elements
+/// with capacity `capacity`.
+///
+/// With [`SizeFlags::CAPACITY`], this is the number of allocated buckets;
+/// otherwise, it is the smallest number of buckets that can contain `len`
+/// elements, which is a lower bound to the number of allocated buckets, and
+/// thus it is also bounded by the latter. In both cases, a table with no
+/// capacity has no buckets, as it has not allocated any memory.
+pub(crate) fn hash_buckets(len: usize, capacity: usize, flags: SizeFlags) -> usize {
+    if capacity == 0 {
+        return 0;
+    }
+    let allocated = estimated_buckets(capacity);
+    if flags.contains(SizeFlags::CAPACITY) {
+        allocated
+    } else {
+        estimated_buckets(len).min(allocated)
+    }
+}
+
+/// Returns the used and allocated size in bytes of the table of a hash table
+/// containing `len` elements of `entry_size` bytes with capacity `capacity`,
+/// including the unused but unavoidable buckets and the control bytes of
+/// Swiss Tables (see [`hash_buckets`]).
+pub(crate) fn hash_table_heap(len: usize, capacity: usize, entry_size: usize) -> (usize, usize) {
+    let bucket_size = entry_size + core::mem::size_of::<u8>();
+    (
+        hash_buckets(len, capacity, SizeFlags::empty()) * bucket_size,
+        hash_buckets(len, capacity, SizeFlags::CAPACITY) * bucket_size,
+    )
+}
+
+/// Reports the table of a hash table, whose elements are then visited by the
+/// caller, if needed.
+#[inline(always)]
+fn hash_table_rec(
+    len: usize,
+    capacity: usize,
+    entry_size: usize,
+    ctx: &mut SizeContext,
+) -> ControlFlow<()> {
+    if capacity != 0 {
+        ctx.add_allocations(1);
+        ctx.mark_estimated();
+    }
+    let (used, allocated) = hash_table_heap(len, capacity, entry_size);
+    ctx.add_heap(used, allocated)
+}
+
 impl<T: CopyType> MemSize for HashSet<T>
 where
     HashSet<T>: MemSizeHelper<<T as CopyType>::Copy>,
 {
     #[inline(always)]
-    fn mem_size(&self, flags: SizeFlags) -> usize {
-        <HashSet<T> as MemSizeHelper<<T as CopyType>::Copy>>::mem_size_impl(self, flags)
-    }
-}
-
-// Add to the given size the space occupied on the stack by the hash set, by the unused
-// but unavoidable buckets, by the speedup bytes of Swiss Tables, and if `flags` contains
-// `SizeFlags::CAPACITY`, by empty buckets.
-fn fix_set_for_capacity<K>(hash_set: &HashSet<K>, size: usize, flags: SizeFlags) -> usize {
-    core::mem::size_of::<HashSet<K>>()
-        + size
-        + if flags.contains(SizeFlags::CAPACITY) {
-            (capacity_to_buckets(hash_set.capacity()).unwrap_or(usize::MAX) - hash_set.len())
-                * std::mem::size_of::<K>()
-                + capacity_to_buckets(hash_set.capacity()).unwrap_or(usize::MAX)
-                    * std::mem::size_of::<u8>()
-        } else {
-            (capacity_to_buckets(hash_set.len()).unwrap_or(usize::MAX) - hash_set.len())
-                * std::mem::size_of::<K>()
-                + capacity_to_buckets(hash_set.len()).unwrap_or(usize::MAX)
-                    * std::mem::size_of::<u8>()
-        }
+    fn _mem_size_rec(&self, ctx: &mut SizeContext) -> ControlFlow<()> {
+        <HashSet<T> as MemSizeHelper<<T as CopyType>::Copy>>::mem_size_impl(self, ctx)
+    }
 }
 
 #[cfg(feature = "alloc")]
 impl<K: CopyType + MemSize> MemSizeHelper<True> for HashSet<K> {
     #[inline(always)]
-    fn mem_size_impl(&self, flags: SizeFlags) -> usize {
-        fix_set_for_capacity(self, std::mem::size_of::<K>() * self.len(), flags)
+    fn mem_size_impl(&self, ctx: &mut SizeContext) -> ControlFlow<()> {
+        hash_table_rec(self.len(), self.capacity(), core::mem::size_of::<K>(), ctx)
     }
 }
 
 #[cfg(feature = "alloc")]
 impl<K: CopyType + MemSize> MemSizeHelper<False> for HashSet<K> {
     #[inline(always)]
-    fn mem_size_impl(&self, flags: SizeFlags) -> usize {
-        fix_set_for_capacity(
-            self,
-            self.iter()
-                .map(|x| <K as MemSize>::mem_size(x, flags))
-                .sum::<usize>(),
-            flags,
-        )
+    fn mem_size_impl(&self, ctx: &mut SizeContext) -> ControlFlow<()> {
+        hash_table_rec(self.len(), self.capacity(), core::mem::size_of::<K>(), ctx)?;
+        self.iter()
+            .try_for_each(|x| <K as MemSize>::_mem_size_rec(x, ctx))
     }
 }
 
@@ -870,7 +1466,7 @@ impl<K: CopyType + MemSize> MemSizeHelper<False> for HashSet<K> {
 ///
 /// See [`crate::CopyType`] for more information.
 pub trait MemSizeHelper2<K: Boolean, V: Boolean> {
-    fn mem_size_impl(&self, flags: SizeFlags) -> usize;
+    fn mem_size_impl(&self, ctx: &mut SizeContext) -> ControlFlow<()>;
 }
 
 impl<K: CopyType, V: CopyType> MemSize for HashMap<K, V>
@@ -878,86 +1474,173 @@ where
     HashMap<K, V>: MemSizeHelper2<<K as CopyType>::Copy, <V as CopyType>::Copy>,
 {
     #[inline(always)]
-    fn mem_size(&self, flags: SizeFlags) -> usize {
-        <HashMap<K, V> as MemSizeHelper2<<K as CopyType>::Copy, <V as CopyType>::Copy>>::mem_size_impl(self, flags)
-    }
-}
-
-// Add to the given size the space occupied on the stack by the hash map, by the unused
-// but unavoidable buckets, by the speedup bytes of Swiss Tables, and if `flags` contains
-// `SizeFlags::CAPACITY`, by empty buckets.
-fn fix_map_for_capacity<K, V>(hash_map: &HashMap<K, V>, size: usize, flags: SizeFlags) -> usize {
-    core::mem::size_of::<HashSet<K>>()
-        + size
-        + if flags.contains(SizeFlags::CAPACITY) {
-            (capacity_to_buckets(hash_map.capacity()).unwrap_or(usize::MAX) - hash_map.len())
-                * (std::mem::size_of::<K>() + std::mem::size_of::<V>())
-                + capacity_to_buckets(hash_map.capacity()).unwrap_or(usize::MAX)
-                    * std::mem::size_of::<u8>()
-        } else {
-            (capacity_to_buckets(hash_map.len()).unwrap_or(usize::MAX) - hash_map.len())
-                * (std::mem::size_of::<K>() + std::mem::size_of::<V>())
-                + capacity_to_buckets(hash_map.len()).unwrap_or(usize::MAX)
-                    * std::mem::size_of::<u8>()
-        }
+    fn _mem_size_rec(&self, ctx: &mut SizeContext) -> ControlFlow<()> {
+        <HashMap<K, V> as MemSizeHelper2<<K as CopyType>::Copy, <V as CopyType>::Copy>>::mem_size_impl(self, ctx)
+    }
+}
+
+/// Reports the table of a hash map, whose keys and values are then visited by
+/// the caller, if needed.
+#[inline(always)]
+fn hash_map_table_rec<K, V>(map: &HashMap<K, V>, ctx: &mut SizeContext) -> ControlFlow<()> {
+    hash_table_rec(
+        map.len(),
+        map.capacity(),
+        core::mem::size_of::<K>() + core::mem::size_of::<V>(),
+        ctx,
+    )
 }
 
 #[cfg(feature = "alloc")]
 impl<K: CopyType + MemSize, V: CopyType + MemSize> MemSizeHelper2<True, True> for HashMap<K, V> {
     #[inline(always)]
-    fn mem_size_impl(&self, flags: SizeFlags) -> usize {
-        fix_map_for_capacity(
-            self,
-            (std::mem::size_of::<K>() + std::mem::size_of::<V>()) * self.len(),
-            flags,
-        )
+    fn mem_size_impl(&self, ctx: &mut SizeContext) -> ControlFlow<()> {
+        hash_map_table_rec(self, ctx)
     }
 }
 
 #[cfg(feature = "alloc")]
 impl<K: CopyType + MemSize, V: CopyType + MemSize> MemSizeHelper2<True, False> for HashMap<K, V> {
     #[inline(always)]
-    fn mem_size_impl(&self, flags: SizeFlags) -> usize {
-        fix_map_for_capacity(
-            self,
-            (std::mem::size_of::<K>()) * self.len()
-                + self
-                    .values()
-                    .map(|v| <V as MemSize>::mem_size(v, flags))
-                    .sum::<usize>(),
-            flags,
-        )
+    fn mem_size_impl(&self, ctx: &mut SizeContext) -> ControlFlow<()> {
+        hash_map_table_rec(self, ctx)?;
+        self.values()
+            .try_for_each(|v| <V as MemSize>::_mem_size_rec(v, ctx))
     }
 }
 
 #[cfg(feature = "alloc")]
 impl<K: CopyType + MemSize, V: CopyType + MemSize> MemSizeHelper2<False, True> for HashMap<K, V> {
     #[inline(always)]
-    fn mem_size_impl(&self, flags: SizeFlags) -> usize {
-        fix_map_for_capacity(
-            self,
-            self.keys()
-                .map(|k| <K as MemSize>::mem_size(k, flags))
-                .sum::<usize>()
-                + (std::mem::size_of::<V>()) * self.len(),
-            flags,
-        )
+    fn mem_size_impl(&self, ctx: &mut SizeContext) -> ControlFlow<()> {
+        hash_map_table_rec(self, ctx)?;
+        self.keys()
+            .try_for_each(|k| <K as MemSize>::_mem_size_rec(k, ctx))
     }
 }
 
 #[cfg(feature = "alloc")]
 impl<K: CopyType + MemSize, V: CopyType + MemSize> MemSizeHelper2<False, False> for HashMap<K, V> {
     #[inline(always)]
-    fn mem_size_impl(&self, flags: SizeFlags) -> usize {
-        fix_map_for_capacity(
-            self,
+    fn mem_size_impl(&self, ctx: &mut SizeContext) -> ControlFlow<()> {
+        hash_map_table_rec(self, ctx)?;
+        self.iter().try_for_each(|(k, v)| {
+            <K as MemSize>::_mem_size_rec(k, ctx)?;
+            <V as MemSize>::_mem_size_rec(v, ctx)
+        })
+    }
+}
+
+// B-tree-based containers from the standard library
+//
+// The nodes of B-trees are private, so we estimate their number assuming
+// that they contain on average 7.5 elements, that is, about 68% of their
+// capacity, as it happens with random insertions. Trees built by sequential
+// insertions have emptier nodes, whereas trees built by collecting an
+// iterator have fuller nodes.
+//
+// If the standard library changes the layout of nodes, this code will have to
+// change accordingly.
+
+#[cfg(all(feature = "alloc", not(feature = "std")))]
+use alloc::collections::{BTreeMap, BTreeSet};
+#[cfg(feature = "std")]
+use std::collections::{BTreeMap, BTreeSet};
+
+const BTREE_CAPACITY: usize = 11;
+
+/// The layout of a leaf node of a B-tree of the standard library.
+#[allow(dead_code)]
+struct BTreeLeafNode<K, V> {
+    parent: *const (),
+    parent_idx: u16,
+    len: u16,
+    keys: [core::mem::MaybeUninit<K>; BTREE_CAPACITY],
+    vals: [core::mem::MaybeUninit<V>; BTREE_CAPACITY],
+}
+
+/// The layout of an internal node of a B-tree of the standard library.
+#[allow(dead_code)]
+struct BTreeInternalNode<K, V> {
+    data: BTreeLeafNode<K, V>,
+    edges: [*const (); BTREE_CAPACITY + 1],
+}
+
+/// Returns the estimated number of leaf and internal nodes of a B-tree with
+/// `len` elements.
+fn btree_nodes(len: usize) -> (usize, usize) {
+    match len {
+        0 => (0, 0),
+        1..=BTREE_CAPACITY => (1, 0),
+        _ => {
+            // 7.5 elements per node and 8.5 children per internal node
+            let nodes = (2 * len).div_ceil(15);
+            let internal = (2 * nodes).div_ceil(17);
+            (nodes - internal, internal)
+        }
+    }
+}
+
+/// Returns the estimated number of nodes of a B-tree with `len` elements.
+pub(crate) fn btree_node_count(len: usize) -> usize {
+    let (leaves, internal) = btree_nodes(len);
+    leaves + internal
+}
+
+/// Returns the estimated overall size of the nodes of a B-tree with `len`
+/// elements.
+pub(crate) fn btree_nodes_size<K, V>(len: usize) -> usize {
+    let (leaves, internal) = btree_nodes(len);
+    leaves * core::mem::size_of::<BTreeLeafNode<K, V>>()
+        + internal * core::mem::size_of::<BTreeInternalNode<K, V>>()
+}
+
+/// Reports the nodes of a B-tree with `len` elements, whose keys and values
+/// are then visited by the caller, if needed.
+fn btree_nodes_rec<K, V>(len: usize, ctx: &mut SizeContext) -> ControlFlow<()> {
+    if len != 0 {
+        ctx.mark_estimated();
+    }
+    let size = btree_nodes_size::<K, V>(len);
+    ctx.add_allocations(btree_node_count(len));
+    ctx.add_heap(size, size)
+}
+
+#[cfg(feature = "alloc")]
+impl<K, V> CopyType for BTreeMap<K, V> {
+    type Copy = False;
+}
+
+#[cfg(feature = "alloc")]
+impl<K: CopyType + MemSize, V: CopyType + MemSize> MemSize for BTreeMap<K, V> {
+    fn _mem_size_rec(&self, ctx: &mut SizeContext) -> ControlFlow<()> {
+        btree_nodes_rec::<K, V>(self.len(), ctx)?;
+        if !<K::Copy as Boolean>::VALUE {
+            self.keys()
+                .try_for_each(|k| <K as MemSize>::_mem_size_rec(k, ctx))?;
+        }
+        if !<V::Copy as Boolean>::VALUE {
+            self.values()
+                .try_for_each(|v| <V as MemSize>::_mem_size_rec(v, ctx))?;
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<K> CopyType for BTreeSet<K> {
+    type Copy = False;
+}
+
+#[cfg(feature = "alloc")]
+impl<K: CopyType + MemSize> MemSize for BTreeSet<K> {
+    fn _mem_size_rec(&self, ctx: &mut SizeContext) -> ControlFlow<()> {
+        btree_nodes_rec::<K, ()>(self.len(), ctx)?;
+        if !<K::Copy as Boolean>::VALUE {
             self.iter()
-                .map(|(k, v)| {
-                    <K as MemSize>::mem_size(k, flags) + <V as MemSize>::mem_size(v, flags)
-                })
-                .sum::<usize>(),
-            flags,
-        )
+                .try_for_each(|k| <K as MemSize>::_mem_size_rec(k, ctx))?;
+        }
+        ControlFlow::Continue(())
     }
 }
 
@@ -968,10 +1651,9 @@ impl<H> CopyType for core::hash::BuildHasherDefault<H> {
 }
 impl<H> MemSize for core::hash::BuildHasherDefault<H> {
     #[inline(always)]
-    fn mem_size(&self, _flags: SizeFlags) -> usize {
+    fn _mem_size_rec(&self, _ctx: &mut SizeContext) -> ControlFlow<()> {
         // it's a phantom hash
-        debug_assert_eq!(core::mem::size_of::<Self>(), 0);
-        0
+        ControlFlow::Continue(())
     }
 }
 
@@ -983,8 +1665,8 @@ impl CopyType for std::collections::hash_map::RandomState {
 #[cfg(feature = "std")]
 impl MemSize for std::collections::hash_map::RandomState {
     #[inline(always)]
-    fn mem_size(&self, _flags: SizeFlags) -> usize {
-        core::mem::size_of::<Self>()
+    fn _mem_size_rec(&self, _ctx: &mut SizeContext) -> ControlFlow<()> {
+        ControlFlow::Continue(())
     }
 }
 
@@ -998,8 +1680,8 @@ impl<T: ?Sized> CopyType for core::ptr::NonNull<T> {
 
 impl<T: ?Sized> MemSize for core::ptr::NonNull<T> {
     #[inline(always)]
-    fn mem_size(&self, _flags: SizeFlags) -> usize {
-        core::mem::size_of::<Self>()
+    fn _mem_size_rec(&self, _ctx: &mut SizeContext) -> ControlFlow<()> {
+        ControlFlow::Continue(())
     }
 }
 
@@ -1025,9 +1707,8 @@ impl<A: maligned::Alignment, T: MemSize> CopyType for maligned::Aligned<A, T> {
 
 #[cfg(feature = "maligned")]
 impl<A: maligned::Alignment, T: MemSize> MemSize for maligned::Aligned<A, T> {
-    fn mem_size(&self, flags: SizeFlags) -> usize {
-        core::mem::size_of::<Self>() - core::mem::size_of::<T>()
-            + <T as MemSize>::mem_size(self.deref(), flags)
+    fn _mem_size_rec(&self, ctx: &mut SizeContext) -> ControlFlow<()> {
+        <T as MemSize>::_mem_size_rec(self.deref(), ctx)
     }
 }
 
@@ -1035,3 +1716,383 @@ impl<A: maligned::Alignment, T: MemSize> MemSize for maligned::Aligned<A, T> {
 
 #[cfg(feature = "half")]
 impl_copy_size_of!(half::f16, half::bf16);
+
+// time crate
+
+#[cfg(feature = "time")]
+impl_copy_size_of!(
+    time::Date,
+    time::Duration,
+    time::OffsetDateTime,
+    time::PrimitiveDateTime,
+    time::Time,
+    time::UtcOffset
+);
+
+// uuid crate
+
+#[cfg(feature = "uuid")]
+impl_copy_size_of!(uuid::Uuid);
+
+// priority-queue crate
+
+#[cfg(feature = "priority-queue")]
+impl<I: core::hash::Hash + Eq, P: Ord, H> CopyType for priority_queue::PriorityQueue<I, P, H> {
+    type Copy = False;
+}
+
+// A priority queue is made of an IndexMap, which in turn is made of a vector
+// of entries (hash, item, priority) plus a Swiss Table of indices into it,
+// and of two vectors of indices implementing the heap and its inverse.
+#[cfg(feature = "priority-queue")]
+impl<I, P, H> MemSize for priority_queue::PriorityQueue<I, P, H>
+where
+    I: MemSize + core::hash::Hash + Eq,
+    P: MemSize + Ord,
+    H: MemSize + core::hash::BuildHasher,
+{
+    fn _mem_size_rec(&self, ctx: &mut SizeContext) -> ControlFlow<()> {
+        let size = |entries: usize, buckets: usize| {
+            // Entries of the IndexMap: the hash is an usize
+            entries * core::mem::size_of::<(usize, I, P)>()
+            // Indices of the IndexMap plus the Swiss Table control bytes
+            + buckets * (core::mem::size_of::<usize>() + core::mem::size_of::<u8>())
+            // The heap and the inverse heap permutation
+            + 2 * entries * core::mem::size_of::<usize>()
+        };
+        let (len, capacity) = (self.len(), self.capacity());
+        if capacity != 0 {
+            // The entries, the indices, the heap, and the inverse heap
+            // permutation
+            ctx.add_allocations(4);
+            ctx.mark_estimated();
+        }
+        ctx.add_heap(
+            size(len, hash_buckets(len, capacity, SizeFlags::empty())),
+            size(capacity, hash_buckets(len, capacity, SizeFlags::CAPACITY)),
+        )?;
+        self.iter().try_for_each(|(i, p)| {
+            <I as MemSize>::_mem_size_rec(i, ctx)?;
+            <P as MemSize>::_mem_size_rec(p, ctx)
+        })
+    }
+}
+
+// dashmap crate
+
+#[cfg(feature = "dashmap")]
+impl<K, V, S> CopyType for dashmap::DashMap<K, V, S> {
+    type Copy = False;
+}
+
+// A DashMap is a boxed slice of shards, each made of a lock and of a Swiss
+// Table of keys and values, which we size as the tables of hash maps. As for
+// mutexes, we use try_read, as the caller might be holding a guard on a
+// shard, and if a shard is locked we count just its stack size.
+#[cfg(feature = "dashmap")]
+impl<K, V, S> MemSize for dashmap::DashMap<K, V, S>
+where
+    K: CopyType + MemSize + Eq + core::hash::Hash,
+    V: CopyType + MemSize,
+    S: core::hash::BuildHasher + Clone,
+{
+    fn _mem_size_rec(&self, ctx: &mut SizeContext) -> ControlFlow<()> {
+        let copy = <K::Copy as Boolean>::VALUE && <V::Copy as Boolean>::VALUE;
+        let shards = core::mem::size_of_val(self.shards());
+        ctx.add_allocations(1);
+        ctx.mark_estimated();
+        ctx.add_heap(shards, shards)?;
+        for shard in self.shards() {
+            let map = match shard.try_read() {
+                Some(map) => map,
+                None => continue,
+            };
+            ctx.add_allocations((map.capacity() != 0) as usize);
+            let (used, allocated) = hash_table_heap(
+                map.len(),
+                map.capacity(),
+                core::mem::size_of::<K>() + core::mem::size_of::<V>(),
+            );
+            ctx.add_heap(used, allocated)?;
+            if !copy {
+                map.iter().try_for_each(|(k, v)| {
+                    <K as MemSize>::_mem_size_rec(k, ctx)?;
+                    <V as MemSize>::_mem_size_rec(v.get(), ctx)
+                })?;
+            }
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+// im crate
+//
+// The internal structure of persistent collections is private, so we can only
+// estimate their size using the number of elements. Chunks of vectors and
+// entries of maps and sets shared among clones are however reachable through
+// the public API, so with SizeFlags::FOLLOW_RCS we identify them by their
+// address and count them once; the internal nodes above them, whose size we
+// do not estimate, are never counted. Their number, however, is estimated
+// from the number of elements counted to report the allocations.
+
+/// Returns whether an element or a chunk of a persistent collection at `ptr`
+/// must be counted, that is, whether [`SizeFlags::FOLLOW_RCS`] is not set or
+/// it has not been counted yet.
+#[cfg(feature = "im")]
+fn im_first_visit<T: ?Sized>(ptr: *const T, ctx: &mut SizeContext) -> bool {
+    !ctx.flags().contains(SizeFlags::FOLLOW_RCS) || ctx.visit(ptr.cast::<u8>() as usize)
+}
+
+/// Returns the number of internal nodes of a tree with `leaves` leaves in
+/// which each internal node has `fanout` children.
+#[cfg(feature = "im")]
+fn im_tree_nodes(leaves: usize, fanout: usize) -> usize {
+    let mut level = leaves;
+    let mut nodes = 0;
+    while level > 1 {
+        level = level.div_ceil(fanout);
+        nodes += level;
+    }
+    nodes
+}
+
+/// Returns the estimated number of allocations of a vector that is not
+/// inline with `chunks` chunks: a vector with a single chunk allocates just
+/// the chunk, whereas larger vectors allocate also two (possibly empty)
+/// further chunks at their ends, and a tree with 64 children per node
+/// referencing the chunks in the middle.
+#[cfg(feature = "im")]
+fn im_vector_allocations(chunks: usize) -> usize {
+    match chunks {
+        0 | 1 => chunks,
+        _ => chunks + 2 + im_tree_nodes(chunks, 64).max(1),
+    }
+}
+
+/// Returns the estimated number of nodes of an ordered map or set with `len`
+/// elements: nodes contain at most 64 elements, and we assume that they are
+/// three-quarters full, which is about what happens after random insertions.
+#[cfg(feature = "im")]
+fn im_btree_nodes(len: usize) -> usize {
+    match len {
+        0..=64 => 1,
+        // At least a root and two children
+        _ => len.div_ceil(48).max(3),
+    }
+}
+
+/// Returns the expected number of nodes of a hash map or set with `len`
+/// elements, assuming hashes are uniformly distributed.
+///
+/// Each node of the trie has 32 children, indexed by the next 5 bits of the
+/// hash: beyond the root, there is a node for every prefix of 5, 10, …, 30
+/// bits shared by at least two elements, and a collision node for every
+/// (32-bit) hash shared by at least two elements.
+#[cfg(feature = "im")]
+fn im_hamt_nodes(len: usize) -> usize {
+    let n = len as f64;
+    let mut nodes = 1.0;
+    for bits in [5, 10, 15, 20, 25, 30, 32] {
+        let buckets = (1_u64 << bits) as f64;
+        // The probabilities that a bucket contains no element and a single
+        // element
+        let empty = (n * (-1.0 / buckets).ln_1p()).exp();
+        let single = n / (buckets - 1.0) * empty;
+        nodes += buckets * (1.0 - empty - single).max(0.0);
+    }
+    nodes.round() as usize
+}
+
+/// Reports the allocations of a map or set with `len` elements, of which
+/// `visited` have been counted, whose nodes are estimated by `nodes`, and
+/// whose hasher, if any, is at `hasher`.
+#[cfg(feature = "im")]
+fn im_allocations(
+    len: usize,
+    visited: usize,
+    nodes: fn(usize) -> usize,
+    hasher: Option<*const u8>,
+    ctx: &mut SizeContext,
+) {
+    // If no element has been counted, the nodes are shared with a collection
+    // counted before
+    if visited != 0 || len == 0 {
+        ctx.add_allocations(nodes(visited));
+    }
+    if let Some(hasher) = hasher {
+        if im_first_visit(hasher, ctx) {
+            ctx.add_allocations(1);
+        }
+    }
+}
+
+#[cfg(feature = "im")]
+impl<A: Clone> CopyType for im::Vector<A> {
+    type Copy = False;
+}
+
+#[cfg(feature = "im")]
+impl<A: Clone + MemSize> MemSize for im::Vector<A> {
+    fn _mem_size_rec(&self, ctx: &mut SizeContext) -> ControlFlow<()> {
+        // Elements are stored in reference-counted chunks of 64 elements,
+        // which also contain the boundaries of the used part
+        const CHUNK_SIZE: usize = 64;
+        const CHUNK_OVERHEAD: usize = 4 * core::mem::size_of::<usize>();
+        ctx.mark_estimated();
+        let inline = self.is_inline();
+        let mut chunks = 0;
+        for chunk in self.leaves() {
+            if !inline {
+                if !im_first_visit(chunk.as_ptr(), ctx) {
+                    continue;
+                }
+                chunks += 1;
+                ctx.add_heap(
+                    CHUNK_OVERHEAD + core::mem::size_of_val(chunk),
+                    CHUNK_OVERHEAD + CHUNK_SIZE * core::mem::size_of::<A>(),
+                )?;
+            }
+            chunk
+                .iter()
+                .try_for_each(|x| <A as MemSize>::_mem_size_rec(x, ctx))?;
+        }
+        ctx.add_allocations(im_vector_allocations(chunks));
+        ControlFlow::Continue(())
+    }
+}
+
+#[cfg(feature = "im")]
+impl<K, V, S> CopyType for im::HashMap<K, V, S> {
+    type Copy = False;
+}
+
+#[cfg(feature = "im")]
+impl<K: MemSize, V: MemSize, S> MemSize for im::HashMap<K, V, S> {
+    fn _mem_size_rec(&self, ctx: &mut SizeContext) -> ControlFlow<()> {
+        ctx.mark_estimated();
+        let mut visited = 0;
+        self.iter().try_for_each(|(k, v)| {
+            if !im_first_visit(k, ctx) {
+                return ControlFlow::Continue(());
+            }
+            visited += 1;
+            let size = core::mem::size_of::<(K, V)>();
+            ctx.add_heap(size, size)?;
+            <K as MemSize>::_mem_size_rec(k, ctx)?;
+            <V as MemSize>::_mem_size_rec(v, ctx)
+        })?;
+        let hasher: *const S = &**self.hasher();
+        im_allocations(self.len(), visited, im_hamt_nodes, Some(hasher.cast()), ctx);
+        ControlFlow::Continue(())
+    }
+}
+
+#[cfg(feature = "im")]
+impl<A, S> CopyType for im::HashSet<A, S> {
+    type Copy = False;
+}
+
+#[cfg(feature = "im")]
+impl<A: MemSize, S> MemSize for im::HashSet<A, S> {
+    fn _mem_size_rec(&self, ctx: &mut SizeContext) -> ControlFlow<()> {
+        ctx.mark_estimated();
+        let mut visited = 0;
+        self.iter().try_for_each(|x| {
+            if !im_first_visit(x, ctx) {
+                return ControlFlow::Continue(());
+            }
+            visited += 1;
+            let size = core::mem::size_of::<A>();
+            ctx.add_heap(size, size)?;
+            <A as MemSize>::_mem_size_rec(x, ctx)
+        })?;
+        let hasher: *const S = &**self.hasher();
+        im_allocations(self.len(), visited, im_hamt_nodes, Some(hasher.cast()), ctx);
+        ControlFlow::Continue(())
+    }
+}
+
+#[cfg(feature = "im")]
+impl<K, V> CopyType for im::OrdMap<K, V> {
+    type Copy = False;
+}
+
+#[cfg(feature = "im")]
+impl<K: MemSize + Ord, V: MemSize> MemSize for im::OrdMap<K, V> {
+    fn _mem_size_rec(&self, ctx: &mut SizeContext) -> ControlFlow<()> {
+        ctx.mark_estimated();
+        let mut visited = 0;
+        self.iter().try_for_each(|(k, v)| {
+            if !im_first_visit(k, ctx) {
+                return ControlFlow::Continue(());
+            }
+            visited += 1;
+            let size = core::mem::size_of::<(K, V)>();
+            ctx.add_heap(size, size)?;
+            <K as MemSize>::_mem_size_rec(k, ctx)?;
+            <V as MemSize>::_mem_size_rec(v, ctx)
+        })?;
+        im_allocations(self.len(), visited, im_btree_nodes, None, ctx);
+        ControlFlow::Continue(())
+    }
+}
+
+#[cfg(feature = "im")]
+impl<A> CopyType for im::OrdSet<A> {
+    type Copy = False;
+}
+
+#[cfg(feature = "im")]
+impl<A: MemSize + Ord> MemSize for im::OrdSet<A> {
+    fn _mem_size_rec(&self, ctx: &mut SizeContext) -> ControlFlow<()> {
+        ctx.mark_estimated();
+        let mut visited = 0;
+        self.iter().try_for_each(|x| {
+            if !im_first_visit(x, ctx) {
+                return ControlFlow::Continue(());
+            }
+            visited += 1;
+            let size = core::mem::size_of::<A>();
+            ctx.add_heap(size, size)?;
+            <A as MemSize>::_mem_size_rec(x, ctx)
+        })?;
+        im_allocations(self.len(), visited, im_btree_nodes, None, ctx);
+        ControlFlow::Continue(())
+    }
+}
+
+// bytes crate
+//
+// A Bytes (or a BytesMut obtained by splitting) is a view into a
+// reference-counted buffer whose address is not exposed, so we count the
+// bytes of the view, and storage shared among views is counted once for each
+// view, even if SizeFlags::FOLLOW_RCS is set. For the same reason, a nonempty
+// Bytes is counted as one allocation, even if it is a view of static data.
+
+#[cfg(feature = "bytes")]
+impl CopyType for bytes::Bytes {
+    type Copy = False;
+}
+
+#[cfg(feature = "bytes")]
+impl MemSize for bytes::Bytes {
+    #[inline(always)]
+    fn _mem_size_rec(&self, ctx: &mut SizeContext) -> ControlFlow<()> {
+        ctx.add_allocations((!self.is_empty()) as usize);
+        ctx.add_heap(self.len(), self.len())
+    }
+}
+
+#[cfg(feature = "bytes")]
+impl CopyType for bytes::BytesMut {
+    type Copy = False;
+}
+
+#[cfg(feature = "bytes")]
+impl MemSize for bytes::BytesMut {
+    #[inline(always)]
+    fn _mem_size_rec(&self, ctx: &mut SizeContext) -> ControlFlow<()> {
+        ctx.add_allocations((self.capacity() != 0) as usize);
+        ctx.add_heap(self.len(), self.capacity())
+    }
+}
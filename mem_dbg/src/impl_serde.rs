@@ -0,0 +1,114 @@
+/*
+ * SPDX-FileCopyrightText: 2023 Tommaso Fontana
+ * SPDX-FileCopyrightText: 2023 Inria
+ * SPDX-FileCopyrightText: 2023 Sebastiano Vigna
+ *
+ * SPDX-License-Identifier: Apache-2.0 OR LGPL-2.1-or-later
+ */
+
+//! Serialization of [`SizeFlags`] and [`DbgFlags`] using
+//! [`serde`](https://crates.io/crates/serde).
+//!
+//! Flags are serialized as sequences of flag names (e.g., `["TYPE_NAME",
+//! "SEPARATOR"]`) rather than as bits, so that serialized flags stay readable
+//! and are not affected by changes in the bit assignment. Bits not
+//! corresponding to a flag are not serialized, and deserializing an unknown
+//! flag name is an error.
+
+use bitflags::Flags;
+use core::marker::PhantomData;
+use serde::de::{Deserialize, Deserializer, Error, SeqAccess, Visitor};
+use serde::ser::{Serialize, Serializer};
+
+use crate::{DbgFlags, SizeFlags};
+
+/// Serializes `flags` as the sequence of the names of the flags it contains.
+fn serialize_flags<F: Flags, S: Serializer>(flags: &F, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(flags.iter_names().map(|(name, _)| name))
+}
+
+/// A [`Visitor`] deserializing a sequence of flag names.
+struct FlagsVisitor<F>(PhantomData<F>);
+
+impl<'de, F: Flags> Visitor<'de> for FlagsVisitor<F> {
+    type Value = F;
+
+    fn expecting(&self, formatter: &mut core::fmt::Formatter) -> core::fmt::Result {
+        formatter.write_str("a sequence of flag names")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<F, A::Error> {
+        let mut flags = F::empty();
+        while let Some(FlagName(flag)) = seq.next_element::<FlagName<F>>()? {
+            flags.insert(flag);
+        }
+        Ok(flags)
+    }
+}
+
+/// A single flag, deserialized from its name.
+struct FlagName<F>(F);
+
+impl<'de, F: Flags> Deserialize<'de> for FlagName<F> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_str(FlagNameVisitor(PhantomData))
+    }
+}
+
+/// A [`Visitor`] deserializing a flag name.
+struct FlagNameVisitor<F>(PhantomData<F>);
+
+impl<'de, F: Flags> Visitor<'de> for FlagNameVisitor<F> {
+    type Value = FlagName<F>;
+
+    fn expecting(&self, formatter: &mut core::fmt::Formatter) -> core::fmt::Result {
+        formatter.write_str("a flag name")
+    }
+
+    fn visit_str<E: Error>(self, name: &str) -> Result<FlagName<F>, E> {
+        F::from_name(name)
+            .map(FlagName)
+            .ok_or_else(|| E::custom(UnknownFlag::<F>(name, PhantomData)))
+    }
+}
+
+/// The message of the error returned when deserializing an unknown flag name,
+/// listing the known names.
+struct UnknownFlag<'a, F>(&'a str, PhantomData<F>);
+
+impl<F: Flags> core::fmt::Display for UnknownFlag<'_, F> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "unknown flag `{}`, expected one of ", self.0)?;
+        for (i, flag) in F::FLAGS.iter().enumerate() {
+            if i != 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "`{}`", flag.name())?;
+        }
+        Ok(())
+    }
+}
+
+impl Serialize for SizeFlags {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_flags(self, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for SizeFlags {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_seq(FlagsVisitor(PhantomData))
+    }
+}
+
+impl Serialize for DbgFlags {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_flags(self, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for DbgFlags {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_seq(FlagsVisitor(PhantomData))
+    }
+}
@@ -15,9 +15,26 @@ extern crate alloc;
 #[cfg(feature = "derive")]
 pub use mem_dbg_derive::{MemDbg, MemSize};
 
+mod allocator;
+#[cfg(feature = "allocator")]
+pub use allocator::enable_allocator_queries;
 mod impl_mem_dbg;
 mod impl_mem_size;
+pub use impl_mem_size::{estimated_buckets, SUPPORTED_TYPES};
+#[cfg(feature = "rayon")]
+mod par_mem_size;
+#[cfg(feature = "rayon")]
+pub use par_mem_size::ParMemSize;
 
+#[cfg(feature = "serde")]
+mod impl_serde;
+
+mod mem_diff;
+pub use mem_diff::*;
+mod mem_node;
+pub use mem_node::*;
+mod size_context;
+pub use size_context::*;
 mod utils;
 pub use utils::*;
 
@@ -29,13 +46,34 @@ on whether a type is [`Copy`] or not.
 It has only two implementations, [`True`] and [`False`].
 
 */
-pub trait Boolean {}
+pub trait Boolean {
+    /// The value represented by the type.
+    const VALUE: bool;
+}
 /// One of the two possible implementations of [`Boolean`].
 pub struct True {}
-impl Boolean for True {}
+impl Boolean for True {
+    const VALUE: bool = true;
+}
 /// One of the two possible implementations of [`Boolean`].
 pub struct False {}
-impl Boolean for False {}
+impl Boolean for False {
+    const VALUE: bool = false;
+}
+
+/// Type-level conjunction of [`Boolean`] values.
+///
+/// It is used to compute the [`CopyType`] of types containing values of
+/// different types, which are [`Copy`] only if all such types are.
+pub trait And<B: Boolean>: Boolean {
+    type Output: Boolean;
+}
+impl<B: Boolean> And<B> for True {
+    type Output = B;
+}
+impl<B: Boolean> And<B> for False {
+    type Output = False;
+}
 
 /**
 
@@ -54,10 +92,14 @@ Since we cannot use negative trait bounds, every type that is used as a paramete
 an array, vector, or slice must implement either `CopyType<Copy=True>` or
 `CopyType<Copy=False>`.  If you do not implement either of these traits,
 you will not be able to compute the size of arrays, vectors, and slices but error
-messages will be very unhelpful due to the contrived way we have to implement
+messages might be unhelpful due to the contrived way we have to implement
 mutually exclusive types [working around the bug that prevents the compiler
 from understanding that implementations for the two flavors of `CopyType` are mutually
 exclusive](https://github.com/rust-lang/rfcs/pull/1672#issuecomment-1405377983).
+For this reason, the [`MemSize`](mem_dbg_derive::MemSize) derive macro checks
+explicitly that the elements of fields that are vectors, arrays, or slices
+implement `CopyType`, reporting the missing implementation with a clear message
+(this is not possible for generic types).
 
 If you use the provided derive macros all this logic will be hidden from you. You'll
 just have to add the attribute `#[copy_type]` to your structures if they
@@ -68,10 +110,149 @@ Note that this approach forces us to compute the size of [`Copy`] types that con
 references by iteration _even if you do not specify_ [`SizeFlags::FOLLOW_REFS`].
 
 */
+#[diagnostic::on_unimplemented(
+    message = "type `{Self}` must derive `MemSize` or implement `CopyType` to be stored in a vector, an array, a slice, or another collection",
+    label = "`{Self}` does not implement `CopyType`",
+    note = "derive `MemSize`, adding `#[copy_type]` if `{Self}` is `Copy`, or implement `CopyType` with `type Copy = True` (for `Copy` types) or `type Copy = False`"
+)]
 pub trait CopyType {
     type Copy: Boolean;
 }
 
+/// Checks, if debug assertions are enabled, the consistency of the
+/// implementation of [`MemSize`] for the type of a value, panicking with the
+/// name of the type if the checks fail.
+///
+/// The first argument must be a reference to the value, and the second the
+/// flags with which it is sized. The heap capacity of the value must not be
+/// smaller than its used heap, that is, its size must not decrease when
+/// [`SizeFlags::CAPACITY`] is added to the flags.
+///
+/// The checks are not performed by [`MemSize::mem_size`] on the value it
+/// sizes, but the [`MemSize`](mem_dbg_derive::MemSize) derive macro performs
+/// them on each field while traversing a structure. Hand-written
+/// implementations and tests can use this macro to check the values they
+/// contain.
+///
+/// ```
+/// use mem_dbg::*;
+///
+/// let v = vec![String::from("a"), String::from("b")];
+/// debug_assert_mem_size!(&v, SizeFlags::default());
+/// ```
+#[macro_export]
+macro_rules! debug_assert_mem_size {
+    ($value:expr, $flags:expr $(,)?) => {
+        if cfg!(debug_assertions) {
+            $crate::_check_mem_size($value, $flags);
+        }
+    };
+}
+
+/// Sizes `value` with `flags` and performs the checks of
+/// [`debug_assert_mem_size`].
+#[doc(hidden)]
+pub fn _check_mem_size<T: ?Sized + MemSize>(value: &T, flags: SizeFlags) {
+    let ctx = SizeContext::traverse(value, flags);
+    check_capacity::<T>(
+        core::mem::size_of_val(value),
+        ctx.heap_used(),
+        ctx.heap_capacity(),
+    );
+}
+
+/// Panics with the name of `T` if the heap capacity of a value of type `T` is
+/// smaller than its used heap.
+#[inline(always)]
+fn check_capacity<T: ?Sized>(stack: usize, heap_used: usize, heap_capacity: usize) {
+    assert!(
+        heap_capacity >= heap_used,
+        "MemSize for {} returned {} bytes with SizeFlags::CAPACITY, less than the {} bytes returned without it",
+        core::any::type_name::<T>(),
+        stack + heap_capacity,
+        stack + heap_used
+    );
+}
+
+/// Visits a field of type `T` of a structure with the given flags.
+///
+/// If debug assertions are enabled and the traversal is not interrupted,
+/// panics with the name of `T` if the heap capacity reported by the field is
+/// smaller than its used heap, as the implementation of [`MemSize`] for `T`
+/// is broken.
+///
+/// This function is used by the [`MemSize`](mem_dbg_derive::MemSize) derive
+/// macro to size the fields of a structure.
+#[doc(hidden)]
+#[inline(always)]
+pub fn _field_rec<T: ?Sized + MemSize>(
+    value: &T,
+    flags: SizeFlags,
+    ctx: &mut SizeContext,
+) -> core::ops::ControlFlow<()> {
+    ctx.with_flags(flags, |ctx| {
+        if cfg!(debug_assertions) {
+            let (heap_used, heap_capacity) = (ctx.heap_used(), ctx.heap_capacity());
+            value._mem_size_rec(ctx)?;
+            check_capacity::<T>(
+                core::mem::size_of_val(value),
+                ctx.heap_used() - heap_used,
+                ctx.heap_capacity() - heap_capacity,
+            );
+            core::ops::ControlFlow::Continue(())
+        } else {
+            value._mem_size_rec(ctx)
+        }
+    })
+}
+
+/// Does nothing, but fails to compile, with a clear error message, if `T`
+/// does not implement [`CopyType`].
+///
+/// This function is used by the [`MemSize`](mem_dbg_derive::MemSize) derive
+/// macro on the element types of vectors, arrays, and slices.
+#[doc(hidden)]
+pub const fn _assert_copy_type<T: ?Sized + _CopyTypeAssertion>() {}
+
+/// Implemented by the types implementing [`CopyType`], so that
+/// [`_assert_copy_type`] can report a missing implementation without listing
+/// all the types implementing [`CopyType`].
+#[doc(hidden)]
+#[diagnostic::on_unimplemented(
+    message = "type `{Self}` must derive `MemSize` or implement `CopyType` to be stored in a vector, an array, a slice, or another collection",
+    label = "`{Self}` does not implement `CopyType`",
+    note = "derive `MemSize`, adding `#[copy_type]` if `{Self}` is `Copy`, or implement `CopyType` with `type Copy = True` (for `Copy` types) or `type Copy = False`"
+)]
+pub trait _CopyTypeAssertion {}
+
+#[diagnostic::do_not_recommend]
+impl<T: ?Sized + CopyType> _CopyTypeAssertion for T {}
+
+/// Does nothing, but fails to compile if `T` is not [`Copy`].
+///
+/// This function is used by the [`MemSize`](mem_dbg_derive::MemSize) derive
+/// macro on the types of the fields of types annotated with `#[copy_type]`.
+#[doc(hidden)]
+pub const fn _assert_copy<T: Copy>() {}
+
+/// Returns a bitwise copy of the possibly unaligned value pointed by `ptr`,
+/// wrapped in a [`ManuallyDrop`](core::mem::ManuallyDrop), so that the value
+/// is not dropped twice.
+///
+/// This function is used by the [`MemSize`](mem_dbg_derive::MemSize) and
+/// [`MemDbg`](mem_dbg_derive::MemDbg) derive macros to size the fields of
+/// `#[repr(packed)]` structures, which cannot be borrowed.
+///
+/// # Safety
+///
+/// `ptr` must point to a valid value, which must not be modified while the
+/// copy is in use.
+#[doc(hidden)]
+#[inline(always)]
+pub unsafe fn _read_packed<T>(ptr: *const T) -> core::mem::ManuallyDrop<T> {
+    core::mem::ManuallyDrop::new(core::ptr::read_unaligned(ptr))
+}
+
 bitflags::bitflags! {
     /// Flags for [`MemDbg`].
     #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -99,6 +280,35 @@ bitflags::bitflags! {
         /// [`MemSize::mem_size`] call [`Vec::capacity`] rather than
         /// [`Vec::len`].
         const CAPACITY = 1 << 1;
+        /// Follow reference-counted pointers ([`Rc`](std::rc::Rc) and
+        /// [`Arc`](std::sync::Arc)), counting each shared allocation once.
+        ///
+        /// By default [`MemSize::mem_size`] counts the memory owned by the
+        /// content of a reference-counted pointer once for each pointer, but
+        /// not the allocation containing the content and the counters. When
+        /// this flag is specified, the whole allocation is counted the first
+        /// time it is reached, and only the pointer is counted afterwards.
+        const FOLLOW_RCS = 1 << 2;
+        /// Return the size of the blocks handed out by the allocator.
+        ///
+        /// Even with [`SizeFlags::CAPACITY`], which this flag implies,
+        /// [`MemSize::mem_size`] returns the memory requested by a structure,
+        /// but allocators round requests to their size classes. When this
+        /// flag is specified, the size of the heap blocks of vectors, binary
+        /// heaps, strings, boxes, paths, and OS strings is queried from the
+        /// system allocator (using `malloc_usable_size` on Linux, Android,
+        /// and FreeBSD, `malloc_size` on macOS and iOS, and `HeapSize` on
+        /// Windows). For other structures, such as vector deques and hash
+        /// tables, which do not expose the address of their buffer, and on
+        /// other platforms, the result is the same as with
+        /// [`SizeFlags::CAPACITY`].
+        ///
+        /// Querying the system allocator is sound only if it is the global
+        /// allocator, so queries are performed only after calling the unsafe
+        /// function [`enable_allocator_queries`]: until then, this flag is
+        /// equivalent to [`SizeFlags::CAPACITY`].
+        #[cfg(feature = "allocator")]
+        const ALLOCATOR = 1 << 3 | Self::CAPACITY.bits();
     }
 }
 
@@ -115,11 +325,218 @@ impl Default for SizeFlags {
 ///
 /// You can derive this trait with `#[derive(MemSize)]` if all the fields of
 /// your type implement [`MemSize`].
-
+///
+/// Implementations must define [`_mem_size_rec`](MemSize::_mem_size_rec),
+/// which reports to a [`SizeContext`] the heap memory owned by the structure;
+/// all the other methods are computed from a single traversal.
+///
+/// The trait is dyn compatible, so trait objects such as `dyn MemSize` or
+/// `dyn MemDbgImpl + Send + Sync` can be sized behind a [`Box`], an
+/// [`Rc`](std::rc::Rc), or an [`Arc`](std::sync::Arc): the size of the
+/// erased value is computed by its implementation. Shared allocations are
+/// identified by the address of their data, so pointers to the same value
+/// are recognized even if their types are different.
 pub trait MemSize {
     /// Returns the (recursively computed) overall
     /// memory size of the structure in bytes.
-    fn mem_size(&self, flags: SizeFlags) -> usize;
+    fn mem_size(&self, flags: SizeFlags) -> usize {
+        core::mem::size_of_val(self) + self.heap_size(flags)
+    }
+
+    /// Reports to `ctx` the (recursively computed) memory owned by the
+    /// structure beyond its stack size, that is, the memory owned by the
+    /// values it points to and the heap memory owned by the values it
+    /// contains.
+    ///
+    /// Implementations report the heap memory they own using
+    /// [`SizeContext::add_heap`], both used and allocated, independently of
+    /// [`SizeFlags::CAPACITY`], and pass `ctx` to the values they contain,
+    /// so that allocations shared by reference-counted pointers are counted
+    /// once. The traversal must stop as soon as a [`ControlFlow::Break`] is
+    /// returned, which is easily achieved using the `?` operator: in this
+    /// way, implementations containing many values can stop early when the
+    /// size exceeds the limit of [`MemSize::mem_size_bounded`].
+    ///
+    /// [`ControlFlow::Break`]: core::ops::ControlFlow::Break
+    fn _mem_size_rec(&self, ctx: &mut SizeContext) -> core::ops::ControlFlow<()>;
+
+    /// Returns the (recursively computed) overall memory size of the
+    /// structure in bytes, or `None` if it exceeds `limit`.
+    ///
+    /// The traversal stops as soon as the size computed so far exceeds
+    /// `limit`, so checking whether a large structure is larger than a given
+    /// size can be much faster than computing its size. For every structure,
+    /// `x.mem_size(flags)` is equal to
+    /// `x.mem_size_bounded(usize::MAX, flags).unwrap()`.
+    ///
+    /// ```
+    /// use mem_dbg::*;
+    ///
+    /// let v = vec![String::from("hello"); 1000];
+    /// let size = v.mem_size(SizeFlags::default());
+    /// assert_eq!(v.mem_size_bounded(size, SizeFlags::default()), Some(size));
+    /// assert_eq!(v.mem_size_bounded(size - 1, SizeFlags::default()), None);
+    /// ```
+    fn mem_size_bounded(&self, limit: usize, flags: SizeFlags) -> Option<usize> {
+        let stack = core::mem::size_of_val(self);
+        let mut ctx = SizeContext::with_limit(flags, limit.checked_sub(stack)?);
+        self._mem_size_rec(&mut ctx)
+            .is_continue()
+            .then(|| stack + ctx.heap_size())
+    }
+
+    /// Returns the (recursively computed) memory size of the heap part of the
+    /// structure in bytes, that is, [`MemSize::mem_size`] minus the stack
+    /// size of the structure.
+    fn heap_size(&self, flags: SizeFlags) -> usize {
+        SizeContext::traverse(self, flags).heap_size()
+    }
+
+    /// Returns the stack size, the heap size, and the heap capacity of the
+    /// structure, computed in a single traversal.
+    ///
+    /// The heap size and capacity are the results of
+    /// [`MemSize::heap_size`] without and with [`SizeFlags::CAPACITY`],
+    /// respectively; the other flags are used as they are.
+    ///
+    /// ```
+    /// use mem_dbg::*;
+    ///
+    /// let mut v = Vec::<u64>::with_capacity(10);
+    /// v.push(0);
+    /// let stats = v.mem_stats(SizeFlags::default());
+    /// assert_eq!(stats.stack, core::mem::size_of::<Vec<u64>>());
+    /// assert_eq!(stats.heap_used, 8);
+    /// assert_eq!(stats.heap_capacity, 80);
+    /// assert_eq!(stats.slack(), 72);
+    /// ```
+    fn mem_stats(&self, flags: SizeFlags) -> SizeStats {
+        let ctx = SizeContext::traverse(self, flags);
+        SizeStats {
+            stack: core::mem::size_of_val(self),
+            heap_used: ctx.heap_used(),
+            heap_capacity: ctx.heap_capacity(),
+        }
+    }
+
+    /// Returns the (recursively computed) number of heap allocations owned
+    /// by the structure.
+    ///
+    /// A container with a nonempty buffer, such as a vector, a string, or
+    /// a hash table, owns one allocation plus the allocations owned by its
+    /// elements, and a box owns one allocation plus the allocations owned by
+    /// its content. The allocations of reference-counted pointers are
+    /// counted (once) only if [`SizeFlags::FOLLOW_RCS`] is set, and
+    /// references are followed only if [`SizeFlags::FOLLOW_REFS`] is set.
+    /// Allocated but unused buffers are counted independently of
+    /// [`SizeFlags::CAPACITY`].
+    ///
+    /// The allocations of types whose structure is not visible, such as the
+    /// internal nodes of the persistent collections of the `im` crate, are
+    /// estimated, and a memory map counts as one allocation if
+    /// [`SizeFlags::FOLLOW_REFS`] is set.
+    ///
+    /// ```
+    /// use mem_dbg::*;
+    ///
+    /// let v = vec![String::from("a"), String::new(), String::from("b")];
+    /// assert_eq!(v.mem_allocations(SizeFlags::default()), 3);
+    /// ```
+    fn mem_allocations(&self, flags: SizeFlags) -> usize {
+        SizeContext::traverse(self, flags).allocations()
+    }
+
+    /// Returns the (recursively computed) overall memory size of the
+    /// structure in bytes, as [`MemSize::mem_size`], and whether it is an
+    /// estimate.
+    ///
+    /// The size is an estimate if the structure contains a value whose
+    /// internal layout is private, so that its size is computed using a
+    /// model of the implementation: nonempty hash-based and B-tree-based
+    /// containers from the standard library, and the collections of the
+    /// `dashmap`, `im`, and `priority-queue` crates. Otherwise, the size is
+    /// exact to the byte.
+    ///
+    /// ```
+    /// use mem_dbg::*;
+    /// use std::collections::HashMap;
+    ///
+    /// let v = vec![1_u32, 2, 3];
+    /// assert!(!v.mem_size_detailed(SizeFlags::default()).estimated);
+    ///
+    /// let m = HashMap::from([(0, v)]);
+    /// let report = m.mem_size_detailed(SizeFlags::default());
+    /// assert_eq!(report.bytes, m.mem_size(SizeFlags::default()));
+    /// assert!(report.estimated);
+    /// ```
+    fn mem_size_detailed(&self, flags: SizeFlags) -> MemSizeReport {
+        let ctx = SizeContext::traverse(self, flags);
+        MemSizeReport {
+            bytes: core::mem::size_of_val(self) + ctx.heap_size(),
+            estimated: ctx.estimated(),
+        }
+    }
+}
+
+/// The memory size of a structure, as returned by
+/// [`MemSize::mem_size_detailed`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct MemSizeReport {
+    /// The overall memory size of the structure in bytes (see
+    /// [`MemSize::mem_size`]).
+    pub bytes: usize,
+    /// Whether the size is an estimate rather than exact to the byte.
+    pub estimated: bool,
+}
+
+/// The memory usage of a structure, as returned by [`MemSize::mem_stats`].
+///
+/// Statistics can be added, so that the statistics of a structure can be
+/// computed from those of its parts.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct SizeStats {
+    /// The stack size of the structure.
+    pub stack: usize,
+    /// The memory owned by the structure, excluding its stack size (see
+    /// [`MemSize::heap_size`]).
+    pub heap_used: usize,
+    /// The memory owned by the structure, excluding its stack size, including
+    /// memory allocated but not used (see [`SizeFlags::CAPACITY`]).
+    pub heap_capacity: usize,
+}
+
+impl SizeStats {
+    /// Returns the memory allocated but not used.
+    ///
+    /// The result is zero if the heap capacity is smaller than the used heap,
+    /// which happens only with broken implementations of [`MemSize`].
+    pub fn slack(&self) -> usize {
+        self.heap_capacity.saturating_sub(self.heap_used)
+    }
+}
+
+impl core::ops::Add for SizeStats {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        Self {
+            stack: self.stack + rhs.stack,
+            heap_used: self.heap_used + rhs.heap_used,
+            heap_capacity: self.heap_capacity + rhs.heap_capacity,
+        }
+    }
+}
+
+impl core::ops::AddAssign for SizeStats {
+    fn add_assign(&mut self, rhs: Self) {
+        *self = *self + rhs;
+    }
+}
+
+impl core::iter::Sum for SizeStats {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(Self::default(), |a, b| a + b)
+    }
 }
 
 bitflags::bitflags! {
@@ -141,10 +558,141 @@ bitflags::bitflags! {
         /// Print fields in memory order (i.e., using the layout chosen by the
         /// compiler), rather than in declaration order.
         const RUST_LAYOUT = 1 << 6;
+        /// Print the type name only for values owning memory beyond their
+        /// stack size (e.g., vectors, strings, and structures containing
+        /// them). Implies [`DbgFlags::TYPE_NAME`] for such values.
+        const QUIET_TYPES = 1 << 7;
+        /// Draw the tree using only ASCII characters, for terminals or logs
+        /// that cannot display box-drawing characters.
+        const ASCII = 1 << 8;
+        /// Display the elements of arrays, slices, vectors, and double-ended
+        /// queues whose elements are not [`Copy`] (see [`CopyType`]): the
+        /// first eight elements are displayed, labeled by their index, and
+        /// the remaining ones are summarized in a single line.
+        const EXPAND_ELEMENTS = 1 << 9;
+        /// Display siblings of the same type, such as fields of a structure
+        /// or expanded elements (see [`DbgFlags::EXPAND_ELEMENTS`]), in a
+        /// single line containing their number, their type name, and their
+        /// overall size.
+        const DEDUP_TYPES = 1 << 10;
+        /// Follow reference-counted pointers. See [`SizeFlags::FOLLOW_RCS`].
+        ///
+        /// When this flag is specified, the content of an allocation is
+        /// displayed only the first time it is reached, and afterwards a line
+        /// with its address is displayed instead. Without this flag this
+        /// happens only when following a cycle.
+        const FOLLOW_RCS = 1 << 11;
+        /// Display the size of the blocks handed out by the allocator. See
+        /// [`SizeFlags::ALLOCATOR`].
+        #[cfg(feature = "allocator")]
+        const ALLOCATOR = 1 << 12 | Self::CAPACITY.bits();
+        /// Display, after the size, the number of heap allocations owned by
+        /// each value, followed by `A`. See [`MemSize::mem_allocations`].
+        const ALLOCATIONS = 1 << 13;
+        /// Display a flat list of nodes, each identified by its dotted path
+        /// from the root (e.g., `root.b.c`, or `root.v[0]` for an element),
+        /// rather than a tree, so that outputs are easy to search and
+        /// compare.
+        const FLAT = 1 << 14;
+        /// Display, after the type name of collections such as vectors,
+        /// strings, and maps, their length and, if [`DbgFlags::CAPACITY`] is
+        /// set, their capacity.
+        const LEN = 1 << 15;
+        /// Display, before the name, the offset in bytes of each field within
+        /// its parent and its alignment, separated by a slash (e.g., `16/8`).
+        /// The offset is replaced by `-` for values that are not fields of a
+        /// structure or of an enum variant, such as the elements of tuples and
+        /// collections. Combined with [`DbgFlags::RUST_LAYOUT`], this flag
+        /// makes it possible to inspect the layout of a structure.
+        const OFFSETS = 1 << 16;
+        /// Display, as the last child of each value with children, the
+        /// overall padding of its descendants and its percentage of the size
+        /// of the value (e.g., `padding: 1.200 kB (3.40%)`), omitting the
+        /// line if there is no padding.
+        const PADDING_SUMMARY = 1 << 17;
+        /// Print memory usage as a percentage of the size of the parent,
+        /// rather than of the total size. If [`DbgFlags::PERCENTAGE`] is set,
+        /// too, both percentages are printed, the one relative to the total
+        /// size first.
+        const PARENT_PERCENTAGE = 1 << 18;
+        /// Display, after the type name of the root, its stack size, that is,
+        /// the size returned by [`core::mem::size_of_val`] (e.g.,
+        /// `(stack 96 B)`), which does not include the memory owned by the
+        /// value.
+        const STACK_SIZE = 1 << 19;
+        /// Color the size column with ANSI escape sequences depending on the
+        /// size, using the thresholds of the [`ColorScheme`] in
+        /// [`DbgConfig::color_scheme`]. The flag is ignored if the `NO_COLOR`
+        /// environment variable is set to a nonempty value, and, for the
+        /// methods writing to stdout, if stdout is not a terminal.
+        const COLOR = 1 << 20;
+        /// In the trees of differences displayed by
+        /// [`MemDbg::mem_diff_on`] and [`MemDiff::write_on`], do not display
+        /// the descendants of nodes whose subtree is unchanged.
+        const HIDE_UNCHANGED = 1 << 21;
+        /// Display type names (see [`DbgFlags::TYPE_NAME`] and
+        /// [`DbgFlags::DEDUP_TYPES`]) keeping only the last segment of each
+        /// path, as in `HashSet<String>` instead of
+        /// `std::collections::hash::set::HashSet<alloc::string::String>` (see
+        /// [`short_type_name`]).
+        const SHORT_TYPE_NAME = 1 << 22;
+        /// Display, after the type name of the hash-based containers of the
+        /// standard library, the estimated number of buckets and of control
+        /// bytes counted in their size, and the width of the groups of
+        /// control bytes on the current target (e.g., `buckets=128,
+        /// ctrl_bytes=128, group_width=16`). Actual tables contain a group
+        /// width of control bytes more than the ones counted, and the width
+        /// depends on the SIMD instructions available (16 bytes with SSE2, 8
+        /// bytes on ARM64), so their actual size differs across targets.
+        const GROUP_WIDTH_NOTE = 1 << 23;
+        /// Do not display the children of values owning no heap memory, that
+        /// is, values whose size is equal to their stack size, such as a
+        /// tuple of [`Duration`](core::time::Duration): only the values
+        /// owning heap memory are expanded.
+        const HIDE_LEAVES = 1 << 24;
+        /// Display, after the type name, the alignment of each value (e.g.,
+        /// `align=8`), as [`DbgFlags::OFFSETS`] does in a column before the
+        /// name.
+        const ALIGN = 1 << 25;
+        /// Do not display the nodes of size zero, such as
+        /// [`PhantomData`](core::marker::PhantomData) fields, unit fields, or
+        /// empty ranges of zero-sized types, and their descendants. Tree
+        /// connectors are computed on the displayed nodes only.
+        const HIDE_EMPTY = 1 << 26;
+        /// With [`DbgFlags::HIDE_EMPTY`], display after the children of each
+        /// node with hidden children a line with their number (e.g., `(3
+        /// zero-sized fields hidden)`).
+        const COUNT_HIDDEN = 1 << 27;
+        /// Display on a single line each chain of values in which every value
+        /// but the last has a single child of the same size, such as a
+        /// newtype or a structure with a single field, joining the names as
+        /// in a path and the type names with arrows (e.g., `id.0: Id →
+        /// alloc::string::String`). The information in parentheses is that
+        /// of the last value of the chain.
+        const COLLAPSE_WRAPPERS = 1 << 28;
     }
 }
 
 impl DbgFlags {
+    /// Translates [`SizeFlags`] into the corresponding [`DbgFlags`].
+    pub fn from_size_flags(flags: SizeFlags) -> Self {
+        let mut dbg_flags = DbgFlags::empty();
+        if flags.contains(SizeFlags::FOLLOW_REFS) {
+            dbg_flags |= DbgFlags::FOLLOW_REFS;
+        }
+        if flags.contains(SizeFlags::CAPACITY) {
+            dbg_flags |= DbgFlags::CAPACITY;
+        }
+        if flags.contains(SizeFlags::FOLLOW_RCS) {
+            dbg_flags |= DbgFlags::FOLLOW_RCS;
+        }
+        #[cfg(feature = "allocator")]
+        if flags.contains(SizeFlags::ALLOCATOR) {
+            dbg_flags |= DbgFlags::ALLOCATOR;
+        }
+        dbg_flags
+    }
+
     /// Translates flags that are in common with [`MemSize`] into [`SizeFlags`].
     pub fn to_size_flags(&self) -> SizeFlags {
         let mut flags = SizeFlags::empty();
@@ -154,6 +702,13 @@ impl DbgFlags {
         if self.contains(DbgFlags::CAPACITY) {
             flags |= SizeFlags::CAPACITY;
         }
+        if self.contains(DbgFlags::FOLLOW_RCS) {
+            flags |= SizeFlags::FOLLOW_RCS;
+        }
+        #[cfg(feature = "allocator")]
+        if self.contains(DbgFlags::ALLOCATOR) {
+            flags |= SizeFlags::ALLOCATOR;
+        }
         flags
     }
 }
@@ -167,6 +722,251 @@ impl Default for DbgFlags {
     }
 }
 
+/// A complete configuration for [`MemDbg`], bundling [`DbgFlags`] with the
+/// options that are not boolean, to be passed to
+/// [`mem_dbg_config`](MemDbg::mem_dbg_config) and to the other methods whose
+/// name ends in `_config_on`.
+///
+/// The default configuration uses the default flags and options, so the
+/// methods taking only flags behave as if they were passed a configuration
+/// built by [`DbgConfig::new`] with those flags.
+///
+/// ```
+/// use mem_dbg::*;
+///
+/// let config = DbgConfig::new()
+///     .flags(DbgFlags::default() | DbgFlags::HUMANIZE)
+///     .min_percentage(0.5)
+///     .humanize_precision(Some(2))
+///     .max_elements(4);
+/// assert_eq!(config.max_elements, 4);
+///
+/// let config = DbgConfig::new().flags(DbgFlags::empty()).max_size_width(Some(8));
+/// // Sizes in the gigabytes would need ten digits
+/// assert_eq!(config.adjust_flags(5_000_000_000), DbgFlags::HUMANIZE);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DbgConfig {
+    /// The boolean options.
+    pub flags: DbgFlags,
+    /// The maximum width of the column of sizes, excluding the unit of
+    /// measure: if displaying sizes in bytes would require a wider column,
+    /// sizes are displayed in human readable format, as with
+    /// [`DbgFlags::HUMANIZE`]. If `None` (the default), the width is not
+    /// bounded.
+    pub max_size_width: Option<usize>,
+    /// The thresholds used to color sizes when [`DbgFlags::COLOR`] is set,
+    /// changes of size in differences, and the vertices of DOT digraphs.
+    pub color_scheme: ColorScheme,
+    /// The percentage of the total size below which children are summarized
+    /// in a single line, as with
+    /// [`mem_dbg_filtered`](MemDbg::mem_dbg_filtered). The default is zero,
+    /// that is, all children are displayed.
+    pub min_percentage: f64,
+    /// The number of decimal digits of sizes displayed in human readable
+    /// format. If `None` (the default), sizes have four significant digits.
+    pub humanize_precision: Option<usize>,
+    /// The maximum number of elements of a sequence displayed when
+    /// [`DbgFlags::EXPAND_ELEMENTS`] is set; the remaining elements are
+    /// summarized in a single line. The default is 8.
+    pub max_elements: usize,
+}
+
+impl Default for DbgConfig {
+    fn default() -> Self {
+        Self {
+            flags: DbgFlags::default(),
+            max_size_width: None,
+            color_scheme: ColorScheme::default(),
+            min_percentage: 0.0,
+            humanize_precision: None,
+            max_elements: impl_mem_dbg::MAX_EXPANDED_ELEMENTS,
+        }
+    }
+}
+
+impl DbgConfig {
+    /// Returns a configuration with the default flags and options.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets [`DbgConfig::flags`].
+    pub fn flags(mut self, flags: DbgFlags) -> Self {
+        self.flags = flags;
+        self
+    }
+
+    /// Sets [`DbgConfig::max_size_width`].
+    pub fn max_size_width(mut self, max_size_width: Option<usize>) -> Self {
+        self.max_size_width = max_size_width;
+        self
+    }
+
+    /// Sets [`DbgConfig::color_scheme`].
+    pub fn color_scheme(mut self, color_scheme: ColorScheme) -> Self {
+        self.color_scheme = color_scheme;
+        self
+    }
+
+    /// Sets [`DbgConfig::min_percentage`].
+    pub fn min_percentage(mut self, min_percentage: f64) -> Self {
+        self.min_percentage = min_percentage;
+        self
+    }
+
+    /// Sets [`DbgConfig::humanize_precision`].
+    pub fn humanize_precision(mut self, humanize_precision: Option<usize>) -> Self {
+        self.humanize_precision = humanize_precision;
+        self
+    }
+
+    /// Sets [`DbgConfig::max_elements`].
+    pub fn max_elements(mut self, max_elements: usize) -> Self {
+        self.max_elements = max_elements;
+        self
+    }
+
+    /// Returns the flags to be used to display a structure of overall size
+    /// `total_size`, adding [`DbgFlags::HUMANIZE`] to [`DbgConfig::flags`] if
+    /// the sizes would not fit in [`DbgConfig::max_size_width`].
+    pub fn adjust_flags(&self, total_size: usize) -> DbgFlags {
+        match self.max_size_width {
+            Some(width) if ColumnLayout::new(total_size, self).size_width() > width => {
+                self.flags | DbgFlags::HUMANIZE
+            }
+            _ => self.flags,
+        }
+    }
+}
+
+/// The thresholds, in bytes, used to color sizes when [`DbgFlags::COLOR`] is
+/// set: sizes smaller than [`ColorScheme::green`] are white, and sizes at
+/// least as large as [`ColorScheme::green`], [`ColorScheme::yellow`], and
+/// [`ColorScheme::red`] are green, yellow, and red, respectively.
+///
+/// The same thresholds are used in all output modes: for the changes of size
+/// displayed by [`mem_diff_on`](MemDbg::mem_diff_on), and for the vertices of
+/// the DOT digraphs written by [`mem_dbg_dot_on`](MemDbg::mem_dbg_dot_on),
+/// which are filled with light versions of the colors.
+///
+/// The default thresholds are 1 KiB, 1 MiB, and 1 GiB.
+///
+/// ```
+/// use mem_dbg::*;
+///
+/// // Structures that are small overall
+/// let scheme = ColorScheme {
+///     green: 16,
+///     yellow: 256,
+///     red: 4096,
+/// };
+/// let config = DbgConfig::new().color_scheme(scheme);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ColorScheme {
+    /// The smallest size displayed in green.
+    pub green: usize,
+    /// The smallest size displayed in yellow.
+    pub yellow: usize,
+    /// The smallest size displayed in red.
+    pub red: usize,
+}
+
+impl Default for ColorScheme {
+    fn default() -> Self {
+        Self {
+            green: 1 << 10,
+            yellow: 1 << 20,
+            red: 1 << 30,
+        }
+    }
+}
+
+impl ColorScheme {
+    /// Returns the ANSI escape sequence setting the color of `size`.
+    pub fn ansi_color(&self, size: usize) -> &'static str {
+        if size >= self.red {
+            "\x1b[31m"
+        } else if size >= self.yellow {
+            "\x1b[33m"
+        } else if size >= self.green {
+            "\x1b[32m"
+        } else {
+            "\x1b[37m"
+        }
+    }
+
+    /// Returns the name of the color, as understood by
+    /// [Graphviz](https://graphviz.org/), filling the vertex of a value of
+    /// size `size`: a light version of the color returned by
+    /// [`ColorScheme::ansi_color`], so that labels stay readable.
+    pub fn dot_color(&self, size: usize) -> &'static str {
+        if size >= self.red {
+            "salmon"
+        } else if size >= self.yellow {
+            "khaki"
+        } else if size >= self.green {
+            "palegreen"
+        } else {
+            "white"
+        }
+    }
+}
+
+/// Returns `flags` without [`DbgFlags::COLOR`] if stdout is not a terminal.
+#[cfg(feature = "std")]
+fn stdout_flags(flags: DbgFlags) -> DbgFlags {
+    use std::io::IsTerminal;
+    if std::io::stdout().is_terminal() {
+        flags
+    } else {
+        flags - DbgFlags::COLOR
+    }
+}
+
+/// A [`core::fmt::Write`] writing to a [`std::io::Write`].
+///
+/// Since [`core::fmt::Error`] carries no information, the first IO error is
+/// stored, so that it can be returned by [`IoWriter::finish`].
+#[cfg(feature = "std")]
+struct IoWriter<W> {
+    writer: W,
+    error: Option<std::io::Error>,
+}
+
+#[cfg(feature = "std")]
+impl<W: std::io::Write> IoWriter<W> {
+    fn new(writer: W) -> Self {
+        Self {
+            writer,
+            error: None,
+        }
+    }
+
+    /// Returns the IO error that made writing fail, if any, or an error
+    /// wrapping `result` if the failure was not due to IO.
+    fn finish(self, result: core::fmt::Result) -> std::io::Result<()> {
+        match (self.error, result) {
+            (Some(error), _) => Err(error),
+            (None, Ok(())) => Ok(()),
+            (None, Err(error)) => Err(std::io::Error::other(error)),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<W: std::io::Write> core::fmt::Write for IoWriter<W> {
+    #[inline(always)]
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        // write_all, as write might write only part of the string
+        self.writer.write_all(s.as_bytes()).map_err(|error| {
+            self.error = Some(error);
+            core::fmt::Error
+        })
+    }
+}
+
 /// A trait providing methods to display recursively the content and size of a
 /// structure.
 ///
@@ -179,64 +979,688 @@ pub trait MemDbg: MemDbgImpl {
     #[cfg(feature = "std")]
     #[inline(always)]
     fn mem_dbg(&self, flags: DbgFlags) -> core::fmt::Result {
-        // TODO: fix padding
-        self._mem_dbg_depth(
-            <Self as MemSize>::mem_size(self, flags.to_size_flags()),
-            usize::MAX,
-            std::mem::size_of_val(self),
-            flags,
-        )
+        self.mem_dbg_depth(usize::MAX, flags)
     }
 
     /// Writes to a [`core::fmt::Write`] debug infos about the structure memory
     /// usage, expanding all levels of nested structures.
     #[inline(always)]
     fn mem_dbg_on(&self, writer: &mut impl core::fmt::Write, flags: DbgFlags) -> core::fmt::Result {
-        // TODO: fix padding
-        self._mem_dbg_depth_on(
-            writer,
-            <Self as MemSize>::mem_size(self, flags.to_size_flags()),
-            usize::MAX,
-            &mut String::new(),
-            Some("⏺"),
-            true,
-            std::mem::size_of_val(self),
-            flags,
-        )
+        self.mem_dbg_depth_on(writer, usize::MAX, flags)
+    }
+
+    /// Writes to a [`std::io::Write`], such as a file or a socket, debug infos
+    /// about the structure memory usage as [`mem_dbg_on`](MemDbg::mem_dbg_on).
+    ///
+    /// Differently from the methods writing to a [`core::fmt::Write`], IO
+    /// errors are returned as they are.
+    ///
+    /// ```
+    /// use mem_dbg::*;
+    ///
+    /// let mut output = Vec::new();
+    /// vec![0_u8; 10].mem_dbg_io_on(&mut output, DbgFlags::empty())?;
+    /// assert_eq!(String::from_utf8(output).unwrap(), "34 B ⏺\n");
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    #[cfg(feature = "std")]
+    fn mem_dbg_io_on(
+        &self,
+        writer: &mut impl std::io::Write,
+        flags: DbgFlags,
+    ) -> std::io::Result<()> {
+        let mut writer = IoWriter::new(writer);
+        let result = self.mem_dbg_on(&mut writer, flags);
+        writer.finish(result)
+    }
+
+    /// Writes to stdout debug infos about the structure memory usage as
+    /// [`mem_dbg`](MemDbg::mem_dbg), but through a buffer of 8 KiB that is
+    /// flushed at the end.
+    ///
+    /// Lines are written as soon as they are formatted, but the tree of the
+    /// structure is built before writing (see [`mem_tree`](MemDbg::mem_tree)).
+    /// Since [`mem_dbg`](MemDbg::mem_dbg) writes each part of each line
+    /// separately to stdout, buffering reduces considerably the number of
+    /// system calls for large structures, in particular when stdout is not a
+    /// terminal, in which case it is not line-buffered.
+    #[cfg(feature = "std")]
+    fn mem_dbg_buffered(&self, flags: DbgFlags) -> core::fmt::Result {
+        let mut writer = std::io::BufWriter::with_capacity(8 * 1024, std::io::stdout().lock());
+        self.mem_dbg_io_on(&mut writer, stdout_flags(flags))
+            .and_then(|()| std::io::Write::flush(&mut writer))
+            .map_err(|_| core::fmt::Error)
     }
 
     /// Writes to stdout debug infos about the structure memory usage as
     /// [`mem_dbg`](MemDbg::mem_dbg), but expanding only up to `max_depth`
     /// levels of nested structures.
+    ///
+    /// The depth counts levels of the tree: a `max_depth` of zero displays
+    /// only the root, one displays the root and its direct parts (e.g., the
+    /// fields of a structure), and so on, whereas `usize::MAX` expands all
+    /// levels. See [`mem_dbg_depth_on`](MemDbg::mem_dbg_depth_on) for an
+    /// example.
+    #[cfg(feature = "std")]
     fn mem_dbg_depth(&self, max_depth: usize, flags: DbgFlags) -> core::fmt::Result {
-        self._mem_dbg_depth(
-            <Self as MemSize>::mem_size(self, flags.to_size_flags()),
+        self.mem_dbg_depth_on(
+            &mut IoWriter::new(std::io::stdout().lock()),
             max_depth,
-            std::mem::size_of_val(self),
-            flags,
+            stdout_flags(flags),
         )
     }
 
     /// Writes to a [`core::fmt::Write`] debug infos about the structure memory
     /// usage as [`mem_dbg_on`](MemDbg::mem_dbg_on), but expanding only up to
     /// `max_depth` levels of nested structures.
+    ///
+    /// The total size and the padded size of the root are computed from
+    /// `self`. The depth counts levels of the tree, independently of the
+    /// glyphs used to draw it: with a `max_depth` of zero only the root is
+    /// displayed, with one the root and its direct parts, and so on, whereas
+    /// `usize::MAX` expands all levels.
+    ///
+    /// ```
+    /// use mem_dbg::*;
+    ///
+    /// let mut output = String::new();
+    /// (0_u8, (1_u16, 2_u32)).mem_dbg_depth_on(&mut output, 1, DbgFlags::empty())?;
+    /// assert_eq!(
+    ///     output,
+    ///     "\
+    /// 12 B ⏺
+    ///  1 B ├╴0 [3B]
+    ///  8 B ╰╴1
+    /// "
+    /// );
+    /// # Ok::<(), core::fmt::Error>(())
+    /// ```
     fn mem_dbg_depth_on(
         &self,
         writer: &mut impl core::fmt::Write,
         max_depth: usize,
         flags: DbgFlags,
     ) -> core::fmt::Result {
-        self._mem_dbg_depth_on(
-            writer,
-            <Self as MemSize>::mem_size(self, flags.to_size_flags()),
-            max_depth,
-            &mut String::new(),
-            None,
-            false,
-            std::mem::size_of_val(self),
-            flags,
+        self.mem_tree_depth(max_depth, flags)
+            .write_on(writer, flags)
+    }
+
+    /// Writes to stdout debug infos about the structure memory usage as
+    /// [`mem_dbg`](MemDbg::mem_dbg), using the given configuration.
+    #[cfg(feature = "std")]
+    fn mem_dbg_config(&self, config: &DbgConfig) -> core::fmt::Result {
+        self.mem_dbg_config_on(
+            &mut IoWriter::new(std::io::stdout().lock()),
+            &DbgConfig {
+                flags: stdout_flags(config.flags),
+                ..*config
+            },
+        )
+    }
+
+    /// Writes to a [`core::fmt::Write`] debug infos about the structure memory
+    /// usage as [`mem_dbg_on`](MemDbg::mem_dbg_on), using the given
+    /// configuration.
+    ///
+    /// ```
+    /// use mem_dbg::*;
+    ///
+    /// let mut output = String::new();
+    /// let config = DbgConfig::new()
+    ///     .flags(DbgFlags::HUMANIZE)
+    ///     .humanize_precision(Some(1));
+    /// vec![0_u8; 5000].mem_dbg_config_on(&mut output, &config)?;
+    /// assert_eq!(output, "  5.0 kB ⏺\n");
+    ///
+    /// let mut output = String::new();
+    /// let config = DbgConfig::new()
+    ///     .flags(DbgFlags::empty())
+    ///     .max_size_width(Some(3));
+    /// vec![0_u8; 5000].mem_dbg_config_on(&mut output, &config)?;
+    /// assert_eq!(output, "5.024 kB ⏺\n");
+    /// # Ok::<(), core::fmt::Error>(())
+    /// ```
+    fn mem_dbg_config_on(
+        &self,
+        writer: &mut impl core::fmt::Write,
+        config: &DbgConfig,
+    ) -> core::fmt::Result {
+        let mut tree =
+            impl_mem_dbg::with_max_elements(config.max_elements, || self.mem_tree(config.flags));
+        if config.min_percentage > 0.0 {
+            tree.filter(0, config.min_percentage, config.flags);
+        }
+        tree.write_config_on(writer, config)
+    }
+
+    /// Writes to stdout debug infos about the structure memory usage as
+    /// [`mem_dbg`](MemDbg::mem_dbg), but summarizing in a single line the
+    /// children of each node whose size is smaller than `min_size` bytes, or
+    /// than `min_percentage` percent of the total size.
+    ///
+    /// See [`MemNode::filter`] for more details.
+    #[cfg(feature = "std")]
+    fn mem_dbg_filtered(
+        &self,
+        min_size: usize,
+        min_percentage: f64,
+        flags: DbgFlags,
+    ) -> core::fmt::Result {
+        self.mem_dbg_filtered_on(
+            &mut IoWriter::new(std::io::stdout().lock()),
+            min_size,
+            min_percentage,
+            stdout_flags(flags),
+        )
+    }
+
+    /// Writes to a [`core::fmt::Write`] debug infos about the structure memory
+    /// usage as [`mem_dbg_on`](MemDbg::mem_dbg_on), but summarizing in a
+    /// single line the children of each node whose size is smaller than
+    /// `min_size` bytes, or than `min_percentage` percent of the total size.
+    ///
+    /// See [`MemNode::filter`] for more details; to limit the depth, too,
+    /// filter the tree returned by [`mem_tree_depth`](MemDbg::mem_tree_depth).
+    ///
+    /// ```
+    /// use mem_dbg::*;
+    ///
+    /// let mut output = String::new();
+    /// (0_u8, 1_u16, vec![2_u32; 100]).mem_dbg_filtered_on(&mut output, 10, 0.0, DbgFlags::empty())?;
+    /// assert_eq!(
+    ///     output,
+    ///     "\
+    /// 432 B ⏺
+    /// 424 B ├╴2
+    ///   8 B ╰╴… 2 nodes below threshold
+    /// "
+    /// );
+    /// # Ok::<(), core::fmt::Error>(())
+    /// ```
+    fn mem_dbg_filtered_on(
+        &self,
+        writer: &mut impl core::fmt::Write,
+        min_size: usize,
+        min_percentage: f64,
+        flags: DbgFlags,
+    ) -> core::fmt::Result {
+        let mut tree = self.mem_tree(flags);
+        tree.filter(min_size, min_percentage, flags);
+        tree.write_on(writer, flags)
+    }
+
+    /// Writes to stdout the `n` largest leaves of the tree displayed by
+    /// [`mem_dbg`](MemDbg::mem_dbg), that is, the parts of the structure that
+    /// are not further expanded, in decreasing order of size.
+    ///
+    /// See [`MemNode::write_top_on`] for more details.
+    #[cfg(feature = "std")]
+    fn mem_dbg_top(&self, n: usize, flags: DbgFlags) -> core::fmt::Result {
+        self.mem_dbg_top_on(
+            &mut IoWriter::new(std::io::stdout().lock()),
+            n,
+            stdout_flags(flags),
+        )
+    }
+
+    /// Writes to a [`core::fmt::Write`] the `n` largest leaves of the tree
+    /// displayed by [`mem_dbg_on`](MemDbg::mem_dbg_on), that is, the parts of
+    /// the structure that are not further expanded, in decreasing order of
+    /// size.
+    ///
+    /// See [`MemNode::write_top_on`] for more details.
+    ///
+    /// ```
+    /// use mem_dbg::*;
+    ///
+    /// let mut output = String::new();
+    /// (0_u8, (vec![1_u32, 2, 3], 4_u16)).mem_dbg_top_on(&mut output, 2, DbgFlags::empty())?;
+    /// assert_eq!(
+    ///     output,
+    ///     "\
+    /// 36 B root.1.0
+    ///  2 B root.1.1
+    /// "
+    /// );
+    /// # Ok::<(), core::fmt::Error>(())
+    /// ```
+    fn mem_dbg_top_on(
+        &self,
+        writer: &mut impl core::fmt::Write,
+        n: usize,
+        flags: DbgFlags,
+    ) -> core::fmt::Result {
+        self.mem_tree(flags).write_top_on(writer, n, flags)
+    }
+
+    /// Writes to a [`core::fmt::Write`] the `n` largest leaves of the tree
+    /// displayed by [`mem_dbg_config_on`](MemDbg::mem_dbg_config_on) as
+    /// [`mem_dbg_top_on`](MemDbg::mem_dbg_top_on), using the given
+    /// configuration.
+    ///
+    /// See [`MemNode::write_top_config_on`] for more details.
+    fn mem_dbg_top_config_on(
+        &self,
+        writer: &mut impl core::fmt::Write,
+        n: usize,
+        config: &DbgConfig,
+    ) -> core::fmt::Result {
+        impl_mem_dbg::with_max_elements(config.max_elements, || self.mem_tree(config.flags))
+            .write_top_config_on(writer, n, config)
+    }
+
+    /// Returns the size of the part of the structure at the given path, or
+    /// `None` if there is no such part.
+    ///
+    /// The path is made of the dot-separated names of fields, such as
+    /// `b.c.1` (numeric segments are the indices of tuple fields), as in
+    /// [`MemNode::find`]. The fields of an enum are the ones of the active
+    /// variant. Elements of collections cannot be addressed.
+    ///
+    /// ```
+    /// use mem_dbg::*;
+    ///
+    /// let s = (0_u8, (vec![1_u32, 2, 3], 4_u16));
+    /// assert_eq!(s.mem_size_of_path("1.0", SizeFlags::default()), Some(36));
+    /// assert_eq!(s.mem_size_of_path("1.2", SizeFlags::default()), None);
+    /// ```
+    fn mem_size_of_path(&self, path: &str, flags: SizeFlags) -> Option<usize> {
+        self.mem_tree(DbgFlags::from_size_flags(flags))
+            .find(path)
+            .map(|node| node.size)
+    }
+
+    /// Returns the paths and sizes of the `n` largest leaves of the tree
+    /// displayed by [`mem_dbg`](MemDbg::mem_dbg), that is, the parts of the
+    /// structure that are not further expanded, in decreasing order of size.
+    ///
+    /// See [`MemNode::largest`] for the format of paths and for ties.
+    ///
+    /// ```
+    /// use mem_dbg::*;
+    ///
+    /// let largest = (0_u8, (vec![1_u32, 2, 3], 4_u16)).largest(1, DbgFlags::empty());
+    /// assert_eq!(largest, vec![("1.0".to_string(), 36)]);
+    /// ```
+    fn largest(&self, n: usize, flags: DbgFlags) -> Vec<(String, usize)> {
+        self.mem_tree(flags).largest(n)
+    }
+
+    /// Returns the paths and sizes of the `n` largest subtrees of the tree
+    /// displayed by [`mem_dbg`](MemDbg::mem_dbg), excluding the root, that
+    /// is, the parts of the structure that are further expanded, in
+    /// decreasing order of size.
+    ///
+    /// See [`MemNode::largest_subtrees`] for more details.
+    fn largest_subtrees(&self, n: usize, flags: DbgFlags) -> Vec<(String, usize)> {
+        self.mem_tree(flags).largest_subtrees(n)
+    }
+
+    /// Returns, for each type name of the values in the structure, their
+    /// overall size and their number, in decreasing order of size.
+    ///
+    /// Type names are always computed, as if [`DbgFlags::TYPE_NAME`] were
+    /// set; the other flags determine the tree from which sizes are
+    /// computed (e.g., with [`DbgFlags::EXPAND_ELEMENTS`] the elements of
+    /// sequences are counted separately). See [`MemNode::by_type`] for more
+    /// details.
+    ///
+    /// ```
+    /// use mem_dbg::*;
+    ///
+    /// let v = vec![String::from("a"), String::from("bc")];
+    /// let types = v.mem_dbg_by_type(DbgFlags::EXPAND_ELEMENTS | DbgFlags::SHORT_TYPE_NAME);
+    /// assert_eq!(types[0], ("String".to_string(), 51, 2));
+    /// assert_eq!(types[1], ("Vec<String>".to_string(), 24, 1));
+    /// ```
+    fn mem_dbg_by_type(&self, flags: DbgFlags) -> Vec<(String, usize, usize)> {
+        self.mem_tree((flags | DbgFlags::TYPE_NAME) - DbgFlags::QUIET_TYPES)
+            .by_type()
+    }
+
+    /// Writes to stdout a table of the `n` types accounting for the largest
+    /// part of the structure, as returned by
+    /// [`mem_dbg_by_type`](MemDbg::mem_dbg_by_type).
+    ///
+    /// See [`MemNode::write_top_types_on`] for more details.
+    #[cfg(feature = "std")]
+    fn mem_dbg_top_types(&self, n: usize, flags: DbgFlags) -> core::fmt::Result {
+        self.mem_dbg_top_types_on(
+            &mut IoWriter::new(std::io::stdout().lock()),
+            n,
+            stdout_flags(flags),
         )
     }
+
+    /// Writes to a [`core::fmt::Write`] a table of the `n` types accounting
+    /// for the largest part of the structure, as returned by
+    /// [`mem_dbg_by_type`](MemDbg::mem_dbg_by_type).
+    ///
+    /// See [`MemNode::write_top_types_on`] for more details.
+    ///
+    /// ```
+    /// use mem_dbg::*;
+    ///
+    /// let mut output = String::new();
+    /// let v = vec![String::from("a"), String::from("bc")];
+    /// v.mem_dbg_top_types_on(&mut output, 2, DbgFlags::EXPAND_ELEMENTS | DbgFlags::SHORT_TYPE_NAME)?;
+    /// assert_eq!(
+    ///     output,
+    ///     "\
+    /// 51 B 2× String
+    /// 24 B 1× Vec<String>
+    /// "
+    /// );
+    /// # Ok::<(), core::fmt::Error>(())
+    /// ```
+    fn mem_dbg_top_types_on(
+        &self,
+        writer: &mut impl core::fmt::Write,
+        n: usize,
+        flags: DbgFlags,
+    ) -> core::fmt::Result {
+        self.mem_dbg_top_types_config_on(writer, n, &DbgConfig::new().flags(flags))
+    }
+
+    /// Writes to a [`core::fmt::Write`] a table of the `n` types accounting
+    /// for the largest part of the structure as
+    /// [`mem_dbg_top_types_on`](MemDbg::mem_dbg_top_types_on), using the
+    /// given configuration.
+    ///
+    /// See [`MemNode::write_top_types_config_on`] for more details.
+    fn mem_dbg_top_types_config_on(
+        &self,
+        writer: &mut impl core::fmt::Write,
+        n: usize,
+        config: &DbgConfig,
+    ) -> core::fmt::Result {
+        let flags = (config.flags | DbgFlags::TYPE_NAME) - DbgFlags::QUIET_TYPES;
+        impl_mem_dbg::with_max_elements(config.max_elements, || self.mem_tree(flags))
+            .write_top_types_config_on(writer, n, &DbgConfig { flags, ..*config })
+    }
+
+    /// Writes to stdout the differences between the memory usage of the
+    /// structure and a snapshot `before` of the memory usage of the same
+    /// structure, as returned by [`mem_tree`](MemDbg::mem_tree).
+    ///
+    /// See [`mem_diff_on`](MemDbg::mem_diff_on) for more details.
+    #[cfg(feature = "std")]
+    fn mem_diff(&self, before: &MemNode, flags: DbgFlags) -> core::fmt::Result {
+        self.mem_diff_on(
+            &mut IoWriter::new(std::io::stdout().lock()),
+            before,
+            stdout_flags(flags),
+        )
+    }
+
+    /// Writes to a [`core::fmt::Write`] the differences between the memory
+    /// usage of the structure and a snapshot `before` of the memory usage of
+    /// the same structure, as returned by [`mem_tree`](MemDbg::mem_tree),
+    /// annotating each node with the change of its size.
+    ///
+    /// Nodes are matched by path, so containers may change length between
+    /// the snapshots (see [`mem_tree_diff`]). `flags` should be the ones used
+    /// to build `before`; with [`DbgFlags::COLOR`], changes are colored
+    /// depending on their absolute value as sizes (see [`ColorScheme`]), and
+    /// with [`DbgFlags::HIDE_UNCHANGED`] the descendants of unchanged nodes
+    /// are not displayed.
+    ///
+    /// ```
+    /// use mem_dbg::*;
+    ///
+    /// let mut v = (vec![0_u8; 10], vec![0_u8; 10]);
+    /// let flags = DbgFlags::HIDE_UNCHANGED;
+    /// let before = v.mem_tree(flags);
+    /// v.1.extend([0; 2000]);
+    /// let mut output = String::new();
+    /// v.mem_diff_on(&mut output, &before, flags)?;
+    /// assert_eq!(
+    ///     output,
+    ///     "\
+    /// +2.000 kB ⏺
+    ///       0 B ├╴0
+    /// +2.000 kB ╰╴1
+    /// "
+    /// );
+    /// # Ok::<(), core::fmt::Error>(())
+    /// ```
+    fn mem_diff_on(
+        &self,
+        writer: &mut impl core::fmt::Write,
+        before: &MemNode,
+        flags: DbgFlags,
+    ) -> core::fmt::Result {
+        mem_tree_diff(before, &self.mem_tree(flags)).write_on(writer, flags)
+    }
+
+    /// Writes to a [`core::fmt::Write`] the differences between the memory
+    /// usage of the structure and a snapshot `before` as
+    /// [`mem_diff_on`](MemDbg::mem_diff_on), using the flags of the given
+    /// configuration and coloring changes following
+    /// [`DbgConfig::color_scheme`].
+    fn mem_diff_config_on(
+        &self,
+        writer: &mut impl core::fmt::Write,
+        before: &MemNode,
+        config: &DbgConfig,
+    ) -> core::fmt::Result {
+        mem_tree_diff(before, &self.mem_tree(config.flags)).write_config_on(writer, config)
+    }
+
+    /// Emits a [`tracing`] event for each line that would be displayed by
+    /// [`mem_dbg_depth`](MemDbg::mem_dbg_depth), except for labels, within
+    /// nested spans mirroring the structure.
+    ///
+    /// See [`MemNode::trace`] for a description of the events.
+    #[cfg(feature = "tracing")]
+    fn mem_dbg_trace(&self, max_depth: usize, flags: DbgFlags) {
+        self.mem_tree_depth(max_depth, flags).trace()
+    }
+
+    /// Writes to a [`core::fmt::Write`] debug infos about the structure memory
+    /// usage as comma-separated values, with a row for each line displayed by
+    /// [`mem_dbg_on`](MemDbg::mem_dbg_on).
+    ///
+    /// See [`MemNode::write_csv_on`] for a description of the columns.
+    ///
+    /// ```
+    /// use mem_dbg::*;
+    ///
+    /// let mut output = String::new();
+    /// (0_u8, vec![1_u32, 2, 3]).mem_dbg_csv_on(&mut output, DbgFlags::TYPE_NAME)?;
+    /// assert_eq!(
+    ///     output,
+    ///     "\
+    /// path,type,self_bytes,subtree_bytes,percent,depth
+    /// ,\"(u8, alloc::vec::Vec<u32>)\",7,44,100.00,0
+    /// 0,u8,1,1,2.27,1
+    /// 1,alloc::vec::Vec<u32>,36,36,81.82,1
+    /// "
+    /// );
+    /// # Ok::<(), core::fmt::Error>(())
+    /// ```
+    fn mem_dbg_csv_on(
+        &self,
+        writer: &mut impl core::fmt::Write,
+        flags: DbgFlags,
+    ) -> core::fmt::Result {
+        self.mem_tree(flags).write_csv_on(writer, ',')
+    }
+
+    /// Writes to a [`core::fmt::Write`] debug infos about the structure memory
+    /// usage as tab-separated values, with a row for each line displayed by
+    /// [`mem_dbg_on`](MemDbg::mem_dbg_on).
+    ///
+    /// See [`MemNode::write_csv_on`] for a description of the columns.
+    fn mem_dbg_tsv_on(
+        &self,
+        writer: &mut impl core::fmt::Write,
+        flags: DbgFlags,
+    ) -> core::fmt::Result {
+        self.mem_tree(flags).write_csv_on(writer, '\t')
+    }
+
+    /// Writes to a [`core::fmt::Write`] debug infos about the structure memory
+    /// usage in the folded-stacks format used by flame graph tools, such as
+    /// [`inferno-flamegraph`](https://crates.io/crates/inferno).
+    ///
+    /// See [`MemNode::write_folded_on`] for a description of the format.
+    ///
+    /// ```
+    /// use mem_dbg::*;
+    ///
+    /// let mut output = String::new();
+    /// (0_u8, vec![1_u32, 2, 3]).mem_dbg_folded_on(&mut output, DbgFlags::empty())?;
+    /// assert_eq!(
+    ///     output,
+    ///     "\
+    /// root 7
+    /// root;0 1
+    /// root;1 36
+    /// "
+    /// );
+    /// # Ok::<(), core::fmt::Error>(())
+    /// ```
+    fn mem_dbg_folded_on(
+        &self,
+        writer: &mut impl core::fmt::Write,
+        flags: DbgFlags,
+    ) -> core::fmt::Result {
+        self.mem_tree(flags).write_folded_on(writer)
+    }
+
+    /// Writes to a [`core::fmt::Write`] debug infos about the structure memory
+    /// usage as a [Graphviz](https://graphviz.org/) DOT digraph, in which the
+    /// vertices are the lines displayed by [`mem_dbg_on`](MemDbg::mem_dbg_on)
+    /// and the edges follow ownership.
+    ///
+    /// See [`MemNode::write_dot_on`] for a description of the graph.
+    ///
+    /// ```
+    /// use mem_dbg::*;
+    ///
+    /// let mut output = String::new();
+    /// (0_u8, vec![1_u32, 2, 3]).mem_dbg_dot_on(&mut output, DbgFlags::default())?;
+    /// assert!(output.starts_with("digraph mem_dbg {"));
+    /// # Ok::<(), core::fmt::Error>(())
+    /// ```
+    fn mem_dbg_dot_on(
+        &self,
+        writer: &mut impl core::fmt::Write,
+        flags: DbgFlags,
+    ) -> core::fmt::Result {
+        self.mem_tree(flags).write_dot_on(writer)
+    }
+
+    /// Writes to a [`core::fmt::Write`] debug infos about the structure memory
+    /// usage as [`mem_dbg_dot_on`](MemDbg::mem_dbg_dot_on), using the flags of
+    /// the given configuration and filling the vertices following
+    /// [`DbgConfig::color_scheme`].
+    fn mem_dbg_dot_config_on(
+        &self,
+        writer: &mut impl core::fmt::Write,
+        config: &DbgConfig,
+    ) -> core::fmt::Result {
+        self.mem_tree(config.flags)
+            .write_dot_config_on(writer, config)
+    }
+
+    /// Returns debug infos about the structure memory usage in JSON format,
+    /// expanding all levels of nested structures.
+    ///
+    /// See [`MemNode::to_json`] for a description of the format.
+    #[cfg(feature = "json")]
+    fn mem_dbg_json(&self, flags: DbgFlags) -> String {
+        self.mem_tree(flags).to_json(flags).to_string()
+    }
+
+    /// Writes to a [`core::fmt::Write`] debug infos about the structure memory
+    /// usage in JSON format, expanding all levels of nested structures.
+    ///
+    /// See [`MemNode::to_json`] for a description of the format.
+    #[cfg(feature = "json")]
+    fn mem_dbg_json_on(
+        &self,
+        writer: &mut impl core::fmt::Write,
+        flags: DbgFlags,
+    ) -> core::fmt::Result {
+        write!(writer, "{}", self.mem_tree(flags).to_json(flags))
+    }
+
+    /// Returns the tree describing the structure memory usage, expanding all
+    /// levels of nested structures.
+    ///
+    /// The tree contains the same information displayed by
+    /// [`mem_dbg`](MemDbg::mem_dbg), which is indeed obtained by writing it
+    /// with [`MemNode::write_on`].
+    ///
+    /// ```
+    /// use mem_dbg::*;
+    ///
+    /// let tree = (0_u8, vec![1_u32, 2, 3]).mem_tree(DbgFlags::empty());
+    /// assert_eq!(tree.size, 8 + 24 + 12);
+    /// assert_eq!(tree.children[0].name, "0");
+    /// assert_eq!(tree.children[0].padding, 7);
+    /// assert_eq!(tree.children[1].size, 24 + 12);
+    /// ```
+    #[inline(always)]
+    fn mem_tree(&self, flags: DbgFlags) -> MemNode {
+        self.mem_tree_depth(usize::MAX, flags)
+    }
+
+    /// Returns the tree describing the structure memory usage as
+    /// [`mem_tree`](MemDbg::mem_tree), but expanding only up to `max_depth`
+    /// levels of nested structures.
+    fn mem_tree_depth(&self, max_depth: usize, flags: DbgFlags) -> MemNode {
+        let mut tree = crate::impl_mem_dbg::with_new_rc_visited(|| {
+            self._mem_dbg_node("", core::mem::size_of_val(self), max_depth, flags)
+        });
+        if flags.contains(DbgFlags::STACK_SIZE) {
+            let stack = format!("stack {}", format_size(core::mem::size_of_val(self), flags));
+            tree.info = Some(match tree.info {
+                Some(info) => format!("{}, {}", info, stack),
+                None => stack,
+            });
+        }
+        tree
+    }
+
+    /// Returns an adapter implementing [`core::fmt::Display`] by writing the
+    /// debug infos about the structure memory usage as
+    /// [`mem_dbg_on`](MemDbg::mem_dbg_on), so that they can be passed to
+    /// macros such as [`format!`] or `log::info!`.
+    ///
+    /// The adapter is lazy: sizes are computed each time it is formatted, and
+    /// not when it is created, so they reflect the state of the structure at
+    /// formatting time.
+    ///
+    /// ```
+    /// use mem_dbg::*;
+    ///
+    /// let v = vec![0_u8; 10];
+    /// assert_eq!(
+    ///     format!("stats:\n{}", v.mem_dbg_display(DbgFlags::empty())),
+    ///     "stats:\n34 B ⏺\n"
+    /// );
+    /// ```
+    #[inline(always)]
+    fn mem_dbg_display(&self, flags: DbgFlags) -> MemDbgDisplay<'_, Self> {
+        MemDbgDisplay { value: self, flags }
+    }
+}
+
+/// An adapter displaying the memory usage of a value, as returned by
+/// [`MemDbg::mem_dbg_display`].
+#[derive(Debug, Clone, Copy)]
+pub struct MemDbgDisplay<'a, T: ?Sized> {
+    value: &'a T,
+    flags: DbgFlags,
+}
+
+impl<T: ?Sized + MemDbg> core::fmt::Display for MemDbgDisplay<'_, T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        self.value.mem_dbg_on(f, self.flags)
+    }
 }
 
 /// Implemens [`MemDbg`] for all types that implement [`MemDbgImpl`].
@@ -253,165 +1677,182 @@ impl<T: MemDbgImpl> MemDbg for T {}
 /// The default no-op implementation is used by all types in which it does not
 /// make sense, or it is impossible, to recurse.
 pub trait MemDbgImpl: MemSize {
+    /// Adds to `node`, which describes `self`, the nodes describing the parts
+    /// of `self`, such as its fields.
+    ///
+    /// This method is called only if `max_depth` is positive, and the
+    /// children must be built using a maximum depth of `max_depth - 1` (see
+    /// [`MemDbgImpl::_mem_dbg_node`]).
     #[inline(always)]
-    fn _mem_dbg_rec_on(
-        &self,
-        _writer: &mut impl core::fmt::Write,
-        _total_size: usize,
-        _max_depth: usize,
-        _prefix: &mut String,
-        _is_last: bool,
-        _flags: DbgFlags,
-    ) -> core::fmt::Result {
-        Ok(())
-    }
+    fn _mem_dbg_rec(&self, _node: &mut MemNode, _max_depth: usize, _flags: DbgFlags) {}
 
-    #[cfg(feature = "std")]
-    #[doc(hidden)]
+    /// Returns additional information about `self` to be displayed after its
+    /// name and type name, such as the length of a collection when `flags`
+    /// contains [`DbgFlags::LEN`].
     #[inline(always)]
-    fn _mem_dbg_depth(
-        &self,
-        total_size: usize,
-        max_depth: usize,
-        padded_size: usize,
-        flags: DbgFlags,
-    ) -> core::fmt::Result {
-        struct Wrapper(std::io::Stdout);
-        impl core::fmt::Write for Wrapper {
-            #[inline(always)]
-            fn write_str(&mut self, s: &str) -> core::fmt::Result {
-                use std::io::Write;
-                self.0
-                    .lock()
-                    .write(s.as_bytes())
-                    .map_err(|_| core::fmt::Error)
-                    .map(|_| ())
-            }
-        }
-        self._mem_dbg_depth_on(
-            &mut Wrapper(std::io::stdout()),
-            total_size,
-            max_depth,
-            &mut String::new(),
-            Some("⏺"),
-            true,
-            padded_size,
-            flags,
-        )
+    fn _mem_dbg_info(&self, _flags: DbgFlags) -> Option<String> {
+        None
     }
 
+    /// Returns the node describing `self`, given its name and its padded
+    /// size, expanding only up to `max_depth` levels of nested structures.
     #[inline(always)]
-    #[allow(clippy::too_many_arguments)]
-    fn _mem_dbg_depth_on(
+    fn _mem_dbg_node(
         &self,
-        writer: &mut impl core::fmt::Write,
-        total_size: usize,
-        max_depth: usize,
-        prefix: &mut String,
-        field_name: Option<&str>,
-        is_last: bool,
+        name: &str,
         padded_size: usize,
+        max_depth: usize,
         flags: DbgFlags,
-    ) -> core::fmt::Result {
-        if prefix.len() > max_depth {
-            return Ok(());
+    ) -> MemNode {
+        let mut node = MemNode::new(self, name, padded_size, flags);
+        node.info = self._mem_dbg_info(flags);
+        let hidden =
+            flags.contains(DbgFlags::HIDE_LEAVES) && node.size == core::mem::size_of_val(self);
+        if max_depth > 0 && !hidden {
+            self._mem_dbg_rec(&mut node, max_depth, flags);
         }
-        let real_size = <Self as MemSize>::mem_size(self, flags.to_size_flags());
-        if flags.contains(DbgFlags::HUMANIZE) {
-            let (value, uom) = crate::utils::humanize_float(real_size as f64);
-            if uom == " B" {
-                writer.write_fmt(format_args!("{:>5}  B ", real_size))?;
-            } else {
-                let mut precision = 4;
-                let a = value.abs();
-                if a >= 100.0 {
-                    precision = 1;
-                } else if a >= 10.0 {
-                    precision = 2;
-                } else if a >= 1.0 {
-                    precision = 3;
-                }
-                writer.write_fmt(format_args!("{0:>4.1$} {2} ", value, precision, uom))?;
-            }
-        } else if flags.contains(DbgFlags::SEPARATOR) {
-            let mut align = crate::utils::n_of_digits(total_size);
-            let mut real_size = real_size;
-            align += align / 3;
-            let mut digits = crate::utils::n_of_digits(real_size);
-            let digit_align = digits + digits / 3;
-            for _ in digit_align..align {
-                writer.write_char(' ')?;
-            }
-
-            let first_digits = digits % 3;
-            let mut multiplier = 10_usize.pow((digits - first_digits) as u32);
-            if first_digits != 0 {
-                writer.write_fmt(format_args!("{}", real_size / multiplier))?;
-            } else {
-                multiplier /= 1000;
-                digits -= 3;
-                writer.write_fmt(format_args!(" {}", real_size / multiplier))?;
-            }
+        node
+    }
+}
 
-            while digits >= 3 {
-                real_size %= multiplier;
-                multiplier /= 1000;
-                writer.write_fmt(format_args!("_{:03}", real_size / multiplier))?;
-                digits -= 3;
-            }
+/// Given the offsets of the fields of a structure and its size, returns pairs
+/// made of the index of a field and its padded size, that is, its size plus
+/// the padding following it.
+///
+/// The pairs are sorted by index, or by offset if `layout_order` is true.
+///
+/// This function is used by the [`MemDbg`](mem_dbg_derive::MemDbg) derive
+/// macro to compute padded sizes at compile time.
+#[doc(hidden)]
+pub const fn _padded_sizes<const N: usize>(
+    offsets: [usize; N],
+    size: usize,
+    layout_order: bool,
+) -> [(usize, usize); N] {
+    let mut id_offsets = [(0, 0); N];
+    let mut i = 0;
+    while i < N {
+        id_offsets[i] = (i, offsets[i]);
+        i += 1;
+    }
 
-            writer.write_str(" B ")?;
-        } else {
-            let align = crate::utils::n_of_digits(total_size);
-            writer.write_fmt(format_args!("{:>align$} B ", real_size, align = align))?;
-        }
-
-        if flags.contains(DbgFlags::PERCENTAGE) {
-            writer.write_fmt(format_args!(
-                "{:>6.2}% ",
-                if total_size == 0 {
-                    100.0
-                } else {
-                    100.0 * real_size as f64 / total_size as f64
-                }
-            ))?;
-        }
-        if !prefix.is_empty() {
-            writer.write_str(&prefix[2..])?;
-            if is_last {
-                writer.write_char('╰')?;
-            } else {
-                writer.write_char('├')?;
-            }
-            writer.write_char('╴')?;
+    // Stable insertion sort by offset
+    i = 1;
+    while i < N {
+        let mut j = i;
+        while j > 0 && id_offsets[j - 1].1 > id_offsets[j].1 {
+            let t = id_offsets[j - 1];
+            id_offsets[j - 1] = id_offsets[j];
+            id_offsets[j] = t;
+            j -= 1;
         }
+        i += 1;
+    }
 
-        if let Some(field_name) = field_name {
-            writer.write_fmt(format_args!("{:}", field_name))?;
-        }
+    let mut id_sizes = [(0, 0); N];
+    i = 0;
+    while i < N {
+        let (id, offset) = id_offsets[i];
+        let next_offset = if i + 1 < N { id_offsets[i + 1].1 } else { size };
+        let pos = if layout_order { i } else { id };
+        id_sizes[pos] = (id, next_offset - offset);
+        i += 1;
+    }
+    id_sizes
+}
 
-        if flags.contains(DbgFlags::TYPE_NAME) {
-            writer.write_fmt(format_args!(": {:}", core::any::type_name::<Self>()))?;
-        }
+/// Returns the node displaying `count` siblings of type `type_name` with
+/// overall size `size` when [`DbgFlags::DEDUP_TYPES`] is set.
+pub(crate) fn dedup_node(type_name: &str, count: usize, size: usize, flags: DbgFlags) -> MemNode {
+    let times = if flags.contains(DbgFlags::ASCII) {
+        "x"
+    } else {
+        "×"
+    };
+    let type_name = if flags.contains(DbgFlags::SHORT_TYPE_NAME) {
+        short_type_name(type_name)
+    } else {
+        type_name.to_string()
+    };
+    MemNode::group(&format!("[{}{}] {}", count, times, type_name), size)
+}
 
-        let padding = padded_size - std::mem::size_of_val(self);
-        if padding != 0 {
-            writer.write_fmt(format_args!(" [{}B]", padding))?;
-        }
+/// The line displayed by the [`MemDbg`](mem_dbg_derive::MemDbg) derive macro
+/// for a field.
+#[doc(hidden)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum _FieldLine {
+    /// The field is displayed as usual.
+    Field,
+    /// The field is the first of a group of fields of the same type, which
+    /// are displayed in a single line if [`DbgFlags::DEDUP_TYPES`] is set.
+    Group {
+        type_name: &'static str,
+        count: usize,
+        size: usize,
+    },
+    /// The field belongs to a group displayed by a previous line.
+    Hidden,
+}
 
-        writer.write_char('\n')?;
+impl _FieldLine {
+    /// Returns the lines of `N` fields displayed as usual.
+    pub fn fields<const N: usize>() -> [Self; N] {
+        [Self::Field; N]
+    }
 
-        if is_last {
-            prefix.push_str("  ");
-        } else {
-            prefix.push_str("│ ");
+    /// Returns the lines of the fields of a structure when
+    /// [`DbgFlags::DEDUP_TYPES`] is set, given the pairs returned by
+    /// [`_padded_sizes`], and the type names and sizes of the fields in
+    /// declaration order.
+    ///
+    /// Fields whose type is unique are displayed as usual; the other ones are
+    /// grouped at the position of the first field of the group.
+    pub fn dedup<const N: usize>(
+        id_sizes: &[(usize, usize)],
+        type_names: [&'static str; N],
+        sizes: [usize; N],
+    ) -> [Self; N] {
+        let mut lines = [Self::Hidden; N];
+        for (i, &(field_idx, _)) in id_sizes.iter().enumerate() {
+            let type_name = type_names[field_idx];
+            if id_sizes[..i]
+                .iter()
+                .any(|&(j, _)| type_names[j] == type_name)
+            {
+                continue;
+            }
+            let (count, size) = (0..N)
+                .filter(|&j| type_names[j] == type_name)
+                .fold((0, 0), |(count, size), j| (count + 1, size + sizes[j]));
+            lines[i] = if count == 1 {
+                Self::Field
+            } else {
+                Self::Group {
+                    type_name,
+                    count,
+                    size,
+                }
+            };
         }
+        lines
+    }
 
-        self._mem_dbg_rec_on(writer, total_size, max_depth, prefix, is_last, flags)?;
-
-        prefix.pop();
-        prefix.pop();
-
-        Ok(())
+    /// Adds to `node` the node of a group, if any, and returns whether the
+    /// field must be added by the caller.
+    pub fn push_on(self, node: &mut MemNode, flags: DbgFlags) -> bool {
+        match self {
+            Self::Field => true,
+            Self::Group {
+                type_name,
+                count,
+                size,
+            } => {
+                node.children
+                    .push(dedup_node(type_name, count, size, flags));
+                false
+            }
+            Self::Hidden => false,
+        }
     }
 }
@@ -0,0 +1,234 @@
+/*
+ * SPDX-FileCopyrightText: 2023 Tommaso Fontana
+ * SPDX-FileCopyrightText: 2023 Inria
+ * SPDX-FileCopyrightText: 2023 Sebastiano Vigna
+ *
+ * SPDX-License-Identifier: Apache-2.0 OR LGPL-2.1-or-later
+ */
+
+//! Comparison of the trees returned by
+//! [`MemDbg::mem_tree`](crate::MemDbg::mem_tree).
+
+use crate::utils::*;
+use crate::{ColorScheme, DbgConfig, DbgFlags, MemNode, MemNodeKind};
+
+/// Whether a node of a [`MemDiff`] is present in both trees, or only in one
+/// of them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MemDiffStatus {
+    /// The node is present in both trees.
+    Both,
+    /// The node is present only in the second tree.
+    Added,
+    /// The node is present only in the first tree.
+    Removed,
+}
+
+/// A node of the tree of size differences returned by [`mem_tree_diff`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MemDiff {
+    /// The name of the node (see [`MemNode::name`]).
+    pub name: String,
+    /// The size of the node in the first tree, or zero if the node has been
+    /// added.
+    pub before: usize,
+    /// The size of the node in the second tree, or zero if the node has been
+    /// removed.
+    pub after: usize,
+    /// Whether the node is present in both trees.
+    pub status: MemDiffStatus,
+    /// The differences of the children of the node.
+    pub children: Vec<MemDiff>,
+}
+
+impl MemDiff {
+    /// Returns the difference between the size after and the size before.
+    pub fn delta(&self) -> isize {
+        self.after as isize - self.before as isize
+    }
+
+    /// Returns the difference of a node present in a single tree, with its
+    /// descendants.
+    fn one_sided(node: &MemNode, status: MemDiffStatus) -> Self {
+        let (before, after) = match status {
+            MemDiffStatus::Removed => (node.size, 0),
+            _ => (0, node.size),
+        };
+        Self {
+            name: node.name.clone(),
+            before,
+            after,
+            status,
+            children: sized_children(node)
+                .map(|child| Self::one_sided(child, status))
+                .collect(),
+        }
+    }
+
+    /// Returns whether the sizes of this node and of all its descendants are
+    /// unchanged.
+    pub fn is_unchanged(&self) -> bool {
+        self.status == MemDiffStatus::Both
+            && self.delta() == 0
+            && self.children.iter().all(MemDiff::is_unchanged)
+    }
+
+    /// Writes to a [`core::fmt::Write`] the tree of differences, annotating
+    /// each node with the change of its size in human readable format (e.g.,
+    /// `+3.200 MB` or `-1.100 kB`).
+    ///
+    /// The flags [`DbgFlags::ASCII`], [`DbgFlags::COLOR`] (changes are
+    /// colored depending on their absolute value following the thresholds of
+    /// the default [`ColorScheme`]), and [`DbgFlags::HIDE_UNCHANGED`] are
+    /// honored; the other flags are ignored.
+    pub fn write_on(
+        &self,
+        writer: &mut impl core::fmt::Write,
+        flags: DbgFlags,
+    ) -> core::fmt::Result {
+        self.write_config_on(writer, &DbgConfig::new().flags(flags))
+    }
+
+    /// Writes to a [`core::fmt::Write`] the tree of differences as
+    /// [`MemDiff::write_on`], using the flags of the given configuration and
+    /// coloring changes following the thresholds of
+    /// [`DbgConfig::color_scheme`].
+    pub fn write_config_on(
+        &self,
+        writer: &mut impl core::fmt::Write,
+        config: &DbgConfig,
+    ) -> core::fmt::Result {
+        self.write_rec_on(
+            writer,
+            &mut String::new(),
+            true,
+            &config.color_scheme,
+            no_color(config.flags),
+        )
+    }
+
+    fn write_rec_on(
+        &self,
+        writer: &mut impl core::fmt::Write,
+        prefix: &mut String,
+        is_last: bool,
+        color_scheme: &ColorScheme,
+        flags: DbgFlags,
+    ) -> core::fmt::Result {
+        let delta = self.delta();
+        let (value, uom) = humanize_float(delta.unsigned_abs() as f64);
+        let sign = match delta.signum() {
+            1 => "+",
+            -1 => "-",
+            _ => "",
+        };
+        let delta_str = if uom == " B" {
+            format!("{}{} B", sign, delta.unsigned_abs())
+        } else {
+            format!("{}{:.*} {}", sign, humanized_precision(value), value, uom)
+        };
+        let color = flags.contains(DbgFlags::COLOR) && delta != 0;
+        if color {
+            writer.write_str(color_scheme.ansi_color(delta.unsigned_abs()))?;
+        }
+        write!(writer, "{:>9} ", delta_str)?;
+        if color {
+            writer.write_str("\x1b[0m")?;
+        }
+        write_tree_prefix_on(writer, prefix, is_last, flags)?;
+        if prefix.is_empty() {
+            writer.write_str(tree_root(flags))?;
+        }
+        writer.write_str(&self.name)?;
+        match self.status {
+            MemDiffStatus::Both => {}
+            MemDiffStatus::Added => writer.write_str(" (added)")?,
+            MemDiffStatus::Removed => writer.write_str(" (removed)")?,
+        }
+        writeln!(writer)?;
+
+        if self.children.is_empty()
+            || (flags.contains(DbgFlags::HIDE_UNCHANGED) && self.is_unchanged())
+        {
+            return Ok(());
+        }
+        prefix.push_str(tree_indent(is_last, flags));
+        for (i, child) in self.children.iter().enumerate() {
+            child.write_rec_on(
+                writer,
+                prefix,
+                i == self.children.len() - 1,
+                color_scheme,
+                flags,
+            )?;
+        }
+        prefix.pop();
+        prefix.pop();
+        Ok(())
+    }
+}
+
+/// Displays the tree of differences as [`MemDiff::write_on`] with no flags.
+impl core::fmt::Display for MemDiff {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        self.write_on(f, DbgFlags::empty())
+    }
+}
+
+/// Returns the children of `node` that have a size, that is, all children
+/// but labels.
+fn sized_children(node: &MemNode) -> impl Iterator<Item = &MemNode> {
+    node.children
+        .iter()
+        .filter(|child| child.kind != MemNodeKind::Label)
+}
+
+/// Returns the tree of the differences in size between two trees returned by
+/// [`MemDbg::mem_tree`](crate::MemDbg::mem_tree), such as the trees of a
+/// structure before and after an operation.
+///
+/// Nodes are matched by their path from the root, that is, children are
+/// matched by name: the children present in both trees appear in the order of
+/// `before`, followed by the children present only in `after`. Labels (see
+/// [`MemNodeKind::Label`]), which carry no size, are ignored.
+///
+/// ```
+/// use mem_dbg::*;
+///
+/// let mut v = vec![1_u32, 2, 3];
+/// let before = v.mem_tree(DbgFlags::empty());
+/// v.push(4);
+/// let diff = mem_tree_diff(&before, &v.mem_tree(DbgFlags::empty()));
+/// assert_eq!(diff.delta(), 4);
+/// ```
+pub fn mem_tree_diff(before: &MemNode, after: &MemNode) -> MemDiff {
+    let after_children = sized_children(after).collect::<Vec<_>>();
+    let mut matched = vec![false; after_children.len()];
+    let mut children = Vec::new();
+    for child in sized_children(before) {
+        // The first unmatched child with the same name, if any
+        match (0..after_children.len())
+            .find(|&i| !matched[i] && after_children[i].name == child.name)
+        {
+            Some(i) => {
+                matched[i] = true;
+                children.push(mem_tree_diff(child, after_children[i]));
+            }
+            None => children.push(MemDiff::one_sided(child, MemDiffStatus::Removed)),
+        }
+    }
+    for (child, _) in after_children
+        .iter()
+        .zip(matched)
+        .filter(|(_, matched)| !matched)
+    {
+        children.push(MemDiff::one_sided(child, MemDiffStatus::Added));
+    }
+    MemDiff {
+        name: after.name.clone(),
+        before: before.size,
+        after: after.size,
+        status: MemDiffStatus::Both,
+        children,
+    }
+}
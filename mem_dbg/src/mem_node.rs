@@ -0,0 +1,1164 @@
+/*
+ * SPDX-FileCopyrightText: 2023 Tommaso Fontana
+ * SPDX-FileCopyrightText: 2023 Inria
+ * SPDX-FileCopyrightText: 2023 Sebastiano Vigna
+ *
+ * SPDX-License-Identifier: Apache-2.0 OR LGPL-2.1-or-later
+ */
+
+//! The tree describing the memory usage of a value, as returned by
+//! [`MemDbg::mem_tree`](crate::MemDbg::mem_tree), and its textual rendering.
+
+use crate::utils::*;
+use crate::{ColorScheme, DbgConfig, DbgFlags, MemSize};
+
+/// The kind of a [`MemNode`], which determines how it is displayed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum MemNodeKind {
+    /// A value, such as a field of a structure or an element of a vector.
+    Value,
+    /// A set of values displayed as a whole, such as the keys of a map, or
+    /// siblings of the same type grouped by [`DbgFlags::DEDUP_TYPES`]. The
+    /// size of the node is the overall size of the values.
+    Group,
+    /// A line carrying information but no size, such as the variant of an
+    /// enum.
+    Label,
+}
+
+/// A node of the tree describing the memory usage of a value, as returned by
+/// [`MemDbg::mem_tree`](crate::MemDbg::mem_tree).
+///
+/// Each line displayed by [`MemDbg`](crate::MemDbg) corresponds to a node,
+/// and the text is obtained by [`MemNode::write_on`].
+///
+/// With the `serde` feature, the tree can be serialized and deserialized, with
+/// a field for each field of the node. Names, type names, and information are
+/// owned [`String`]s rather than borrowed strings, so deserialization does not
+/// borrow from its input (the tree implements
+/// [`DeserializeOwned`](https://docs.rs/serde/latest/serde/de/trait.DeserializeOwned.html)
+/// and has no `'de` lifetime), and a tree can be read from a file or a stream.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MemNode {
+    /// The name of the node, such as the name of a field or the index of an
+    /// element; the root has an empty name.
+    pub name: String,
+    /// The type name of the value, if it must be displayed (see
+    /// [`DbgFlags::TYPE_NAME`] and [`DbgFlags::QUIET_TYPES`]).
+    pub type_name: Option<String>,
+    /// Additional information about the value, such as the length of a
+    /// collection when [`DbgFlags::LEN`] is set, displayed in parentheses
+    /// after the type name.
+    pub info: Option<String>,
+    /// The (recursively computed) size of the value, as returned by
+    /// [`MemSize::mem_size`].
+    pub size: usize,
+    /// The padding following the value within its parent.
+    pub padding: usize,
+    /// The offset in bytes of the value within its parent, if the value is a
+    /// field of a structure or of an enum variant.
+    pub offset: Option<usize>,
+    /// The alignment of the value, if the node is of kind
+    /// [`MemNodeKind::Value`].
+    pub align: Option<usize>,
+    /// The number of heap allocations owned by the value, if
+    /// [`DbgFlags::ALLOCATIONS`] is set.
+    pub allocations: Option<usize>,
+    /// The kind of the node.
+    pub kind: MemNodeKind,
+    /// The address of the allocation shared by the value, if the value is a
+    /// reference-counted pointer such as an [`Rc`](std::rc::Rc) or an
+    /// [`Arc`](std::sync::Arc).
+    pub address: Option<usize>,
+    /// The nodes describing the parts of the value, such as its fields.
+    pub children: Vec<MemNode>,
+}
+
+impl MemNode {
+    /// Returns a node without children describing `value`, given its name and
+    /// its padded size, that is, its size plus the padding following it.
+    ///
+    /// This method is used by [`MemDbgImpl::_mem_dbg_node`](crate::MemDbgImpl::_mem_dbg_node)
+    /// and by the [`MemDbg`](mem_dbg_derive::MemDbg) derive macro to describe
+    /// fields annotated with `#[mem_dbg(opaque)]`, which need only implement
+    /// [`MemSize`].
+    pub fn new<T: ?Sized + MemSize>(
+        value: &T,
+        name: &str,
+        padded_size: usize,
+        flags: DbgFlags,
+    ) -> Self {
+        let stack_size = core::mem::size_of_val(value);
+        debug_assert!(
+            padded_size >= stack_size,
+            "The padded size of {} ({}) is smaller than its size ({})",
+            core::any::type_name::<T>(),
+            padded_size,
+            stack_size
+        );
+        let size = <T as MemSize>::mem_size(value, flags.to_size_flags());
+        let allocations = flags
+            .contains(DbgFlags::ALLOCATIONS)
+            .then(|| <T as MemSize>::mem_allocations(value, flags.to_size_flags()));
+        let print_type_name = if flags.contains(DbgFlags::QUIET_TYPES) {
+            size > stack_size
+        } else {
+            flags.contains(DbgFlags::TYPE_NAME)
+        };
+        Self {
+            name: name.to_string(),
+            type_name: print_type_name.then(|| {
+                let type_name = core::any::type_name::<T>();
+                if flags.contains(DbgFlags::SHORT_TYPE_NAME) {
+                    short_type_name(type_name)
+                } else {
+                    type_name.to_string()
+                }
+            }),
+            info: None,
+            size,
+            padding: padded_size.saturating_sub(stack_size),
+            offset: None,
+            align: Some(core::mem::align_of_val(value)),
+            allocations,
+            kind: MemNodeKind::Value,
+            address: None,
+            children: Vec::new(),
+        }
+    }
+
+    /// Returns a node of kind [`MemNodeKind::Group`] with the given name and
+    /// size.
+    pub fn group(name: &str, size: usize) -> Self {
+        Self {
+            name: name.to_string(),
+            type_name: None,
+            info: None,
+            size,
+            padding: 0,
+            offset: None,
+            align: None,
+            allocations: None,
+            kind: MemNodeKind::Group,
+            address: None,
+            children: Vec::new(),
+        }
+    }
+
+    /// Returns a node of kind [`MemNodeKind::Label`] with the given name.
+    pub fn label(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            type_name: None,
+            info: None,
+            size: 0,
+            padding: 0,
+            offset: None,
+            align: None,
+            allocations: None,
+            kind: MemNodeKind::Label,
+            address: None,
+            children: Vec::new(),
+        }
+    }
+
+    /// Returns the overall padding of the descendants of this node, that is,
+    /// the sum of the [padding](MemNode::padding) of the nodes of the tree
+    /// rooted at this node, excluding the node itself.
+    pub fn descendants_padding(&self) -> usize {
+        self.children
+            .iter()
+            .map(|child| child.padding + child.descendants_padding())
+            .sum()
+    }
+
+    /// Returns whether this node is not displayed because `flags` contains
+    /// [`DbgFlags::HIDE_EMPTY`] and its size is zero. Labels, which have no
+    /// size, are always displayed.
+    fn is_hidden(&self, flags: DbgFlags) -> bool {
+        flags.contains(DbgFlags::HIDE_EMPTY) && self.kind != MemNodeKind::Label && self.size == 0
+    }
+
+    /// Returns the text of the line displayed after the children of this node
+    /// if `flags` contains [`DbgFlags::COUNT_HIDDEN`] and some children are
+    /// hidden by [`DbgFlags::HIDE_EMPTY`], if any.
+    fn hidden_summary(&self, flags: DbgFlags) -> Option<String> {
+        if !flags.contains(DbgFlags::COUNT_HIDDEN) {
+            return None;
+        }
+        match self
+            .children
+            .iter()
+            .filter(|child| child.is_hidden(flags))
+            .count()
+        {
+            0 => None,
+            1 => Some("(1 zero-sized field hidden)".to_string()),
+            hidden => Some(format!("({} zero-sized fields hidden)", hidden)),
+        }
+    }
+
+    /// Returns the text of the line displayed after the children of this node
+    /// if `flags` contains [`DbgFlags::PADDING_SUMMARY`], if any.
+    fn padding_summary(&self, flags: DbgFlags) -> Option<String> {
+        if !flags.contains(DbgFlags::PADDING_SUMMARY) || self.kind != MemNodeKind::Value {
+            return None;
+        }
+        let padding = self.descendants_padding();
+        if padding == 0 {
+            return None;
+        }
+        Some(format!(
+            "padding: {} ({:.2}%)",
+            format_size(padding, flags),
+            100.0 * padding as f64 / self.size as f64
+        ))
+    }
+
+    /// Replaces, at every level of the tree rooted at this node, the children
+    /// whose size is smaller than `min_size` bytes, or than `min_percentage`
+    /// percent of the size of this node, with a single child of kind
+    /// [`MemNodeKind::Group`] summarizing them, whose size is the overall
+    /// size of the removed children, including their padding, so that sizes
+    /// still add up.
+    ///
+    /// The summary follows the other children, and it is displayed as, say,
+    /// `… 37 nodes below threshold` (with three dots in place of the ellipsis
+    /// if `flags` contains [`DbgFlags::ASCII`]). Labels are never removed.
+    pub fn filter(&mut self, min_size: usize, min_percentage: f64, flags: DbgFlags) {
+        let min_size = min_size.max((self.size as f64 * min_percentage / 100.0).ceil() as usize);
+        self.filter_rec(min_size, flags);
+    }
+
+    fn filter_rec(&mut self, min_size: usize, flags: DbgFlags) {
+        let mut hidden = 0;
+        let mut hidden_size = 0;
+        self.children.retain(|child| {
+            let keep = child.kind == MemNodeKind::Label || child.size >= min_size;
+            if !keep {
+                hidden += 1;
+                hidden_size += child.size + child.padding;
+            }
+            keep
+        });
+        for child in &mut self.children {
+            child.filter_rec(min_size, flags);
+        }
+        if hidden != 0 {
+            self.children.push(MemNode::group(
+                &format!(
+                    "{} {} {} below threshold",
+                    if flags.contains(DbgFlags::ASCII) {
+                        "..."
+                    } else {
+                        "…"
+                    },
+                    hidden,
+                    if hidden == 1 { "node" } else { "nodes" }
+                ),
+                hidden_size,
+            ));
+        }
+    }
+
+    /// Writes to a [`core::fmt::Write`] the tree rooted at this node, as
+    /// displayed by [`MemDbg`](crate::MemDbg).
+    ///
+    /// Percentages are relative to the size of this node, and the formatting
+    /// flags (e.g., [`DbgFlags::HUMANIZE`] or [`DbgFlags::ASCII`]) should be
+    /// the ones used to build the tree.
+    pub fn write_on(
+        &self,
+        writer: &mut impl core::fmt::Write,
+        flags: DbgFlags,
+    ) -> core::fmt::Result {
+        self.write_config_on(writer, &DbgConfig::new().flags(flags))
+    }
+
+    /// Writes to a [`core::fmt::Write`] the tree rooted at this node as
+    /// [`MemNode::write_on`], using the given configuration.
+    pub fn write_config_on(
+        &self,
+        writer: &mut impl core::fmt::Write,
+        config: &DbgConfig,
+    ) -> core::fmt::Result {
+        let mut path = if self.name.is_empty() {
+            "root".to_string()
+        } else {
+            self.name.clone()
+        };
+        let flags = no_color(config.adjust_flags(self.size));
+        self.write_rec_on(
+            writer,
+            &ColumnLayout::new(self.size, &DbgConfig { flags, ..*config }),
+            self.size,
+            &mut String::new(),
+            &mut path,
+            true,
+        )
+    }
+
+    /// Writes the tree rooted at this node given the prefix of the node in the
+    /// tree and, for [`DbgFlags::FLAT`], its path, which are both restored
+    /// before returning.
+    fn write_rec_on(
+        &self,
+        writer: &mut impl core::fmt::Write,
+        layout: &ColumnLayout,
+        parent_size: usize,
+        prefix: &mut String,
+        path: &mut String,
+        is_last: bool,
+    ) -> core::fmt::Result {
+        let flags = layout.flags();
+        let flat = flags.contains(DbgFlags::FLAT);
+        // The root is never collapsed, as it has no name
+        let chain = if prefix.is_empty() {
+            vec![self]
+        } else {
+            self.wrapper_chain(flags)
+        };
+        let last = chain[chain.len() - 1];
+        let path_len = path.len();
+        for node in &chain[1..] {
+            node.push_path_segment(path);
+        }
+        match self.kind {
+            MemNodeKind::Value => {
+                layout.write_size(writer, self.size, parent_size)?;
+                layout.write_allocations(writer, self.allocations)?;
+                layout.write_offsets(writer, self.offset, self.align)?;
+                if flat {
+                    writer.write_str(path)?;
+                } else {
+                    write_tree_prefix_on(writer, prefix, is_last, flags)?;
+                    if prefix.is_empty() {
+                        writer.write_str(tree_root(flags))?;
+                    }
+                    let mut name = self.name.clone();
+                    for node in &chain[1..] {
+                        node.push_path_segment(&mut name);
+                    }
+                    writer.write_str(&name)?;
+                }
+                let arrow = if flags.contains(DbgFlags::ASCII) {
+                    " -> "
+                } else {
+                    " → "
+                };
+                for (i, type_name) in chain
+                    .iter()
+                    .filter_map(|node| node.type_name.as_ref())
+                    .enumerate()
+                {
+                    writer.write_str(if i == 0 { ": " } else { arrow })?;
+                    writer.write_str(type_name)?;
+                }
+                let align = self.align.filter(|_| flags.contains(DbgFlags::ALIGN));
+                match (&last.info, align) {
+                    (Some(info), Some(align)) => {
+                        writer.write_fmt(format_args!(" ({}, align={})", info, align))?
+                    }
+                    (Some(info), None) => writer.write_fmt(format_args!(" ({})", info))?,
+                    (None, Some(align)) => writer.write_fmt(format_args!(" (align={})", align))?,
+                    (None, None) => {}
+                }
+                if self.padding != 0 {
+                    writer.write_fmt(format_args!(" [{}B]", self.padding))?;
+                }
+                writer.write_char('\n')?;
+            }
+            MemNodeKind::Group if flat => {
+                write_sized_label_on(writer, layout, self.size, parent_size, "", true, path)?
+            }
+            MemNodeKind::Group => write_sized_label_on(
+                writer,
+                layout,
+                self.size,
+                parent_size,
+                prefix,
+                is_last,
+                &self.name,
+            )?,
+            // Labels have no path of their own, so we display them after the
+            // path of their parent
+            MemNodeKind::Label if flat => write_label_on(
+                writer,
+                layout,
+                "",
+                true,
+                &format!("{} ({})", path, self.name),
+            )?,
+            MemNodeKind::Label => write_label_on(writer, layout, prefix, is_last, &self.name)?,
+        }
+
+        if !last.children.is_empty() {
+            prefix.push_str(tree_indent(is_last, flags));
+            let children = last
+                .children
+                .iter()
+                .filter(|child| !child.is_hidden(flags))
+                .collect::<Vec<_>>();
+            let hidden_summary = last.hidden_summary(flags);
+            let padding_summary = last.padding_summary(flags);
+            let len = path.len();
+            for (i, child) in children.iter().enumerate() {
+                child.push_path_segment(path);
+                let is_last = i == children.len() - 1
+                    && hidden_summary.is_none()
+                    && padding_summary.is_none();
+                child.write_rec_on(writer, layout, last.size, prefix, path, is_last)?;
+                path.truncate(len);
+            }
+            match hidden_summary {
+                Some(summary) if flat => {
+                    write_label_on(writer, layout, "", true, &format!("{} {}", path, summary))?
+                }
+                Some(summary) => {
+                    write_label_on(writer, layout, prefix, padding_summary.is_none(), &summary)?
+                }
+                None => {}
+            }
+            match padding_summary {
+                Some(summary) if flat => {
+                    write_label_on(writer, layout, "", true, &format!("{} ({})", path, summary))?
+                }
+                Some(summary) => write_label_on(writer, layout, prefix, true, &summary)?,
+                None => {}
+            }
+            prefix.pop();
+            prefix.pop();
+        }
+        path.truncate(path_len);
+        Ok(())
+    }
+
+    /// Returns the nodes displayed on a single line by
+    /// [`DbgFlags::COLLAPSE_WRAPPERS`] starting from this node: this node,
+    /// followed by its only child as long as the child is a value of the same
+    /// size.
+    fn wrapper_chain(&self, flags: DbgFlags) -> Vec<&MemNode> {
+        let mut chain = vec![self];
+        if !flags.contains(DbgFlags::COLLAPSE_WRAPPERS) || self.kind != MemNodeKind::Value {
+            return chain;
+        }
+        while let [child] = chain[chain.len() - 1].children.as_slice() {
+            if child.kind != MemNodeKind::Value || child.size != self.size {
+                break;
+            }
+            chain.push(child);
+        }
+        chain
+    }
+
+    /// Writes to a [`core::fmt::Write`] the nodes of the tree rooted at this
+    /// node as rows of values separated by `separator`, preceded by a header.
+    ///
+    /// There is a row for each line displayed by [`MemNode::write_on`], with
+    /// columns `path` (the dot-separated names of the node and of its
+    /// ancestors, excluding the root, such as `b.c.1` or `v[0]`, followed by
+    /// the text of the node for labels), `type` (empty if the type name is
+    /// not displayed), `self_bytes` (the size of the node minus the size of
+    /// its children), `subtree_bytes` (the size of the node), `percent` (the
+    /// size as a percentage of the size of this node), and `depth` (zero for
+    /// this node). Values containing the separator, double quotes, or line
+    /// breaks are enclosed in double quotes.
+    pub fn write_csv_on(
+        &self,
+        writer: &mut impl core::fmt::Write,
+        separator: char,
+    ) -> core::fmt::Result {
+        for (i, column) in [
+            "path",
+            "type",
+            "self_bytes",
+            "subtree_bytes",
+            "percent",
+            "depth",
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            if i != 0 {
+                writer.write_char(separator)?;
+            }
+            writer.write_str(column)?;
+        }
+        writer.write_char('\n')?;
+        self.write_csv_rec_on(writer, self.size, &mut String::new(), 0, separator)
+    }
+
+    fn write_csv_rec_on(
+        &self,
+        writer: &mut impl core::fmt::Write,
+        total_size: usize,
+        path: &mut String,
+        depth: usize,
+        separator: char,
+    ) -> core::fmt::Result {
+        let children_size = self.children.iter().map(|child| child.size).sum::<usize>();
+        let percent = if total_size == 0 {
+            100.0
+        } else {
+            100.0 * self.size as f64 / total_size as f64
+        };
+        if self.kind == MemNodeKind::Label {
+            let label = format!("{} ({})", path, self.name);
+            write_csv_value_on(writer, label.trim_start(), separator)?;
+        } else {
+            write_csv_value_on(writer, path, separator)?;
+        }
+        writer.write_char(separator)?;
+        write_csv_value_on(
+            writer,
+            self.type_name.as_deref().unwrap_or_default(),
+            separator,
+        )?;
+        writer.write_fmt(format_args!(
+            "{sep}{}{sep}{}{sep}{:.2}{sep}{}\n",
+            self.size.saturating_sub(children_size),
+            self.size,
+            percent,
+            depth,
+            sep = separator,
+        ))?;
+
+        let len = path.len();
+        for child in &self.children {
+            child.push_path_segment(path);
+            child.write_csv_rec_on(writer, total_size, path, depth + 1, separator)?;
+            path.truncate(len);
+        }
+        Ok(())
+    }
+
+    /// Writes to a [`core::fmt::Write`] the tree rooted at this node in the
+    /// folded-stacks format used by flame graph tools such as
+    /// [`inferno`](https://crates.io/crates/inferno).
+    ///
+    /// There is a line for each node, except for labels, whose size minus the
+    /// size of its children is not zero, containing the names of the node and
+    /// of its ancestors joined by semicolons (the name of this node is `root`,
+    /// if it has no name), a space, and the size minus the size of the
+    /// children. Thus, unless children share memory with their siblings (as
+    /// it happens with reference-counted pointers when
+    /// [`DbgFlags::FOLLOW_RCS`] is set), the sizes of the lines add up to the
+    /// size of this node.
+    pub fn write_folded_on(&self, writer: &mut impl core::fmt::Write) -> core::fmt::Result {
+        let mut stack = if self.name.is_empty() {
+            "root".to_string()
+        } else {
+            self.name.clone()
+        };
+        self.write_folded_rec_on(writer, &mut stack)
+    }
+
+    fn write_folded_rec_on(
+        &self,
+        writer: &mut impl core::fmt::Write,
+        stack: &mut String,
+    ) -> core::fmt::Result {
+        let children_size = self.children.iter().map(|child| child.size).sum::<usize>();
+        let self_size = self.size.saturating_sub(children_size);
+        if self_size != 0 {
+            writer.write_fmt(format_args!("{} {}\n", stack, self_size))?;
+        }
+
+        let len = stack.len();
+        for child in &self.children {
+            if child.kind == MemNodeKind::Label {
+                continue;
+            }
+            if !child.name.is_empty() {
+                stack.push(';');
+                stack.push_str(&child.name);
+            }
+            child.write_folded_rec_on(writer, stack)?;
+            stack.truncate(len);
+        }
+        Ok(())
+    }
+
+    /// Writes to a [`core::fmt::Write`] the `n` largest leaves of the tree
+    /// rooted at this node, that is, the nodes, except for labels, without
+    /// children other than labels, in decreasing order of size.
+    ///
+    /// Each line contains the size columns, as displayed by
+    /// [`MemNode::write_on`], the path of the leaf, as displayed when
+    /// [`DbgFlags::FLAT`] is set, and its type name, if displayed. Leaves of
+    /// the same size appear in the order of the tree. Sizes are colored, if
+    /// [`DbgFlags::COLOR`] is set, following the default [`ColorScheme`](crate::ColorScheme).
+    pub fn write_top_on(
+        &self,
+        writer: &mut impl core::fmt::Write,
+        n: usize,
+        flags: DbgFlags,
+    ) -> core::fmt::Result {
+        self.write_top_config_on(writer, n, &DbgConfig::new().flags(flags))
+    }
+
+    /// Writes to a [`core::fmt::Write`] the `n` largest leaves of the tree
+    /// rooted at this node as [`MemNode::write_top_on`], using the given
+    /// configuration.
+    pub fn write_top_config_on(
+        &self,
+        writer: &mut impl core::fmt::Write,
+        n: usize,
+        config: &DbgConfig,
+    ) -> core::fmt::Result {
+        let flags = no_color(config.adjust_flags(self.size));
+        let mut path = if self.name.is_empty() {
+            "root".to_string()
+        } else {
+            self.name.clone()
+        };
+        let mut leaves = Vec::new();
+        self.collect_leaves(self.size, &mut path, &mut leaves);
+        leaves.sort_by_key(|(_, _, leaf)| core::cmp::Reverse(leaf.size));
+        let layout = ColumnLayout::new(self.size, &DbgConfig { flags, ..*config });
+        for (path, parent_size, leaf) in leaves.into_iter().take(n) {
+            layout.write_size(writer, leaf.size, parent_size)?;
+            layout.write_allocations(writer, leaf.allocations)?;
+            writer.write_str(&path)?;
+            if let Some(type_name) = &leaf.type_name {
+                writer.write_fmt(format_args!(": {}", type_name))?;
+            }
+            writer.write_char('\n')?;
+        }
+        Ok(())
+    }
+
+    /// Returns the node of the tree rooted at this node at the given path, or
+    /// `None` if there is no such node.
+    ///
+    /// Paths are as in the output of [`MemNode::write_csv_on`] and
+    /// [`MemNode::largest`]: the dot-separated names of the nodes from a
+    /// child of this node to the node to be returned, excluding labels, with
+    /// indices in brackets not preceded by a dot (e.g., `b.c.1` or `v[0]`);
+    /// the empty path returns this node. Elements of collections can be found
+    /// only if the tree contains them (see [`DbgFlags::EXPAND_ELEMENTS`]).
+    ///
+    /// ```
+    /// use mem_dbg::*;
+    ///
+    /// let tree = (0_u8, (vec![1_u32, 2, 3], 4_u16)).mem_tree(DbgFlags::empty());
+    /// assert_eq!(tree.find("1.0").map(|node| node.size), Some(36));
+    /// assert_eq!(tree.find("").map(|node| node.size), Some(tree.size));
+    /// assert!(tree.find("2").is_none());
+    /// assert!(tree.find("1.0[0]").is_none());
+    /// ```
+    pub fn find(&self, path: &str) -> Option<&MemNode> {
+        if path.is_empty() {
+            return Some(self);
+        }
+        let mut node = self;
+        for mut rest in path.split('.') {
+            if rest.is_empty() {
+                return None;
+            }
+            while !rest.is_empty() {
+                // Indices in brackets are separate segments
+                let end = rest
+                    .char_indices()
+                    .skip(1)
+                    .find(|&(_, c)| c == '[')
+                    .map_or(rest.len(), |(i, _)| i);
+                let (segment, tail) = rest.split_at(end);
+                node = node
+                    .children
+                    .iter()
+                    .find(|child| child.kind != MemNodeKind::Label && child.name == segment)?;
+                rest = tail;
+            }
+        }
+        Some(node)
+    }
+
+    /// Returns the paths and sizes of the `n` largest leaves of the tree
+    /// rooted at this node, that is, of the nodes, except for labels, without
+    /// children other than labels, in decreasing order of size.
+    ///
+    /// Paths are the dot-separated names of the ancestors of the leaf,
+    /// excluding this node, followed by the name of the leaf, as in the
+    /// output of [`MemNode::write_csv_on`] (e.g., `b.c.1` or `v[0]`). Leaves
+    /// of the same size appear in the order of the tree, that is, in
+    /// depth-first order.
+    ///
+    /// ```
+    /// use mem_dbg::*;
+    ///
+    /// let tree = (0_u8, (vec![1_u32, 2, 3], 4_u16)).mem_tree(DbgFlags::empty());
+    /// assert_eq!(
+    ///     tree.largest(2),
+    ///     vec![("1.0".to_string(), 36), ("1.1".to_string(), 2)]
+    /// );
+    /// ```
+    pub fn largest(&self, n: usize) -> Vec<(String, usize)> {
+        let mut leaves = Vec::new();
+        self.collect_leaves(self.size, &mut String::new(), &mut leaves);
+        leaves.sort_by_key(|(_, _, leaf)| core::cmp::Reverse(leaf.size));
+        leaves
+            .into_iter()
+            .take(n)
+            .map(|(path, _, leaf)| (path, leaf.size))
+            .collect()
+    }
+
+    /// Returns the paths and sizes of the `n` largest subtrees of the tree
+    /// rooted at this node, that is, of the nodes, except for this node, with
+    /// children other than labels, in decreasing order of size.
+    ///
+    /// Paths and ties are as in [`MemNode::largest`]. Since the size of a
+    /// node includes the size of its descendants, nested subtrees can appear
+    /// together in the result.
+    ///
+    /// ```
+    /// use mem_dbg::*;
+    ///
+    /// let tree = (0_u8, (vec![1_u32, 2, 3], 4_u16)).mem_tree(DbgFlags::empty());
+    /// assert_eq!(tree.largest_subtrees(2), vec![("1".to_string(), 44)]);
+    /// ```
+    pub fn largest_subtrees(&self, n: usize) -> Vec<(String, usize)> {
+        let mut subtrees = Vec::new();
+        let mut path = String::new();
+        for child in &self.children {
+            child.push_path_segment(&mut path);
+            child.collect_subtrees(&mut path, &mut subtrees);
+            path.clear();
+        }
+        subtrees.sort_by_key(|&(_, size)| core::cmp::Reverse(size));
+        subtrees.truncate(n);
+        subtrees
+    }
+
+    /// Appends to `subtrees` the paths and sizes of the nodes of the tree
+    /// rooted at this node with children other than labels, given the path
+    /// of this node.
+    fn collect_subtrees(&self, path: &mut String, subtrees: &mut Vec<(String, usize)>) {
+        if self
+            .children
+            .iter()
+            .all(|child| child.kind == MemNodeKind::Label)
+        {
+            return;
+        }
+        subtrees.push((path.clone(), self.size));
+        let len = path.len();
+        for child in &self.children {
+            child.push_path_segment(path);
+            child.collect_subtrees(path, subtrees);
+            path.truncate(len);
+        }
+    }
+
+    /// Returns, for each type name of the values of the tree rooted at this
+    /// node, the overall self size of the values of that type and their
+    /// number, in decreasing order of size; types of the same size appear in
+    /// order of first appearance in the tree.
+    ///
+    /// The self size of a value is its size minus the sizes of its children
+    /// of kind [`MemNodeKind::Value`], so that the bytes of each value are
+    /// counted once, and the self sizes add up to the size of this node.
+    /// Children of kind [`MemNodeKind::Group`], such as the keys of a map,
+    /// and values that are not expanded, such as the elements of a vector
+    /// without [`DbgFlags::EXPAND_ELEMENTS`], are counted in the self size of
+    /// their parent. Values whose type name is not displayed are counted
+    /// under the empty string.
+    ///
+    /// ```
+    /// use mem_dbg::*;
+    ///
+    /// let tree = (String::from("a"), String::from("bc"), 0_u64)
+    ///     .mem_tree(DbgFlags::TYPE_NAME | DbgFlags::SHORT_TYPE_NAME);
+    /// assert_eq!(
+    ///     tree.by_type(),
+    ///     vec![
+    ///         ("String".to_string(), 51, 2),
+    ///         ("u64".to_string(), 8, 1),
+    ///         ("(String, String, u64)".to_string(), 0, 1),
+    ///     ]
+    /// );
+    /// ```
+    pub fn by_type(&self) -> Vec<(String, usize, usize)> {
+        let mut types = Vec::new();
+        let mut index = std::collections::HashMap::new();
+        self.by_type_rec(&mut types, &mut index);
+        types.sort_by_key(|&(_, size, _)| core::cmp::Reverse(size));
+        types
+    }
+
+    fn by_type_rec(
+        &self,
+        types: &mut Vec<(String, usize, usize)>,
+        index: &mut std::collections::HashMap<String, usize>,
+    ) {
+        let values = || {
+            self.children
+                .iter()
+                .filter(|child| child.kind == MemNodeKind::Value)
+        };
+        let self_size = self
+            .size
+            .saturating_sub(values().map(|child| child.size).sum::<usize>());
+        let type_name = self.type_name.as_deref().unwrap_or("");
+        let i = *index.entry(type_name.to_string()).or_insert_with(|| {
+            types.push((type_name.to_string(), 0, 0));
+            types.len() - 1
+        });
+        types[i].1 += self_size;
+        types[i].2 += 1;
+        for child in values() {
+            child.by_type_rec(types, index);
+        }
+    }
+
+    /// Writes to a [`core::fmt::Write`] a table of the `n` types with the
+    /// largest overall self size in the tree rooted at this node, as
+    /// returned by [`MemNode::by_type`].
+    ///
+    /// Each line contains the size columns, as displayed by
+    /// [`MemNode::write_on`], the number of values of the type, and the type
+    /// name. Sizes are colored, if [`DbgFlags::COLOR`] is set, following the
+    /// default [`ColorScheme`](crate::ColorScheme).
+    pub fn write_top_types_on(
+        &self,
+        writer: &mut impl core::fmt::Write,
+        n: usize,
+        flags: DbgFlags,
+    ) -> core::fmt::Result {
+        self.write_top_types_config_on(writer, n, &DbgConfig::new().flags(flags))
+    }
+
+    /// Writes to a [`core::fmt::Write`] a table of the `n` types with the
+    /// largest overall self size in the tree rooted at this node as
+    /// [`MemNode::write_top_types_on`], using the given configuration.
+    pub fn write_top_types_config_on(
+        &self,
+        writer: &mut impl core::fmt::Write,
+        n: usize,
+        config: &DbgConfig,
+    ) -> core::fmt::Result {
+        let flags = no_color(config.adjust_flags(self.size));
+        let times = if flags.contains(DbgFlags::ASCII) {
+            "x"
+        } else {
+            "×"
+        };
+        let types = self.by_type().into_iter().take(n).collect::<Vec<_>>();
+        let align = types
+            .iter()
+            .map(|&(_, _, count)| n_of_digits(count))
+            .max()
+            .unwrap_or(0);
+        let layout = ColumnLayout::new(self.size, &DbgConfig { flags, ..*config });
+        for (type_name, size, count) in types {
+            layout.write_size(writer, size, self.size)?;
+            writer.write_fmt(format_args!(
+                "{:>align$}{} {}\n",
+                count,
+                times,
+                type_name,
+                align = align
+            ))?;
+        }
+        Ok(())
+    }
+
+    /// Appends to `leaves` the leaves of the tree rooted at this node, with
+    /// their paths and the sizes of their parents, given the path of this
+    /// node and the size of its parent.
+    fn collect_leaves<'a>(
+        &'a self,
+        parent_size: usize,
+        path: &mut String,
+        leaves: &mut Vec<(String, usize, &'a MemNode)>,
+    ) {
+        if self.kind == MemNodeKind::Label {
+            return;
+        }
+        if self
+            .children
+            .iter()
+            .all(|child| child.kind == MemNodeKind::Label)
+        {
+            leaves.push((path.clone(), parent_size, self));
+            return;
+        }
+        let len = path.len();
+        for child in &self.children {
+            child.push_path_segment(path);
+            child.collect_leaves(self.size, path, leaves);
+            path.truncate(len);
+        }
+    }
+
+    /// Appends to `path` the segment identifying this node within its parent:
+    /// indices are appended as they are, other names are preceded by a dot
+    /// unless the path is empty.
+    fn push_path_segment(&self, path: &mut String) {
+        match self.kind {
+            MemNodeKind::Label => {}
+            _ if self.name.is_empty() => {}
+            _ if self.name.starts_with('[') && self.name.ends_with(']') => {
+                path.push_str(&self.name)
+            }
+            _ => {
+                if !path.is_empty() {
+                    path.push('.');
+                }
+                path.push_str(&self.name);
+            }
+        }
+    }
+}
+
+impl MemNode {
+    /// Writes to a [`core::fmt::Write`] the tree rooted at this node as a
+    /// [Graphviz](https://graphviz.org/) DOT digraph.
+    ///
+    /// Each node, except for labels, becomes a vertex labeled with its name
+    /// (`root` for this node, if it has no name), its type name (if
+    /// displayed), and its size in human readable format, filled with a color
+    /// depending on its size following the thresholds of the default
+    /// [`ColorScheme`] (see [`ColorScheme::dot_color`]); groups have a dashed
+    /// border. Labels (see [`MemNodeKind::Label`]) are appended
+    /// to the label of the vertex of their parent, and edges go from a value
+    /// to its parts.
+    ///
+    /// The parts of a reference-counted pointer (see [`MemNode::address`])
+    /// hang from a vertex representing the shared allocation, labeled with
+    /// its address, that is written only for the first pointer to the
+    /// allocation. Thus, if the tree has been built with
+    /// [`DbgFlags::FOLLOW_RCS`], all the pointers to an allocation converge
+    /// on a single vertex.
+    pub fn write_dot_on(&self, writer: &mut impl core::fmt::Write) -> core::fmt::Result {
+        self.write_dot_config_on(writer, &DbgConfig::new())
+    }
+
+    /// Writes to a [`core::fmt::Write`] the tree rooted at this node as
+    /// [`MemNode::write_dot_on`], filling the vertices following the
+    /// thresholds of [`DbgConfig::color_scheme`]; the flags of the
+    /// configuration are ignored.
+    pub fn write_dot_config_on(
+        &self,
+        writer: &mut impl core::fmt::Write,
+        config: &DbgConfig,
+    ) -> core::fmt::Result {
+        writer.write_str("digraph mem_dbg {\n")?;
+        writer.write_str("    node [shape=box, style=filled];\n")?;
+        self.write_dot_rec_on(writer, &config.color_scheme, &mut 0, &mut Vec::new())?;
+        writer.write_str("}\n")
+    }
+
+    /// Writes the vertex `n{next_id}` describing this node and the vertices
+    /// of its descendants, given the addresses of the allocations whose
+    /// vertices have been already written.
+    fn write_dot_rec_on(
+        &self,
+        writer: &mut impl core::fmt::Write,
+        color_scheme: &ColorScheme,
+        next_id: &mut usize,
+        allocations: &mut Vec<usize>,
+    ) -> core::fmt::Result {
+        let id = *next_id;
+        *next_id += 1;
+        let mut label = if id == 0 && self.name.is_empty() {
+            "root".to_string()
+        } else {
+            dot_escape(&self.name)
+        };
+        if let Some(type_name) = &self.type_name {
+            label.push_str("\\n");
+            label.push_str(&dot_escape(type_name));
+        }
+        label.push_str("\\n");
+        label.push_str(&humanize_size(self.size));
+
+        // The parts of a shared allocation are written only once, and hang
+        // from the vertex of the allocation
+        let (source, expand) = match self.address {
+            Some(address) => match allocations.iter().position(|&a| a == address) {
+                Some(index) => (format!("a{}", index), false),
+                None => {
+                    allocations.push(address);
+                    (format!("a{}", allocations.len() - 1), true)
+                }
+            },
+            None => (format!("n{}", id), true),
+        };
+        let mut source_label = String::new();
+        if expand {
+            for child in &self.children {
+                if child.kind == MemNodeKind::Label {
+                    source_label.push_str("\\n");
+                    source_label.push_str(&dot_escape(&child.name));
+                }
+            }
+        }
+
+        if self.address.is_none() {
+            label.push_str(&source_label);
+        }
+        writer.write_fmt(format_args!(
+            "    n{} [label=\"{}\", fillcolor={}{}];\n",
+            id,
+            label,
+            color_scheme.dot_color(self.size),
+            if self.kind == MemNodeKind::Group {
+                ", style=\"filled,dashed\""
+            } else {
+                ""
+            }
+        ))?;
+        if let Some(address) = self.address {
+            if expand {
+                writer.write_fmt(format_args!(
+                    "    {} [label=\"@{:#x}{}\", shape=ellipse, style=solid];\n",
+                    source, address, source_label
+                ))?;
+            }
+            writer.write_fmt(format_args!("    n{} -> {};\n", id, source))?;
+        }
+        if expand {
+            for child in &self.children {
+                if child.kind != MemNodeKind::Label {
+                    writer.write_fmt(format_args!("    {} -> n{};\n", source, *next_id))?;
+                    child.write_dot_rec_on(writer, color_scheme, next_id, allocations)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Escapes double quotes and backslashes in a string to be written within a
+/// double-quoted DOT string.
+fn dot_escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Writes a value of a row written by [`MemNode::write_csv_on`], enclosing it
+/// in double quotes if it contains the separator, double quotes, or line
+/// breaks.
+fn write_csv_value_on(
+    writer: &mut impl core::fmt::Write,
+    value: &str,
+    separator: char,
+) -> core::fmt::Result {
+    if value.contains([separator, '"', '\n', '\r']) {
+        writer.write_fmt(format_args!("\"{}\"", value.replace('"', "\"\"")))
+    } else {
+        writer.write_str(value)
+    }
+}
+
+#[cfg(feature = "json")]
+impl MemNode {
+    /// Returns the JSON representation of the tree rooted at this node.
+    ///
+    /// Each node is represented by an object with fields `name`, `kind`
+    /// (`"value"`, `"group"`, or `"label"`), `type` (`null` if the type name
+    /// is not displayed), `size` and `padding` (in bytes), `percent` (the size
+    /// as a percentage of the size of this node), `allocations` (`null` if
+    /// [`DbgFlags::ALLOCATIONS`] is not set), and `children`. If `flags`
+    /// contains [`DbgFlags::HUMANIZE`], the field `humanized_size` contains
+    /// the size in human readable format.
+    pub fn to_json(&self, flags: DbgFlags) -> serde_json::Value {
+        self.to_json_rec(self.size, flags)
+    }
+
+    fn to_json_rec(&self, total_size: usize, flags: DbgFlags) -> serde_json::Value {
+        let mut object = serde_json::Map::new();
+        object.insert("name".into(), self.name.clone().into());
+        object.insert(
+            "kind".into(),
+            match self.kind {
+                MemNodeKind::Value => "value",
+                MemNodeKind::Group => "group",
+                MemNodeKind::Label => "label",
+            }
+            .into(),
+        );
+        object.insert("type".into(), self.type_name.clone().into());
+        object.insert("size".into(), self.size.into());
+        if flags.contains(DbgFlags::HUMANIZE) {
+            object.insert("humanized_size".into(), humanize_size(self.size).into());
+        }
+        object.insert(
+            "percent".into(),
+            if total_size == 0 {
+                100.0
+            } else {
+                100.0 * self.size as f64 / total_size as f64
+            }
+            .into(),
+        );
+        object.insert("padding".into(), self.padding.into());
+        object.insert("allocations".into(), self.allocations.into());
+        object.insert(
+            "children".into(),
+            self.children
+                .iter()
+                .map(|child| child.to_json_rec(total_size, flags))
+                .collect::<Vec<_>>()
+                .into(),
+        );
+        object.into()
+    }
+}
+
+#[cfg(feature = "tracing")]
+impl MemNode {
+    /// Emits a [`tracing`] event at level `INFO` for each node of the tree
+    /// rooted at this node, except for labels, so that sizes can be
+    /// collected by structured logs.
+    ///
+    /// The event of a node has fields `path` (the path displayed when
+    /// [`DbgFlags::FLAT`] is set), `type` (the type name, if displayed),
+    /// `bytes` (the size of the node), and `percent` (the size as a
+    /// percentage of the size of this node). Each event is emitted within a
+    /// span named `mem_dbg` with a field `name` containing the name of the
+    /// node (`root` for this node, if it has no name), and the spans are
+    /// nested following the tree, so that collectors can aggregate sizes.
+    pub fn trace(&self) {
+        let mut path = if self.name.is_empty() {
+            "root".to_string()
+        } else {
+            self.name.clone()
+        };
+        self.trace_rec(self.size, &mut path);
+    }
+
+    fn trace_rec(&self, total_size: usize, path: &mut String) {
+        if self.kind == MemNodeKind::Label {
+            return;
+        }
+        let name = if self.name.is_empty() {
+            path.as_str()
+        } else {
+            self.name.as_str()
+        };
+        let span = tracing::info_span!("mem_dbg", name);
+        let _guard = span.enter();
+        tracing::info!(
+            path = path.as_str(),
+            r#type = self.type_name.as_deref(),
+            bytes = self.size,
+            percent = if total_size == 0 {
+                100.0
+            } else {
+                100.0 * self.size as f64 / total_size as f64
+            },
+        );
+
+        let len = path.len();
+        for child in &self.children {
+            child.push_path_segment(path);
+            child.trace_rec(total_size, path);
+            path.truncate(len);
+        }
+    }
+}
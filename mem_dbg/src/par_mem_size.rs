@@ -0,0 +1,181 @@
+/*
+ * SPDX-FileCopyrightText: 2023 Tommaso Fontana
+ * SPDX-FileCopyrightText: 2023 Inria
+ * SPDX-FileCopyrightText: 2023 Sebastiano Vigna
+ *
+ * SPDX-License-Identifier: Apache-2.0 OR LGPL-2.1-or-later
+ */
+
+//! Parallel computation of the size of large collections using
+//! [`rayon`](https://crates.io/crates/rayon).
+
+use rayon::prelude::*;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::impl_mem_size::{btree_nodes_size, hash_table_heap, vec_heap};
+use crate::{Boolean, CopyType, MemSize, SizeContext, SizeFlags};
+use core::ops::ControlFlow;
+
+/// The number of elements below which the size is computed sequentially.
+const PAR_THRESHOLD: usize = 100_000;
+
+/// A trait computing [`MemSize::mem_size`] in parallel for collections whose
+/// elements are [`Sync`].
+///
+/// If the collection contains at least 100 000 non-[`Copy`] elements (see
+/// [`CopyType`]), their sizes are computed in parallel and summed; otherwise,
+/// or if [`SizeFlags::FOLLOW_RCS`] is set, which requires to visit the
+/// elements in order, the size is computed sequentially. In every case, the
+/// result is the same as that of [`MemSize::mem_size`].
+///
+/// ```
+/// use mem_dbg::*;
+///
+/// let v = (0..200_000).map(|i| i.to_string()).collect::<Vec<_>>();
+/// assert_eq!(
+///     v.par_mem_size(SizeFlags::default()),
+///     v.mem_size(SizeFlags::default())
+/// );
+/// ```
+pub trait ParMemSize: MemSize {
+    /// Returns the (recursively computed) overall memory size of the
+    /// structure in bytes, using multiple threads.
+    fn par_mem_size(&self, flags: SizeFlags) -> usize;
+}
+
+/// Returns whether a collection of `len` elements, which are [`Copy`] if
+/// `copy` is true, should be sized in parallel.
+fn parallel(copy: bool, len: usize, flags: SizeFlags) -> bool {
+    !copy && len >= PAR_THRESHOLD && !flags.contains(SizeFlags::FOLLOW_RCS)
+}
+
+/// Returns the overall heap size of the items returned by `iter`, which are
+/// visited by `visit`.
+///
+/// Since [`SizeFlags::FOLLOW_RCS`] is not set, visiting an item leaves the
+/// set of visited allocations unchanged, so each thread can use its own
+/// context, and the heap sizes of the contexts are summed.
+fn par_heap_size<I: ParallelIterator>(
+    iter: I,
+    flags: SizeFlags,
+    visit: impl Fn(I::Item, &mut SizeContext) -> ControlFlow<()> + Send + Sync,
+) -> usize {
+    iter.fold(
+        || SizeContext::new(flags),
+        |mut ctx, item| {
+            let _ = visit(item, &mut ctx);
+            ctx
+        },
+    )
+    .map(|ctx| ctx.heap_size())
+    .sum()
+}
+
+/// Visits `x`, unless `T` is [`Copy`].
+fn heap_part<T: CopyType + MemSize>(x: &T, ctx: &mut SizeContext) -> ControlFlow<()> {
+    if <T::Copy as Boolean>::VALUE {
+        ControlFlow::Continue(())
+    } else {
+        <T as MemSize>::_mem_size_rec(x, ctx)
+    }
+}
+
+/// Returns the used or the allocated size of a buffer, depending on
+/// [`SizeFlags::CAPACITY`].
+fn buffer_size((used, capacity): (usize, usize), flags: SizeFlags) -> usize {
+    if flags.contains(SizeFlags::CAPACITY) {
+        capacity
+    } else {
+        used
+    }
+}
+
+impl<T: CopyType + MemSize + Sync> ParMemSize for [T]
+where
+    [T]: MemSize,
+{
+    fn par_mem_size(&self, flags: SizeFlags) -> usize {
+        if !parallel(<T::Copy as Boolean>::VALUE, self.len(), flags) {
+            return self.mem_size(flags);
+        }
+        core::mem::size_of_val(self)
+            + par_heap_size(self.par_iter(), flags, <T as MemSize>::_mem_size_rec)
+    }
+}
+
+impl<T: CopyType + MemSize + Sync> ParMemSize for Vec<T>
+where
+    Vec<T>: MemSize,
+{
+    fn par_mem_size(&self, flags: SizeFlags) -> usize {
+        if !parallel(<T::Copy as Boolean>::VALUE, self.len(), flags) {
+            return self.mem_size(flags);
+        }
+        core::mem::size_of::<Self>()
+            + buffer_size(vec_heap(self, flags), flags)
+            + par_heap_size(self.par_iter(), flags, <T as MemSize>::_mem_size_rec)
+    }
+}
+
+impl<K: CopyType + MemSize + Sync + core::hash::Hash + Eq> ParMemSize for HashSet<K>
+where
+    HashSet<K>: MemSize,
+{
+    fn par_mem_size(&self, flags: SizeFlags) -> usize {
+        if !parallel(<K::Copy as Boolean>::VALUE, self.len(), flags) {
+            return self.mem_size(flags);
+        }
+        core::mem::size_of::<Self>()
+            + buffer_size(
+                hash_table_heap(self.len(), self.capacity(), core::mem::size_of::<K>()),
+                flags,
+            )
+            + par_heap_size(self.par_iter(), flags, <K as MemSize>::_mem_size_rec)
+    }
+}
+
+impl<K, V> ParMemSize for HashMap<K, V>
+where
+    K: CopyType + MemSize + Sync + core::hash::Hash + Eq,
+    V: CopyType + MemSize + Sync,
+    HashMap<K, V>: MemSize,
+{
+    fn par_mem_size(&self, flags: SizeFlags) -> usize {
+        let copy = <K::Copy as Boolean>::VALUE && <V::Copy as Boolean>::VALUE;
+        if !parallel(copy, self.len(), flags) {
+            return self.mem_size(flags);
+        }
+        core::mem::size_of::<Self>()
+            + buffer_size(
+                hash_table_heap(
+                    self.len(),
+                    self.capacity(),
+                    core::mem::size_of::<K>() + core::mem::size_of::<V>(),
+                ),
+                flags,
+            )
+            + par_heap_size(self.par_iter(), flags, |(k, v), ctx| {
+                heap_part(k, ctx)?;
+                heap_part(v, ctx)
+            })
+    }
+}
+
+impl<K, V> ParMemSize for BTreeMap<K, V>
+where
+    K: CopyType + MemSize + Sync + Ord,
+    V: CopyType + MemSize + Sync,
+{
+    fn par_mem_size(&self, flags: SizeFlags) -> usize {
+        let copy = <K::Copy as Boolean>::VALUE && <V::Copy as Boolean>::VALUE;
+        if !parallel(copy, self.len(), flags) {
+            return self.mem_size(flags);
+        }
+        core::mem::size_of::<Self>()
+            + btree_nodes_size::<K, V>(self.len())
+            + par_heap_size(self.par_iter(), flags, |(k, v), ctx| {
+                heap_part(k, ctx)?;
+                heap_part(v, ctx)
+            })
+    }
+}
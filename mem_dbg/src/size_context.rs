@@ -0,0 +1,230 @@
+/*
+ * SPDX-FileCopyrightText: 2023 Tommaso Fontana
+ * SPDX-FileCopyrightText: 2023 Inria
+ * SPDX-FileCopyrightText: 2023 Sebastiano Vigna
+ *
+ * SPDX-License-Identifier: Apache-2.0 OR LGPL-2.1-or-later
+ */
+
+//! The state of a traversal of [`MemSize`].
+
+#[cfg(all(feature = "alloc", not(feature = "std")))]
+use alloc::collections::BTreeSet;
+use core::ops::ControlFlow;
+#[cfg(feature = "std")]
+use std::collections::HashSet;
+
+use crate::{MemSize, SizeFlags};
+
+/// The state of a traversal of a structure by [`MemSize::_mem_size_rec`],
+/// which accumulates the memory owned by the values visited.
+///
+/// Implementations of [`MemSize::_mem_size_rec`] report the heap memory they
+/// own with [`add_heap`](SizeContext::add_heap), giving both the memory used
+/// and the memory allocated, so that a single traversal computes all the
+/// results of the methods of [`MemSize`]; they report their allocations with
+/// [`add_allocations`](SizeContext::add_allocations), and whether their size
+/// is an estimate with [`mark_estimated`](SizeContext::mark_estimated).
+///
+/// The context also records the addresses of the reference-counted
+/// allocations visited so far, so that allocations shared by several pointers
+/// are counted once (see [`SizeFlags::FOLLOW_RCS`]), and cycles are not
+/// followed. Its content is private, so that it does not depend on the
+/// collections available: with the `std` feature addresses are stored in a
+/// hash set, and otherwise in a B-tree set.
+///
+/// ```
+/// use core::ops::ControlFlow;
+/// use mem_dbg::*;
+///
+/// /// A buffer of `len` bytes, of which only `used` are used.
+/// struct Buffer {
+///     data: Box<[u8]>,
+///     used: usize,
+/// }
+///
+/// impl MemSize for Buffer {
+///     fn _mem_size_rec(&self, ctx: &mut SizeContext) -> ControlFlow<()> {
+///         ctx.add_allocations((!self.data.is_empty()) as usize);
+///         ctx.add_heap(self.used, self.data.len())
+///     }
+/// }
+///
+/// let buffer = Buffer { data: vec![0; 10].into_boxed_slice(), used: 4 };
+/// let stats = buffer.mem_stats(SizeFlags::default());
+/// assert_eq!(stats.heap_used, 4);
+/// assert_eq!(stats.heap_capacity, 10);
+/// assert_eq!(buffer.mem_allocations(SizeFlags::default()), 1);
+/// ```
+#[derive(Debug, Clone)]
+pub struct SizeContext {
+    flags: SizeFlags,
+    #[cfg(feature = "std")]
+    refs: HashSet<usize>,
+    #[cfg(all(feature = "alloc", not(feature = "std")))]
+    refs: BTreeSet<usize>,
+    heap_used: usize,
+    heap_capacity: usize,
+    allocations: usize,
+    estimated: bool,
+    limit: usize,
+}
+
+impl SizeContext {
+    /// Creates a context for a new traversal with the given flags, in which
+    /// nothing has been visited.
+    pub fn new(flags: SizeFlags) -> Self {
+        Self::with_limit(flags, usize::MAX)
+    }
+
+    /// Creates a context for a new traversal with the given flags that stops
+    /// as soon as the heap size exceeds `limit`.
+    pub(crate) fn with_limit(flags: SizeFlags, limit: usize) -> Self {
+        Self {
+            flags,
+            refs: Default::default(),
+            heap_used: 0,
+            heap_capacity: 0,
+            allocations: 0,
+            estimated: false,
+            limit,
+        }
+    }
+
+    /// Returns a context after traversing `value` with the given flags.
+    pub(crate) fn traverse<T: ?Sized + MemSize>(value: &T, flags: SizeFlags) -> Self {
+        let mut ctx = Self::new(flags);
+        let _ = value._mem_size_rec(&mut ctx);
+        ctx
+    }
+
+    /// Returns the flags of the traversal.
+    ///
+    /// Implementations need not check [`SizeFlags::CAPACITY`], as they report
+    /// both the memory used and the memory allocated.
+    #[inline(always)]
+    pub fn flags(&self) -> SizeFlags {
+        self.flags
+    }
+
+    /// Calls `f` on this context with the flags replaced by `flags`,
+    /// restoring them afterwards.
+    #[inline(always)]
+    pub fn with_flags<R>(&mut self, flags: SizeFlags, f: impl FnOnce(&mut Self) -> R) -> R {
+        let old_flags = core::mem::replace(&mut self.flags, flags);
+        let result = f(self);
+        self.flags = old_flags;
+        result
+    }
+
+    /// Adds heap memory owned by the value being visited: `used` bytes are
+    /// used, and `capacity` bytes, including the used ones, are allocated.
+    ///
+    /// Returns [`ControlFlow::Break`] if the traversal must stop, as the size
+    /// computed so far exceeds the limit of
+    /// [`mem_size_bounded`](MemSize::mem_size_bounded); the result should be
+    /// propagated using the `?` operator.
+    #[inline(always)]
+    pub fn add_heap(&mut self, used: usize, capacity: usize) -> ControlFlow<()> {
+        self.heap_used += used;
+        self.heap_capacity += capacity;
+        self.check_limit()
+    }
+
+    /// Adds `allocations` to the number of heap allocations owned by the
+    /// values visited (see [`MemSize::mem_allocations`]).
+    #[inline(always)]
+    pub fn add_allocations(&mut self, allocations: usize) {
+        self.allocations += allocations;
+    }
+
+    /// Records that the size of the value being visited is an estimate (see
+    /// [`MemSize::mem_size_detailed`]).
+    #[inline(always)]
+    pub fn mark_estimated(&mut self) {
+        self.estimated = true;
+    }
+
+    /// Records that the allocation at `addr` is being visited, returning
+    /// true if it was not visited before.
+    ///
+    /// Implementations for pointers to shared allocations should not follow
+    /// the pointer if this method returns false, and should call
+    /// [`leave`](SizeContext::leave) after following it if the allocation
+    /// must be counted again when reached by another path.
+    #[inline(always)]
+    pub fn visit(&mut self, addr: usize) -> bool {
+        self.refs.insert(addr)
+    }
+
+    /// Forgets that the allocation at `addr` was visited.
+    #[inline(always)]
+    pub fn leave(&mut self, addr: usize) {
+        self.refs.remove(&addr);
+    }
+
+    /// Returns the heap memory used by the values visited (see
+    /// [`SizeStats::heap_used`](crate::SizeStats::heap_used)).
+    #[inline(always)]
+    pub fn heap_used(&self) -> usize {
+        self.heap_used
+    }
+
+    /// Returns the heap memory allocated by the values visited (see
+    /// [`SizeStats::heap_capacity`](crate::SizeStats::heap_capacity)).
+    #[inline(always)]
+    pub fn heap_capacity(&self) -> usize {
+        self.heap_capacity
+    }
+
+    /// Returns the heap size of the values visited, that is, the heap
+    /// capacity if [`SizeFlags::CAPACITY`] is set, and the used heap
+    /// otherwise (see [`MemSize::heap_size`]).
+    #[inline(always)]
+    pub fn heap_size(&self) -> usize {
+        if self.flags.contains(SizeFlags::CAPACITY) {
+            self.heap_capacity
+        } else {
+            self.heap_used
+        }
+    }
+
+    /// Returns the number of heap allocations owned by the values visited.
+    #[inline(always)]
+    pub fn allocations(&self) -> usize {
+        self.allocations
+    }
+
+    /// Returns whether the size of a value visited is an estimate.
+    #[inline(always)]
+    pub fn estimated(&self) -> bool {
+        self.estimated
+    }
+
+    /// Returns [`ControlFlow::Break`] if the heap size exceeds the limit.
+    #[inline(always)]
+    fn check_limit(&self) -> ControlFlow<()> {
+        if self.heap_size() > self.limit {
+            ControlFlow::Break(())
+        } else {
+            ControlFlow::Continue(())
+        }
+    }
+
+    /// Calls `f` on this context, counting the heap memory it allocates as
+    /// used, as if [`SizeFlags::CAPACITY`] were set.
+    ///
+    /// This method is used by the `MemSize`
+    /// derive macro on types annotated with `#[mem_dbg(capacity)]`.
+    #[doc(hidden)]
+    pub fn _capacity_as_used(
+        &mut self,
+        f: impl FnOnce(&mut Self) -> ControlFlow<()>,
+    ) -> ControlFlow<()> {
+        let (heap_used, heap_capacity) = (self.heap_used, self.heap_capacity);
+        let result = f(self);
+        self.heap_used = heap_used + (self.heap_capacity - heap_capacity);
+        result?;
+        self.check_limit()
+    }
+}
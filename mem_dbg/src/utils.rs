@@ -5,6 +5,8 @@
  * SPDX-License-Identifier: Apache-2.0 OR LGPL-2.1-or-later
  */
 
+use crate::{DbgConfig, DbgFlags};
+
 /// Given a float, returns it in a human readable format using SI suffixes.
 pub fn humanize_float(mut x: f64) -> (f64, &'static str) {
     const UOM: &[&str] = &[
@@ -33,6 +35,53 @@ pub fn humanize_float(mut x: f64) -> (f64, &'static str) {
     (x, UOM[uom_idx])
 }
 
+/// Returns the number of decimal digits used to display a value returned by
+/// [`humanize_float`], so that four significant digits are displayed.
+pub(crate) fn humanized_precision(value: f64) -> usize {
+    let a = value.abs();
+    if a >= 100.0 {
+        1
+    } else if a >= 10.0 {
+        2
+    } else if a >= 1.0 {
+        3
+    } else {
+        4
+    }
+}
+
+/// Returns a size in human readable format, as displayed by
+/// [`MemDbg`](crate::MemDbg) when [`DbgFlags::HUMANIZE`] is set.
+pub(crate) fn humanize_size(size: usize) -> String {
+    let (value, uom) = humanize_float(size as f64);
+    if uom == " B" {
+        format!("{} B", size)
+    } else {
+        format!("{:.*} {}", humanized_precision(value), value, uom)
+    }
+}
+
+/// Returns a size in bytes as displayed by [`MemDbg`](crate::MemDbg), that
+/// is, in human readable format if `flags` contains [`DbgFlags::HUMANIZE`],
+/// and followed by `B` otherwise.
+pub(crate) fn format_size(size: usize, flags: DbgFlags) -> String {
+    if flags.contains(DbgFlags::HUMANIZE) {
+        humanize_size(size)
+    } else {
+        format!("{} B", format_number(size, flags))
+    }
+}
+
+/// Returns `flags` without [`DbgFlags::COLOR`] if the `NO_COLOR` environment
+/// variable is set to a nonempty value (see <https://no-color.org/>).
+pub(crate) fn no_color(flags: DbgFlags) -> DbgFlags {
+    #[cfg(feature = "std")]
+    if std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty()) {
+        return flags - DbgFlags::COLOR;
+    }
+    flags
+}
+
 /// Returns the number of digits of a number.
 ///
 /// ```
@@ -58,3 +107,504 @@ pub fn n_of_digits(x: usize) -> usize {
     }
     digits
 }
+
+/// Returns a type name, as returned by [`core::any::type_name`], keeping only
+/// the last segment of each path, as displayed by [`MemDbg`](crate::MemDbg)
+/// when [`DbgFlags::SHORT_TYPE_NAME`] is set.
+///
+/// Paths are shortened everywhere, including in generic arguments, tuples,
+/// arrays, references, pointers, and trait objects, whereas the rest of the
+/// name is left unchanged. A path following a delimiter, as in a qualified
+/// path such as `<T as Trait>::Assoc`, is kept.
+///
+/// ```
+/// use mem_dbg::short_type_name;
+///
+/// assert_eq!(short_type_name("u8"), "u8");
+/// assert_eq!(short_type_name("alloc::string::String"), "String");
+/// assert_eq!(
+///     short_type_name("std::collections::hash::set::HashSet<alloc::string::String>"),
+///     "HashSet<String>"
+/// );
+/// assert_eq!(
+///     short_type_name("std::collections::hash::map::HashMap<alloc::string::String, alloc::vec::Vec<core::option::Option<u8>>, std::hash::random::RandomState>"),
+///     "HashMap<String, Vec<Option<u8>>, RandomState>"
+/// );
+/// assert_eq!(
+///     short_type_name("(u8, alloc::string::String, (alloc::boxed::Box<str>,))"),
+///     "(u8, String, (Box<str>,))"
+/// );
+/// assert_eq!(
+///     short_type_name("&'static [alloc::vec::Vec<u8>; 4]"),
+///     "&'static [Vec<u8>; 4]"
+/// );
+/// assert_eq!(
+///     short_type_name("*const &mut core::cell::RefCell<i32>"),
+///     "*const &mut RefCell<i32>"
+/// );
+/// assert_eq!(
+///     short_type_name("alloc::boxed::Box<dyn core::error::Error + core::marker::Send>"),
+///     "Box<dyn Error + Send>"
+/// );
+/// assert_eq!(
+///     short_type_name("alloc::vec::Vec<fn(alloc::string::String) -> core::option::Option<u8>>"),
+///     "Vec<fn(String) -> Option<u8>>"
+/// );
+/// assert_eq!(short_type_name("my_crate::main::{{closure}}"), "{{closure}}");
+/// assert_eq!(
+///     short_type_name("<alloc::vec::Vec<u8> as core::ops::deref::Deref>::Target"),
+///     "<Vec<u8> as Deref>::Target"
+/// );
+/// ```
+pub fn short_type_name(type_name: &str) -> String {
+    let mut result = String::with_capacity(type_name.len());
+    // The start in result of the path being parsed
+    let mut start = 0;
+    let mut chars = type_name.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            ':' if chars.peek() == Some(&':') => {
+                chars.next();
+                if result.len() == start {
+                    // The path follows a delimiter, as in <T as Trait>::Assoc
+                    result.push_str("::");
+                    start = result.len();
+                } else {
+                    result.truncate(start);
+                }
+            }
+            '<' | '>' | ',' | '(' | ')' | '[' | ']' | ';' | '&' | '*' | '+' | '=' | '-' => {
+                result.push(c);
+                start = result.len();
+            }
+            c if c.is_whitespace() => {
+                result.push(c);
+                start = result.len();
+            }
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+/// Returns a number as displayed by [`MemDbg`](crate::MemDbg), that is, with
+/// groups of three digits separated by underscores if `flags` contains
+/// [`DbgFlags::SEPARATOR`].
+pub(crate) fn format_number(x: usize, flags: DbgFlags) -> String {
+    let digits = x.to_string();
+    if !flags.contains(DbgFlags::SEPARATOR) {
+        return digits;
+    }
+    let mut result = String::with_capacity(digits.len() + digits.len() / 3);
+    for (i, digit) in digits.chars().enumerate() {
+        if i != 0 && (digits.len() - i).is_multiple_of(3) {
+            result.push('_');
+        }
+        result.push(digit);
+    }
+    result
+}
+
+/// The width of the column containing the percentages printed by
+/// [`MemDbg`](crate::MemDbg), including the trailing space.
+pub(crate) const PERCENTAGE_COLUMN_WIDTH: usize = "100.00% ".len();
+
+/// The layout of the columns preceding the tree in the lines printed by
+/// [`MemDbg`](crate::MemDbg), that is, the size, percentage(s), allocations,
+/// and offsets columns.
+///
+/// The widths of the columns depend on the configuration and on the
+/// overall size of the structure, so the layout is computed once at the root
+/// and used to write every line, including the lines without a size, such as
+/// the variants of an enum, which are left blank in the columns.
+///
+/// ```
+/// use mem_dbg::*;
+///
+/// let config = DbgConfig::new().flags(DbgFlags::SEPARATOR | DbgFlags::PERCENTAGE);
+/// let layout = ColumnLayout::new(1000, &config);
+/// let mut line = String::new();
+/// layout.write_size(&mut line, 10, 1000)?;
+/// assert_eq!(line, "   10 B   1.00% ");
+/// assert_eq!(layout.width(), line.len());
+/// # Ok::<(), core::fmt::Error>(())
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ColumnLayout {
+    total_size: usize,
+    config: DbgConfig,
+    size_width: usize,
+}
+
+impl ColumnLayout {
+    /// Returns the layout of the lines describing a structure of overall
+    /// size `total_size`, displayed with the given configuration.
+    pub fn new(total_size: usize, config: &DbgConfig) -> Self {
+        let flags = config.flags;
+        let size_width = if flags.contains(DbgFlags::HUMANIZE) {
+            match config.humanize_precision {
+                // At most three integral digits, the point, and the decimal
+                // digits up to a total of four digits
+                None => 5,
+                Some(0) => 3,
+                Some(precision) => 4 + precision,
+            }
+        } else {
+            let digits = n_of_digits(total_size);
+            if flags.contains(DbgFlags::SEPARATOR) {
+                digits + digits / 3
+            } else {
+                digits
+            }
+        };
+        Self {
+            total_size,
+            config: *config,
+            size_width,
+        }
+    }
+
+    /// Returns the flags the layout has been computed for.
+    pub(crate) fn flags(&self) -> DbgFlags {
+        self.config.flags
+    }
+
+    /// Returns the width of the column containing the sizes, excluding the
+    /// unit of measure.
+    pub(crate) fn size_width(&self) -> usize {
+        self.size_width
+    }
+
+    /// Returns the width of the unit of measure following the sizes,
+    /// including the surrounding spaces.
+    fn unit_width(&self) -> usize {
+        if self.config.flags.contains(DbgFlags::HUMANIZE) {
+            " kB ".len()
+        } else {
+            " B ".len()
+        }
+    }
+
+    /// Returns the width of the column containing the number of allocations
+    /// if [`DbgFlags::ALLOCATIONS`] is set, including the unit of measure
+    /// and the surrounding spaces, or zero otherwise.
+    fn allocations_width(&self) -> usize {
+        if self.config.flags.contains(DbgFlags::ALLOCATIONS) {
+            // Every allocation is owned through a pointer, so the number of
+            // allocations of a value is (almost always) smaller than its size
+            n_of_digits(self.total_size) + " A ".len()
+        } else {
+            0
+        }
+    }
+
+    /// Returns the width of the column containing the offsets and
+    /// alignments if [`DbgFlags::OFFSETS`] is set, including the slash and
+    /// the trailing space, or zero otherwise.
+    fn offsets_width(&self) -> usize {
+        if self.config.flags.contains(DbgFlags::OFFSETS) {
+            // Offsets are smaller than the size of the parent, and alignments
+            // are at most the size of the value (except for zero-sized types)
+            2 * n_of_digits(self.total_size) + "/ ".len()
+        } else {
+            0
+        }
+    }
+
+    /// Returns the number of characters preceding the tree, that is, the
+    /// overall width of the columns.
+    pub fn width(&self) -> usize {
+        let mut width = self.size_width + self.unit_width();
+        if self.config.flags.contains(DbgFlags::PERCENTAGE) {
+            width += PERCENTAGE_COLUMN_WIDTH;
+        }
+        if self.config.flags.contains(DbgFlags::PARENT_PERCENTAGE) {
+            width += PERCENTAGE_COLUMN_WIDTH;
+        }
+        width + self.allocations_width() + self.offsets_width()
+    }
+
+    /// Writes the size columns, that is, `size` and, if
+    /// [`DbgFlags::PERCENTAGE`] or [`DbgFlags::PARENT_PERCENTAGE`] are set,
+    /// its percentage of the overall size or of `parent_size`, respectively.
+    ///
+    /// If [`DbgFlags::COLOR`] is set, the size is colored following
+    /// [`DbgConfig::color_scheme`].
+    pub fn write_size(
+        &self,
+        writer: &mut impl core::fmt::Write,
+        size: usize,
+        parent_size: usize,
+    ) -> core::fmt::Result {
+        let align = self.size_width;
+        if self.config.flags.contains(DbgFlags::COLOR) {
+            writer.write_str(self.config.color_scheme.ansi_color(size))?;
+        }
+        if self.config.flags.contains(DbgFlags::HUMANIZE) {
+            let (value, uom) = humanize_float(size as f64);
+            if uom == " B" {
+                writer.write_fmt(format_args!("{:>align$} {} ", size, uom, align = align))?;
+            } else {
+                writer.write_fmt(format_args!(
+                    "{0:>align$.1$} {2} ",
+                    value,
+                    self.config
+                        .humanize_precision
+                        .unwrap_or_else(|| humanized_precision(value)),
+                    uom,
+                    align = align
+                ))?;
+            }
+        } else if self.config.flags.contains(DbgFlags::SEPARATOR) {
+            let mut size = size;
+            let mut digits = n_of_digits(size);
+            let digit_align = digits + digits / 3;
+            for _ in digit_align..align {
+                writer.write_char(' ')?;
+            }
+
+            let first_digits = digits % 3;
+            let mut multiplier = 10_usize.pow((digits - first_digits) as u32);
+            if first_digits != 0 {
+                writer.write_fmt(format_args!("{}", size / multiplier))?;
+            } else {
+                multiplier /= 1000;
+                digits -= 3;
+                writer.write_fmt(format_args!(" {}", size / multiplier))?;
+            }
+
+            while digits >= 3 {
+                size %= multiplier;
+                multiplier /= 1000;
+                writer.write_fmt(format_args!("_{:03}", size / multiplier))?;
+                digits -= 3;
+            }
+
+            writer.write_str(" B ")?;
+        } else {
+            writer.write_fmt(format_args!("{:>align$} B ", size, align = align))?;
+        }
+        if self.config.flags.contains(DbgFlags::COLOR) {
+            writer.write_str("\x1b[0m")?;
+        }
+
+        if self.config.flags.contains(DbgFlags::PERCENTAGE) {
+            write_percentage_on(writer, size, self.total_size)?;
+        }
+        if self.config.flags.contains(DbgFlags::PARENT_PERCENTAGE) {
+            write_percentage_on(writer, size, parent_size)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the allocations column if [`DbgFlags::ALLOCATIONS`] is set,
+    /// leaving it blank if `allocations` is `None`.
+    pub(crate) fn write_allocations(
+        &self,
+        writer: &mut impl core::fmt::Write,
+        allocations: Option<usize>,
+    ) -> core::fmt::Result {
+        let width = self.allocations_width();
+        match allocations {
+            _ if width == 0 => Ok(()),
+            Some(allocations) => writer.write_fmt(format_args!(
+                "{:>align$} A ",
+                allocations,
+                align = width - " A ".len()
+            )),
+            None => writer.write_fmt(format_args!("{:width$}", "", width = width)),
+        }
+    }
+
+    /// Writes the offsets column if [`DbgFlags::OFFSETS`] is set, writing
+    /// `-` in place of a missing offset, and leaving the column blank if
+    /// `align` is `None`.
+    pub(crate) fn write_offsets(
+        &self,
+        writer: &mut impl core::fmt::Write,
+        offset: Option<usize>,
+        align: Option<usize>,
+    ) -> core::fmt::Result {
+        let width = self.offsets_width();
+        match align {
+            _ if width == 0 => Ok(()),
+            Some(align) => {
+                let digits = n_of_digits(self.total_size);
+                match offset {
+                    Some(offset) => {
+                        writer.write_fmt(format_args!("{:>digits$}/{:<digits$} ", offset, align))
+                    }
+                    None => writer.write_fmt(format_args!("{:>digits$}/{:<digits$} ", "-", align)),
+                }
+            }
+            None => writer.write_fmt(format_args!("{:width$}", "", width = width)),
+        }
+    }
+
+    /// Writes the columns of a line without a size, that is, as many spaces
+    /// as [`ColumnLayout::width`].
+    pub fn write_blank(&self, writer: &mut impl core::fmt::Write) -> core::fmt::Result {
+        writer.write_fmt(format_args!("{:width$}", "", width = self.width()))
+    }
+}
+
+/// Returns the number of characters preceding the tree in the lines printed by
+/// [`MemDbg`](crate::MemDbg), that is, the width of the size, percentage(s),
+/// allocations, and offsets columns, given the total size of the value and the flags.
+///
+/// Lines without a size, such as the variants of an enum, must be indented by
+/// this number of spaces to be aligned with the other lines. The width with
+/// a non-default configuration is returned by [`ColumnLayout::width`].
+///
+/// ```
+/// use mem_dbg::{header_indent_width, DbgFlags};
+///
+/// assert_eq!(header_indent_width(1000, DbgFlags::empty()), "1000 B ".len());
+/// assert_eq!(header_indent_width(1000, DbgFlags::SEPARATOR), "1_000 B ".len());
+/// assert_eq!(header_indent_width(1000, DbgFlags::HUMANIZE), "1.000 kB ".len());
+/// assert_eq!(
+///     header_indent_width(1000, DbgFlags::PERCENTAGE),
+///     "1000 B 100.00% ".len()
+/// );
+/// assert_eq!(
+///     header_indent_width(1000, DbgFlags::PERCENTAGE | DbgFlags::PARENT_PERCENTAGE),
+///     "1000 B 100.00% 100.00% ".len()
+/// );
+/// assert_eq!(
+///     header_indent_width(1000, DbgFlags::ALLOCATIONS),
+///     "1000 B    1 A ".len()
+/// );
+/// assert_eq!(
+///     header_indent_width(1000, DbgFlags::OFFSETS),
+///     "1000 B    0/8    ".len()
+/// );
+/// ```
+pub fn header_indent_width(total_size: usize, flags: DbgFlags) -> usize {
+    ColumnLayout::new(total_size, &DbgConfig::new().flags(flags)).width()
+}
+
+/// Returns the glyph marking the root of the tree printed by
+/// [`MemDbg`](crate::MemDbg).
+///
+/// The glyph is ASCII if `flags` contains [`DbgFlags::ASCII`].
+pub fn tree_root(flags: DbgFlags) -> &'static str {
+    if flags.contains(DbgFlags::ASCII) {
+        "*"
+    } else {
+        "⏺"
+    }
+}
+
+/// Returns the glyphs connecting a node of the tree printed by
+/// [`MemDbg`](crate::MemDbg) to its parent, depending on whether the node is
+/// the last child.
+///
+/// The glyphs are ASCII if `flags` contains [`DbgFlags::ASCII`].
+pub fn tree_branch(is_last: bool, flags: DbgFlags) -> &'static str {
+    match (is_last, flags.contains(DbgFlags::ASCII)) {
+        (false, false) => "├╴",
+        (true, false) => "╰╴",
+        (false, true) => "+-",
+        (true, true) => "\\-",
+    }
+}
+
+/// Returns the glyphs indenting the descendants of a node of the tree printed
+/// by [`MemDbg`](crate::MemDbg), depending on whether the node is the last
+/// child.
+///
+/// The glyphs are ASCII if `flags` contains [`DbgFlags::ASCII`].
+pub fn tree_indent(is_last: bool, flags: DbgFlags) -> &'static str {
+    match (is_last, flags.contains(DbgFlags::ASCII)) {
+        (true, _) => "  ",
+        (false, false) => "│ ",
+        (false, true) => "| ",
+    }
+}
+
+/// Returns the depth of a node of the tree printed by
+/// [`MemDbg`](crate::MemDbg) given its prefix, that is, the number of its
+/// ancestors; the root has depth zero.
+///
+/// The length of the prefix in bytes is not a measure of depth, as the glyphs
+/// indenting the nodes have different lengths in UTF-8.
+pub fn tree_depth(prefix: &str) -> usize {
+    prefix.chars().count() / 2
+}
+
+/// Writes the part of the prefix of a node of the tree printed by
+/// [`MemDbg`](crate::MemDbg) that follows the size columns, that is, the
+/// indentation of its ancestors and the glyphs connecting it to its parent.
+///
+/// Nothing is written for the root, whose prefix is empty.
+pub fn write_tree_prefix_on(
+    writer: &mut impl core::fmt::Write,
+    prefix: &str,
+    is_last: bool,
+    flags: DbgFlags,
+) -> core::fmt::Result {
+    if !prefix.is_empty() {
+        writer.write_str(&prefix[2..])?;
+        writer.write_str(tree_branch(is_last, flags))?;
+    }
+    Ok(())
+}
+
+/// Writes a percentage column containing `size` as a percentage of `of`.
+fn write_percentage_on(
+    writer: &mut impl core::fmt::Write,
+    size: usize,
+    of: usize,
+) -> core::fmt::Result {
+    writer.write_fmt(format_args!(
+        "{:>1$.2}% ",
+        if of == 0 {
+            100.0
+        } else {
+            100.0 * size as f64 / of as f64
+        },
+        PERCENTAGE_COLUMN_WIDTH - "% ".len()
+    ))
+}
+
+/// Writes a line of the tree printed by [`MemDbg`](crate::MemDbg) that has no
+/// size, such as the variant of an enum, leaving the columns of `layout`
+/// blank so that `label` is aligned with the names of the other nodes.
+pub fn write_label_on(
+    writer: &mut impl core::fmt::Write,
+    layout: &ColumnLayout,
+    prefix: &str,
+    is_last: bool,
+    label: &str,
+) -> core::fmt::Result {
+    layout.write_blank(writer)?;
+    write_tree_prefix_on(writer, prefix, is_last, layout.flags())?;
+    writer.write_str(label)?;
+    writer.write_char('\n')
+}
+
+/// Writes a line of the tree printed by [`MemDbg`](crate::MemDbg) that does
+/// not correspond to a value, such as an aggregate of the keys of a map,
+/// displaying `size` in the size columns of `layout` and `label` in place of
+/// a field name.
+///
+/// Percentages relative to the parent (see [`DbgFlags::PARENT_PERCENTAGE`])
+/// are computed with respect to `parent_size`, whereas the allocations and
+/// offsets columns (see [`DbgFlags::ALLOCATIONS`] and [`DbgFlags::OFFSETS`])
+/// are left blank.
+pub fn write_sized_label_on(
+    writer: &mut impl core::fmt::Write,
+    layout: &ColumnLayout,
+    size: usize,
+    parent_size: usize,
+    prefix: &str,
+    is_last: bool,
+    label: &str,
+) -> core::fmt::Result {
+    layout.write_size(writer, size, parent_size)?;
+    layout.write_allocations(writer, None)?;
+    layout.write_offsets(writer, None, None)?;
+    write_tree_prefix_on(writer, prefix, is_last, layout.flags())?;
+    writer.write_str(label)?;
+    writer.write_char('\n')
+}
@@ -0,0 +1,173 @@
+/*
+ * SPDX-FileCopyrightText: 2023 Tommaso Fontana
+ * SPDX-FileCopyrightText: 2023 Inria
+ * SPDX-FileCopyrightText: 2023 Sebastiano Vigna
+ *
+ * SPDX-License-Identifier: Apache-2.0 OR LGPL-2.1-or-later
+ */
+
+//! Test suite comparing the number of heap allocations of structures with the
+//! number of blocks actually allocated. Since the allocator is global, this
+//! file must contain a single test.
+
+use mem_dbg::*;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+#[cfg(feature = "im")]
+use std::hash::{BuildHasherDefault, DefaultHasher};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+struct CountingAllocator;
+
+static BLOCKS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        BLOCKS.fetch_add(1, Ordering::SeqCst);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        BLOCKS.fetch_sub(1, Ordering::SeqCst);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+/// Returns the value built by `f` and the number of blocks it allocated.
+fn measure<T>(f: impl FnOnce() -> T) -> (T, usize) {
+    let before = BLOCKS.load(Ordering::SeqCst);
+    let value = f();
+    (value, BLOCKS.load(Ordering::SeqCst) - before)
+}
+
+#[derive(MemSize)]
+enum Contact {
+    None,
+    Email(String),
+    Phone { prefix: u16, number: Box<[u8]> },
+}
+
+#[derive(MemSize)]
+struct Person {
+    name: String,
+    nick: Option<Box<str>>,
+    contacts: Vec<Contact>,
+    scores: HashMap<u32, Vec<u8>>,
+    history: VecDeque<(u64, String)>,
+    notes: BTreeMap<usize, String>,
+    groups: Vec<Rc<String>>,
+    unit: Box<()>,
+}
+
+/// Checks that the estimated number of allocations of a persistent
+/// collection is within 25% of the number of blocks.
+#[cfg(feature = "im")]
+fn check_im<T: MemSize + Clone>(len: u64, f: impl FnOnce() -> T) {
+    let (value, blocks) = measure(f);
+    let allocations = value.mem_allocations(SizeFlags::default());
+    assert!(
+        allocations.abs_diff(blocks) <= blocks / 4,
+        "{} with {} elements: {} allocations, {} blocks",
+        core::any::type_name::<T>(),
+        len,
+        allocations,
+        blocks
+    );
+    // Clones share their nodes, which are identified by the elements, so
+    // the root of empty collections is counted once for each clone
+    if len != 0 {
+        let clones = (value.clone(), value);
+        assert_eq!(clones.mem_allocations(SizeFlags::FOLLOW_RCS), allocations);
+    }
+}
+
+#[cfg(feature = "im")]
+fn test_im() {
+    for len in [0_u64, 1, 10, 65, 1000, 100_000] {
+        // A deterministic hasher, as the number of nodes depends on the hashes
+        type Hasher = BuildHasherDefault<DefaultHasher>;
+        // Keys in pseudorandom order
+        let keys = || (0..len).map(|i| i.wrapping_mul(0x9E3779B97F4A7C15) >> 8);
+        check_im(len, || keys().collect::<im::Vector<u64>>());
+        check_im(len, || {
+            keys()
+                .map(|k| (k, k))
+                .collect::<im::HashMap<u64, u64, Hasher>>()
+        });
+        check_im(len, || keys().collect::<im::HashSet<u64, Hasher>>());
+        check_im(len, || {
+            keys().map(|k| (k, k)).collect::<im::OrdMap<u64, u64>>()
+        });
+        check_im(len, || keys().collect::<im::OrdSet<u64>>());
+    }
+}
+
+#[cfg(feature = "bytes")]
+fn test_bytes() {
+    let (b, blocks) = measure(|| bytes::Bytes::copy_from_slice(b"bytes"));
+    assert_eq!(b.mem_allocations(SizeFlags::default()), blocks);
+    let (b, blocks) = measure(|| bytes::BytesMut::with_capacity(10));
+    assert_eq!(b.mem_allocations(SizeFlags::default()), blocks);
+    let (b, blocks) = measure(bytes::Bytes::new);
+    assert_eq!(b.mem_allocations(SizeFlags::default()), blocks);
+}
+
+#[cfg(feature = "mmap-rs")]
+fn test_mmap() {
+    let (m, blocks) = measure(|| mmap_rs::MmapOptions::new(4096).unwrap().map_mut().unwrap());
+    assert_eq!(m.mem_allocations(SizeFlags::default()), blocks);
+    // The map is not obtained from the global allocator
+    assert_eq!(m.mem_allocations(SizeFlags::FOLLOW_REFS), blocks + 1);
+}
+
+#[test]
+fn test_allocations() {
+    // Initialize the random keys of hash maps, which might allocate
+    let _ = HashMap::<u32, u32>::new();
+
+    let (v, blocks) = measure(|| vec![String::from("a"), String::new(), String::from("b")]);
+    assert_eq!(v.mem_allocations(SizeFlags::default()), blocks);
+    assert_eq!(blocks, 3);
+
+    // Buffers with capacity are counted even if unused
+    let (v, blocks) = measure(|| Vec::<Vec<u32>>::with_capacity(10));
+    assert_eq!(v.mem_allocations(SizeFlags::default()), blocks);
+    assert_eq!(blocks, 1);
+
+    let (person, blocks) = measure(|| {
+        let group = Rc::new(String::from("admins"));
+        Person {
+            name: String::from("Ada"),
+            nick: Some("ada".into()),
+            contacts: vec![
+                Contact::None,
+                Contact::Email(String::from("ada@example.com")),
+                Contact::Phone {
+                    prefix: 39,
+                    number: vec![1, 2, 3].into_boxed_slice(),
+                },
+            ],
+            scores: (0..100).map(|i| (i, vec![i as u8; 3])).collect(),
+            history: (0..10).map(|i| (i, i.to_string())).collect(),
+            notes: (0..5).map(|i| (i, i.to_string())).collect(),
+            groups: vec![group.clone(), group, Rc::new(String::new())],
+            unit: Box::new(()),
+        }
+    });
+    assert_eq!(person.mem_allocations(SizeFlags::FOLLOW_RCS), blocks);
+    // Without FOLLOW_RCS, the two allocations of the reference-counted
+    // pointers are not counted, whereas the string shared by two pointers is
+    // counted twice
+    assert_eq!(person.mem_allocations(SizeFlags::default()), blocks - 2 + 1);
+
+    #[cfg(feature = "im")]
+    test_im();
+    #[cfg(feature = "bytes")]
+    test_bytes();
+    #[cfg(feature = "mmap-rs")]
+    test_mmap();
+}
@@ -0,0 +1,96 @@
+/*
+ * SPDX-FileCopyrightText: 2023 Tommaso Fontana
+ * SPDX-FileCopyrightText: 2023 Inria
+ * SPDX-FileCopyrightText: 2023 Sebastiano Vigna
+ *
+ * SPDX-License-Identifier: Apache-2.0 OR LGPL-2.1-or-later
+ */
+
+//! Test suite checking that the sizes returned with [`SizeFlags::ALLOCATOR`]
+//! are at least those returned with [`SizeFlags::CAPACITY`].
+
+#![cfg(feature = "allocator")]
+
+use mem_dbg::*;
+use std::collections::{BTreeMap, BinaryHeap, HashMap, HashSet, VecDeque};
+use std::path::PathBuf;
+
+const SIZES: [usize; 10] = [1, 3, 7, 10, 24, 100, 1000, 4097, 10_000, 1_000_000];
+
+/// Checks that `ALLOCATOR` ≥ `CAPACITY` ≥ default.
+fn check<T: MemSize>(value: &T) {
+    let default = value.mem_size(SizeFlags::default());
+    let capacity = value.mem_size(SizeFlags::CAPACITY);
+    let allocator = value.mem_size(SizeFlags::ALLOCATOR);
+    assert!(capacity >= default, "{} < {}", capacity, default);
+    assert!(allocator >= capacity, "{} < {}", allocator, capacity);
+}
+
+#[test]
+fn test_allocator() {
+    // Without queries, ALLOCATOR is equivalent to CAPACITY
+    let v = Vec::<u64>::with_capacity(10);
+    assert_eq!(
+        v.mem_size(SizeFlags::ALLOCATOR),
+        v.mem_size(SizeFlags::CAPACITY)
+    );
+    // SAFETY: tests use the system allocator
+    unsafe { enable_allocator_queries() };
+
+    for size in SIZES {
+        let mut v = Vec::<u64>::with_capacity(size);
+        v.extend(0..size as u64 / 2);
+        check(&v);
+        check(&"a".repeat(size));
+        check(&PathBuf::from("a".repeat(size)));
+        check(&vec![0_u8; size].into_boxed_slice());
+        check(
+            &(0..size.min(1000))
+                .map(|i| "b".repeat(i))
+                .collect::<Vec<_>>(),
+        );
+        check(&Box::new(vec![0_u32; size]));
+        check(&BinaryHeap::from(vec![0_u32; size]));
+        check(&(0..size as u64).collect::<VecDeque<_>>());
+        check(&(0..size.min(1000)).collect::<HashSet<_>>());
+        check(
+            &(0..size.min(1000))
+                .map(|i| (i, "c".repeat(i)))
+                .collect::<HashMap<_, _>>(),
+        );
+        check(
+            &(0..size.min(1000))
+                .map(|i| (i, i))
+                .collect::<BTreeMap<_, _>>(),
+        );
+    }
+    // Binary heaps expose their buffer, and allocators round up requests
+    let heap = BinaryHeap::<u8>::with_capacity(3);
+    assert!(heap.mem_size(SizeFlags::ALLOCATOR) > heap.mem_size(SizeFlags::CAPACITY));
+    // Vector deques and hash tables do not, so their size is the same as
+    // with CAPACITY
+    let deque = VecDeque::<u8>::with_capacity(3);
+    assert_eq!(
+        deque.mem_size(SizeFlags::ALLOCATOR),
+        deque.mem_size(SizeFlags::CAPACITY)
+    );
+    let set = HashSet::<u8>::with_capacity(3);
+    assert_eq!(
+        set.mem_size(SizeFlags::ALLOCATOR),
+        set.mem_size(SizeFlags::CAPACITY)
+    );
+    // Nothing is allocated for empty structures
+    assert_eq!(
+        Vec::<u64>::new().mem_size(SizeFlags::ALLOCATOR),
+        core::mem::size_of::<Vec<u64>>()
+    );
+    assert_eq!(
+        Box::new(()).mem_size(SizeFlags::ALLOCATOR),
+        core::mem::size_of::<Box<()>>()
+    );
+    // ALLOCATOR implies CAPACITY
+    assert!(SizeFlags::ALLOCATOR.contains(SizeFlags::CAPACITY));
+    assert!(DbgFlags::ALLOCATOR
+        .to_size_flags()
+        .contains(SizeFlags::ALLOCATOR));
+}
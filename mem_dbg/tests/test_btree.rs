@@ -0,0 +1,97 @@
+/*
+ * SPDX-FileCopyrightText: 2023 Tommaso Fontana
+ * SPDX-FileCopyrightText: 2023 Inria
+ * SPDX-FileCopyrightText: 2023 Sebastiano Vigna
+ *
+ * SPDX-License-Identifier: Apache-2.0 OR LGPL-2.1-or-later
+ */
+
+//! Test suite comparing the estimated size of B-trees with the memory
+//! actually allocated. Since the allocator is global, this file must contain
+//! a single test.
+
+use mem_dbg::*;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+struct CountingAllocator;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATED.fetch_add(layout.size(), Ordering::SeqCst);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        ALLOCATED.fetch_sub(layout.size(), Ordering::SeqCst);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+/// Returns the value built by `f` and the memory it allocated, including
+/// its stack size.
+fn measure<T>(f: impl FnOnce() -> T) -> (T, usize) {
+    let before = ALLOCATED.load(Ordering::SeqCst);
+    let value = f();
+    let allocated = ALLOCATED.load(Ordering::SeqCst) - before;
+    (value, allocated + core::mem::size_of::<T>())
+}
+
+/// A pseudorandom sequence of keys.
+fn keys(n: usize) -> impl Iterator<Item = u64> {
+    let mut x = 1_u64;
+    (0..n).map(move |_| {
+        x = x
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        x >> 1
+    })
+}
+
+fn relative_error(estimate: usize, actual: usize) -> f64 {
+    (estimate as f64 - actual as f64).abs() / actual as f64
+}
+
+#[test]
+fn test_btree() {
+    // A tree with few elements is made of a single leaf
+    for n in [0, 1, 11] {
+        let (map, allocated) = measure(|| keys(n).map(|k| (k, k)).collect::<BTreeMap<_, _>>());
+        assert_eq!(map.mem_size(SizeFlags::default()), allocated);
+    }
+
+    // Large trees built by random insertions
+    let (map, allocated) = measure(|| {
+        let mut map = BTreeMap::new();
+        for k in keys(1_000_000) {
+            map.insert(k, k);
+        }
+        map
+    });
+    assert!(relative_error(map.mem_size(SizeFlags::default()), allocated) < 0.01);
+
+    let (set, allocated) = measure(|| {
+        let mut set = BTreeSet::new();
+        for k in keys(1_000_000) {
+            set.insert(k);
+        }
+        set
+    });
+    assert!(relative_error(set.mem_size(SizeFlags::default()), allocated) < 0.01);
+
+    // Heap memory of keys and values is followed
+    let (map, allocated) = measure(|| {
+        let mut map = BTreeMap::new();
+        for k in keys(100_000) {
+            map.insert(k.to_string(), vec![0_u8; 10]);
+        }
+        map
+    });
+    assert!(relative_error(map.mem_size(SizeFlags::default()), allocated) < 0.01);
+}
@@ -0,0 +1,179 @@
+/*
+ * SPDX-FileCopyrightText: 2023 Tommaso Fontana
+ * SPDX-FileCopyrightText: 2023 Inria
+ * SPDX-FileCopyrightText: 2023 Sebastiano Vigna
+ *
+ * SPDX-License-Identifier: Apache-2.0 OR LGPL-2.1-or-later
+ */
+
+//! Test suite checking the JSON output of [`MemDbg`] against snapshots.
+
+#![cfg(feature = "json")]
+#![allow(dead_code)]
+
+use mem_dbg::*;
+use serde_json::json;
+
+#[derive(MemSize, MemDbg)]
+struct Struct<A, B> {
+    a: A,
+    b: B,
+    test: isize,
+}
+
+#[derive(MemSize, MemDbg)]
+struct Data<A> {
+    a: A,
+    b: Vec<i32>,
+    c: (u8, String),
+}
+
+#[derive(MemSize, MemDbg)]
+union SingletonUnion<A: Copy> {
+    a: A,
+}
+
+#[derive(MemSize, MemDbg)]
+enum TestEnum {
+    Unit,
+    Unit2(),
+    Unit3 {},
+    Union(SingletonUnion<u8>),
+    Unnamed(usize, u8),
+    Named { first: usize, second: u8 },
+}
+
+/// The value of the example of the README.
+fn readme_example() -> Struct<TestEnum, Data<Vec<u8>>> {
+    Struct {
+        a: TestEnum::Unnamed(0, 16),
+        b: Data {
+            a: vec![0x42_u8; 700],
+            b: Vec::with_capacity(100),
+            c: (1, "foo".to_owned()),
+        },
+        test: -0xbadf00d,
+    }
+}
+
+/// Returns the JSON object of a node of kind value without type name.
+fn value(
+    name: &str,
+    size: usize,
+    percent: f64,
+    padding: usize,
+    children: Vec<serde_json::Value>,
+) -> serde_json::Value {
+    json!({
+        "name": name,
+        "kind": "value",
+        "type": null,
+        "size": size,
+        "percent": percent,
+        "padding": padding,
+        "allocations": null,
+        "children": children,
+    })
+}
+
+#[test]
+fn test_readme_example() {
+    let s = readme_example();
+    let output = s.mem_dbg_json(DbgFlags::empty());
+    assert!(serde_json::from_str::<serde_json::Value>(&output).is_ok());
+    let percent = |size: usize| 100.0 * size as f64 / 807.0;
+    assert_eq!(
+        output,
+        value(
+            "",
+            807,
+            100.0,
+            0,
+            vec![
+                value(
+                    "a",
+                    16,
+                    percent(16),
+                    0,
+                    vec![
+                        json!({
+                            "name": "Variant: Unnamed",
+                            "kind": "label",
+                            "type": null,
+                            "size": 0,
+                            "percent": 0.0,
+                            "padding": 0,
+                            "allocations": null,
+                            "children": [],
+                        }),
+                        value("0", 8, percent(8), 0, vec![]),
+                        value("1", 1, percent(1), 6, vec![]),
+                    ]
+                ),
+                value(
+                    "b",
+                    783,
+                    percent(783),
+                    0,
+                    vec![
+                        value("a", 724, percent(724), 0, vec![]),
+                        value("b", 24, percent(24), 0, vec![]),
+                        value(
+                            "c",
+                            35,
+                            percent(35),
+                            0,
+                            vec![
+                                value("0", 1, percent(1), 7, vec![]),
+                                value("1", 27, percent(27), 0, vec![]),
+                            ]
+                        ),
+                    ]
+                ),
+                value("test", 8, percent(8), 0, vec![]),
+            ]
+        )
+        .to_string()
+    );
+
+    // The writer-based method returns the same output
+    let mut written = String::new();
+    s.mem_dbg_json_on(&mut written, DbgFlags::empty()).unwrap();
+    assert_eq!(written, output);
+}
+
+#[test]
+fn test_optional_fields() {
+    let s = readme_example();
+    let flags =
+        DbgFlags::TYPE_NAME | DbgFlags::CAPACITY | DbgFlags::HUMANIZE | DbgFlags::ALLOCATIONS;
+    let parsed: serde_json::Value = serde_json::from_str(&s.mem_dbg_json(flags)).unwrap();
+    // Sizes are in bytes even when humanized
+    assert_eq!(parsed["size"], 1207);
+    assert_eq!(parsed["humanized_size"], "1.207 kB");
+    assert_eq!(parsed["allocations"], 3);
+    let b = &parsed["children"][1]["children"][1];
+    assert_eq!(b["name"], "b");
+    assert_eq!(b["type"], "alloc::vec::Vec<i32>");
+    assert_eq!(b["size"], 424);
+    assert_eq!(b["humanized_size"], "424 B");
+    assert_eq!(b["allocations"], 1);
+}
+
+#[test]
+fn test_escaping() {
+    #[derive(MemSize, MemDbg)]
+    struct Quoted {
+        text: String,
+    }
+
+    let parsed: serde_json::Value = serde_json::from_str(
+        &Quoted {
+            text: String::new(),
+        }
+        .mem_dbg_json(DbgFlags::TYPE_NAME),
+    )
+    .unwrap();
+    assert_eq!(parsed["type"], "test_json::test_escaping::Quoted");
+    assert_eq!(parsed["children"][0]["type"], "alloc::string::String");
+}
@@ -0,0 +1,2653 @@
+/*
+ * SPDX-FileCopyrightText: 2023 Tommaso Fontana
+ * SPDX-FileCopyrightText: 2023 Inria
+ * SPDX-FileCopyrightText: 2023 Sebastiano Vigna
+ *
+ * SPDX-License-Identifier: Apache-2.0 OR LGPL-2.1-or-later
+ */
+
+//! Test suite checking the output of [`MemDbg`] against snapshots.
+
+#![allow(dead_code)]
+
+use mem_dbg::*;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+#[derive(Clone, Copy, MemSize, MemDbg)]
+#[copy_type]
+enum TestEnum {
+    Unit,
+    Unit2(),
+    Unit3 {},
+    Unnamed(usize, u8),
+    Named { first: usize, second: u8 },
+}
+
+#[derive(MemSize, MemDbg)]
+struct Struct<A, B> {
+    a: A,
+    b: B,
+    test: isize,
+    h: HashSet<usize>,
+}
+
+#[derive(MemSize, MemDbg)]
+struct Data<A> {
+    a: A,
+    b: Vec<i32>,
+    c: (u8, String),
+}
+
+/// The structure of the `example.rs` example.
+fn example() -> Struct<TestEnum, Data<Vec<u8>>> {
+    let mut b = Vec::with_capacity(100);
+    b.extend(0..10);
+    let mut h = HashSet::with_capacity(100);
+    h.extend(0..10);
+
+    Struct {
+        a: TestEnum::Unnamed(0, 16),
+        b: Data {
+            a: vec![0x42_u8; 700],
+            b,
+            c: (1, "foo".to_owned()),
+        },
+        test: -0xbadf00d,
+        h,
+    }
+}
+
+#[test]
+fn test_quiet_types() -> core::fmt::Result {
+    let mut output = String::new();
+    example().mem_dbg_on(&mut output, DbgFlags::QUIET_TYPES)?;
+    assert_eq!(
+        output,
+        "\
+1039 B ⏺: test_mem_dbg::Struct<test_mem_dbg::TestEnum, test_mem_dbg::Data<alloc::vec::Vec<u8>>>
+  16 B ├╴a
+       │ ├╴Variant: Unnamed
+   8 B │ ├╴0
+   1 B │ ╰╴1 [6B]
+ 823 B ├╴b: test_mem_dbg::Data<alloc::vec::Vec<u8>>
+ 724 B │ ├╴a: alloc::vec::Vec<u8>
+  64 B │ ├╴b: alloc::vec::Vec<i32>
+  35 B │ ╰╴c: (u8, alloc::string::String)
+   1 B │   ├╴0 [7B]
+  27 B │   ╰╴1: alloc::string::String
+   8 B ├╴test
+ 192 B ╰╴h: std::collections::hash::set::HashSet<usize>
+"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_ascii() -> core::fmt::Result {
+    let mut utf8 = String::new();
+    example().mem_dbg_on(&mut utf8, DbgFlags::empty())?;
+    let mut ascii = String::new();
+    example().mem_dbg_on(&mut ascii, DbgFlags::ASCII)?;
+    assert_eq!(
+        ascii,
+        "\
+1039 B *
+  16 B +-a
+       | +-Variant: Unnamed
+   8 B | +-0
+   1 B | \\-1 [6B]
+ 823 B +-b
+ 724 B | +-a
+  64 B | +-b
+  35 B | \\-c
+   1 B |   +-0 [7B]
+  27 B |   \\-1
+   8 B +-test
+ 192 B \\-h
+"
+    );
+    assert!(ascii.is_ascii());
+    assert_eq!(
+        utf8.replace('├', "+")
+            .replace('╰', "\\")
+            .replace('│', "|")
+            .replace('╴', "-")
+            .replace('⏺', "*"),
+        ascii
+    );
+    Ok(())
+}
+
+#[test]
+fn test_opaque() -> core::fmt::Result {
+    #[derive(MemSize, MemDbg)]
+    struct Opaque<A, B> {
+        a: A,
+        #[mem_dbg(opaque)]
+        b: B,
+        test: isize,
+    }
+
+    #[derive(MemSize, MemDbg)]
+    enum OpaqueEnum<B> {
+        Variant(#[mem_dbg(opaque)] B, u8),
+    }
+
+    let s = example();
+    let s = Opaque {
+        a: s.a,
+        b: s.b,
+        test: s.test,
+    };
+    let mut output = String::new();
+    s.mem_dbg_on(&mut output, DbgFlags::empty())?;
+    assert_eq!(
+        output,
+        "\
+847 B ⏺
+ 16 B ├╴a
+      │ ├╴Variant: Unnamed
+  8 B │ ├╴0
+  1 B │ ╰╴1 [6B]
+823 B ├╴b
+  8 B ╰╴test
+"
+    );
+
+    let e = OpaqueEnum::Variant(example().b, 0);
+    let mut output = String::new();
+    e.mem_dbg_on(&mut output, DbgFlags::empty())?;
+    assert_eq!(
+        output,
+        "\
+831 B ⏺
+      ├╴Variant: Variant
+823 B ├╴0
+  1 B ╰╴1 [7B]
+"
+    );
+
+    // Opaque fields need only implement MemSize
+    #[derive(MemSize)]
+    struct OnlySize(u64);
+
+    #[derive(MemSize, MemDbg)]
+    struct WithOnlySize(#[mem_dbg(opaque)] OnlySize);
+
+    let mut output = String::new();
+    WithOnlySize(OnlySize(0)).mem_dbg_on(&mut output, DbgFlags::empty())?;
+    assert_eq!(output, "8 B ⏺\n8 B ╰╴0\n");
+    Ok(())
+}
+
+#[derive(MemSize, MemDbg)]
+enum Derived {
+    A(u64, u64),
+    B { first: u64, second: u64 },
+    C,
+}
+
+#[derive(MemSize)]
+enum Manual {
+    A(u64, u64),
+    B { first: u64, second: u64 },
+    C,
+}
+
+/// The implementation the derive macro generates for [`Derived`], written
+/// using the public helpers.
+impl MemDbgImpl for Manual {
+    fn _mem_dbg_rec(&self, node: &mut MemNode, max_depth: usize, flags: DbgFlags) {
+        match self {
+            Manual::A(a, b) => {
+                node.children.push(MemNode::label("Variant: A"));
+                node.children
+                    .push(a._mem_dbg_node("0", 8, max_depth - 1, flags));
+                node.children
+                    .push(b._mem_dbg_node("1", 8, max_depth - 1, flags));
+            }
+            Manual::B { first, second } => {
+                node.children.push(MemNode::label("Variant: B"));
+                node.children
+                    .push(first._mem_dbg_node("first", 8, max_depth - 1, flags));
+                node.children
+                    .push(second._mem_dbg_node("second", 8, max_depth - 1, flags));
+            }
+            Manual::C => node.children.push(MemNode::label("Variant: C")),
+        }
+    }
+}
+
+#[derive(MemSize, MemDbg)]
+struct Pair<E> {
+    first: E,
+    last: E,
+}
+
+#[test]
+fn test_enum_prefixes() -> core::fmt::Result {
+    let values = [
+        (Derived::A(0, 1), Manual::A(0, 1)),
+        (
+            Derived::B {
+                first: 0,
+                second: 1,
+            },
+            Manual::B {
+                first: 0,
+                second: 1,
+            },
+        ),
+        (Derived::C, Manual::C),
+    ];
+    let flags = [
+        DbgFlags::empty(),
+        DbgFlags::HUMANIZE,
+        DbgFlags::SEPARATOR | DbgFlags::PERCENTAGE,
+        DbgFlags::HUMANIZE | DbgFlags::PERCENTAGE | DbgFlags::ASCII,
+    ];
+    for (derived, manual) in values {
+        let derived = Pair {
+            first: Derived::C,
+            last: derived,
+        };
+        let manual = Pair {
+            first: Manual::C,
+            last: manual,
+        };
+        for flags in flags {
+            let mut derived_output = String::new();
+            derived.mem_dbg_on(&mut derived_output, flags)?;
+            let mut manual_output = String::new();
+            manual.mem_dbg_on(&mut manual_output, flags)?;
+            assert_eq!(derived_output, manual_output);
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_enum_alignment() -> core::fmt::Result {
+    let mut output = String::new();
+    example().mem_dbg_on(
+        &mut output,
+        DbgFlags::HUMANIZE | DbgFlags::PERCENTAGE | DbgFlags::SEPARATOR,
+    )?;
+    assert_eq!(
+        output,
+        "\
+1.039 kB 100.00% ⏺
+   16  B   1.54% ├╴a
+                 │ ├╴Variant: Unnamed
+    8  B   0.77% │ ├╴0
+    1  B   0.10% │ ╰╴1 [6B]
+  823  B  79.21% ├╴b
+  724  B  69.68% │ ├╴a
+   64  B   6.16% │ ├╴b
+   35  B   3.37% │ ╰╴c
+    1  B   0.10% │   ├╴0 [7B]
+   27  B   2.60% │   ╰╴1
+    8  B   0.77% ├╴test
+  192  B  18.48% ╰╴h
+"
+    );
+    // The tree must start in the same column on every line
+    let width = header_indent_width(
+        example().mem_size(SizeFlags::default()),
+        DbgFlags::HUMANIZE | DbgFlags::PERCENTAGE | DbgFlags::SEPARATOR,
+    );
+    for line in output.lines() {
+        assert!(line[..width].ends_with(' '), "{}", line);
+        assert!(!line[width..].starts_with(' '), "{}", line);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_non_exhaustive() -> core::fmt::Result {
+    #[derive(MemSize, MemDbg)]
+    #[non_exhaustive]
+    enum Event {
+        Start,
+        Data(Vec<u8>),
+        Stop { code: u32 },
+    }
+
+    #[derive(MemSize, MemDbg)]
+    #[non_exhaustive]
+    struct Record {
+        id: u64,
+        event: Event,
+    }
+
+    #[allow(dead_code)]
+    #[derive(MemSize, MemDbg)]
+    #[non_exhaustive]
+    enum Never {}
+
+    // Matches within the defining crate need no wildcard arm
+    let record = Record {
+        id: 0,
+        event: Event::Data(vec![1, 2, 3]),
+    };
+    assert_eq!(
+        record.mem_size(SizeFlags::default()),
+        core::mem::size_of::<Record>() + 3
+    );
+    let mut output = String::new();
+    record.mem_dbg_on(&mut output, DbgFlags::empty())?;
+    assert_eq!(
+        output,
+        "\
+35 B ⏺
+ 8 B ├╴id
+27 B ╰╴event
+       ├╴Variant: Data
+27 B   ╰╴0
+"
+    );
+
+    for event in [Event::Start, Event::Stop { code: 1 }] {
+        assert_eq!(
+            event.mem_size(SizeFlags::default()),
+            core::mem::size_of::<Event>()
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn test_packed() -> core::fmt::Result {
+    #[derive(MemSize, MemDbg)]
+    #[repr(C, packed)]
+    struct Packed {
+        a: u8,
+        b: u64,
+    }
+
+    let packed = Packed { a: 0, b: 1 };
+    assert_eq!(packed.mem_size(SizeFlags::default()), 9);
+    let mut output = String::new();
+    packed.mem_dbg_on(&mut output, DbgFlags::empty())?;
+    assert_eq!(output, "9 B ⏺\n1 B ├╴a\n8 B ╰╴b\n");
+
+    // Arguments of the representation are accepted
+    #[derive(MemSize, MemDbg)]
+    #[repr(C, packed(2))]
+    struct Packed2 {
+        a: u8,
+        b: u64,
+    }
+
+    let packed = Packed2 { a: 0, b: 1 };
+    assert_eq!(packed.mem_size(SizeFlags::default()), 10);
+
+    // Fields that are not Copy are read through a bitwise copy that is never
+    // dropped, so their heap size is reported, too
+    #[derive(MemSize, MemDbg)]
+    #[repr(C, packed)]
+    struct PackedBox {
+        a: u8,
+        b: Box<[u8; 100]>,
+    }
+
+    let packed = PackedBox {
+        a: 0,
+        b: Box::new([0; 100]),
+    };
+    let report = packed.mem_size_detailed(SizeFlags::default());
+    assert_eq!(report.bytes, 109);
+    assert!(!report.estimated);
+    assert_eq!(packed.mem_allocations(SizeFlags::default()), 1);
+    let mut output = String::new();
+    packed.mem_dbg_on(&mut output, DbgFlags::TYPE_NAME)?;
+    assert_eq!(
+        output,
+        "\
+109 B ⏺: test_mem_dbg::test_packed::PackedBox
+  1 B ├╴a: u8
+108 B ╰╴b: alloc::boxed::Box<[u8; 100]>
+"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_empty_struct() -> core::fmt::Result {
+    #[derive(MemSize, MemDbg)]
+    struct Empty {}
+
+    #[derive(MemSize, MemDbg)]
+    struct Unit;
+
+    let mut output = String::new();
+    Empty {}.mem_dbg_on(&mut output, DbgFlags::empty())?;
+    Unit.mem_dbg_on(&mut output, DbgFlags::RUST_LAYOUT)?;
+    assert_eq!(output, "0 B ⏺\n0 B ⏺\n");
+    Ok(())
+}
+
+#[test]
+fn test_padded_sizes() -> core::fmt::Result {
+    #[derive(MemSize, MemDbg)]
+    #[repr(C)]
+    struct Padded {
+        a: u8,
+        b: u64,
+        c: u16,
+        d: (),
+    }
+
+    let padded = Padded {
+        a: 0,
+        b: 0,
+        c: 0,
+        d: (),
+    };
+    let expected = "\
+24 B ⏺
+ 1 B ├╴a [7B]
+ 8 B ├╴b
+ 2 B ├╴c
+ 0 B ╰╴d [6B]
+";
+    let mut output = String::new();
+    padded.mem_dbg_on(&mut output, DbgFlags::empty())?;
+    assert_eq!(output, expected);
+    let mut output = String::new();
+    padded.mem_dbg_on(&mut output, DbgFlags::RUST_LAYOUT)?;
+    assert_eq!(output, expected);
+
+    // In the Rust layout fields are reordered, but padding is not lost
+    #[derive(MemSize, MemDbg)]
+    struct Reordered {
+        a: u8,
+        b: u64,
+        c: u8,
+    }
+
+    let mut output = String::new();
+    Reordered { a: 0, b: 0, c: 0 }.mem_dbg_on(&mut output, DbgFlags::RUST_LAYOUT)?;
+    let padded_size = output
+        .lines()
+        .skip(1)
+        .map(|line| {
+            let size: usize = line.split_whitespace().next().unwrap().parse().unwrap();
+            let padding = line
+                .split('[')
+                .nth(1)
+                .map_or(0, |p| p.trim_end_matches("B]").parse().unwrap());
+            size + padding
+        })
+        .sum::<usize>();
+    assert_eq!(padded_size, core::mem::size_of::<Reordered>());
+    Ok(())
+}
+
+#[test]
+fn test_field_names_hygiene() -> core::fmt::Result {
+    // Fields named as the variables of the generated code
+    #[derive(MemSize, MemDbg)]
+    enum Clashing {
+        Named {
+            writer: Vec<u8>,
+            i: u8,
+            n: u16,
+            prefix: String,
+            padded_size: u32,
+            _memdbg_writer: u64,
+            _memsize_flags: u64,
+        },
+        Unnamed(u8, Vec<u8>),
+    }
+
+    let named = Clashing::Named {
+        writer: vec![0; 10],
+        i: 0,
+        n: 0,
+        prefix: "prefix".to_owned(),
+        padded_size: 0,
+        _memdbg_writer: 0,
+        _memsize_flags: 0,
+    };
+    let size = core::mem::size_of::<Clashing>();
+    assert_eq!(named.mem_size(SizeFlags::default()), size + 10 + 6);
+    let mut output = String::new();
+    named.mem_dbg_on(&mut output, DbgFlags::empty())?;
+    assert_eq!(
+        output,
+        "\
+88 B ⏺
+     ├╴Variant: Named
+34 B ├╴writer
+ 1 B ├╴i [1B]
+ 2 B ├╴n
+30 B ├╴prefix
+ 4 B ├╴padded_size
+ 8 B ├╴_memdbg_writer
+ 8 B ╰╴_memsize_flags
+"
+    );
+
+    let unnamed = Clashing::Unnamed(0, vec![0; 5]);
+    assert_eq!(unnamed.mem_size(SizeFlags::default()), size + 5);
+    Ok(())
+}
+
+#[test]
+fn test_enum_padded_sizes() -> core::fmt::Result {
+    #[derive(MemSize, MemDbg)]
+    #[repr(C)]
+    enum Padded {
+        A(u8, u64, u16),
+        B { first: u16, second: u32 },
+    }
+
+    let mut output = String::new();
+    Padded::A(0, 0, 0).mem_dbg_on(&mut output, DbgFlags::empty())?;
+    let expected = "\
+32 B ⏺
+     ├╴Variant: A
+ 1 B ├╴0 [7B]
+ 8 B ├╴1
+ 2 B ╰╴2 [6B]
+";
+    assert_eq!(output, expected);
+    // RUST_LAYOUT is supported on stable, too
+    let mut output = String::new();
+    Padded::A(0, 0, 0).mem_dbg_on(&mut output, DbgFlags::RUST_LAYOUT)?;
+    assert_eq!(output, expected);
+
+    let mut output = String::new();
+    Padded::B {
+        first: 0,
+        second: 0,
+    }
+    .mem_dbg_on(&mut output, DbgFlags::empty())?;
+    assert_eq!(
+        output,
+        "\
+32 B ⏺
+     ├╴Variant: B
+ 2 B ├╴first [2B]
+ 4 B ╰╴second [16B]
+"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_expand_elements() -> core::fmt::Result {
+    #[derive(MemSize, MemDbg)]
+    struct Data2 {
+        names: [String; 2],
+        rows: Vec<Vec<u8>>,
+    }
+
+    let data = Data2 {
+        names: ["foo".to_owned(), "quux".to_owned()],
+        rows: (0..10).map(|i| vec![0; i]).collect(),
+    };
+    let mut output = String::new();
+    data.mem_dbg_on(&mut output, DbgFlags::EXPAND_ELEMENTS)?;
+    assert_eq!(
+        output,
+        "\
+364 B ⏺
+ 55 B ├╴names
+ 27 B │ ├╴[0]
+ 28 B │ ╰╴[1]
+309 B ╰╴rows
+ 24 B   ├╴[0]
+ 25 B   ├╴[1]
+ 26 B   ├╴[2]
+ 27 B   ├╴[3]
+ 28 B   ├╴[4]
+ 29 B   ├╴[5]
+ 30 B   ├╴[6]
+ 31 B   ├╴[7]
+        ╰╴… and 2 more (65 bytes)
+"
+    );
+
+    let mut output = String::new();
+    data.mem_dbg_on(&mut output, DbgFlags::EXPAND_ELEMENTS | DbgFlags::ASCII)?;
+    assert!(output.ends_with("        \\-... and 2 more (65 bytes)\n"));
+
+    // Without the flag, elements are not displayed
+    let mut output = String::new();
+    data.mem_dbg_on(&mut output, DbgFlags::empty())?;
+    assert_eq!(output, "364 B ⏺\n 55 B ├╴names\n309 B ╰╴rows\n");
+
+    // Elements of copy types are never displayed
+    let mut output = String::new();
+    vec![0_u8; 10].mem_dbg_on(&mut output, DbgFlags::EXPAND_ELEMENTS)?;
+    assert_eq!(output, "34 B ⏺\n");
+    Ok(())
+}
+
+#[test]
+fn test_option_result_payloads() -> core::fmt::Result {
+    let data = Some(Data {
+        a: vec![0_u8; 10],
+        b: vec![0_i32; 5],
+        c: (1, "foo".to_owned()),
+    });
+    let mut output = String::new();
+    data.mem_dbg_on(&mut output, DbgFlags::empty())?;
+    assert_eq!(
+        output,
+        "\
+113 B ⏺
+113 B ╰╴Some
+ 34 B   ├╴a
+ 44 B   ├╴b
+ 35 B   ╰╴c
+  1 B     ├╴0 [7B]
+ 27 B     ╰╴1
+"
+    );
+
+    // Nothing is displayed for None
+    let mut output = String::new();
+    None::<Data<Vec<u8>>>.mem_dbg_on(&mut output, DbgFlags::empty())?;
+    assert_eq!(output, "80 B ⏺\n");
+
+    // The discriminant is displayed as padding
+    let mut output = String::new();
+    Some(Some(1_u8)).mem_dbg_on(&mut output, DbgFlags::empty())?;
+    assert_eq!(
+        output,
+        "\
+2 B ⏺
+2 B ╰╴Some
+1 B   ╰╴Some [1B]
+"
+    );
+
+    let mut output = String::new();
+    Ok::<_, String>(vec![0_u8; 10]).mem_dbg_on(&mut output, DbgFlags::empty())?;
+    assert_eq!(
+        output,
+        "\
+42 B ⏺
+34 B ╰╴Ok [8B]
+"
+    );
+    let mut output = String::new();
+    Err::<u8, _>("foo".to_owned()).mem_dbg_on(&mut output, DbgFlags::empty())?;
+    assert_eq!(
+        output,
+        "\
+27 B ⏺
+27 B ╰╴Err
+"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_hash_map_breakdown() -> core::fmt::Result {
+    let mut map = HashMap::<String, Vec<u8>>::with_capacity(8);
+    map.insert("a".to_owned(), vec![0; 100]);
+    map.insert("bb".to_owned(), vec![0; 10]);
+    let mut output = String::new();
+    map.mem_dbg_on(&mut output, DbgFlags::PERCENTAGE)?;
+    assert_eq!(
+        output,
+        "\
+357 B 100.00% ⏺
+ 51 B  14.29% ├╴keys
+158 B  44.26% ├╴values
+100 B  28.01% ╰╴overhead
+"
+    );
+
+    let set = map.keys().cloned().collect::<HashSet<_>>();
+    let mut output = String::new();
+    set.mem_dbg_on(&mut output, DbgFlags::CAPACITY)?;
+    assert_eq!(
+        output,
+        "\
+151 B ⏺
+ 51 B ├╴keys
+ 52 B ╰╴overhead
+"
+    );
+
+    // Nothing to break down if keys and values are Copy
+    let mut output = String::new();
+    HashMap::<u8, u8>::from([(0, 0)]).mem_dbg_on(&mut output, DbgFlags::empty())?;
+    assert_eq!(output, "60 B ⏺\n");
+    Ok(())
+}
+
+#[test]
+fn test_depth() -> core::fmt::Result {
+    let value = example();
+    let mut full = String::new();
+    value.mem_dbg_on(&mut full, DbgFlags::empty())?;
+    let mut output = String::new();
+    value.mem_dbg_depth_on(&mut output, usize::MAX, DbgFlags::empty())?;
+    assert_eq!(output, full);
+
+    let mut output = String::new();
+    value.mem_dbg_depth_on(&mut output, 0, DbgFlags::empty())?;
+    assert_eq!(output, "1039 B ⏺\n");
+
+    let mut output = String::new();
+    value.mem_dbg_depth_on(&mut output, 1, DbgFlags::empty())?;
+    assert_eq!(
+        output,
+        "\
+1039 B ⏺
+  16 B ├╴a
+ 823 B ├╴b
+   8 B ├╴test
+ 192 B ╰╴h
+"
+    );
+
+    // The depth does not depend on the glyphs
+    let mut output = String::new();
+    value.mem_dbg_depth_on(&mut output, 1, DbgFlags::ASCII)?;
+    assert_eq!(
+        output,
+        "\
+1039 B *
+  16 B +-a
+ 823 B +-b
+   8 B +-test
+ 192 B \\-h
+"
+    );
+
+    // Each level of a three-level structure
+    #[derive(MemSize, MemDbg)]
+    struct Leaf {
+        x: u64,
+    }
+
+    #[derive(MemSize, MemDbg)]
+    struct Middle {
+        leaf: Leaf,
+        y: u32,
+    }
+
+    #[derive(MemSize, MemDbg)]
+    struct Top {
+        middle: Middle,
+        z: u64,
+    }
+
+    let value = Top {
+        middle: Middle {
+            leaf: Leaf { x: 0 },
+            y: 0,
+        },
+        z: 0,
+    };
+    let levels = [
+        "24 B ⏺\n",
+        "\
+24 B ⏺
+16 B ├╴middle
+ 8 B ╰╴z
+",
+        "\
+24 B ⏺
+16 B ├╴middle
+ 8 B │ ├╴leaf
+ 4 B │ ╰╴y [4B]
+ 8 B ╰╴z
+",
+        "\
+24 B ⏺
+16 B ├╴middle
+ 8 B │ ├╴leaf
+ 8 B │ │ ╰╴x
+ 4 B │ ╰╴y [4B]
+ 8 B ╰╴z
+",
+    ];
+    for (max_depth, expected) in levels.iter().enumerate() {
+        let mut output = String::new();
+        value.mem_dbg_depth_on(&mut output, max_depth, DbgFlags::empty())?;
+        assert_eq!(&output, expected, "max_depth = {}", max_depth);
+    }
+    let mut output = String::new();
+    value.mem_dbg_depth_on(&mut output, usize::MAX, DbgFlags::empty())?;
+    assert_eq!(output, levels[3]);
+    Ok(())
+}
+
+#[test]
+fn test_btree_map_breakdown() -> core::fmt::Result {
+    let mut map = std::collections::BTreeMap::<String, Vec<u8>>::new();
+    map.insert("a".to_owned(), vec![0; 100]);
+    map.insert("bb".to_owned(), vec![0; 10]);
+    let mut output = String::new();
+    map.mem_dbg_on(&mut output, DbgFlags::PERCENTAGE)?;
+    assert_eq!(
+        output,
+        "681 B 100.00% ⏺
+              ├╴2 entries, ~1 node
+ 51 B   7.49% ├╴keys
+158 B  23.20% ├╴values
+448 B  65.79% ╰╴overhead
+"
+    );
+
+    let set = map
+        .keys()
+        .cloned()
+        .collect::<std::collections::BTreeSet<_>>();
+    let mut output = String::new();
+    set.mem_dbg_on(&mut output, DbgFlags::empty())?;
+    assert_eq!(
+        output,
+        "307 B ⏺
+      ├╴2 entries, ~1 node
+ 51 B ├╴keys
+232 B ╰╴overhead
+"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_btree_label() -> core::fmt::Result {
+    let map = (0..1000_u32)
+        .map(|i| (i, i))
+        .collect::<std::collections::BTreeMap<_, _>>();
+    let mut output = String::new();
+    map.mem_dbg_on(&mut output, DbgFlags::empty())?;
+    assert_eq!(
+        output,
+        "\
+15496 B ⏺
+        ╰╴1000 entries, ~134 nodes
+"
+    );
+
+    let mut output = String::new();
+    std::collections::BTreeSet::from([0_u8]).mem_dbg_on(&mut output, DbgFlags::empty())?;
+    assert!(output.ends_with("╰╴1 entry, ~1 node\n"));
+    Ok(())
+}
+
+#[test]
+fn test_dedup_types() -> core::fmt::Result {
+    #[derive(MemSize, MemDbg)]
+    struct Inner {
+        name: String,
+        data: Vec<u8>,
+    }
+
+    #[derive(MemSize, MemDbg)]
+    struct Outer {
+        first: Inner,
+        id: usize,
+        second: Inner,
+        inners: Vec<Inner>,
+    }
+
+    let inner = |n: usize| Inner {
+        name: "a".repeat(n),
+        data: vec![0; n],
+    };
+    let outer = Outer {
+        first: inner(1),
+        id: 0,
+        second: inner(2),
+        inners: (0..42).map(inner).collect(),
+    };
+
+    let mut output = String::new();
+    outer.mem_dbg_on(
+        &mut output,
+        DbgFlags::EXPAND_ELEMENTS | DbgFlags::DEDUP_TYPES,
+    )?;
+    assert_eq!(
+        output,
+        "\
+3872 B ⏺
+ 102 B ├╴[2×] test_mem_dbg::test_dedup_types::Inner
+   8 B ├╴id
+3762 B ╰╴inners
+3738 B   ╰╴[42×] test_mem_dbg::test_dedup_types::Inner
+"
+    );
+
+    // Groups are displayed at the position of their first field
+    let mut output = String::new();
+    outer.mem_dbg_on(
+        &mut output,
+        DbgFlags::EXPAND_ELEMENTS | DbgFlags::DEDUP_TYPES | DbgFlags::ASCII | DbgFlags::RUST_LAYOUT,
+    )?;
+    assert_eq!(
+        output,
+        "\
+3872 B *
+ 102 B +-[2x] test_mem_dbg::test_dedup_types::Inner
+3762 B +-inners
+3738 B | \\-[42x] test_mem_dbg::test_dedup_types::Inner
+   8 B \\-id
+"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_vec_deque_len_capacity() -> core::fmt::Result {
+    let mut deque = std::collections::VecDeque::with_capacity(4);
+    deque.push_back("a".to_owned());
+    deque.push_front("bc".to_owned());
+    let mut output = String::new();
+    deque.mem_dbg_on(&mut output, DbgFlags::LEN | DbgFlags::CAPACITY)?;
+    assert_eq!(output, "131 B ⏺ (len=2, capacity=4)\n");
+    let mut output = String::new();
+    deque.mem_dbg_on(&mut output, DbgFlags::LEN | DbgFlags::EXPAND_ELEMENTS)?;
+    assert_eq!(
+        output,
+        "\
+83 B ⏺ (len=2)
+26 B ├╴[0] (len=2)
+25 B ╰╴[1] (len=1)
+"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_rc_cycle() -> core::fmt::Result {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[derive(MemSize, MemDbg)]
+    #[mem_dbg(bound = "")]
+    struct Node {
+        id: usize,
+        next: Option<Rc<RefCell<Node>>>,
+    }
+
+    let a = Rc::new(RefCell::new(Node { id: 0, next: None }));
+    let b = Rc::new(RefCell::new(Node {
+        id: 1,
+        next: Some(a.clone()),
+    }));
+    a.borrow_mut().next = Some(b.clone());
+
+    let inner = 2 * size_of::<usize>() + size_of::<RefCell<Node>>();
+    // Each allocation is counted once, and the cycle is not followed again
+    assert_eq!(
+        a.mem_size(SizeFlags::FOLLOW_RCS),
+        size_of::<Rc<RefCell<Node>>>() + 2 * inner
+    );
+    // Without the flag, the cycle is followed until it closes, but the nodes
+    // own no memory besides the allocations of the pointers
+    assert_eq!(
+        a.mem_size(SizeFlags::default()),
+        size_of::<Rc<RefCell<Node>>>()
+    );
+
+    let mut output = String::new();
+    a.mem_dbg_on(&mut output, DbgFlags::FOLLOW_RCS)?;
+    // The display stops when the cycle closes
+    assert_eq!(
+        output,
+        format!(
+            "\
+88 B ⏺
+ 8 B ├╴id
+88 B ╰╴next
+88 B   ╰╴Some
+ 8 B     ├╴id
+88 B     ╰╴next
+88 B       ╰╴Some
+             ╰╴→ @{:p} (already counted)
+",
+            Rc::as_ptr(&a)
+        )
+    );
+    let mut output = String::new();
+    a.mem_dbg_on(&mut output, DbgFlags::default() | DbgFlags::ASCII)?;
+    assert!(output.ends_with(&format!("-> @{:p} (already counted)\n", Rc::as_ptr(&a))));
+
+    // Break the cycle so that the nodes are dropped
+    a.borrow_mut().next = None;
+    Ok(())
+}
+
+#[test]
+fn test_rc_shared() -> core::fmt::Result {
+    #[derive(MemSize, MemDbg)]
+    struct Pair {
+        first: std::rc::Rc<Vec<u8>>,
+        second: std::rc::Rc<Vec<u8>>,
+    }
+
+    let shared = std::rc::Rc::new(vec![0_u8; 100]);
+    let pair = Pair {
+        first: shared.clone(),
+        second: shared.clone(),
+    };
+    let mut output = String::new();
+    pair.mem_dbg_on(&mut output, DbgFlags::FOLLOW_RCS)?;
+    // The size of each node is computed independently, but the allocation is
+    // counted once in the total
+    assert_eq!(
+        output,
+        format!(
+            "\
+156 B ⏺
+148 B ├╴first
+148 B ╰╴second
+        ╰╴→ @{:p} (already counted)
+",
+            std::rc::Rc::as_ptr(&shared)
+        )
+    );
+
+    // Without FOLLOW_RCS, shared allocations are displayed at each pointer
+    let mut output = String::new();
+    pair.mem_dbg_on(&mut output, DbgFlags::default())?;
+    assert!(!output.contains("already counted"));
+    Ok(())
+}
+
+#[test]
+fn test_rc_shared_breakdown() -> core::fmt::Result {
+    let shared = std::rc::Rc::new(vec![0_u8; 100]);
+    let map = (0..10_u32)
+        .map(|i| (i, shared.clone()))
+        .collect::<HashMap<_, _>>();
+    let mut output = String::new();
+    // The values share an allocation, which is counted once in the values as
+    // in the overall size of the map
+    map.mem_dbg_on(&mut output, DbgFlags::PERCENTAGE | DbgFlags::FOLLOW_RCS)?;
+    assert_eq!(
+        output,
+        "\
+396 B 100.00% ⏺
+ 40 B  10.10% ├╴keys
+220 B  55.56% ├╴values
+ 88 B  22.22% ╰╴overhead
+"
+    );
+
+    let map = (0..10_u32)
+        .map(|i| (i, shared.clone()))
+        .collect::<BTreeMap<_, _>>();
+    let mut output = String::new();
+    map.mem_dbg_on(&mut output, DbgFlags::PERCENTAGE | DbgFlags::FOLLOW_RCS)?;
+    assert_eq!(
+        output,
+        "\
+308 B 100.00% ⏺
+              ├╴10 entries, ~1 node
+ 40 B  12.99% ├╴keys
+220 B  71.43% ├╴values
+ 24 B   7.79% ╰╴overhead
+"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_rc_trait_objects() -> core::fmt::Result {
+    let shared: std::sync::Arc<dyn MemDbgImpl + Send + Sync> = std::sync::Arc::new(vec![0_u8; 100]);
+    let values = (shared.clone(), shared.clone());
+    let mut output = String::new();
+    values.mem_dbg_on(&mut output, DbgFlags::FOLLOW_RCS)?;
+    // The label contains the address of the data, without the vtable
+    assert_eq!(
+        output,
+        format!(
+            "\
+172 B ⏺
+156 B ├╴0
+156 B ╰╴1
+        ╰╴→ @{:p} (already counted)
+",
+            std::sync::Arc::as_ptr(&shared) as *const u8
+        )
+    );
+    Ok(())
+}
+
+#[test]
+fn test_filtered() -> core::fmt::Result {
+    #[derive(MemSize, MemDbg)]
+    struct Flags {
+        a: bool,
+        b: bool,
+        c: u8,
+        d: u16,
+        name: String,
+    }
+
+    #[derive(MemSize, MemDbg)]
+    struct Config {
+        id: u32,
+        port: u16,
+        retries: u8,
+        verbose: bool,
+        flags: Flags,
+        hosts: Vec<String>,
+    }
+
+    let config = Config {
+        id: 0,
+        port: 8080,
+        retries: 3,
+        verbose: false,
+        flags: Flags {
+            a: true,
+            b: false,
+            c: 0,
+            d: 0,
+            name: "flags".repeat(10),
+        },
+        hosts: vec!["localhost".to_owned(); 10],
+    };
+    let mut output = String::new();
+    config.mem_dbg_filtered_on(&mut output, 0, 5.0, DbgFlags::PERCENTAGE)?;
+    // The filter applies at every level, and the sizes still add up
+    assert_eq!(
+        output,
+        "\
+444 B 100.00% ⏺
+ 82 B  18.47% ├╴flags
+ 74 B  16.67% │ ├╴name
+  8 B   1.80% │ ╰╴… 4 nodes below threshold
+354 B  79.73% ├╴hosts
+  8 B   1.80% ╰╴… 4 nodes below threshold
+"
+    );
+
+    let mut output = String::new();
+    let mut tree = config.mem_tree_depth(1, DbgFlags::empty());
+    tree.filter(16, 0.0, DbgFlags::ASCII);
+    tree.write_on(&mut output, DbgFlags::ASCII)?;
+    assert_eq!(
+        output,
+        "\
+444 B *
+ 82 B +-flags
+354 B +-hosts
+  8 B \\-... 4 nodes below threshold
+"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_len() -> core::fmt::Result {
+    #[derive(MemSize, MemDbg)]
+    struct Index {
+        name: String,
+        ids: Vec<u32>,
+        words: std::collections::BTreeMap<u32, u32>,
+        slice: Box<[u8]>,
+    }
+
+    let mut ids = Vec::with_capacity(2000);
+    ids.extend(0..1234);
+    let index = Index {
+        name: "index".to_owned(),
+        ids,
+        words: (0..3).map(|i| (i, i)).collect(),
+        slice: vec![0; 10].into_boxed_slice(),
+    };
+    let mut output = String::new();
+    index.mem_dbg_on(&mut output, DbgFlags::LEN | DbgFlags::SEPARATOR)?;
+    assert_eq!(
+        output,
+        "\
+5_143 B ⏺
+   29 B ├╴name (len=5)
+4_960 B ├╴ids (len=1_234)
+  128 B ├╴words (len=3)
+        │ ╰╴3 entries, ~1 node
+   26 B ╰╴slice (len=10)
+"
+    );
+    let mut output = String::new();
+    index.mem_dbg_on(
+        &mut output,
+        DbgFlags::LEN | DbgFlags::CAPACITY | DbgFlags::TYPE_NAME,
+    )?;
+    // The capacity follows the length, if the collection has one
+    assert_eq!(
+        output,
+        "\
+8207 B ⏺: test_mem_dbg::test_len::Index
+  29 B ├╴name: alloc::string::String (len=5, capacity=5)
+8024 B ├╴ids: alloc::vec::Vec<u32> (len=1234, capacity=2000)
+ 128 B ├╴words: alloc::collections::btree::map::BTreeMap<u32, u32> (len=3)
+       │ ╰╴3 entries, ~1 node
+  26 B ╰╴slice: alloc::boxed::Box<[u8]> (len=10)
+"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_offsets() -> core::fmt::Result {
+    #[derive(MemSize, MemDbg)]
+    struct Layout {
+        a: u8,
+        b: u64,
+        c: (u8, u16),
+        d: u32,
+    }
+
+    #[derive(MemSize, MemDbg)]
+    enum Shape {
+        Square { side: u16, id: u64 },
+    }
+
+    let layout = Layout {
+        a: 0,
+        b: 1,
+        c: (2, 3),
+        d: 4,
+    };
+    let mut output = String::new();
+    layout.mem_dbg_on(
+        &mut output,
+        DbgFlags::OFFSETS | DbgFlags::RUST_LAYOUT | DbgFlags::TYPE_NAME,
+    )?;
+    // Elements of tuples have no offset
+    assert_eq!(
+        output,
+        "\
+24 B  -/8  ⏺: test_mem_dbg::test_offsets::Layout
+ 8 B  0/8  ├╴b: u64
+ 4 B  8/2  ├╴c: (u8, u16)
+ 1 B  -/1  │ ├╴0: u8 [1B]
+ 2 B  -/2  │ ╰╴1: u16
+ 4 B 12/4  ├╴d: u32
+ 1 B 16/1  ╰╴a: u8 [7B]
+"
+    );
+    let mut output = String::new();
+    Shape::Square { side: 1, id: 2 }.mem_dbg_on(&mut output, DbgFlags::OFFSETS)?;
+    assert_eq!(
+        output,
+        "\
+16 B  -/8  ⏺
+           ├╴Variant: Square
+ 2 B  8/2  ├╴side [6B]
+ 8 B  0/8  ╰╴id
+"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_padding_summary() -> core::fmt::Result {
+    // Badly ordered on purpose
+    #[derive(MemSize, MemDbg)]
+    #[repr(C)]
+    struct Inner {
+        a: u8,
+        b: u32,
+        c: u8,
+    }
+
+    #[derive(MemSize, MemDbg)]
+    #[repr(C)]
+    struct Outer {
+        flag: bool,
+        inner: Inner,
+        value: u64,
+        tag: u16,
+        name: String,
+    }
+
+    let outer = Outer {
+        flag: true,
+        inner: Inner { a: 0, b: 1, c: 2 },
+        value: 3,
+        tag: 4,
+        name: "name".to_owned(),
+    };
+    let mut output = String::new();
+    outer.mem_dbg_on(&mut output, DbgFlags::PADDING_SUMMARY)?;
+    // The summary of the root includes the padding of the fields of inner
+    assert_eq!(
+        output,
+        "\
+60 B ⏺
+ 1 B ├╴flag [3B]
+12 B ├╴inner
+ 1 B │ ├╴a [3B]
+ 4 B │ ├╴b
+ 1 B │ ├╴c [3B]
+     │ ╰╴padding: 6 B (50.00%)
+ 8 B ├╴value
+ 2 B ├╴tag [6B]
+28 B ├╴name
+     ╰╴padding: 15 B (25.00%)
+"
+    );
+    let mut output = String::new();
+    outer.mem_dbg_on(&mut output, DbgFlags::PADDING_SUMMARY | DbgFlags::FLAT)?;
+    assert_eq!(
+        output,
+        "\
+60 B root
+ 1 B root.flag [3B]
+12 B root.inner
+ 1 B root.inner.a [3B]
+ 4 B root.inner.b
+ 1 B root.inner.c [3B]
+     root.inner (padding: 6 B (50.00%))
+ 8 B root.value
+ 2 B root.tag [6B]
+28 B root.name
+     root (padding: 15 B (25.00%))
+"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_top() -> core::fmt::Result {
+    #[derive(MemSize, MemDbg)]
+    struct Inner {
+        names: Vec<String>,
+        id: u64,
+    }
+
+    #[derive(MemSize, MemDbg)]
+    struct Data {
+        small: Vec<u8>,
+        inner: Inner,
+        large: Vec<u64>,
+        flag: bool,
+    }
+
+    let data = Data {
+        small: vec![0; 10],
+        inner: Inner {
+            names: vec!["a".repeat(100), "b".repeat(200)],
+            id: 0,
+        },
+        large: vec![0; 1000],
+        flag: false,
+    };
+    let mut output = String::new();
+    data.mem_dbg_top_on(&mut output, 3, DbgFlags::TYPE_NAME | DbgFlags::PERCENTAGE)?;
+    // The largest field comes first, and nested leaves are ranked, too
+    assert_eq!(
+        output,
+        "\
+8024 B  95.00% root.large: alloc::vec::Vec<u64>
+ 372 B   4.40% root.inner.names: alloc::vec::Vec<alloc::string::String>
+  34 B   0.40% root.small: alloc::vec::Vec<u8>
+"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_largest() {
+    #[derive(MemSize, MemDbg)]
+    struct Inner {
+        names: Vec<String>,
+        id: u64,
+    }
+
+    #[derive(MemSize, MemDbg)]
+    struct Data {
+        first: Vec<u8>,
+        inner: Inner,
+        second: Vec<u8>,
+        flag: bool,
+    }
+
+    let data = Data {
+        first: vec![0; 100],
+        inner: Inner {
+            names: vec!["a".repeat(100), "b".repeat(200)],
+            id: 0,
+        },
+        second: vec![0; 100],
+        flag: false,
+    };
+    let flags = DbgFlags::EXPAND_ELEMENTS;
+    // Leaves of the same size appear in the order of the tree
+    assert_eq!(
+        data.largest(4, flags),
+        vec![
+            ("inner.names[1]".to_string(), 224),
+            ("first".to_string(), 124),
+            ("inner.names[0]".to_string(), 124),
+            ("second".to_string(), 124),
+        ]
+    );
+    assert_eq!(
+        data.largest_subtrees(usize::MAX, flags),
+        vec![("inner".to_string(), 380), ("inner.names".to_string(), 372),]
+    );
+    // The paths are the ones of the CSV export
+    let mut csv = String::new();
+    data.mem_dbg_csv_on(&mut csv, flags).unwrap();
+    assert!(csv.lines().any(|line| line.starts_with("inner.names[1],")));
+}
+
+#[test]
+fn test_mem_size_of_path() {
+    let mut value = example();
+    let flags = SizeFlags::default();
+    // Structures, tuples, and enums
+    assert_eq!(value.mem_size_of_path("", flags), Some(1039));
+    assert_eq!(value.mem_size_of_path("b.a", flags), Some(724));
+    assert_eq!(value.mem_size_of_path("b.c.1", flags), Some(27));
+    assert_eq!(value.mem_size_of_path("a.0", flags), Some(8));
+    assert_eq!(value.mem_size_of_path("a.first", flags), None);
+    value.a = TestEnum::Named {
+        first: 0,
+        second: 1,
+    };
+    assert_eq!(value.mem_size_of_path("a.first", flags), Some(8));
+    assert_eq!(value.mem_size_of_path("a.0", flags), None);
+    // Misses
+    assert_eq!(value.mem_size_of_path("b.d", flags), None);
+    assert_eq!(value.mem_size_of_path("b.c.1.0", flags), None);
+    assert_eq!(value.mem_size_of_path("b..a", flags), None);
+    // Capacity is honored
+    assert_eq!(
+        value.mem_size_of_path("b.b", SizeFlags::CAPACITY),
+        Some(24 + 400)
+    );
+
+    // Elements can be found in trees containing them
+    let tree = vec![String::from("a"), String::from("bc")].mem_tree(DbgFlags::EXPAND_ELEMENTS);
+    assert_eq!(tree.find("[1]").map(|node| node.size), Some(26));
+}
+
+#[test]
+fn test_by_type() -> core::fmt::Result {
+    let value = example();
+    let flags = DbgFlags::SHORT_TYPE_NAME;
+    let types = value.mem_dbg_by_type(flags);
+    // Self sizes add up to the overall size, and each value is counted once
+    assert_eq!(
+        types.iter().map(|&(_, size, _)| size).sum::<usize>(),
+        value.mem_size(SizeFlags::default())
+    );
+    // The padding of the enum and of the tuple is part of their self size
+    let expected = [
+        ("Vec<u8>", 724, 1),
+        ("HashSet<usize>", 192, 1),
+        ("Vec<i32>", 64, 1),
+        ("String", 27, 1),
+        ("usize", 8, 1),
+        ("isize", 8, 1),
+        ("TestEnum", 7, 1),
+        ("(u8, String)", 7, 1),
+        ("u8", 2, 2),
+        ("Struct<TestEnum, Data<Vec<u8>>>", 0, 1),
+        ("Data<Vec<u8>>", 0, 1),
+    ];
+    assert_eq!(
+        types,
+        expected
+            .iter()
+            .map(|&(name, size, count)| (name.to_string(), size, count))
+            .collect::<Vec<_>>()
+    );
+
+    let mut output = String::new();
+    value.mem_dbg_top_types_on(&mut output, 3, flags | DbgFlags::PERCENTAGE)?;
+    assert_eq!(
+        output,
+        " 724 B  69.68% 1× Vec<u8>
+ 192 B  18.48% 1× HashSet<usize>
+  64 B   6.16% 1× Vec<i32>
+"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_parent_percentage() -> core::fmt::Result {
+    #[derive(MemSize, MemDbg)]
+    struct Inner {
+        a: Vec<u8>,
+        b: u64,
+    }
+
+    #[derive(MemSize, MemDbg)]
+    struct Outer {
+        inner: Inner,
+        data: Vec<u64>,
+    }
+
+    let outer = Outer {
+        inner: Inner {
+            a: vec![0; 8],
+            b: 0,
+        },
+        data: vec![0; 100],
+    };
+    let mut output = String::new();
+    outer.mem_dbg_on(&mut output, DbgFlags::PARENT_PERCENTAGE)?;
+    assert_eq!(
+        output,
+        "\
+864 B 100.00% ⏺
+ 40 B   4.63% ├╴inner
+ 32 B  80.00% │ ├╴a
+  8 B  20.00% │ ╰╴b
+824 B  95.37% ╰╴data
+"
+    );
+    let mut output = String::new();
+    outer.mem_dbg_on(
+        &mut output,
+        DbgFlags::PERCENTAGE | DbgFlags::PARENT_PERCENTAGE,
+    )?;
+    // The percentage of the total size comes first
+    assert_eq!(
+        output,
+        "\
+864 B 100.00% 100.00% ⏺
+ 40 B   4.63%   4.63% ├╴inner
+ 32 B   3.70%  80.00% │ ├╴a
+  8 B   0.93%  20.00% │ ╰╴b
+824 B  95.37%  95.37% ╰╴data
+"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_short_type_name() -> core::fmt::Result {
+    let data = Data {
+        a: HashSet::from([String::from("a")]),
+        b: vec![1, 2],
+        c: (3, String::new()),
+    };
+    let mut output = String::new();
+    data.mem_dbg_depth_on(
+        &mut output,
+        1,
+        DbgFlags::TYPE_NAME | DbgFlags::SHORT_TYPE_NAME,
+    )?;
+    assert_eq!(
+        output,
+        "\
+213 B ⏺: Data<HashSet<String>>
+149 B ├╴a: HashSet<String>
+ 32 B ├╴b: Vec<i32>
+ 32 B ╰╴c: (u8, String)
+"
+    );
+
+    let mut output = String::new();
+    vec![(1_u8, String::new()); 3].mem_dbg_on(
+        &mut output,
+        DbgFlags::EXPAND_ELEMENTS | DbgFlags::DEDUP_TYPES | DbgFlags::SHORT_TYPE_NAME,
+    )?;
+    assert_eq!(
+        output,
+        "\
+120 B ⏺
+ 96 B ╰╴[3×] (u8, String)
+"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_group_width_note() -> core::fmt::Result {
+    let map = (0..100_u32)
+        .map(|i| (i, i as u64))
+        .collect::<HashMap<_, _>>();
+    let set = HashSet::<String>::with_capacity(10);
+    let flags = DbgFlags::GROUP_WIDTH_NOTE;
+
+    let mut map_output = String::new();
+    map.mem_dbg_on(&mut map_output, flags | DbgFlags::LEN)?;
+    let mut set_output = String::new();
+    set.mem_dbg_on(&mut set_output, flags | DbgFlags::CAPACITY)?;
+
+    #[cfg(all(target_arch = "x86_64", target_feature = "sse2"))]
+    {
+        assert_eq!(
+            map_output,
+            "1712 B ⏺ (len=100, buckets=128, ctrl_bytes=128, group_width=16)\n"
+        );
+        assert_eq!(
+            set_output,
+            "\
+448 B ⏺ (buckets=16, ctrl_bytes=16, group_width=16)
+  0 B ├╴keys
+400 B ╰╴overhead
+"
+        );
+    }
+    #[cfg(target_arch = "aarch64")]
+    {
+        assert_eq!(
+            map_output,
+            "1712 B ⏺ (len=100, buckets=128, ctrl_bytes=128, group_width=8)\n"
+        );
+        assert_eq!(
+            set_output,
+            "\
+448 B ⏺ (buckets=16, ctrl_bytes=16, group_width=8)
+  0 B ├╴keys
+400 B ╰╴overhead
+"
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn test_hide_leaves() -> core::fmt::Result {
+    #[derive(MemSize, MemDbg)]
+    struct Timing {
+        span: (std::time::Duration, std::time::Duration),
+        name: String,
+        samples: (u32, Vec<u32>),
+    }
+
+    let timing = Timing {
+        span: (
+            std::time::Duration::from_secs(1),
+            std::time::Duration::from_secs(2),
+        ),
+        name: String::from("load"),
+        samples: (3, vec![1, 2, 3]),
+    };
+
+    let mut output = String::new();
+    timing.mem_dbg_on(&mut output, DbgFlags::empty())?;
+    assert_eq!(
+        output,
+        "\
+104 B ⏺
+ 32 B ├╴span
+ 16 B │ ├╴0
+ 16 B │ ╰╴1
+ 28 B ├╴name
+ 44 B ╰╴samples
+  4 B   ├╴0 [4B]
+ 36 B   ╰╴1
+"
+    );
+
+    // The durations own no heap memory, whereas the second sample does
+    let mut output = String::new();
+    timing.mem_dbg_on(&mut output, DbgFlags::HIDE_LEAVES)?;
+    assert_eq!(
+        output,
+        "\
+104 B ⏺
+ 32 B ├╴span
+ 28 B ├╴name
+ 44 B ╰╴samples
+  4 B   ├╴0 [4B]
+ 36 B   ╰╴1
+"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_hide_empty() -> core::fmt::Result {
+    use core::marker::PhantomData;
+
+    #[derive(MemSize, MemDbg)]
+    struct Typed<S> {
+        state: PhantomData<S>,
+        id: u64,
+        unit: (),
+        data: (Vec<u8>, PhantomData<u8>),
+        marker: PhantomData<fn() -> S>,
+    }
+
+    let typed = Typed::<u32> {
+        state: PhantomData,
+        id: 0,
+        unit: (),
+        data: (vec![1, 2, 3], PhantomData),
+        marker: PhantomData,
+    };
+
+    let mut output = String::new();
+    typed.mem_dbg_on(&mut output, DbgFlags::empty())?;
+    assert_eq!(
+        output,
+        "\
+35 B ⏺
+ 0 B ├╴state
+ 8 B ├╴id
+ 0 B ├╴unit
+27 B ├╴data
+27 B │ ├╴0
+ 0 B │ ╰╴1
+ 0 B ╰╴marker
+"
+    );
+
+    // The last child displayed is data, so it must be closed
+    let mut output = String::new();
+    typed.mem_dbg_on(&mut output, DbgFlags::HIDE_EMPTY)?;
+    assert_eq!(
+        output,
+        "\
+35 B ⏺
+ 8 B ├╴id
+27 B ╰╴data
+27 B   ╰╴0
+"
+    );
+
+    let mut output = String::new();
+    typed.mem_dbg_on(&mut output, DbgFlags::HIDE_EMPTY | DbgFlags::COUNT_HIDDEN)?;
+    assert_eq!(
+        output,
+        "\
+35 B ⏺
+ 8 B ├╴id
+27 B ├╴data
+27 B │ ├╴0
+     │ ╰╴(1 zero-sized field hidden)
+     ╰╴(3 zero-sized fields hidden)
+"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_collapse_wrappers() -> core::fmt::Result {
+    use std::sync::{Arc, Mutex};
+
+    #[allow(clippy::redundant_allocation)]
+    #[derive(MemSize, MemDbg)]
+    struct Name(Box<Arc<Mutex<String>>>);
+
+    #[derive(MemSize, MemDbg)]
+    struct Meta {
+        name: Name,
+    }
+
+    #[derive(MemSize, MemDbg)]
+    struct Entry {
+        meta: Meta,
+        values: (u8, Vec<u32>),
+    }
+
+    let entry = Entry {
+        meta: Meta {
+            name: Name(Box::new(Arc::new(Mutex::new(String::from("abc"))))),
+        },
+        values: (1, vec![1, 2]),
+    };
+
+    let mut output = String::new();
+    entry.mem_dbg_on(&mut output, DbgFlags::empty())?;
+    assert_eq!(
+        output,
+        "\
+59 B ⏺
+19 B ├╴meta
+19 B │ ╰╴name
+19 B │   ╰╴0
+40 B ╰╴values
+ 1 B   ├╴0 [7B]
+32 B   ╰╴1
+"
+    );
+
+    // The tuple is not collapsed, as it has two children
+    let flags = DbgFlags::COLLAPSE_WRAPPERS | DbgFlags::TYPE_NAME | DbgFlags::SHORT_TYPE_NAME;
+    let mut output = String::new();
+    entry.mem_dbg_on(&mut output, flags)?;
+    assert_eq!(
+        output,
+        "\
+59 B ⏺: Entry
+19 B ├╴meta.name.0: Meta → Name → Box<Arc<Mutex<String>>>
+40 B ╰╴values: (u8, Vec<u32>)
+ 1 B   ├╴0: u8 [7B]
+32 B   ╰╴1: Vec<u32>
+"
+    );
+
+    let mut output = String::new();
+    entry.mem_dbg_on(&mut output, flags | DbgFlags::FLAT | DbgFlags::ASCII)?;
+    assert_eq!(
+        output,
+        "\
+59 B root: Entry
+19 B root.meta.name.0: Meta -> Name -> Box<Arc<Mutex<String>>>
+40 B root.values: (u8, Vec<u32>)
+ 1 B root.values.0: u8 [7B]
+32 B root.values.1: Vec<u32>
+"
+    );
+
+    // The tree is unaffected
+    assert_eq!(entry.mem_tree(flags).children[0].children[0].name, "name");
+    Ok(())
+}
+
+#[test]
+fn test_align() -> core::fmt::Result {
+    #[derive(MemSize, MemDbg)]
+    struct Mixed {
+        small: u8,
+        large: u128,
+        names: Vec<u8>,
+    }
+
+    let mixed = Mixed {
+        small: 0,
+        large: 0,
+        names: vec![0; 10],
+    };
+    let mut output = String::new();
+    mixed.mem_dbg_on(&mut output, DbgFlags::ALIGN | DbgFlags::LEN)?;
+    // The alignment of u128 is 16 on some targets and 8 on others
+    let align = core::mem::align_of::<u128>();
+    assert_eq!(
+        output,
+        format!(
+            "\
+58 B ⏺ (align={align})
+ 1 B ├╴small (align=1) [7B]
+16 B ├╴large (align={align})
+34 B ╰╴names (len=10, align=8)
+"
+        )
+    );
+    Ok(())
+}
+
+#[test]
+fn test_stack_size() -> core::fmt::Result {
+    let data = Data {
+        a: [0_u64; 4],
+        b: vec![0; 1000],
+        c: (0, String::from("hello")),
+    };
+    let mut output = String::new();
+    data.mem_dbg_depth_on(&mut output, 0, DbgFlags::TYPE_NAME | DbgFlags::STACK_SIZE)?;
+    let stack = format!("(stack {} B)", core::mem::size_of_val(&data));
+    assert_eq!(
+        output,
+        format!("4093 B ⏺: test_mem_dbg::Data<[u64; 4]> {}\n", stack)
+    );
+
+    // The stack size follows the other information about the root
+    let v = vec![0_u8; 10];
+    let mut output = String::new();
+    v.mem_dbg_depth_on(&mut output, 0, DbgFlags::LEN | DbgFlags::STACK_SIZE)?;
+    assert_eq!(output, "34 B ⏺ (len=10, stack 24 B)\n");
+    Ok(())
+}
+
+#[test]
+fn test_color() -> core::fmt::Result {
+    // This is the only test using COLOR, so the environment can be changed
+    std::env::remove_var("NO_COLOR");
+    let data = (vec![0_u8; 2000], 0_u64);
+    let flags = DbgFlags::COLOR;
+
+    let mut output = String::new();
+    data.mem_dbg_on(&mut output, flags)?;
+    assert_eq!(
+        output,
+        "\
+\x1b[32m2032 B \x1b[0m⏺
+\x1b[32m2024 B \x1b[0m├╴0
+\x1b[37m   8 B \x1b[0m╰╴1
+"
+    );
+
+    let scheme = ColorScheme {
+        green: 4,
+        yellow: 16,
+        red: 2030,
+    };
+    let config = DbgConfig::new().flags(flags).color_scheme(scheme);
+    let mut output = String::new();
+    data.mem_dbg_config_on(
+        &mut output,
+        &DbgConfig {
+            flags: flags | DbgFlags::PERCENTAGE,
+            ..config
+        },
+    )?;
+    assert_eq!(
+        output,
+        "\
+\x1b[31m2032 B \x1b[0m100.00% ⏺
+\x1b[33m2024 B \x1b[0m 99.61% ├╴0
+\x1b[32m   8 B \x1b[0m  0.39% ╰╴1
+"
+    );
+
+    let mut output = String::new();
+    data.mem_dbg_top_on(&mut output, 1, flags)?;
+    assert_eq!(output, "\x1b[32m2024 B \x1b[0mroot.0\n");
+    let mut output = String::new();
+    data.mem_dbg_top_config_on(&mut output, 1, &config)?;
+    assert_eq!(output, "\x1b[33m2024 B \x1b[0mroot.0\n");
+    let mut output = String::new();
+    data.mem_dbg_top_types_config_on(&mut output, 1, &config)?;
+    assert_eq!(output, "\x1b[33m2024 B \x1b[0m1× alloc::vec::Vec<u8>\n");
+
+    // Changes are colored by their absolute value, following the scheme
+    let before = data.mem_tree(flags);
+    let after = (vec![0_u8; 5000], 0_u64);
+    let mut output = String::new();
+    after.mem_diff_on(&mut output, &before, flags)?;
+    assert_eq!(
+        output,
+        "\
+\x1b[32m+3.000 kB \x1b[0m⏺
+\x1b[32m+3.000 kB \x1b[0m├╴0
+      0 B ╰╴1
+"
+    );
+    let mut output = String::new();
+    data.mem_diff_on(&mut output, &after.mem_tree(flags), flags)?;
+    assert_eq!(
+        output,
+        "\
+\x1b[32m-3.000 kB \x1b[0m⏺
+\x1b[32m-3.000 kB \x1b[0m├╴0
+      0 B ╰╴1
+"
+    );
+    let mut output = String::new();
+    data.mem_diff_config_on(&mut output, &after.mem_tree(flags), &config)?;
+    assert_eq!(
+        output,
+        "\
+\x1b[31m-3.000 kB \x1b[0m⏺
+\x1b[31m-3.000 kB \x1b[0m├╴0
+      0 B ╰╴1
+"
+    );
+
+    // NO_COLOR disables colors
+    std::env::set_var("NO_COLOR", "1");
+    let mut output = String::new();
+    data.mem_dbg_config_on(&mut output, &config)?;
+    let mut top = String::new();
+    data.mem_dbg_top_on(&mut top, 1, flags)?;
+    std::env::remove_var("NO_COLOR");
+    assert_eq!(
+        output,
+        "\
+2032 B ⏺
+2024 B ├╴0
+   8 B ╰╴1
+"
+    );
+    assert_eq!(top, "2024 B root.0\n");
+
+    // An empty NO_COLOR is ignored
+    std::env::set_var("NO_COLOR", "");
+    let mut output = String::new();
+    data.mem_dbg_top_on(&mut output, 1, flags)?;
+    std::env::remove_var("NO_COLOR");
+    assert_eq!(output, "\x1b[32m2024 B \x1b[0mroot.0\n");
+    Ok(())
+}
+
+#[test]
+fn test_io() -> std::io::Result<()> {
+    /// A writer accepting at most three bytes per call.
+    struct Slow(Vec<u8>);
+
+    impl std::io::Write for Slow {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            let len = buf.len().min(3);
+            self.0.extend_from_slice(&buf[..len]);
+            Ok(len)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let value = (vec![0_u8; 10], String::from("abc"));
+    let mut expected = String::new();
+    value
+        .mem_dbg_on(&mut expected, DbgFlags::default())
+        .unwrap();
+
+    // Partial writes are completed
+    let mut slow = Slow(Vec::new());
+    value.mem_dbg_io_on(&mut slow, DbgFlags::default())?;
+    assert_eq!(String::from_utf8(slow.0).unwrap(), expected);
+
+    // IO errors are returned as they are
+    let mut buffer = [0_u8; 4];
+    let error = value
+        .mem_dbg_io_on(&mut &mut buffer[..], DbgFlags::default())
+        .unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::WriteZero);
+    Ok(())
+}
+
+#[test]
+fn test_buffered() -> std::io::Result<()> {
+    /// A writer counting the calls to write.
+    #[derive(Default)]
+    struct Counting {
+        bytes: Vec<u8>,
+        writes: usize,
+    }
+
+    impl std::io::Write for Counting {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.writes += 1;
+            self.bytes.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// A complete binary tree.
+    #[derive(MemSize, MemDbg)]
+    #[mem_dbg(bound = "")]
+    struct Tree {
+        data: Vec<u8>,
+        children: Option<Box<(Tree, Tree)>>,
+    }
+
+    fn tree(depth: usize) -> Tree {
+        Tree {
+            data: vec![0; depth],
+            children: (depth > 0).then(|| Box::new((tree(depth - 1), tree(depth - 1)))),
+        }
+    }
+
+    let value = tree(10);
+    let flags = DbgFlags::default();
+    let mut expected = String::new();
+    value.mem_dbg_on(&mut expected, flags).unwrap();
+    assert!(expected.lines().count() > 5_000);
+
+    // Output is written incrementally, in several pieces per line
+    let mut counting = Counting::default();
+    value.mem_dbg_io_on(&mut counting, flags)?;
+    assert_eq!(String::from_utf8(counting.bytes).unwrap(), expected);
+    assert!(counting.writes > expected.lines().count());
+
+    // A buffer of 8 KiB, as the one of mem_dbg_buffered, writes it in blocks
+    let mut buffered = std::io::BufWriter::with_capacity(8 * 1024, Counting::default());
+    value.mem_dbg_io_on(&mut buffered, flags)?;
+    let counting = buffered.into_inner().map_err(|e| e.into_error())?;
+    assert_eq!(String::from_utf8(counting.bytes).unwrap(), expected);
+    assert!(counting.writes <= expected.len().div_ceil(8 * 1024) + 1);
+
+    (vec![0_u8; 10], String::from("abc"))
+        .mem_dbg_buffered(flags)
+        .unwrap();
+    Ok(())
+}
+
+#[test]
+fn test_display() -> core::fmt::Result {
+    let value = (vec![0_u8; 10], String::from("abc"));
+    let mut expected = String::new();
+    value.mem_dbg_on(&mut expected, DbgFlags::default())?;
+    assert_eq!(
+        format!("{}", value.mem_dbg_display(DbgFlags::default())),
+        expected
+    );
+    assert_eq!(
+        format!("stats:\n{}", value.mem_dbg_display(DbgFlags::empty())),
+        "\
+stats:
+61 B ⏺
+34 B ├╴0
+27 B ╰╴1
+"
+    );
+
+    // Sizes are computed at formatting time
+    let cell = core::cell::RefCell::new(vec![0_u8; 10]);
+    let display = cell.mem_dbg_display(DbgFlags::empty());
+    cell.borrow_mut().extend([0; 90]);
+    cell.borrow_mut().shrink_to_fit();
+    assert_eq!(format!("{}", display), "132 B ⏺\n");
+    Ok(())
+}
+
+#[test]
+fn test_max_size_width() -> core::fmt::Result {
+    // A fake tree of a structure of a few gigabytes
+    let mut tree = MemNode::new(&0_usize, "", 8, DbgFlags::empty());
+    tree.size = 5_000_000_000;
+    let mut data = MemNode::new(&0_usize, "data", 8, DbgFlags::empty());
+    data.size = 4_999_999_000;
+    tree.children.push(data);
+    tree.children
+        .push(MemNode::new(&0_u64, "len", 8, DbgFlags::empty()));
+
+    let flags = DbgFlags::SEPARATOR;
+    let mut output = String::new();
+    tree.write_on(
+        &mut output,
+        DbgConfig::new().flags(flags).adjust_flags(tree.size),
+    )?;
+    assert_eq!(
+        output,
+        "\
+5_000_000_000 B ⏺
+4_999_999_000 B ├╴data
+            8 B ╰╴len
+"
+    );
+
+    let config = DbgConfig::new().flags(flags).max_size_width(Some(8));
+    let mut output = String::new();
+    tree.write_on(&mut output, config.adjust_flags(tree.size))?;
+    assert_eq!(
+        output,
+        "\
+5.000 GB ⏺
+5.000 GB ├╴data
+    8  B ╰╴len
+"
+    );
+
+    // Sizes that fit are left alone
+    let config = DbgConfig::new().flags(flags).max_size_width(Some(13));
+    assert_eq!(config.adjust_flags(tree.size), flags);
+    Ok(())
+}
+
+#[test]
+fn test_config() -> core::fmt::Result {
+    let value = example();
+
+    // The default configuration behaves as the methods taking only flags
+    let mut expected = String::new();
+    value.mem_dbg_on(&mut expected, DbgFlags::default())?;
+    let mut output = String::new();
+    value.mem_dbg_config_on(&mut output, &DbgConfig::new())?;
+    assert_eq!(output, expected);
+
+    let v = (0..20).map(|i| vec![0_u8; i]).collect::<Vec<_>>();
+    let config = DbgConfig::new()
+        .flags(DbgFlags::EXPAND_ELEMENTS)
+        .max_elements(2);
+    let mut output = String::new();
+    v.mem_dbg_config_on(&mut output, &config)?;
+    assert_eq!(
+        output,
+        "\
+694 B ⏺
+ 24 B ├╴[0]
+ 25 B ├╴[1]
+      ╰╴… and 18 more (621 bytes)
+"
+    );
+
+    let config = DbgConfig::new()
+        .flags(DbgFlags::HUMANIZE | DbgFlags::PERCENTAGE)
+        .humanize_precision(Some(2))
+        .min_percentage(10.0);
+    let mut output = String::new();
+    (vec![0_u8; 5000], vec![0_u8; 1500], 0_u64).mem_dbg_config_on(&mut output, &config)?;
+    assert_eq!(
+        output,
+        "  6.56 kB 100.00% ⏺
+  5.02 kB  76.63% ├╴0
+  1.52 kB  23.25% ├╴1
+     8  B   0.12% ╰╴… 1 node below threshold
+"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_folded() -> core::fmt::Result {
+    let value = example();
+    let mut output = String::new();
+    value.mem_dbg_folded_on(&mut output, DbgFlags::default())?;
+    // The self sizes of the lines add up to the overall size
+    let total = output
+        .lines()
+        .map(|line| line.rsplit_once(' ').unwrap().1.parse::<usize>().unwrap())
+        .sum::<usize>();
+    assert_eq!(total, value.mem_size(SizeFlags::default()));
+    Ok(())
+}
+
+#[test]
+fn test_dot() -> core::fmt::Result {
+    #[derive(MemSize, MemDbg)]
+    enum Kind {
+        Small(u8),
+        Large(Vec<u8>),
+    }
+
+    #[derive(MemSize, MemDbg)]
+    struct Pair {
+        kind: Kind,
+        first: std::rc::Rc<(u8, Vec<u8>)>,
+        second: std::rc::Rc<(u8, Vec<u8>)>,
+    }
+
+    let shared = std::rc::Rc::new((1_u8, vec![0_u8; 2000]));
+    let pair = Pair {
+        kind: Kind::Small(0),
+        first: shared.clone(),
+        second: shared.clone(),
+    };
+    let mut output = String::new();
+    pair.mem_dbg_dot_on(&mut output, DbgFlags::default() | DbgFlags::FOLLOW_RCS)?;
+    // The pointers converge on the vertex of the shared allocation
+    assert_eq!(
+        output,
+        format!(
+            r#"digraph mem_dbg {{
+    node [shape=box, style=filled];
+    n0 [label="root\ntest_mem_dbg::test_dot::Pair\n2.088 kB", fillcolor=palegreen];
+    n0 -> n1;
+    n1 [label="kind\ntest_mem_dbg::test_dot::Kind\n24 B\nVariant: Small", fillcolor=white];
+    n1 -> n2;
+    n2 [label="0\nu8\n1 B", fillcolor=white];
+    n0 -> n3;
+    n3 [label="first\nalloc::rc::Rc<(u8, alloc::vec::Vec<u8>)>\n2.056 kB", fillcolor=palegreen];
+    a0 [label="@{:p}", shape=ellipse, style=solid];
+    n3 -> a0;
+    a0 -> n4;
+    n4 [label="0\nu8\n1 B", fillcolor=white];
+    a0 -> n5;
+    n5 [label="1\nalloc::vec::Vec<u8>\n2.024 kB", fillcolor=palegreen];
+    n0 -> n6;
+    n6 [label="second\nalloc::rc::Rc<(u8, alloc::vec::Vec<u8>)>\n2.056 kB", fillcolor=palegreen];
+    n6 -> a0;
+}}
+"#,
+            std::rc::Rc::as_ptr(&shared)
+        )
+    );
+
+    // The vertices follow the color scheme
+    let scheme = ColorScheme {
+        green: 1,
+        yellow: 24,
+        red: 2088,
+    };
+    let config = DbgConfig::new().color_scheme(scheme);
+    let mut output = String::new();
+    pair.mem_dbg_dot_config_on(&mut output, &config)?;
+    let colors = output
+        .lines()
+        .filter_map(|line| line.split("fillcolor=").nth(1))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        colors,
+        [
+            "salmon];",
+            "khaki];",
+            "palegreen];",
+            "khaki];",
+            "palegreen];",
+            "khaki];",
+            "khaki];"
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_allocations_column() -> core::fmt::Result {
+    #[derive(MemSize, MemDbg)]
+    struct Index {
+        name: String,
+        ids: Vec<u32>,
+        words: HashMap<String, u32>,
+        empty: Vec<String>,
+    }
+
+    let index = Index {
+        name: "index".to_owned(),
+        ids: vec![1, 2, 3],
+        words: HashMap::from([("a".to_owned(), 0), ("b".to_owned(), 1)]),
+        empty: Vec::new(),
+    };
+    assert_eq!(index.mem_allocations(SizeFlags::default()), 5);
+    let mut output = String::new();
+    index.mem_dbg_on(&mut output, DbgFlags::ALLOCATIONS)?;
+    assert_eq!(
+        output,
+        "\
+255 B   5 A ⏺
+ 29 B   1 A ├╴name
+ 36 B   1 A ├╴ids
+166 B   3 A ├╴words
+ 50 B       │ ├╴keys
+  8 B       │ ├╴values
+ 60 B       │ ╰╴overhead
+ 24 B   0 A ╰╴empty
+"
+    );
+    // Lines without a number of allocations must be aligned, too
+    let width = header_indent_width(index.mem_size(SizeFlags::default()), DbgFlags::ALLOCATIONS);
+    for line in output.lines() {
+        assert!(line[..width].ends_with(' '), "{}", line);
+        assert!(!line[width..].starts_with(' '), "{}", line);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_mem_tree() -> core::fmt::Result {
+    #[derive(MemSize, MemDbg)]
+    struct Data {
+        id: u8,
+        name: String,
+        kind: Option<u32>,
+    }
+
+    let data = Data {
+        id: 1,
+        name: "data".to_owned(),
+        kind: None,
+    };
+    let tree = data.mem_tree(DbgFlags::empty());
+    assert_eq!(tree.name, "");
+    assert_eq!(tree.kind, MemNodeKind::Value);
+    assert_eq!(tree.size, data.mem_size(SizeFlags::default()));
+    assert_eq!(tree.padding, 0);
+    assert_eq!(
+        tree.children
+            .iter()
+            .map(|child| (child.name.as_str(), child.size, child.padding))
+            .collect::<Vec<_>>(),
+        vec![("id", 1, 7), ("name", 28, 0), ("kind", 8, 0)]
+    );
+    assert!(tree.children.iter().all(|child| child.children.is_empty()));
+    assert!(tree.children.iter().all(|child| child.type_name.is_none()));
+
+    let tree = data.mem_tree(DbgFlags::TYPE_NAME | DbgFlags::ALLOCATIONS);
+    assert_eq!(
+        tree.children[1].type_name.as_deref(),
+        Some("alloc::string::String")
+    );
+    assert_eq!(tree.children[1].allocations, Some(1));
+
+    // The textual output is the rendering of the tree
+    for flags in [
+        DbgFlags::default(),
+        DbgFlags::default() | DbgFlags::ALLOCATIONS,
+        DbgFlags::HUMANIZE | DbgFlags::ASCII | DbgFlags::RUST_LAYOUT,
+    ] {
+        let mut output = String::new();
+        data.mem_dbg_on(&mut output, flags)?;
+        let mut rendered = String::new();
+        data.mem_tree(flags).write_on(&mut rendered, flags)?;
+        assert_eq!(output, rendered);
+    }
+
+    // Limiting the depth removes descendants
+    assert!(data
+        .mem_tree_depth(0, DbgFlags::empty())
+        .children
+        .is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_mem_tree_kinds() {
+    let map = HashMap::from([("a".to_owned(), 0_u8)]);
+    let tree = map.mem_tree(DbgFlags::empty());
+    assert_eq!(
+        tree.children
+            .iter()
+            .map(|child| (child.name.as_str(), child.kind))
+            .collect::<Vec<_>>(),
+        vec![
+            ("keys", MemNodeKind::Group),
+            ("values", MemNodeKind::Group),
+            ("overhead", MemNodeKind::Group)
+        ]
+    );
+    assert_eq!(
+        tree.children.iter().map(|child| child.size).sum::<usize>(),
+        tree.size - core::mem::size_of::<HashMap<String, u8>>()
+    );
+
+    let tree = Derived::B {
+        first: 0,
+        second: 1,
+    }
+    .mem_tree(DbgFlags::empty());
+    assert_eq!(tree.children[0].kind, MemNodeKind::Label);
+    assert_eq!(tree.children[0].name, "Variant: B");
+    assert_eq!(tree.children[0].size, 0);
+}
+
+#[test]
+fn test_flat() -> core::fmt::Result {
+    #[derive(MemSize, MemDbg)]
+    struct Inner {
+        name: String,
+        tags: Vec<String>,
+    }
+
+    #[derive(MemSize, MemDbg)]
+    struct Outer {
+        inner: Inner,
+        derived: Derived,
+        words: HashMap<String, u8>,
+    }
+
+    let outer = Outer {
+        inner: Inner {
+            name: "inner".to_owned(),
+            tags: vec!["a".to_owned(), "bc".to_owned()],
+        },
+        derived: Derived::A(0, 1),
+        words: HashMap::from([("a".to_owned(), 0)]),
+    };
+    let mut output = String::new();
+    outer.mem_dbg_on(&mut output, DbgFlags::FLAT | DbgFlags::EXPAND_ELEMENTS)?;
+    assert_eq!(
+        output,
+        "\
+281 B root
+104 B root.inner
+ 29 B root.inner.name
+ 75 B root.inner.tags
+ 25 B root.inner.tags[0]
+ 26 B root.inner.tags[1]
+ 24 B root.derived
+      root.derived (Variant: A)
+  8 B root.derived.0
+  8 B root.derived.1
+153 B root.words
+ 25 B root.words.keys
+  1 B root.words.values
+ 79 B root.words.overhead
+"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_mem_tree_diff() {
+    #[derive(MemSize, MemDbg)]
+    struct Log {
+        name: String,
+        lines: Vec<String>,
+    }
+
+    let mut log = Log {
+        name: "log".to_owned(),
+        lines: vec!["a".to_owned()],
+    };
+    let flags = DbgFlags::EXPAND_ELEMENTS;
+    let before = log.mem_tree(flags);
+    log.lines.push("bc".to_owned());
+    log.name.clear();
+    let diff = mem_tree_diff(&before, &log.mem_tree(flags));
+
+    assert_eq!(diff.status, MemDiffStatus::Both);
+    assert_eq!(diff.delta(), 26 - 3);
+    assert_eq!(diff.children[0].name, "name");
+    assert_eq!(diff.children[0].delta(), -3);
+    let lines = &diff.children[1];
+    assert_eq!((lines.before, lines.after), (49, 75));
+    assert_eq!(lines.children[0].status, MemDiffStatus::Both);
+    assert_eq!(lines.children[0].delta(), 0);
+    assert_eq!(lines.children[1].name, "[1]");
+    assert_eq!(lines.children[1].status, MemDiffStatus::Added);
+    assert_eq!((lines.children[1].before, lines.children[1].after), (0, 26));
+
+    assert_eq!(
+        diff.to_string(),
+        "    +23 B ⏺
+     -3 B ├╴name
+    +26 B ╰╴lines
+      0 B   ├╴[0]
+    +26 B   ╰╴[1] (added)
+"
+    );
+
+    // Diffing in the opposite direction marks nodes as removed
+    let diff = mem_tree_diff(&log.mem_tree(flags), &before);
+    assert_eq!(diff.children[1].children[1].status, MemDiffStatus::Removed);
+    assert_eq!(diff.children[1].children[1].delta(), -26);
+}
+
+#[test]
+fn test_mem_diff() -> core::fmt::Result {
+    #[derive(MemSize, MemDbg)]
+    struct Cache {
+        name: String,
+        keys: Vec<u64>,
+        stats: (Vec<u32>, usize),
+    }
+
+    let mut cache = Cache {
+        name: "cache".to_owned(),
+        keys: vec![0; 10],
+        stats: (vec![0; 10], 0),
+    };
+    let flags = DbgFlags::empty();
+    let before = cache.mem_tree(flags);
+    cache.keys.extend(0..1_000_000);
+    cache.name.clear();
+
+    let mut output = String::new();
+    cache.mem_diff_on(&mut output, &before, flags)?;
+    assert_eq!(
+        output,
+        "\
++8.000 MB ⏺
+     -5 B ├╴name
++8.000 MB ├╴keys
+      0 B ╰╴stats
+      0 B   ├╴0
+      0 B   ╰╴1
+"
+    );
+
+    let mut output = String::new();
+    cache.mem_diff_on(&mut output, &before, flags | DbgFlags::HIDE_UNCHANGED)?;
+    assert_eq!(
+        output,
+        "\
++8.000 MB ⏺
+     -5 B ├╴name
++8.000 MB ├╴keys
+      0 B ╰╴stats
+"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_csv() -> core::fmt::Result {
+    #[derive(MemSize, MemDbg)]
+    struct Table {
+        pairs: Vec<(u8, String)>,
+        derived: Derived,
+        words: HashMap<String, u8>,
+    }
+
+    let table = Table {
+        pairs: vec![(0, "a".to_owned()), (1, "b".to_owned())],
+        derived: Derived::B {
+            first: 0,
+            second: 1,
+        },
+        words: HashMap::from([("a".to_owned(), 0)]),
+    };
+    for flags in [
+        DbgFlags::default(),
+        DbgFlags::default() | DbgFlags::EXPAND_ELEMENTS,
+        DbgFlags::default() | DbgFlags::EXPAND_ELEMENTS | DbgFlags::DEDUP_TYPES,
+    ] {
+        let mut tree = String::new();
+        table.mem_dbg_on(&mut tree, flags)?;
+        let mut csv = String::new();
+        table.mem_dbg_csv_on(&mut csv, flags)?;
+        // The header plus a row for each node
+        assert_eq!(csv.lines().count(), tree.lines().count() + 1);
+    }
+
+    let mut csv = String::new();
+    table.mem_dbg_csv_on(&mut csv, DbgFlags::TYPE_NAME | DbgFlags::EXPAND_ELEMENTS)?;
+    assert_eq!(
+        csv,
+        "\
+path,type,self_bytes,subtree_bytes,percent,depth
+,test_mem_dbg::test_csv::Table,0,267,100.00,0
+pairs,\"alloc::vec::Vec<(u8, alloc::string::String)>\",24,90,33.71,1
+pairs[0],\"(u8, alloc::string::String)\",7,33,12.36,2
+pairs[0].0,u8,1,1,0.37,3
+pairs[0].1,alloc::string::String,25,25,9.36,3
+pairs[1],\"(u8, alloc::string::String)\",7,33,12.36,2
+pairs[1].0,u8,1,1,0.37,3
+pairs[1].1,alloc::string::String,25,25,9.36,3
+derived,test_mem_dbg::Derived,8,24,8.99,1
+derived (Variant: B),,0,0,0.00,2
+derived.first,u64,8,8,3.00,2
+derived.second,u64,8,8,3.00,2
+words,\"std::collections::hash::map::HashMap<alloc::string::String, u8>\",48,153,57.30,1
+words.keys,,25,25,9.36,2
+words.values,,1,1,0.37,2
+words.overhead,,79,79,29.59,2
+"
+    );
+
+    // Tab-separated values need not quote commas
+    let mut tsv = String::new();
+    (0_u8, 1_u8).mem_dbg_tsv_on(&mut tsv, DbgFlags::TYPE_NAME)?;
+    assert_eq!(
+        tsv,
+        "\
+path\ttype\tself_bytes\tsubtree_bytes\tpercent\tdepth
+\t(u8, u8)\t0\t2\t100.00\t0
+0\tu8\t1\t1\t50.00\t1
+1\tu8\t1\t1\t50.00\t1
+"
+    );
+    Ok(())
+}
@@ -10,6 +10,7 @@
 
 use core::marker::PhantomData;
 use core::mem::size_of;
+use core::ops::ControlFlow;
 use mem_dbg::*;
 use std::sync::atomic::AtomicU64;
 
@@ -44,6 +45,62 @@ fn test_vec_capacity() {
     );
 }
 
+#[test]
+fn test_vec_spare_capacity() {
+    // As in arenas, the tail of the buffer is uninitialized
+    let mut v = Vec::with_capacity(10);
+    v.push("a".repeat(100));
+    v.push(String::with_capacity(50));
+    v.push(String::new());
+    assert_eq!(v.capacity(), 10);
+    // The headers of all slots, used or not, plus the heap of the used
+    // strings
+    assert_eq!(
+        v.mem_size(SizeFlags::CAPACITY),
+        size_of::<Vec<String>>() + 10 * size_of::<String>() + 100 + 50
+    );
+    // Without capacity, only the used slots and the length of the strings
+    assert_eq!(
+        v.mem_size(SizeFlags::default()),
+        size_of::<Vec<String>>() + 3 * size_of::<String>() + 100
+    );
+
+    // Slices of MaybeUninit count only the headers, initialized or not
+    let mut slots = Box::<[String]>::new_uninit_slice(10);
+    slots[0].write("a".repeat(100));
+    assert_eq!(
+        slots.mem_size(SizeFlags::default()),
+        size_of::<Box<[String]>>() + 10 * size_of::<String>()
+    );
+    // SAFETY: the first slot has been initialized
+    unsafe { slots[0].assume_init_drop() };
+}
+
+#[test]
+fn test_vec_zst() {
+    // Vectors of zero-sized elements never allocate, even if their capacity
+    // is usize::MAX
+    let v = vec![(); 1_000_000];
+    assert_eq!(v.capacity(), usize::MAX);
+    assert_eq!(v.mem_size(SizeFlags::CAPACITY), size_of::<Vec<()>>());
+    assert_eq!(v.mem_size(SizeFlags::default()), size_of::<Vec<()>>());
+    assert_eq!(v.mem_allocations(SizeFlags::default()), 0);
+
+    #[derive(MemSize, Clone)]
+    struct Empty;
+
+    let v = vec![Empty; 1_000_000];
+    assert_eq!(v.mem_size(SizeFlags::CAPACITY), size_of::<Vec<Empty>>());
+    let stats = v.mem_stats(SizeFlags::default());
+    assert_eq!(stats.heap_capacity, 0);
+
+    let d = std::collections::VecDeque::from(vec![(); 1_000]);
+    assert_eq!(
+        d.mem_size(SizeFlags::CAPACITY),
+        size_of::<std::collections::VecDeque<()>>()
+    );
+}
+
 #[test]
 fn test_vec_copy_or_not() {
     #[derive(MemDbg, MemSize, Clone)]
@@ -70,6 +127,33 @@ fn test_boxed_slice_copy_or_not() {
     );
 }
 
+#[test]
+fn test_boxed_slice_capacity() {
+    // Boxed slices have no spare capacity
+    let boxed_slice = vec![1_u64; 10].into_boxed_slice();
+    assert_eq!(
+        boxed_slice.mem_size(SizeFlags::CAPACITY),
+        boxed_slice.mem_size(SizeFlags::default())
+    );
+    assert_eq!(
+        boxed_slice.mem_size(SizeFlags::default()),
+        size_of::<Box<[u64]>>() + 10 * size_of::<u64>()
+    );
+
+    // Even if the vector they come from has spare capacity
+    let mut v = Vec::with_capacity(100);
+    v.extend(["a", "bb", "ccc"].map(String::from));
+    let boxed_slice = v.into_boxed_slice();
+    assert_eq!(
+        boxed_slice.mem_size(SizeFlags::CAPACITY),
+        boxed_slice.mem_size(SizeFlags::default())
+    );
+    assert_eq!(
+        boxed_slice.mem_size(SizeFlags::default()),
+        size_of::<Box<[String]>>() + 3 * size_of::<String>() + 6
+    );
+}
+
 #[test]
 fn test_slice_copy_or_not() {
     #[derive(MemDbg, MemSize, Clone)]
@@ -241,6 +325,70 @@ fn test_enum() {
     );
 }
 
+#[test]
+fn test_estimated() {
+    use std::collections::{BTreeSet, HashMap};
+
+    #[derive(MemSize)]
+    struct Exact {
+        ids: Vec<u64>,
+        names: Vec<String>,
+        extra: Option<Box<[u32]>>,
+    }
+
+    #[derive(MemSize)]
+    struct Indexed {
+        ids: Vec<u64>,
+        index: HashMap<u64, usize>,
+    }
+
+    #[derive(MemSize)]
+    enum Either {
+        Exact(Exact),
+        Indexed { indexed: Indexed },
+    }
+
+    let exact = Exact {
+        ids: vec![1, 2, 3],
+        names: vec![String::from("a")],
+        extra: Some(Box::new([1, 2])),
+    };
+    let report = exact.mem_size_detailed(SizeFlags::default());
+    assert_eq!(report.bytes, exact.mem_size(SizeFlags::default()));
+    assert!(!report.estimated);
+    assert!(
+        !Either::Exact(exact)
+            .mem_size_detailed(SizeFlags::default())
+            .estimated
+    );
+
+    let mut indexed = Indexed {
+        ids: vec![1, 2, 3],
+        index: HashMap::new(),
+    };
+    // An empty table does not allocate, so its size is exact
+    assert!(!indexed.mem_size_detailed(SizeFlags::default()).estimated);
+    indexed.index.insert(1, 0);
+    let report = indexed.mem_size_detailed(SizeFlags::CAPACITY);
+    assert_eq!(report.bytes, indexed.mem_size(SizeFlags::CAPACITY));
+    assert!(report.estimated);
+    let either = Either::Indexed { indexed };
+    assert!(either.mem_size_detailed(SizeFlags::default()).estimated);
+
+    // Estimates propagate through containers and pointers
+    assert!(
+        vec![(0, BTreeSet::from([1]))]
+            .mem_size_detailed(SizeFlags::default())
+            .estimated
+    );
+    let shared = std::rc::Rc::new(vec![BTreeSet::from([1])]);
+    assert!(shared.mem_size_detailed(SizeFlags::default()).estimated);
+    // References are followed only if requested
+    let borrowed = (0, &shared);
+    assert!(!borrowed.mem_size_detailed(SizeFlags::default()).estimated);
+    assert!(borrowed.mem_size_detailed(SizeFlags::FOLLOW_REFS).estimated);
+}
+
 #[test]
 /// <https://github.com/rust-lang/rfcs/issues/1230>
 fn test_exotic() {
@@ -322,6 +470,39 @@ fn test_phantom() {
         .unwrap();
 }
 
+#[test]
+fn test_maybe_uninit() {
+    // The content is never read, so uninitialized values can be sized
+    let arena: Box<[core::mem::MaybeUninit<u64>]> = Box::new_uninit_slice(100);
+    assert_eq!(
+        arena.mem_size(SizeFlags::default()),
+        core::mem::size_of::<Box<[core::mem::MaybeUninit<u64>]>>() + 100 * 8
+    );
+    arena.mem_dbg(DbgFlags::default()).unwrap();
+}
+
+#[test]
+fn test_io_slice() {
+    #[derive(MemDbg, MemSize)]
+    struct Request<'a> {
+        header: std::io::IoSlice<'a>,
+        body: std::io::IoSliceMut<'a>,
+    }
+
+    let header = [0_u8; 10];
+    let mut body = [0_u8; 100];
+    let request = Request {
+        header: std::io::IoSlice::new(&header),
+        body: std::io::IoSliceMut::new(&mut body),
+    };
+    // An iovec (a WSABUF on Windows) is a pointer and a length
+    let iovec = size_of::<(*const u8, usize)>();
+    assert_eq!(request.header.mem_size(SizeFlags::default()), iovec);
+    assert_eq!(request.mem_size(SizeFlags::default()), 2 * iovec);
+    assert_eq!(request.mem_size(SizeFlags::FOLLOW_REFS), 2 * iovec + 110);
+    request.mem_dbg(DbgFlags::default()).unwrap();
+}
+
 #[test]
 fn test_vec_strings() {
     let data = vec![String::new(), String::new()];
@@ -344,6 +525,185 @@ fn test_array_empty_struct() {
     data.mem_dbg(DbgFlags::default()).unwrap();
 }
 
+#[test]
+fn test_array_zero_length() {
+    let all_flags = [
+        SizeFlags::default(),
+        SizeFlags::CAPACITY,
+        SizeFlags::FOLLOW_REFS,
+        SizeFlags::CAPACITY | SizeFlags::FOLLOW_REFS,
+    ];
+
+    // Copy elements
+    let data: [u8; 0] = [];
+    let data_i128: [i128; 0] = [];
+    // Non-Copy elements
+    let data_string: [String; 0] = [];
+    let data_vec: [Vec<usize>; 0] = [];
+    // Reference elements
+    let data_ref: [&String; 0] = [];
+    let data_mut_ref: [&mut String; 0] = [];
+
+    for flags in all_flags {
+        assert_eq!(data.mem_size(flags), 0);
+        assert_eq!(data_i128.mem_size(flags), 0);
+        assert_eq!(data_string.mem_size(flags), 0);
+        assert_eq!(data_vec.mem_size(flags), 0);
+        assert_eq!(data_ref.mem_size(flags), 0);
+        assert_eq!(data_mut_ref.mem_size(flags), 0);
+        // The same must hold for the corresponding empty slices
+        assert_eq!(data.as_slice().mem_size(flags), 0);
+        assert_eq!(data_string.as_slice().mem_size(flags), 0);
+        assert_eq!(data_ref.as_slice().mem_size(flags), 0);
+    }
+
+    // A zero-length array inside a structure adds nothing
+    #[derive(MemSize, MemDbg)]
+    struct Data {
+        a: u64,
+        b: [String; 0],
+    }
+    let v = Data { a: 0, b: [] };
+    assert_eq!(v.mem_size(SizeFlags::default()), 8);
+    assert_eq!(v.mem_size(SizeFlags::FOLLOW_REFS), 8);
+    v.mem_dbg(DbgFlags::default()).unwrap();
+}
+
+#[test]
+fn test_nested_arrays() {
+    #[derive(MemSize, MemDbg)]
+    struct Data {
+        a: u8,
+        b: String,
+    }
+
+    let string = |i: usize| "x".repeat(i);
+    let data = |i: usize| Data { a: 0, b: string(i) };
+    let flags = SizeFlags::default();
+
+    // Copy elements
+    let copy = [[[0_u32; 2]; 3]; 4];
+    assert_eq!(copy.mem_size(flags), size_of::<[[[u32; 2]; 3]; 4]>());
+    assert_eq!(copy.mem_allocations(flags), 0);
+
+    // Strings
+    let strings_2d: [[String; 2]; 3] =
+        core::array::from_fn(|i| core::array::from_fn(|j| string(2 * i + j)));
+    assert_eq!(
+        strings_2d.mem_size(flags),
+        size_of::<[[String; 2]; 3]>() + (0..6).sum::<usize>()
+    );
+    // The empty string does not allocate
+    assert_eq!(strings_2d.mem_allocations(flags), 5);
+
+    let strings_3d: [[[String; 2]; 2]; 2] = core::array::from_fn(|i| {
+        core::array::from_fn(|j| core::array::from_fn(|k| string(4 * i + 2 * j + k)))
+    });
+    assert_eq!(
+        strings_3d.mem_size(flags),
+        size_of::<[[[String; 2]; 2]; 2]>() + (0..8).sum::<usize>()
+    );
+    assert_eq!(strings_3d.mem_allocations(flags), 7);
+
+    // Derived structures
+    let data_2d: [[Data; 3]; 2] =
+        core::array::from_fn(|i| core::array::from_fn(|j| data(3 * i + j)));
+    assert_eq!(
+        data_2d.mem_size(flags),
+        size_of::<[[Data; 3]; 2]>() + (0..6).sum::<usize>()
+    );
+
+    let data_3d: [[[Data; 2]; 2]; 2] = core::array::from_fn(|i| {
+        core::array::from_fn(|j| core::array::from_fn(|k| data(4 * i + 2 * j + k)))
+    });
+    assert_eq!(
+        data_3d.mem_size(flags),
+        size_of::<[[[Data; 2]; 2]; 2]>() + (0..8).sum::<usize>()
+    );
+
+    // The size with capacity is the same, as strings have no spare capacity
+    assert_eq!(
+        data_3d.mem_size(SizeFlags::CAPACITY),
+        data_3d.mem_size(flags)
+    );
+    // Slices of arrays are sized in the same way
+    assert_eq!(data_3d.as_slice().mem_size(flags), data_3d.mem_size(flags));
+    data_3d.mem_dbg(DbgFlags::default()).unwrap();
+}
+
+#[test]
+fn test_hash_map_buckets() {
+    use std::collections::{HashMap, HashSet};
+
+    /// Returns the number of buckets needed for the given capacity.
+    fn buckets(capacity: usize) -> usize {
+        if capacity < 4 {
+            4
+        } else if capacity < 8 {
+            8
+        } else {
+            (capacity * 8 / 7).next_power_of_two()
+        }
+    }
+    let entry = size_of::<(u64, u64)>() + 1;
+
+    // An empty map does not allocate
+    let map = HashMap::<u64, u64>::new();
+    assert_eq!(map.capacity(), 0);
+    assert_eq!(
+        map.mem_size(SizeFlags::default()),
+        size_of::<HashMap<u64, u64>>()
+    );
+    assert_eq!(
+        map.mem_size(SizeFlags::CAPACITY),
+        size_of::<HashMap<u64, u64>>()
+    );
+
+    // An over-provisioned map: without CAPACITY only the buckets needed for
+    // the elements are counted
+    let mut map = HashMap::<u64, u64>::with_capacity(1000);
+    map.extend((0..10).map(|i| (i, i)));
+    assert_eq!(
+        map.mem_size(SizeFlags::default()),
+        size_of::<HashMap<u64, u64>>() + buckets(10) * entry
+    );
+    assert_eq!(
+        map.mem_size(SizeFlags::CAPACITY),
+        size_of::<HashMap<u64, u64>>() + buckets(map.capacity()) * entry
+    );
+
+    // A tightly packed map: the two sizes coincide
+    let mut map = HashMap::<u64, u64>::with_capacity(14);
+    let capacity = map.capacity();
+    map.extend((0..capacity as u64).map(|i| (i, i)));
+    assert_eq!(map.capacity(), capacity);
+    assert_eq!(
+        map.mem_size(SizeFlags::default()),
+        map.mem_size(SizeFlags::CAPACITY)
+    );
+    assert_eq!(
+        map.mem_size(SizeFlags::default()),
+        size_of::<HashMap<u64, u64>>() + buckets(capacity) * entry
+    );
+
+    // The same holds for sets
+    let set = HashSet::<u64>::new();
+    assert_eq!(
+        set.mem_size(SizeFlags::default()),
+        size_of::<HashSet<u64>>()
+    );
+    let mut set = HashSet::<u64>::with_capacity(1000);
+    set.extend(0..10);
+    assert_eq!(
+        set.mem_size(SizeFlags::default()),
+        size_of::<HashSet<u64>>() + buckets(10) * (size_of::<u64>() + 1)
+    );
+    assert_eq!(
+        set.mem_size(SizeFlags::CAPACITY),
+        size_of::<HashSet<u64>>() + buckets(set.capacity()) * (size_of::<u64>() + 1)
+    );
+}
+
 #[test]
 fn test_slice_u8() {
     let data = [0_u8; 10].as_slice();
@@ -710,33 +1070,23 @@ macro_rules! test_size {
     };
 }
 
-#[derive(MemSize)]
+#[derive(MemSize, Default)]
 enum TestEnum2 {
+    #[default]
     A,
     _B(u64),
     _C(u64, Vec<usize>),
 }
 
-impl Default for TestEnum2 {
-    fn default() -> Self {
-        TestEnum2::A
-    }
-}
-
 #[repr(u8)]
-#[derive(MemSize)]
+#[derive(MemSize, Default)]
 enum TestEnumReprU8 {
+    #[default]
     A,
     _B(u64),
     _C(u64, Vec<usize>),
 }
 
-impl Default for TestEnumReprU8 {
-    fn default() -> Self {
-        TestEnumReprU8::A
-    }
-}
-
 #[derive(MemSize, MemDbg)]
 union TestUnion {
     a: u64,
@@ -815,3 +1165,1310 @@ fn test_single_field_union_follow_ref() {
             + <TestUnion as MemSize>::mem_size(&test_union, SizeFlags::default()),
     );
 }
+
+#[cfg(feature = "priority-queue")]
+#[test]
+fn test_priority_queue() {
+    let mut pq = priority_queue::PriorityQueue::<u64, u64>::new();
+    // An empty queue has no heap memory
+    assert_eq!(
+        pq.mem_size(SizeFlags::default()),
+        core::mem::size_of::<priority_queue::PriorityQueue<u64, u64>>()
+    );
+    assert_eq!(
+        pq.mem_size(SizeFlags::CAPACITY),
+        core::mem::size_of::<priority_queue::PriorityQueue<u64, u64>>()
+    );
+    for i in 0..3 {
+        pq.push(i, 10 - i);
+    }
+    // Three entries (hash, item, priority), four buckets of indices with their
+    // control bytes, and the heap plus its inverse
+    let expected = core::mem::size_of::<priority_queue::PriorityQueue<u64, u64>>()
+        + 3 * 3 * 8
+        + 4 * (8 + 1)
+        + 2 * 3 * 8;
+    assert_eq!(pq.mem_size(SizeFlags::default()), expected);
+    assert!(pq.mem_size(SizeFlags::CAPACITY) >= expected);
+    pq.mem_dbg(DbgFlags::default()).unwrap();
+
+    // Items are recursed into
+    let mut pq = priority_queue::PriorityQueue::<String, u64>::new();
+    pq.push("a".repeat(100), 0);
+    let empty_pq = {
+        let mut pq = priority_queue::PriorityQueue::<String, u64>::new();
+        pq.push(String::new(), 0);
+        pq
+    };
+    assert_eq!(
+        pq.mem_size(SizeFlags::default()),
+        empty_pq.mem_size(SizeFlags::default()) + 100
+    );
+}
+
+#[test]
+fn test_single_field_union_padding() {
+    #[derive(MemSize, MemDbg)]
+    #[repr(C, align(8))]
+    union Padded {
+        a: u8,
+    }
+
+    let v = Padded { a: 0 };
+    assert_eq!(v.mem_size(SizeFlags::default()), 8);
+    let mut output = String::new();
+    v.mem_dbg_on(&mut output, DbgFlags::RUST_LAYOUT).unwrap();
+    // The padding of the field includes the tail padding of the union
+    assert_eq!(
+        output,
+        "\
+8 B ⏺
+    ├╴union
+1 B ╰╴ [7B]
+"
+    );
+}
+
+#[test]
+fn test_multi_field_union() {
+    #[allow(dead_code)]
+    #[derive(MemSize, MemDbg)]
+    #[mem_size(union = "shallow")]
+    union Shallow {
+        a: u8,
+        b: u64,
+    }
+
+    let v = Shallow { b: 0 };
+    assert_eq!(v.mem_size(SizeFlags::default()), 8);
+    assert_eq!(v.mem_size(SizeFlags::FOLLOW_REFS), 8);
+    let mut output = String::new();
+    v.mem_dbg_on(&mut output, DbgFlags::empty()).unwrap();
+    // Just the label, without fields
+    assert_eq!(
+        output,
+        "\
+8 B ⏺
+    ╰╴union
+"
+    );
+
+    // The first bit tells us which field is initialized
+    #[derive(MemSize, MemDbg)]
+    #[mem_size(union = "with = tagged_size")]
+    union Tagged {
+        small: u64,
+        boxed: core::mem::ManuallyDrop<Box<[u8; 100]>>,
+    }
+
+    fn tagged_size(tagged: &Tagged, flags: SizeFlags) -> usize {
+        unsafe {
+            if tagged.small & 1 == 1 {
+                core::mem::size_of::<Tagged>()
+            } else {
+                tagged.boxed.mem_size(flags)
+            }
+        }
+    }
+
+    let v = Tagged { small: 1 };
+    assert_eq!(v.mem_size(SizeFlags::default()), 8);
+    let mut v = Tagged {
+        boxed: core::mem::ManuallyDrop::new(Box::new([0; 100])),
+    };
+    assert_eq!(v.mem_size(SizeFlags::default()), 108);
+    let mut output = String::new();
+    v.mem_dbg_on(&mut output, DbgFlags::empty()).unwrap();
+    assert_eq!(
+        output,
+        "\
+108 B ⏺
+      ╰╴union
+"
+    );
+    unsafe { core::mem::ManuallyDrop::drop(&mut v.boxed) };
+}
+
+#[test]
+fn test_bound() {
+    // The automatically generated bound Vec<Tree<T>>: MemSize would make the
+    // compiler overflow while evaluating the requirements.
+    #[derive(MemSize, MemDbg)]
+    #[mem_dbg(bound = "T: MemSize + MemDbgImpl")]
+    struct Tree<T> {
+        value: T,
+        children: Vec<Tree<T>>,
+    }
+
+    let tree = Tree {
+        value: 0_u64,
+        children: vec![
+            Tree {
+                value: 1_u64,
+                children: vec![],
+            },
+            Tree {
+                value: 2_u64,
+                children: vec![],
+            },
+        ],
+    };
+    let node_size = core::mem::size_of::<Tree<u64>>();
+    assert_eq!(tree.mem_size(SizeFlags::default()), 3 * node_size);
+    tree.mem_dbg(DbgFlags::default()).unwrap();
+
+    // Same, but omitting just the bound of the recursive field
+    #[derive(MemSize, MemDbg)]
+    struct List<T> {
+        value: T,
+        #[mem_dbg(no_bound)]
+        next: Option<Box<List<T>>>,
+    }
+
+    let list = List {
+        value: 0_u64,
+        next: Some(Box::new(List {
+            value: 1_u64,
+            next: None,
+        })),
+    };
+    let node_size = core::mem::size_of::<List<u64>>();
+    assert_eq!(list.mem_size(SizeFlags::default()), 2 * node_size);
+    list.mem_dbg(DbgFlags::default()).unwrap();
+}
+
+#[cfg(feature = "bytes")]
+#[test]
+fn test_bytes() {
+    let bytes_size = core::mem::size_of::<bytes::Bytes>();
+    let b = bytes::Bytes::from(vec![0_u8; 1000]);
+    assert_eq!(b.mem_size(SizeFlags::default()), bytes_size + 1000);
+
+    // Clones and slices count the bytes they view
+    let c = b.slice(..100);
+    assert_eq!(c.mem_size(SizeFlags::default()), bytes_size + 100);
+    assert_eq!(
+        vec![b.clone(), b.clone()].mem_size(SizeFlags::FOLLOW_RCS),
+        core::mem::size_of::<Vec<bytes::Bytes>>() + 2 * (bytes_size + 1000)
+    );
+    b.mem_dbg(DbgFlags::default()).unwrap();
+
+    let bytes_mut_size = core::mem::size_of::<bytes::BytesMut>();
+    let mut b = bytes::BytesMut::with_capacity(1000);
+    b.extend_from_slice(&[0; 10]);
+    assert_eq!(b.mem_size(SizeFlags::default()), bytes_mut_size + 10);
+    assert_eq!(b.mem_size(SizeFlags::CAPACITY), bytes_mut_size + 1000);
+    assert_eq!(b.mem_allocations(SizeFlags::default()), 1);
+    b.mem_dbg(DbgFlags::default()).unwrap();
+}
+
+#[cfg(feature = "time")]
+#[test]
+fn test_time() {
+    let date = time::OffsetDateTime::UNIX_EPOCH;
+    assert_eq!(
+        date.mem_size(SizeFlags::default()),
+        core::mem::size_of::<time::OffsetDateTime>()
+    );
+    // Dates own no heap memory
+    let dates = vec![date.date(); 10];
+    assert_eq!(
+        dates.mem_size(SizeFlags::default()),
+        core::mem::size_of::<Vec<time::Date>>() + 10 * core::mem::size_of::<time::Date>()
+    );
+    let mut output = String::new();
+    (date, time::Duration::ZERO)
+        .mem_dbg_on(&mut output, DbgFlags::empty())
+        .unwrap();
+    assert_eq!(output.lines().count(), 3);
+}
+
+#[cfg(feature = "uuid")]
+#[test]
+fn test_uuid() {
+    let id = uuid::Uuid::nil();
+    assert_eq!(id.mem_size(SizeFlags::default()), 16);
+    assert_eq!(vec![id; 10].mem_size(SizeFlags::default()), 24 + 160);
+    let mut output = String::new();
+    id.mem_dbg_on(&mut output, DbgFlags::empty()).unwrap();
+    assert_eq!(output, "16 B ⏺\n");
+}
+
+#[cfg(feature = "dashmap")]
+#[test]
+fn test_dashmap() {
+    let map = (0..1000_u64)
+        .map(|i| (i, i))
+        .collect::<dashmap::DashMap<u64, u64>>();
+    let shards = map.shards();
+    // The shards, each with its lock, and the buckets of their tables with
+    // their control bytes
+    let expected = core::mem::size_of::<dashmap::DashMap<u64, u64>>()
+        + shards
+            .iter()
+            .map(|shard| {
+                core::mem::size_of_val(shard) + estimated_buckets(shard.read().len()) * (8 + 8 + 1)
+            })
+            .sum::<usize>();
+    assert_eq!(map.mem_size(SizeFlags::default()), expected);
+    assert!(map.mem_size(SizeFlags::CAPACITY) >= expected);
+    assert_eq!(
+        map.mem_allocations(SizeFlags::default()),
+        1 + shards
+            .iter()
+            .filter(|shard| shard.read().capacity() != 0)
+            .count()
+    );
+    map.mem_dbg(DbgFlags::default()).unwrap();
+
+    // Keys and values are recursed into
+    let map = dashmap::DashMap::<u64, String>::new();
+    map.insert(0, String::new());
+    let empty_size = map.mem_size(SizeFlags::default());
+    map.insert(0, "a".repeat(100));
+    assert_eq!(map.mem_size(SizeFlags::default()), empty_size + 100);
+
+    // Sizing does not deadlock if the caller holds a guard on a shard, whose
+    // content is not counted
+    let guard = map.get_mut(&0).unwrap();
+    let shard_size = core::mem::size_of_val(&map.shards()[0]);
+    assert!(map.mem_size(SizeFlags::default()) < empty_size);
+    assert_eq!(
+        map.mem_size(SizeFlags::default()),
+        core::mem::size_of::<dashmap::DashMap<u64, String>>() + map.shards().len() * shard_size
+    );
+    assert_eq!(map.mem_allocations(SizeFlags::default()), 1);
+    map.mem_dbg(DbgFlags::default()).unwrap();
+    drop(guard);
+}
+
+#[cfg(feature = "im")]
+#[test]
+fn test_im() {
+    let vector_size = core::mem::size_of::<im::Vector<u64>>();
+
+    // Small vectors are stored inline
+    let v: im::Vector<u64> = (0..2).collect();
+    assert!(v.is_inline());
+    assert_eq!(v.mem_size(SizeFlags::default()), vector_size);
+
+    // Two vectors sharing all chunks but the last one: each vector counts
+    // its own chunks, but with FOLLOW_RCS shared chunks are counted once
+    let v1: im::Vector<u64> = (0..1000).collect();
+    let mut v2 = v1.clone();
+    v2.push_back(1000);
+    let chunk_overhead = 4 * core::mem::size_of::<usize>();
+    assert_eq!(
+        v1.mem_size(SizeFlags::default()),
+        vector_size + 16 * chunk_overhead + 1000 * 8
+    );
+    assert_eq!(
+        v2.mem_size(SizeFlags::default()),
+        vector_size + 16 * chunk_overhead + 1001 * 8
+    );
+    assert_eq!(
+        v2.mem_size(SizeFlags::CAPACITY),
+        vector_size + 16 * chunk_overhead + 16 * 64 * 8
+    );
+    assert_eq!(
+        (v1.clone(), v2.clone()).mem_size(SizeFlags::FOLLOW_RCS),
+        2 * vector_size + 17 * chunk_overhead + (1000 + 41) * 8
+    );
+    assert_eq!(
+        (v1.clone(), v1.clone()).mem_size(SizeFlags::FOLLOW_RCS),
+        2 * vector_size + v1.mem_size(SizeFlags::default()) - vector_size
+    );
+    v2.mem_dbg(DbgFlags::default()).unwrap();
+
+    // Elements are recursed into
+    let v: im::Vector<String> = (0..100).map(|_| "a".repeat(10)).collect();
+    let w: im::Vector<String> = (0..100).map(|_| String::new()).collect();
+    assert_eq!(
+        v.mem_size(SizeFlags::default()),
+        w.mem_size(SizeFlags::default()) + 100 * 10
+    );
+
+    let m: im::HashMap<u64, String> = (0..10_u64).map(|i| (i, "a".repeat(10))).collect();
+    assert_eq!(
+        m.mem_size(SizeFlags::default()),
+        core::mem::size_of::<im::HashMap<u64, String>>()
+            + 10 * core::mem::size_of::<(u64, String)>()
+            + 10 * 10
+    );
+    // Entries of clones are counted once with FOLLOW_RCS
+    let map_size = core::mem::size_of::<im::HashMap<u64, String>>();
+    assert_eq!(
+        (m.clone(), m.clone()).mem_size(SizeFlags::FOLLOW_RCS),
+        2 * map_size + m.mem_size(SizeFlags::default()) - map_size
+    );
+    let s: im::HashSet<String> = (0..10).map(|i| i.to_string()).collect();
+    let set_size = core::mem::size_of::<im::HashSet<String>>();
+    assert_eq!(
+        (s.clone(), s.clone()).mem_size(SizeFlags::FOLLOW_RCS),
+        2 * set_size + s.mem_size(SizeFlags::default()) - set_size
+    );
+
+    let m: im::OrdMap<u64, u64> = (0..10_u64).map(|i| (i, i)).collect();
+    assert_eq!(
+        m.mem_size(SizeFlags::default()),
+        core::mem::size_of::<im::OrdMap<u64, u64>>() + 10 * 16
+    );
+    let map_size = core::mem::size_of::<im::OrdMap<u64, u64>>();
+    assert_eq!(
+        (m.clone(), m.clone()).mem_size(SizeFlags::FOLLOW_RCS),
+        2 * map_size + 10 * 16
+    );
+    let s: im::OrdSet<u64> = (0..10_u64).collect();
+    let set_size = core::mem::size_of::<im::OrdSet<u64>>();
+    assert_eq!(
+        (s.clone(), s.clone()).mem_size(SizeFlags::FOLLOW_RCS),
+        2 * set_size + 10 * 8
+    );
+}
+
+#[test]
+fn test_control_flow() {
+    use core::ops::ControlFlow;
+
+    #[derive(MemSize, MemDbg)]
+    struct Search {
+        state: ControlFlow<String, Vec<u8>>,
+        steps: usize,
+    }
+
+    let mut v = Vec::with_capacity(10);
+    v.extend([1_u8, 2, 3]);
+    let search = Search {
+        state: ControlFlow::Continue(v),
+        steps: 0,
+    };
+    assert_eq!(
+        search.mem_size(SizeFlags::default()),
+        core::mem::size_of::<Search>() + 3
+    );
+    assert_eq!(
+        search.mem_size(SizeFlags::CAPACITY),
+        core::mem::size_of::<Search>() + 10
+    );
+    search.mem_dbg(DbgFlags::default()).unwrap();
+
+    let search = Search {
+        state: ControlFlow::Break("found".to_owned()),
+        steps: 1,
+    };
+    assert_eq!(
+        search.mem_size(SizeFlags::default()),
+        core::mem::size_of::<Search>() + 5
+    );
+    search.mem_dbg(DbgFlags::default()).unwrap();
+}
+
+#[test]
+fn test_result() {
+    let mut v = Vec::with_capacity(10);
+    v.extend([1_u8, 2, 3]);
+    let ok: Result<Vec<u8>, String> = Ok(v);
+    assert_eq!(
+        ok.mem_size(SizeFlags::default()),
+        core::mem::size_of::<Result<Vec<u8>, String>>() + 3
+    );
+    assert_eq!(
+        ok.mem_size(SizeFlags::CAPACITY),
+        core::mem::size_of::<Result<Vec<u8>, String>>() + 10
+    );
+    ok.mem_dbg(DbgFlags::default()).unwrap();
+
+    let mut s = String::with_capacity(20);
+    s.push_str("error");
+    let err: Result<Vec<u8>, String> = Err(s);
+    assert_eq!(
+        err.mem_size(SizeFlags::default()),
+        core::mem::size_of::<Result<Vec<u8>, String>>() + 5
+    );
+    assert_eq!(
+        err.mem_size(SizeFlags::CAPACITY),
+        core::mem::size_of::<Result<Vec<u8>, String>>() + 20
+    );
+    err.mem_dbg(DbgFlags::default()).unwrap();
+
+    // Results are copy types only if both types are
+    fn copy_type<T: CopyType<Copy = True>>() {}
+    fn non_copy_type<T: CopyType<Copy = False>>() {}
+    copy_type::<Result<u64, u8>>();
+    non_copy_type::<Result<u64, String>>();
+    non_copy_type::<Result<String, u64>>();
+
+    let v: Vec<Result<u64, String>> = vec![Ok(0), Err("error".to_owned())];
+    assert_eq!(
+        v.mem_size(SizeFlags::default()),
+        core::mem::size_of::<Vec<Result<u64, String>>>()
+            + 2 * core::mem::size_of::<Result<u64, String>>()
+            + 5
+    );
+}
+
+#[test]
+fn test_empty_enum() {
+    use core::convert::Infallible;
+
+    #[derive(MemSize, MemDbg)]
+    enum Never {}
+
+    assert_eq!(core::mem::size_of::<Never>(), 0);
+    let ok: Result<u64, Never> = Ok(0);
+    assert_eq!(ok.mem_size(SizeFlags::default()), 8);
+    ok.mem_dbg(DbgFlags::default()).unwrap();
+
+    let ok: Result<String, Infallible> = Ok("hello".to_owned());
+    assert_eq!(
+        ok.mem_size(SizeFlags::default()),
+        core::mem::size_of::<String>() + 5
+    );
+    ok.mem_dbg(DbgFlags::default()).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn test_process_output() {
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::{ExitStatus, Output};
+
+    let output = Output {
+        status: ExitStatus::from_raw(0),
+        stdout: b"hello\n".to_vec(),
+        stderr: Vec::with_capacity(10),
+    };
+    assert_eq!(
+        output.mem_size(SizeFlags::default()),
+        core::mem::size_of::<Output>() + 6
+    );
+    assert_eq!(
+        output.mem_size(SizeFlags::CAPACITY),
+        core::mem::size_of::<Output>() + 6 + 10
+    );
+    output.mem_dbg(DbgFlags::default()).unwrap();
+
+    // Output of an actual command, if available
+    if let Ok(output) = std::process::Command::new("echo").arg("hello").output() {
+        assert_eq!(
+            output.mem_size(SizeFlags::default()),
+            core::mem::size_of::<Output>() + output.stdout.len() + output.stderr.len()
+        );
+    }
+}
+
+#[test]
+fn test_vec_deque_capacity() {
+    use std::collections::VecDeque;
+
+    let mut v = VecDeque::<u64>::with_capacity(10);
+    let capacity = v.capacity();
+    // Push and pop to force the ring buffer to wrap around
+    for i in 0..capacity as u64 {
+        v.push_back(i);
+    }
+    for _ in 0..capacity / 2 {
+        v.pop_front();
+    }
+    for i in 0..capacity as u64 / 4 {
+        v.push_back(i);
+    }
+    assert!(!v.as_slices().1.is_empty());
+    assert_eq!(v.capacity(), capacity);
+    assert_eq!(
+        v.mem_size(SizeFlags::default()),
+        core::mem::size_of::<VecDeque<u64>>() + v.len() * 8
+    );
+    assert_eq!(
+        v.mem_size(SizeFlags::CAPACITY) - v.mem_size(SizeFlags::default()),
+        (v.capacity() - v.len()) * 8
+    );
+
+    // The same with non-copy elements
+    let mut v = VecDeque::<String>::with_capacity(10);
+    let capacity = v.capacity();
+    for _ in 0..capacity {
+        v.push_back("a".to_owned());
+    }
+    for _ in 0..capacity / 2 {
+        v.pop_front();
+    }
+    for _ in 0..capacity / 4 {
+        v.push_back("a".to_owned());
+    }
+    assert!(!v.as_slices().1.is_empty());
+    assert_eq!(
+        v.mem_size(SizeFlags::default()),
+        core::mem::size_of::<VecDeque<String>>() + v.len() * (core::mem::size_of::<String>() + 1)
+    );
+    assert_eq!(
+        v.mem_size(SizeFlags::CAPACITY) - v.mem_size(SizeFlags::default()),
+        (v.capacity() - v.len()) * core::mem::size_of::<String>()
+    );
+    v.mem_dbg(DbgFlags::default()).unwrap();
+}
+
+#[test]
+fn test_vec_deque_wraparound() {
+    use std::collections::VecDeque;
+
+    // Strings of distinct lengths, so that skipping or counting twice an
+    // element would change the size
+    let mut v = VecDeque::<String>::with_capacity(8);
+    let capacity = v.capacity();
+    for i in 0..capacity {
+        v.push_back("a".repeat(i));
+    }
+    for _ in 0..capacity / 2 {
+        v.pop_front();
+    }
+    for i in 0..capacity / 4 {
+        v.push_back("b".repeat(100 + i));
+    }
+    let (front, back) = v.as_slices();
+    assert!(!front.is_empty() && !back.is_empty());
+    let heap = v.iter().map(String::capacity).sum::<usize>();
+    assert_eq!(
+        v.mem_size(SizeFlags::default()),
+        size_of::<VecDeque<String>>() + v.len() * size_of::<String>() + heap
+    );
+    // The buffer and the nonempty strings
+    assert_eq!(v.mem_allocations(SizeFlags::default()), 1 + v.len());
+}
+
+#[test]
+fn test_binary_heap() {
+    use std::collections::BinaryHeap;
+
+    let mut h = BinaryHeap::<u32>::with_capacity(10);
+    h.extend([3, 1, 2]);
+    assert_eq!(
+        h.mem_size(SizeFlags::default()),
+        size_of::<BinaryHeap<u32>>() + 3 * 4
+    );
+    assert_eq!(
+        h.mem_size(SizeFlags::CAPACITY),
+        size_of::<BinaryHeap<u32>>() + h.capacity() * 4
+    );
+
+    let h = ["a", "bb", "ccc"]
+        .map(String::from)
+        .into_iter()
+        .collect::<BinaryHeap<_>>();
+    assert_eq!(
+        h.mem_size(SizeFlags::default()),
+        size_of::<BinaryHeap<String>>() + 3 * size_of::<String>() + 6
+    );
+    assert_eq!(h.mem_allocations(SizeFlags::default()), 4);
+    // Sizing does not rearrange the heap
+    assert_eq!(h.peek().map(String::as_str), Some("ccc"));
+    h.mem_dbg(DbgFlags::default() | DbgFlags::EXPAND_ELEMENTS)
+        .unwrap();
+}
+
+#[test]
+fn test_wrappers_no_underflow() {
+    // Niche-optimized contents: the wrapper is no larger than its content
+    let x = 0_u8;
+    let c = core::cell::Cell::new(Some(&x));
+    assert_eq!(
+        c.mem_size(SizeFlags::default()),
+        core::mem::size_of::<core::cell::Cell<Option<&u8>>>()
+    );
+    assert_eq!(
+        c.mem_size(SizeFlags::FOLLOW_REFS),
+        core::mem::size_of::<core::cell::Cell<Option<&u8>>>() + 1
+    );
+    let r = core::cell::RefCell::new(Some(core::ptr::NonNull::from(&x)));
+    assert_eq!(
+        r.mem_size(SizeFlags::default()),
+        core::mem::size_of::<core::cell::RefCell<Option<core::ptr::NonNull<u8>>>>()
+    );
+
+    // The content is larger than the wrapper
+    let a = std::sync::Arc::new([0_u8; 64]);
+    assert_eq!(
+        a.mem_size(SizeFlags::default()),
+        core::mem::size_of::<std::sync::Arc<[u8; 64]>>()
+    );
+    let m = std::sync::Mutex::new([0_u8; 64]);
+    let g = m.lock().unwrap();
+    assert_eq!(
+        Some(&g).mem_size(SizeFlags::default()),
+        core::mem::size_of::<Option<&std::sync::MutexGuard<[u8; 64]>>>()
+    );
+    assert_eq!(
+        g.mem_size(SizeFlags::default()),
+        core::mem::size_of::<std::sync::MutexGuard<[u8; 64]>>()
+    );
+    drop(g);
+
+    // OnceCell counts the heap memory of its content
+    let o = core::cell::OnceCell::new();
+    assert_eq!(
+        o.mem_size(SizeFlags::default()),
+        core::mem::size_of::<core::cell::OnceCell<Vec<u8>>>()
+    );
+    o.set(vec![0_u8; 10]).unwrap();
+    assert_eq!(
+        o.mem_size(SizeFlags::default()),
+        core::mem::size_of::<core::cell::OnceCell<Vec<u8>>>() + 10
+    );
+}
+
+#[test]
+fn test_lazy_cell() {
+    let forced = core::cell::Cell::new(false);
+    let lazy = core::cell::LazyCell::new(|| {
+        forced.set(true);
+        vec![0_u8; 10]
+    });
+    let stack = core::mem::size_of_val(&lazy);
+    // Sizing does not force the value
+    assert_eq!(lazy.mem_size(SizeFlags::default()), stack);
+    assert_eq!(lazy.mem_allocations(SizeFlags::default()), 0);
+    lazy.mem_dbg(DbgFlags::default()).unwrap();
+    assert!(!forced.get());
+
+    assert_eq!(lazy.len(), 10);
+    assert_eq!(lazy.mem_size(SizeFlags::default()), stack + 10);
+    assert_eq!(lazy.mem_allocations(SizeFlags::default()), 1);
+}
+
+/// A type whose implementation of [`MemSize`] swaps the length and the
+/// capacity of its vector.
+struct Broken(Vec<u8>);
+
+impl CopyType for Broken {
+    type Copy = False;
+}
+
+impl MemSize for Broken {
+    fn _mem_size_rec(&self, ctx: &mut SizeContext) -> ControlFlow<()> {
+        ctx.add_heap(self.0.capacity(), self.0.len())
+    }
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(
+    expected = "MemSize for test_mem_size::Broken returned 24 bytes with SizeFlags::CAPACITY, less than the 34 bytes returned without it"
+)]
+fn test_sanity_check_derive() {
+    #[derive(MemSize)]
+    struct Outer {
+        broken: Broken,
+    }
+
+    // The derived implementation checks its fields
+    let _ = Outer {
+        broken: Broken(Vec::with_capacity(10)),
+    }
+    .mem_size(SizeFlags::default());
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(
+    expected = "MemSize for test_mem_size::Broken returned 24 bytes with SizeFlags::CAPACITY"
+)]
+fn test_sanity_check_capacity() {
+    let broken = Broken(Vec::with_capacity(10));
+    // Hand-written implementations can check the values they contain
+    debug_assert_mem_size!(&broken, SizeFlags::default());
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(
+    expected = "MemSize for test_mem_size::Broken returned 24 bytes with SizeFlags::CAPACITY, less than the 34 bytes returned without it"
+)]
+fn test_sanity_check_derive_capacity() {
+    #[derive(MemSize)]
+    enum Outer {
+        Broken(Broken),
+    }
+
+    // The derived implementation checks the statistics of its fields, which
+    // are computed with and without SizeFlags::CAPACITY in a single traversal
+    let _ = Outer::Broken(Broken(Vec::with_capacity(10))).mem_stats(SizeFlags::default());
+}
+
+#[test]
+fn test_sanity_check_opt_in() {
+    // mem_size does not check the value itself, only the derived
+    // implementations check their fields
+    let broken = Broken(Vec::with_capacity(10));
+    assert_eq!(
+        broken.mem_size(SizeFlags::default()),
+        size_of::<Broken>() + 10
+    );
+}
+
+#[test]
+fn test_broken_saturation() {
+    // Sizes computed by subtraction are zero rather than overflowing
+    let broken = Broken(Vec::with_capacity(10));
+    let stats = broken.mem_stats(SizeFlags::default());
+    assert_eq!(stats.heap_used, 10);
+    assert_eq!(stats.heap_capacity, 0);
+    assert_eq!(stats.slack(), 0);
+}
+
+#[test]
+fn test_rc_trait_objects() {
+    use std::rc::Rc;
+    use std::sync::Arc;
+
+    let vec = Arc::new(vec![0_u64; 10]);
+    let string = Arc::new("x".repeat(100));
+    let dyn_vec: Arc<dyn MemSize + Send + Sync> = vec.clone();
+    let dyn_string: Arc<dyn MemSize + Send + Sync> = string.clone();
+    let inner = |value_size| 2 * size_of::<usize>() + value_size;
+
+    // The fat pointer, plus the memory owned by the erased value
+    assert_eq!(
+        dyn_vec.mem_size(SizeFlags::default()),
+        size_of::<Arc<dyn MemSize>>() + 10 * size_of::<u64>()
+    );
+    assert_eq!(
+        dyn_string.mem_size(SizeFlags::default()),
+        size_of::<Arc<dyn MemSize>>() + 100
+    );
+    // The control block and the value, too
+    assert_eq!(
+        dyn_vec.mem_size(SizeFlags::FOLLOW_RCS),
+        size_of::<Arc<dyn MemSize>>() + inner(size_of::<Vec<u64>>()) + 10 * size_of::<u64>()
+    );
+    assert_eq!(dyn_vec.mem_allocations(SizeFlags::FOLLOW_RCS), 2);
+
+    // Pointers to the same allocation are recognized regardless of their
+    // type, as they are identified by their data pointer
+    let values: Vec<Arc<dyn MemSize + Send + Sync>> = vec![dyn_vec.clone(), dyn_string, dyn_vec];
+    assert_eq!(
+        values.mem_size(SizeFlags::FOLLOW_RCS),
+        size_of::<Vec<Arc<dyn MemSize>>>()
+            + 3 * size_of::<Arc<dyn MemSize>>()
+            + inner(size_of::<Vec<u64>>())
+            + 10 * size_of::<u64>()
+            + inner(size_of::<String>())
+            + 100
+    );
+    let both = (vec.clone(), values[0].clone());
+    assert_eq!(
+        both.mem_size(SizeFlags::FOLLOW_RCS),
+        size_of::<(Arc<Vec<u64>>, Arc<dyn MemSize>)>()
+            + inner(size_of::<Vec<u64>>())
+            + 10 * size_of::<u64>()
+    );
+
+    let rc: Rc<dyn MemDbgImpl> = Rc::new(String::from("abc"));
+    assert_eq!(
+        rc.mem_size(SizeFlags::FOLLOW_RCS),
+        size_of::<Rc<dyn MemDbgImpl>>() + inner(size_of::<String>()) + 3
+    );
+}
+
+#[test]
+fn test_boxed_error() {
+    #[derive(Debug)]
+    struct Error {
+        _code: u64,
+        _line: u32,
+    }
+
+    impl core::fmt::Display for Error {
+        fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+            write!(f, "error")
+        }
+    }
+
+    impl std::error::Error for Error {}
+
+    let e: Box<dyn std::error::Error + Send + Sync> = Box::new(Error { _code: 0, _line: 0 });
+    assert_eq!(
+        e.mem_size(SizeFlags::default()),
+        core::mem::size_of::<Box<dyn std::error::Error + Send + Sync>>()
+            + core::mem::size_of::<Error>()
+    );
+    let e: Box<dyn std::error::Error> = Box::new(Error { _code: 0, _line: 0 });
+    assert!(e.mem_size(SizeFlags::default()) >= 2 * core::mem::size_of::<usize>() + 16);
+    e.mem_dbg(DbgFlags::default()).unwrap();
+
+    #[cfg(feature = "anyhow")]
+    {
+        let e = anyhow::Error::new(Error { _code: 0, _line: 0 });
+        assert!(
+            e.mem_size(SizeFlags::default())
+                >= core::mem::size_of::<anyhow::Error>() + core::mem::size_of::<Error>()
+        );
+    }
+}
+
+#[test]
+fn test_boxed_closure() {
+    let data = [1_u8; 64];
+    let f: Box<dyn Fn(u32) -> u32 + Send> = Box::new(move |i| data[i as usize] as u32);
+    assert_eq!(
+        f.mem_size(SizeFlags::default()),
+        core::mem::size_of::<Box<dyn Fn(u32) -> u32 + Send>>() + 64
+    );
+    f.mem_dbg(DbgFlags::default()).unwrap();
+
+    let mut count = [0_u64; 8];
+    let mut f: Box<dyn FnMut()> = Box::new(move || count[0] += 1);
+    f();
+    assert_eq!(
+        f.mem_size(SizeFlags::default()),
+        core::mem::size_of::<Box<dyn FnMut()>>() + 64
+    );
+
+    let s = String::from("closure");
+    let f: Box<dyn FnOnce() -> String> = Box::new(move || s);
+    // The string is captured by value, but its heap buffer cannot be followed
+    assert_eq!(
+        f.mem_size(SizeFlags::default()),
+        core::mem::size_of::<Box<dyn FnOnce() -> String>>() + core::mem::size_of::<String>()
+    );
+
+    // Capture-less closures do not allocate
+    let v: Vec<Box<dyn Fn(u32, u32) -> u32>> = vec![Box::new(|a, b| a + b), Box::new(|a, b| a * b)];
+    assert_eq!(
+        v.mem_size(SizeFlags::default()),
+        core::mem::size_of::<Vec<Box<dyn Fn(u32, u32) -> u32>>>()
+            + 2 * core::mem::size_of::<Box<dyn Fn(u32, u32) -> u32>>()
+    );
+}
+
+#[test]
+fn test_paths() {
+    let long = "/very/long/path/to/an/interned/file/".repeat(10);
+    let boxed_str: Box<str> = long.clone().into_boxed_str();
+    let boxed_path: Box<std::path::Path> = std::path::PathBuf::from(&long).into_boxed_path();
+    let boxed_os_str: Box<std::ffi::OsStr> = std::ffi::OsString::from(&long).into_boxed_os_str();
+    for flags in [SizeFlags::default(), SizeFlags::FOLLOW_REFS] {
+        let expected = boxed_str.mem_size(flags);
+        assert!(expected >= size_of::<Box<str>>() + long.len());
+        assert_eq!(boxed_path.mem_size(flags), expected);
+        assert_eq!(boxed_os_str.mem_size(flags), expected);
+    }
+
+    let mut path = std::path::PathBuf::with_capacity(1000);
+    path.push(&long);
+    assert_eq!(
+        path.mem_size(SizeFlags::default()),
+        size_of::<std::path::PathBuf>() + long.len()
+    );
+    assert_eq!(
+        path.mem_size(SizeFlags::CAPACITY),
+        size_of::<std::path::PathBuf>() + path.capacity()
+    );
+    let os_string = path.into_os_string();
+    assert_eq!(
+        os_string.mem_size(SizeFlags::default()),
+        size_of::<std::ffi::OsString>() + long.len()
+    );
+}
+
+#[test]
+fn test_follow_rcs() {
+    #[derive(MemSize)]
+    struct Big {
+        data: Vec<u64>,
+    }
+
+    #[derive(MemSize)]
+    struct Left(std::sync::Arc<Big>);
+
+    #[derive(MemSize)]
+    struct Right {
+        big: std::sync::Arc<Big>,
+        other: std::sync::Arc<Big>,
+    }
+
+    #[derive(MemSize)]
+    struct Diamond {
+        left: Left,
+        right: Right,
+    }
+
+    let big = std::sync::Arc::new(Big {
+        data: vec![0; 1000],
+    });
+    let other = std::sync::Arc::new(Big { data: vec![0; 10] });
+    let diamond = Diamond {
+        left: Left(big.clone()),
+        right: Right {
+            big,
+            other: other.clone(),
+        },
+    };
+
+    let arc = size_of::<std::sync::Arc<Big>>();
+    let inner = 2 * size_of::<usize>() + size_of::<Big>();
+    // Without the flag, the content is counted for each pointer, but not the
+    // counters and the stack size of the content
+    assert_eq!(
+        diamond.mem_size(SizeFlags::default()),
+        size_of::<Diamond>() + 2 * 8000 + 80
+    );
+    // With the flag, each allocation is counted once
+    assert_eq!(
+        diamond.mem_size(SizeFlags::FOLLOW_RCS),
+        size_of::<Diamond>() + 2 * inner + 8000 + 80
+    );
+    assert_eq!(size_of::<Diamond>(), 3 * arc);
+
+    // The same with Rc, through containers
+    let shared = std::rc::Rc::new("mem_dbg".to_owned());
+    let v = vec![shared.clone(), shared.clone(), shared];
+    assert_eq!(
+        v.mem_size(SizeFlags::FOLLOW_RCS),
+        size_of::<Vec<std::rc::Rc<String>>>()
+            + 3 * size_of::<std::rc::Rc<String>>()
+            + 2 * size_of::<usize>()
+            + size_of::<String>()
+            + 7
+    );
+    // Separate calls do not share the allocations already counted
+    assert_eq!(
+        v.mem_size(SizeFlags::FOLLOW_RCS),
+        v.mem_size(SizeFlags::FOLLOW_RCS)
+    );
+    drop(other);
+}
+
+#[test]
+fn test_follow_attributes() {
+    static TABLE: [u8; 1000] = [0; 1000];
+    static OTHER: [u8; 100] = [0; 100];
+
+    #[derive(MemSize, MemDbg)]
+    struct Lookup {
+        #[mem_dbg(follow)]
+        table: &'static [u8],
+        #[mem_dbg(no_follow)]
+        shared: std::rc::Rc<Vec<u8>>,
+        other: &'static [u8],
+    }
+
+    let lookup = Lookup {
+        table: &TABLE,
+        shared: std::rc::Rc::new(vec![0; 10]),
+        other: &OTHER,
+    };
+
+    // The table is always followed, the allocation of the shared vector never
+    assert_eq!(
+        lookup.mem_size(SizeFlags::default()),
+        size_of::<Lookup>() + 1000 + 10
+    );
+    assert_eq!(
+        lookup.mem_size(SizeFlags::FOLLOW_REFS | SizeFlags::FOLLOW_RCS),
+        size_of::<Lookup>() + 1000 + 10 + 100
+    );
+
+    // The same holds for the sizes displayed by MemDbg
+    let mut output = String::new();
+    lookup
+        .mem_dbg_on(&mut output, DbgFlags::FOLLOW_RCS)
+        .unwrap();
+    assert_eq!(
+        output,
+        "\
+1050 B ⏺
+1016 B ├╴table
+  18 B ├╴shared
+  16 B ╰╴other
+"
+    );
+}
+
+#[test]
+fn test_mem_stats() {
+    /// Checks that statistics are consistent with the sizes.
+    fn check<T: MemSize>(value: &T, flags: SizeFlags) {
+        let stats = value.mem_stats(flags);
+        assert_eq!(stats.stack, size_of::<T>());
+        assert_eq!(
+            stats.stack + stats.heap_used,
+            value.mem_size(flags.difference(SizeFlags::CAPACITY))
+        );
+        assert_eq!(
+            stats.stack + stats.heap_capacity,
+            value.mem_size(flags | SizeFlags::CAPACITY)
+        );
+        assert_eq!(
+            stats.heap_used,
+            value.heap_size(flags.difference(SizeFlags::CAPACITY))
+        );
+    }
+
+    #[derive(MemSize)]
+    struct Data {
+        names: Vec<String>,
+        map: std::collections::HashMap<String, Vec<u8>>,
+        boxed: Box<[u32]>,
+        maybe: Option<String>,
+        shared: (std::rc::Rc<String>, std::rc::Rc<String>),
+        deque: std::collections::VecDeque<String>,
+        kind: Kind,
+    }
+
+    #[derive(MemSize)]
+    enum Kind {
+        Empty,
+        Named { name: String },
+    }
+
+    let mut names = Vec::with_capacity(10);
+    for i in 0..5 {
+        let mut name = String::with_capacity(20);
+        name.push_str(&i.to_string());
+        names.push(name);
+    }
+    let mut map = std::collections::HashMap::with_capacity(100);
+    map.insert("a".to_owned(), Vec::with_capacity(7));
+    let shared = std::rc::Rc::new("shared".to_owned());
+    let mut deque = std::collections::VecDeque::with_capacity(8);
+    deque.push_back(String::with_capacity(3));
+    let data = Data {
+        names,
+        map,
+        boxed: vec![0; 10].into_boxed_slice(),
+        maybe: Some(String::with_capacity(5)),
+        shared: (shared.clone(), shared),
+        deque,
+        kind: Kind::Named {
+            name: String::with_capacity(9),
+        },
+    };
+    check(&data, SizeFlags::default());
+    check(&data, SizeFlags::FOLLOW_RCS);
+    check(&Kind::Empty, SizeFlags::default());
+    check(
+        &[Some(String::with_capacity(4)), None],
+        SizeFlags::default(),
+    );
+
+    let stats = data.mem_stats(SizeFlags::default());
+    assert!(stats.slack() > 0);
+    let names_stats = data.names.mem_stats(SizeFlags::default());
+    assert_eq!(names_stats.slack(), 5 * 24 + 5 * 19);
+    assert_eq!(
+        (names_stats + names_stats).heap_capacity,
+        2 * names_stats.heap_capacity
+    );
+}
+
+#[test]
+fn test_capacity_attribute() {
+    #[derive(MemSize, MemDbg)]
+    #[mem_dbg(capacity)]
+    struct Arena {
+        data: Vec<u64>,
+        names: Vec<String>,
+    }
+
+    #[derive(MemSize)]
+    struct Outer {
+        arena: Arena,
+        other: Vec<u64>,
+    }
+
+    let mut data = Vec::with_capacity(100);
+    data.push(0);
+    let mut names = Vec::with_capacity(4);
+    names.push(String::with_capacity(10));
+    let arena = Arena { data, names };
+    let capacity = core::mem::size_of::<Arena>() + 800 + 4 * 24 + 10;
+
+    // The capacity is reported whatever the flags
+    assert_eq!(arena.mem_size(SizeFlags::default()), capacity);
+    assert_eq!(arena.mem_size(SizeFlags::CAPACITY), capacity);
+    let stats = arena.mem_stats(SizeFlags::default());
+    assert_eq!(stats.heap_used, capacity - core::mem::size_of::<Arena>());
+    assert_eq!(stats.slack(), 0);
+
+    // Only the annotated type is affected
+    let mut other = Vec::with_capacity(10);
+    other.push(0);
+    let outer = Outer { arena, other };
+    assert_eq!(
+        outer.mem_size(SizeFlags::default()),
+        core::mem::size_of::<Outer>() + 800 + 4 * 24 + 10 + 8
+    );
+    assert_eq!(
+        outer.mem_size(SizeFlags::CAPACITY),
+        core::mem::size_of::<Outer>() + 800 + 4 * 24 + 10 + 80
+    );
+
+    // The fields are displayed with their capacity
+    let mut output = String::new();
+    outer
+        .arena
+        .mem_dbg_on(&mut output, DbgFlags::empty())
+        .unwrap();
+    assert_eq!(
+        output,
+        format!("{} B ⏺\n824 B ├╴data\n130 B ╰╴names\n", capacity)
+    );
+}
+
+#[test]
+fn test_held_and_poisoned_locks() {
+    let stack = core::mem::size_of::<std::sync::Mutex<Vec<u8>>>();
+    let m = std::sync::Mutex::new(vec![0_u8; 100]);
+    assert_eq!(m.mem_size(SizeFlags::default()), stack + 100);
+
+    // A held lock is not followed, rather than deadlocking
+    let g = m.lock().unwrap();
+    assert_eq!(m.mem_size(SizeFlags::default()), stack);
+    let mut output = String::new();
+    assert!(m.mem_dbg_on(&mut output, DbgFlags::empty()).is_ok());
+    drop(g);
+
+    // A poisoned lock is not followed, rather than panicking
+    let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        let _g = m.lock().unwrap();
+        panic!("poisoning the mutex");
+    }));
+    assert!(m.is_poisoned());
+    assert_eq!(m.mem_size(SizeFlags::default()), stack);
+    assert_eq!(m.mem_allocations(SizeFlags::default()), 0);
+    let mut output = String::new();
+    assert!(m.mem_dbg_on(&mut output, DbgFlags::empty()).is_ok());
+
+    let stack = core::mem::size_of::<std::sync::RwLock<Vec<u8>>>();
+    let l = std::sync::RwLock::new(vec![0_u8; 100]);
+    // Read locks can be shared
+    let r = l.read().unwrap();
+    assert_eq!(l.mem_size(SizeFlags::default()), stack + 100);
+    drop(r);
+    let w = l.write().unwrap();
+    assert_eq!(l.mem_size(SizeFlags::default()), stack);
+    let mut output = String::new();
+    assert!(l.mem_dbg_on(&mut output, DbgFlags::empty()).is_ok());
+    drop(w);
+
+    let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        let _w = l.write().unwrap();
+        panic!("poisoning the lock");
+    }));
+    assert!(l.is_poisoned());
+    assert_eq!(l.mem_size(SizeFlags::default()), stack);
+    let mut output = String::new();
+    assert!(l.mem_dbg_on(&mut output, DbgFlags::empty()).is_ok());
+}
+
+#[test]
+fn test_sockets() {
+    #[derive(MemSize, MemDbg)]
+    struct Server {
+        listener: std::net::TcpListener,
+        clients: Vec<std::net::TcpStream>,
+        socket: Option<std::net::UdpSocket>,
+    }
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let client = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let server = Server {
+        listener,
+        clients: vec![client],
+        socket: None,
+    };
+    assert_eq!(
+        server.mem_size(SizeFlags::default()),
+        size_of::<Server>() + size_of::<std::net::TcpStream>()
+    );
+    server.mem_dbg(DbgFlags::default()).unwrap();
+}
+
+#[test]
+fn test_mem_size_bounded() {
+    /// Checks that the bounded size is consistent with the size.
+    fn check<T: MemSize>(value: &T, flags: SizeFlags) {
+        let size = value.mem_size(flags);
+        assert_eq!(value.mem_size_bounded(usize::MAX, flags), Some(size));
+        assert_eq!(value.mem_size_bounded(size, flags), Some(size));
+        assert_eq!(value.mem_size_bounded(size - 1, flags), None);
+    }
+
+    #[derive(MemSize)]
+    struct Data<'a> {
+        names: Vec<String>,
+        map: std::collections::HashMap<String, Vec<u8>>,
+        set: std::collections::HashSet<Box<str>>,
+        tree: std::collections::BTreeMap<u32, String>,
+        boxed: Box<[String]>,
+        maybe: Option<String>,
+        shared: (std::rc::Rc<[u8; 64]>, std::rc::Rc<[u8; 64]>),
+        deque: std::collections::VecDeque<String>,
+        array: [Option<Vec<u16>>; 3],
+        reference: &'a Vec<u64>,
+        kind: Kind,
+        union: SingletonUnion<u8>,
+    }
+
+    #[derive(MemSize)]
+    enum Kind {
+        Empty,
+        Named { name: String, _id: u8 },
+    }
+
+    let shared = std::rc::Rc::new([0; 64]);
+    let referenced = vec![0; 100];
+    let data = Data {
+        names: (0..5).map(|i| i.to_string()).collect(),
+        map: (0..5).map(|i| (i.to_string(), vec![0; i])).collect(),
+        set: (0..5).map(|i| i.to_string().into_boxed_str()).collect(),
+        tree: (0..20).map(|i| (i, i.to_string())).collect(),
+        boxed: vec![String::from("a"); 3].into_boxed_slice(),
+        maybe: Some(String::with_capacity(5)),
+        shared: (shared.clone(), shared),
+        deque: (0..5).map(|i| i.to_string()).collect(),
+        array: [Some(vec![1, 2]), None, Some(Vec::with_capacity(8))],
+        reference: &referenced,
+        kind: Kind::Named {
+            name: String::from("name"),
+            _id: 0,
+        },
+        union: SingletonUnion { a: 0 },
+    };
+    for flags in [
+        SizeFlags::default(),
+        SizeFlags::CAPACITY,
+        SizeFlags::FOLLOW_REFS,
+        SizeFlags::FOLLOW_RCS,
+        SizeFlags::all(),
+    ] {
+        check(&data, flags);
+    }
+    check(&Kind::Empty, SizeFlags::default());
+    check(&TestEnum::Unnamed(0, 0), SizeFlags::default());
+
+    /// An element counting how many times its size is computed.
+    struct Counted;
+
+    static COUNTED: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+    impl CopyType for Counted {
+        type Copy = False;
+    }
+
+    impl MemSize for Counted {
+        fn _mem_size_rec(&self, ctx: &mut SizeContext) -> ControlFlow<()> {
+            COUNTED.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            ctx.add_heap(100, 100)
+        }
+    }
+
+    // The traversal stops as soon as the limit is exceeded
+    let v = (0..10_000_000).map(|_| Counted).collect::<Vec<_>>();
+    assert_eq!(v.mem_size_bounded(1000, SizeFlags::default()), None);
+    assert_eq!(COUNTED.load(std::sync::atomic::Ordering::Relaxed), 10);
+    assert_eq!(
+        v.mem_size_bounded(usize::MAX, SizeFlags::default()),
+        Some(size_of::<Vec<Counted>>() + 100 * 10_000_000)
+    );
+}
@@ -0,0 +1,85 @@
+/*
+ * SPDX-FileCopyrightText: 2023 Tommaso Fontana
+ * SPDX-FileCopyrightText: 2023 Inria
+ * SPDX-FileCopyrightText: 2023 Sebastiano Vigna
+ *
+ * SPDX-License-Identifier: Apache-2.0 OR LGPL-2.1-or-later
+ */
+
+//! Test suite checking that sizes computed in parallel are equal to those
+//! computed sequentially.
+
+#![cfg(feature = "rayon")]
+
+use mem_dbg::*;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
+
+const N: usize = 150_000;
+
+fn check<T: ParMemSize + ?Sized>(value: &T) {
+    for flags in [
+        SizeFlags::default(),
+        SizeFlags::CAPACITY,
+        SizeFlags::FOLLOW_REFS,
+        SizeFlags::FOLLOW_RCS | SizeFlags::CAPACITY,
+    ] {
+        assert_eq!(
+            value.par_mem_size(flags),
+            value.mem_size(flags),
+            "{:?}",
+            flags
+        );
+    }
+}
+
+#[derive(MemSize)]
+struct Item {
+    name: String,
+    shared: Arc<Vec<u8>>,
+}
+
+#[test]
+fn test_par_mem_size() {
+    let shared = Arc::new(vec![0_u8; 100]);
+    let mut items = Vec::with_capacity(N + 10);
+    for i in 0..N {
+        let mut name = String::with_capacity(10);
+        name.push_str(&i.to_string());
+        items.push(Item {
+            name,
+            shared: shared.clone(),
+        });
+    }
+    check(&items);
+    check(items.as_slice());
+    // Below the threshold
+    check(&items[..10]);
+
+    let strings = (0..N).map(|i| i.to_string()).collect::<Vec<_>>();
+    check(&strings.iter().cloned().collect::<HashSet<_>>());
+    check(
+        &strings
+            .iter()
+            .enumerate()
+            .map(|(i, s)| (s.clone(), i))
+            .collect::<HashMap<_, _>>(),
+    );
+    check(
+        &strings
+            .iter()
+            .enumerate()
+            .map(|(i, s)| (i, s.as_str()))
+            .collect::<HashMap<_, _>>(),
+    );
+    check(
+        &strings
+            .iter()
+            .enumerate()
+            .map(|(i, s)| (i, vec![s.clone(); 2]))
+            .collect::<BTreeMap<_, _>>(),
+    );
+
+    // Copy elements are sized instantly anyway
+    check(&vec![0_u64; N]);
+}
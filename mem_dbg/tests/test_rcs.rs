@@ -0,0 +1,100 @@
+/*
+ * SPDX-FileCopyrightText: 2023 Tommaso Fontana
+ * SPDX-FileCopyrightText: 2023 Inria
+ * SPDX-FileCopyrightText: 2023 Sebastiano Vigna
+ *
+ * SPDX-License-Identifier: Apache-2.0 OR LGPL-2.1-or-later
+ */
+
+//! Test suite comparing the size of structures sharing reference-counted
+//! allocations with the memory actually allocated. Since the allocator is
+//! global, this file must contain a single test.
+
+use mem_dbg::*;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+struct CountingAllocator;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATED.fetch_add(layout.size(), Ordering::SeqCst);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        ALLOCATED.fetch_sub(layout.size(), Ordering::SeqCst);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+/// Returns the value built by `f` and the memory it allocated, including
+/// its stack size.
+fn measure<T>(f: impl FnOnce() -> T) -> (T, usize) {
+    let before = ALLOCATED.load(Ordering::SeqCst);
+    let value = f();
+    let allocated = ALLOCATED.load(Ordering::SeqCst) - before;
+    (value, allocated + core::mem::size_of::<T>())
+}
+
+#[derive(MemSize)]
+struct Leaf {
+    name: String,
+}
+
+#[derive(MemSize)]
+struct Node {
+    name: String,
+    children: Vec<Arc<Leaf>>,
+}
+
+#[derive(MemSize)]
+struct Graph {
+    roots: Vec<Arc<Node>>,
+    names: Vec<Rc<String>>,
+}
+
+#[test]
+fn test_rcs() {
+    let (graph, allocated) = measure(|| {
+        // Many roots sharing the same child, twice each
+        let shared = Arc::new(Leaf {
+            name: "shared".to_owned(),
+        });
+        let mut roots = Vec::with_capacity(100);
+        for i in 0..100 {
+            roots.push(Arc::new(Node {
+                name: format!("root {}", i),
+                children: vec![shared.clone(); 2],
+            }));
+        }
+        let name = Rc::new("a shared name".to_owned());
+        Graph {
+            roots,
+            names: vec![name; 10],
+        }
+    });
+
+    assert_eq!(
+        graph.mem_size(SizeFlags::FOLLOW_RCS | SizeFlags::CAPACITY),
+        allocated
+    );
+    // Without the flag, the allocations of the pointers are not counted
+    assert!(graph.mem_size(SizeFlags::CAPACITY) < allocated);
+
+    // Slices share their allocation with the counters
+    let v: Vec<u64> = (0..1000).collect();
+    let (slice, allocated) = measure(|| Arc::<[u64]>::from(&v[..]));
+    assert_eq!(slice.mem_size(SizeFlags::FOLLOW_RCS), allocated);
+    assert_eq!(
+        allocated,
+        core::mem::size_of::<Arc<[u64]>>() + 2 * core::mem::size_of::<usize>() + 8000
+    );
+}
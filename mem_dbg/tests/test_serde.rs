@@ -0,0 +1,87 @@
+/*
+ * SPDX-FileCopyrightText: 2023 Tommaso Fontana
+ * SPDX-FileCopyrightText: 2023 Inria
+ * SPDX-FileCopyrightText: 2023 Sebastiano Vigna
+ *
+ * SPDX-License-Identifier: Apache-2.0 OR LGPL-2.1-or-later
+ */
+
+//! Test suite for the serialization of flags and trees.
+
+#![cfg(feature = "serde")]
+
+use mem_dbg::*;
+
+#[test]
+fn test_flags_round_trip() {
+    let flags = DbgFlags::default() | DbgFlags::HUMANIZE;
+    let json = serde_json::to_string(&flags).unwrap();
+    assert_eq!(json, r#"["HUMANIZE","PERCENTAGE","TYPE_NAME","SEPARATOR"]"#);
+    assert_eq!(serde_json::from_str::<DbgFlags>(&json).unwrap(), flags);
+
+    let flags = SizeFlags::CAPACITY | SizeFlags::FOLLOW_RCS;
+    let json = serde_json::to_string(&flags).unwrap();
+    assert_eq!(json, r#"["CAPACITY","FOLLOW_RCS"]"#);
+    assert_eq!(serde_json::from_str::<SizeFlags>(&json).unwrap(), flags);
+
+    // The order of the names is irrelevant
+    assert_eq!(
+        serde_json::from_str::<SizeFlags>(r#"["FOLLOW_RCS","CAPACITY"]"#).unwrap(),
+        flags
+    );
+    assert_eq!(serde_json::to_string(&DbgFlags::empty()).unwrap(), "[]");
+    assert_eq!(
+        serde_json::from_str::<DbgFlags>("[]").unwrap(),
+        DbgFlags::empty()
+    );
+}
+
+#[test]
+fn test_unknown_flag() {
+    let error = serde_json::from_str::<SizeFlags>(r#"["CAPACITY","FOLLOW_ARCS"]"#)
+        .unwrap_err()
+        .to_string();
+    assert!(
+        error.starts_with("unknown flag `FOLLOW_ARCS`, expected one of `FOLLOW_REFS`"),
+        "{}",
+        error
+    );
+
+    // Bits are not accepted in place of names
+    assert!(serde_json::from_str::<DbgFlags>("3").is_err());
+    assert!(serde_json::from_str::<DbgFlags>("[1]").is_err());
+}
+
+#[test]
+fn test_tree() {
+    let tree = (0_u8, vec![1_u32, 2]).mem_tree(DbgFlags::empty());
+    let value = serde_json::to_value(&tree).unwrap();
+    assert_eq!(value["size"], 40);
+    assert_eq!(value["kind"], "value");
+    assert_eq!(value["children"][0]["name"], "0");
+    assert_eq!(value["children"][0]["padding"], 7);
+    assert_eq!(value["children"][1]["size"], 32);
+    assert_eq!(value["children"][1]["type_name"], serde_json::Value::Null);
+}
+
+#[test]
+fn test_tree_round_trip() {
+    #[derive(MemSize, MemDbg)]
+    enum Data {
+        A(u8, Vec<String>),
+        _B,
+    }
+
+    let data = Data::A(0, vec!["a".repeat(10); 3]);
+    let flags = DbgFlags::default() | DbgFlags::ALLOCATIONS | DbgFlags::EXPAND_ELEMENTS;
+    let tree = data.mem_tree(flags);
+    let json = serde_json::to_string(&tree).unwrap();
+    // Names are owned, so the tree does not borrow from the input
+    let read: MemNode = serde_json::from_reader(json.as_bytes()).unwrap();
+    assert_eq!(read, tree);
+    let mut expected = String::new();
+    tree.write_on(&mut expected, flags).unwrap();
+    let mut output = String::new();
+    read.write_on(&mut output, flags).unwrap();
+    assert_eq!(output, expected);
+}
@@ -0,0 +1,183 @@
+/*
+ * SPDX-FileCopyrightText: 2023 Tommaso Fontana
+ * SPDX-FileCopyrightText: 2023 Inria
+ * SPDX-FileCopyrightText: 2023 Sebastiano Vigna
+ *
+ * SPDX-License-Identifier: Apache-2.0 OR LGPL-2.1-or-later
+ */
+
+//! Test suite checking that the types listed in [`SUPPORTED_TYPES`] implement
+//! [`MemSize`].
+
+use mem_dbg::*;
+
+fn implements_mem_size<T: ?Sized + MemSize>() {}
+
+/// Checks that the given names are exactly [`SUPPORTED_TYPES`], and that an
+/// instance of each type implements [`MemSize`].
+macro_rules! check_supported_types {
+    ($($name:literal => $ty:ty,)*) => {
+        #[test]
+        fn test_supported_types() {
+            $(implements_mem_size::<$ty>();)*
+            assert_eq!(SUPPORTED_TYPES, [$($name),*]);
+        }
+    };
+}
+
+check_supported_types! {
+    "()" => (),
+    "bool" => bool,
+    "char" => char,
+    "f32" => f32,
+    "f64" => f64,
+    "u8" => u8,
+    "u16" => u16,
+    "u32" => u32,
+    "u64" => u64,
+    "u128" => u128,
+    "usize" => usize,
+    "i8" => i8,
+    "i16" => i16,
+    "i32" => i32,
+    "i64" => i64,
+    "i128" => i128,
+    "isize" => isize,
+    "str" => str,
+    "String" => String,
+    "core::sync::atomic::AtomicBool" => core::sync::atomic::AtomicBool,
+    "core::sync::atomic::AtomicI8" => core::sync::atomic::AtomicI8,
+    "core::sync::atomic::AtomicI16" => core::sync::atomic::AtomicI16,
+    "core::sync::atomic::AtomicI32" => core::sync::atomic::AtomicI32,
+    "core::sync::atomic::AtomicI64" => core::sync::atomic::AtomicI64,
+    "core::sync::atomic::AtomicIsize" => core::sync::atomic::AtomicIsize,
+    "core::sync::atomic::AtomicU8" => core::sync::atomic::AtomicU8,
+    "core::sync::atomic::AtomicU16" => core::sync::atomic::AtomicU16,
+    "core::sync::atomic::AtomicU32" => core::sync::atomic::AtomicU32,
+    "core::sync::atomic::AtomicU64" => core::sync::atomic::AtomicU64,
+    "core::sync::atomic::AtomicUsize" => core::sync::atomic::AtomicUsize,
+    "core::num::NonZeroI8" => core::num::NonZeroI8,
+    "core::num::NonZeroI16" => core::num::NonZeroI16,
+    "core::num::NonZeroI32" => core::num::NonZeroI32,
+    "core::num::NonZeroI64" => core::num::NonZeroI64,
+    "core::num::NonZeroI128" => core::num::NonZeroI128,
+    "core::num::NonZeroIsize" => core::num::NonZeroIsize,
+    "core::num::NonZeroU8" => core::num::NonZeroU8,
+    "core::num::NonZeroU16" => core::num::NonZeroU16,
+    "core::num::NonZeroU32" => core::num::NonZeroU32,
+    "core::num::NonZeroU64" => core::num::NonZeroU64,
+    "core::num::NonZeroU128" => core::num::NonZeroU128,
+    "core::num::NonZeroUsize" => core::num::NonZeroUsize,
+    "core::marker::PhantomPinned" => core::marker::PhantomPinned,
+    "core::convert::Infallible" => core::convert::Infallible,
+    "core::marker::PhantomData<T>" => core::marker::PhantomData<u8>,
+    "core::mem::MaybeUninit<T>" => core::mem::MaybeUninit<u8>,
+    "&T" => &'static u8,
+    "&mut T" => &'static mut u8,
+    "Box<T>" => Box<str>,
+    "std::rc::Rc<T>" => std::rc::Rc<u8>,
+    "std::sync::Arc<T>" => std::sync::Arc<u8>,
+    "core::ptr::NonNull<T>" => core::ptr::NonNull<u8>,
+    "Option<T>" => Option<u8>,
+    "Result<T, E>" => Result<u8, String>,
+    "core::ops::ControlFlow<B, C>" => core::ops::ControlFlow<u8, String>,
+    "[T]" => [String],
+    "[T; N]" => [u8; 4],
+    "(T0, ..., T9)" => (u8, u8, u8, u8, u8, u8, u8, u8, u8, String),
+    "Vec<T>" => Vec<String>,
+    "std::collections::VecDeque<T>" => std::collections::VecDeque<String>,
+    "std::collections::BinaryHeap<T>" => std::collections::BinaryHeap<String>,
+    "std::collections::HashSet<T>" => std::collections::HashSet<String>,
+    "std::collections::HashMap<K, V>" => std::collections::HashMap<String, u8>,
+    "std::collections::BTreeSet<T>" => std::collections::BTreeSet<String>,
+    "std::collections::BTreeMap<K, V>" => std::collections::BTreeMap<String, u8>,
+    "core::hash::BuildHasherDefault<H>" => core::hash::BuildHasherDefault<std::hash::DefaultHasher>,
+    "std::collections::hash_map::RandomState" => std::collections::hash_map::RandomState,
+    "fn() -> R" => fn() -> u8,
+    "fn(A) -> R" => fn(u8) -> u8,
+    "fn(A, B) -> R" => fn(u8, u8) -> u8,
+    "fn(A, B, C) -> R" => fn(u8, u8, u8) -> u8,
+    "fn(A, B, C, D) -> R" => fn(u8, u8, u8, u8) -> u8,
+    "dyn Fn(A, ...) -> R" => dyn Fn(u8, u8, u8, u8) -> u8 + Send + Sync,
+    "dyn FnMut(A, ...) -> R" => dyn FnMut(u8) -> u8 + Send,
+    "dyn FnOnce(A, ...) -> R" => dyn FnOnce() -> u8,
+    "dyn std::error::Error" => dyn std::error::Error,
+    "dyn std::error::Error + Send" => dyn std::error::Error + Send,
+    "dyn std::error::Error + Send + Sync" => dyn std::error::Error + Send + Sync,
+    "core::ops::Range<Idx>" => core::ops::Range<u8>,
+    "core::ops::RangeFrom<Idx>" => core::ops::RangeFrom<u8>,
+    "core::ops::RangeInclusive<Idx>" => core::ops::RangeInclusive<u8>,
+    "core::ops::RangeTo<Idx>" => core::ops::RangeTo<u8>,
+    "core::ops::RangeToInclusive<Idx>" => core::ops::RangeToInclusive<u8>,
+    "core::cell::Cell<T>" => core::cell::Cell<u8>,
+    "core::cell::RefCell<T>" => core::cell::RefCell<String>,
+    "core::cell::UnsafeCell<T>" => core::cell::UnsafeCell<String>,
+    "core::cell::OnceCell<T>" => core::cell::OnceCell<String>,
+    "core::cell::LazyCell<T, F>" => core::cell::LazyCell<String>,
+    "std::sync::Mutex<T>" => std::sync::Mutex<String>,
+    "std::sync::MutexGuard<'_, T>" => std::sync::MutexGuard<'static, String>,
+    "std::sync::RwLock<T>" => std::sync::RwLock<String>,
+    "std::sync::RwLockReadGuard<'_, T>" => std::sync::RwLockReadGuard<'static, String>,
+    "std::sync::RwLockWriteGuard<'_, T>" => std::sync::RwLockWriteGuard<'static, String>,
+    "core::alloc::Layout" => core::alloc::Layout,
+    "std::path::Path" => std::path::Path,
+    "std::path::PathBuf" => std::path::PathBuf,
+    "std::ffi::OsStr" => std::ffi::OsStr,
+    "std::ffi::OsString" => std::ffi::OsString,
+    "std::fs::File" => std::fs::File,
+    "std::fs::OpenOptions" => std::fs::OpenOptions,
+    "std::fs::Metadata" => std::fs::Metadata,
+    "std::fs::FileType" => std::fs::FileType,
+    "std::fs::FileTimes" => std::fs::FileTimes,
+    "std::fs::Permissions" => std::fs::Permissions,
+    "std::io::BufReader<T>" => std::io::BufReader<std::fs::File>,
+    "std::io::BufWriter<T>" => std::io::BufWriter<std::fs::File>,
+    "std::io::Cursor<T>" => std::io::Cursor<Vec<u8>>,
+    "std::io::IoSlice<'_>" => std::io::IoSlice<'static>,
+    "std::io::IoSliceMut<'_>" => std::io::IoSliceMut<'static>,
+    "std::net::TcpStream" => std::net::TcpStream,
+    "std::net::TcpListener" => std::net::TcpListener,
+    "std::net::UdpSocket" => std::net::UdpSocket,
+    "std::net::Ipv4Addr" => std::net::Ipv4Addr,
+    "std::net::Ipv6Addr" => std::net::Ipv6Addr,
+    "std::net::IpAddr" => std::net::IpAddr,
+    "std::net::SocketAddrV4" => std::net::SocketAddrV4,
+    "std::net::SocketAddrV6" => std::net::SocketAddrV6,
+    "std::net::SocketAddr" => std::net::SocketAddr,
+    "std::time::Duration" => std::time::Duration,
+    "std::time::Instant" => std::time::Instant,
+    "std::time::SystemTime" => std::time::SystemTime,
+    "std::time::SystemTimeError" => std::time::SystemTimeError,
+    "std::process::ExitStatus" => std::process::ExitStatus,
+    "std::process::ExitCode" => std::process::ExitCode,
+    "std::process::Output" => std::process::Output,
+}
+
+#[cfg(feature = "all-impls")]
+#[test]
+fn test_optional_crates() {
+    implements_mem_size::<anyhow::Error>();
+    implements_mem_size::<bytes::Bytes>();
+    implements_mem_size::<bytes::BytesMut>();
+    implements_mem_size::<dashmap::DashMap<String, u8>>();
+    implements_mem_size::<half::f16>();
+    implements_mem_size::<half::bf16>();
+    implements_mem_size::<im::Vector<String>>();
+    implements_mem_size::<im::HashMap<String, u8>>();
+    implements_mem_size::<im::HashSet<String>>();
+    implements_mem_size::<im::OrdMap<String, u8>>();
+    implements_mem_size::<im::OrdSet<String>>();
+    implements_mem_size::<maligned::Aligned<maligned::A64, [u8; 64]>>();
+    implements_mem_size::<mmap_rs::Mmap>();
+    implements_mem_size::<mmap_rs::MmapMut>();
+    implements_mem_size::<priority_queue::PriorityQueue<String, u8>>();
+    implements_mem_size::<rand::rngs::SmallRng>();
+    implements_mem_size::<rand::rngs::StdRng>();
+    implements_mem_size::<time::Date>();
+    implements_mem_size::<time::Duration>();
+    implements_mem_size::<time::OffsetDateTime>();
+    implements_mem_size::<time::PrimitiveDateTime>();
+    implements_mem_size::<time::Time>();
+    implements_mem_size::<time::UtcOffset>();
+    implements_mem_size::<uuid::Uuid>();
+}
@@ -0,0 +1,78 @@
+/*
+ * SPDX-FileCopyrightText: 2023 Tommaso Fontana
+ * SPDX-FileCopyrightText: 2023 Inria
+ * SPDX-FileCopyrightText: 2023 Sebastiano Vigna
+ *
+ * SPDX-License-Identifier: Apache-2.0 OR LGPL-2.1-or-later
+ */
+
+//! Test suite checking the events emitted by [`MemDbg::mem_dbg_trace`].
+
+#![cfg(feature = "tracing")]
+
+use mem_dbg::*;
+use std::sync::{Arc, Mutex};
+
+#[derive(MemSize, MemDbg)]
+struct Data {
+    a: u64,
+    b: Vec<u8>,
+    c: (u8, String),
+}
+
+/// A writer collecting the output of a subscriber in a shared buffer.
+#[derive(Clone, Default)]
+struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+impl std::io::Write for Buffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Returns the lines emitted by a subscriber while calling `f`.
+fn capture(f: impl FnOnce()) -> Vec<String> {
+    let buffer = Buffer::default();
+    let writer = buffer.clone();
+    let subscriber = tracing_subscriber::fmt()
+        .with_writer(move || writer.clone())
+        .with_ansi(false)
+        .without_time()
+        .with_target(false)
+        .with_level(false)
+        .finish();
+    tracing::subscriber::with_default(subscriber, f);
+    let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+    output.lines().map(str::to_string).collect()
+}
+
+#[test]
+fn test_trace() {
+    let data = Data {
+        a: 1,
+        b: vec![1, 2, 3, 4],
+        c: (2, String::from("hello")),
+    };
+    let lines = capture(|| data.mem_dbg_trace(1, DbgFlags::TYPE_NAME));
+
+    assert_eq!(
+        lines,
+        [
+            "mem_dbg{name=\"root\"}: path=\"root\" type=\"test_tracing::Data\" bytes=73 percent=100.0",
+            "mem_dbg{name=\"root\"}:mem_dbg{name=\"a\"}: path=\"root.a\" type=\"u64\" bytes=8 percent=10.95890410958904",
+            "mem_dbg{name=\"root\"}:mem_dbg{name=\"b\"}: path=\"root.b\" type=\"alloc::vec::Vec<u8>\" bytes=28 percent=38.35616438356164",
+            "mem_dbg{name=\"root\"}:mem_dbg{name=\"c\"}: path=\"root.c\" type=\"(u8, alloc::string::String)\" bytes=37 percent=50.68493150684932",
+        ]
+    );
+
+    // Without type names, the type field is not recorded
+    let lines = capture(|| data.mem_dbg_trace(0, DbgFlags::empty()));
+    assert_eq!(
+        lines,
+        ["mem_dbg{name=\"root\"}: path=\"root\" bytes=73 percent=100.0"]
+    );
+}
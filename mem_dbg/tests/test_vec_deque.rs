@@ -0,0 +1,87 @@
+/*
+ * SPDX-FileCopyrightText: 2023 Tommaso Fontana
+ * SPDX-FileCopyrightText: 2023 Inria
+ * SPDX-FileCopyrightText: 2023 Sebastiano Vigna
+ *
+ * SPDX-License-Identifier: Apache-2.0 OR LGPL-2.1-or-later
+ */
+
+//! Test suite comparing the size of double-ended queues with the memory
+//! actually allocated. Since the allocator is global, this file must contain
+//! a single test.
+
+use mem_dbg::*;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+struct CountingAllocator;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATED.fetch_add(layout.size(), Ordering::SeqCst);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        ALLOCATED.fetch_sub(layout.size(), Ordering::SeqCst);
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATED.fetch_add(new_size, Ordering::SeqCst);
+        ALLOCATED.fetch_sub(layout.size(), Ordering::SeqCst);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+/// Returns the value built by `f` and the memory it allocated, including
+/// its stack size.
+fn measure<T>(f: impl FnOnce() -> T) -> (T, usize) {
+    let before = ALLOCATED.load(Ordering::SeqCst);
+    let value = f();
+    let allocated = ALLOCATED.load(Ordering::SeqCst) - before;
+    (value, allocated + core::mem::size_of::<T>())
+}
+
+const POWERS: [usize; 6] = [1, 10, 100, 1_000, 10_000, 100_000];
+
+#[test]
+fn test_vec_deque() {
+    for n in POWERS {
+        // Copy elements, wrapping around the ring buffer
+        let (deque, allocated) = measure(|| {
+            let mut deque = VecDeque::new();
+            for i in 0..n as u64 {
+                deque.push_back(i);
+            }
+            for _ in 0..n / 2 {
+                deque.pop_front();
+            }
+            for i in 0..n as u64 / 4 {
+                deque.push_back(i);
+            }
+            deque
+        });
+        assert_eq!(deque.mem_size(SizeFlags::CAPACITY), allocated);
+        assert_eq!(
+            deque.mem_size(SizeFlags::default()),
+            core::mem::size_of::<VecDeque<u64>>() + deque.len() * 8
+        );
+
+        // Non-copy elements
+        let (deque, allocated) = measure(|| {
+            let mut deque = VecDeque::new();
+            for _ in 0..n {
+                deque.push_front(String::from("mem_dbg"));
+            }
+            deque
+        });
+        assert_eq!(deque.mem_size(SizeFlags::CAPACITY), allocated);
+    }
+}
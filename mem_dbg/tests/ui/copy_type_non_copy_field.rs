@@ -0,0 +1,11 @@
+use mem_dbg::*;
+use std::borrow::Cow;
+
+#[derive(MemSize)]
+#[copy_type]
+struct Data {
+    a: u8,
+    name: Option<Cow<'static, str>>,
+}
+
+fn main() {}
@@ -0,0 +1,7 @@
+use mem_dbg::*;
+
+struct Empty;
+
+impl MemSize for Empty {}
+
+fn main() {}
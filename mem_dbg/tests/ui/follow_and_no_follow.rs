@@ -0,0 +1,9 @@
+use mem_dbg::*;
+
+#[derive(MemSize)]
+struct Table {
+    #[mem_dbg(follow, no_follow)]
+    data: &'static [u8],
+}
+
+fn main() {}
@@ -0,0 +1,10 @@
+use mem_dbg::*;
+
+#[derive(MemSize)]
+#[mem_size(union = "deep")]
+union TwoFields {
+    a: u8,
+    b: u64,
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use mem_dbg::*;
+
+#[derive(MemSize, MemDbg)]
+union TwoFields {
+    a: u8,
+    b: u64,
+}
+
+fn main() {}
@@ -0,0 +1,11 @@
+use mem_dbg::*;
+
+struct ThirdParty(u8);
+
+#[derive(MemSize)]
+struct Data {
+    v: Vec<ThirdParty>,
+    a: [ThirdParty; 2],
+}
+
+fn main() {}